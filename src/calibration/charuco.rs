@@ -0,0 +1,573 @@
+use std::collections::HashMap;
+
+use image::GrayImage;
+use nalgebra::{Matrix3, Vector3};
+
+use super::{
+    checkerboard::{
+        cross,
+        distance,
+        is_saddle,
+        refine_corner,
+        sub,
+        Plane,
+        DETECTION_SIZE,
+    },
+    intrinsics::estimate_homography,
+    BoardCorners,
+    Checkerboard,
+};
+
+
+/// cells along a marker edge, the 5x5 bits of the original ArUco dictionary inside a black border
+const MARKER_CELLS: usize = 7;
+
+/// the rows a marker is made of, each encoding 2 bits of its id. set bits are white, the most significant on the left
+const MARKER_WORDS: [u8; 4] = [0x10, 0x17, 0x09, 0x0e];
+
+/// the 10 bit ids of the original ArUco dictionary
+const MARKER_IDS: usize = 1024;
+
+const SMOOTHING_SIGMA: f32 = 1.0;
+
+/// half widths of the adaptive threshold windows in detection pixels, each finds markers of a different size
+const THRESHOLD_RADII: [usize; 3] = [3, 7, 15];
+
+/// a pixel is dark below the mean of its window minus this offset
+const THRESHOLD_OFFSET: f32 = 7.0;
+
+/// shortest marker edge in detection pixels
+const MIN_MARKER_SIZE: f64 = 14.0;
+
+/// minimum intensity range between the cells of a marker
+const MIN_CONTRAST: f32 = 20.0;
+
+/// accepted distance of a marker corner from the board homography of all markers, relative to the marker edge
+const MARKER_TOLERANCE: f64 = 0.25;
+
+/// fewer corners hardly constrain a view
+const MIN_CORNERS: usize = 6;
+
+
+/// finds the inner corners of a ChArUco `board` next to its visible markers, with subpixel accuracy
+///
+/// the light squares hold the markers of the original ArUco dictionary (OpenCV's `DICT_ARUCO_ORIGINAL`), numbered from
+/// 0 row by row, and the top left square is dark. every marker labels the corners of its square, so corners are
+/// returned with their index into `Checkerboard::object_points` and the board may be partially visible or occluded
+pub fn detect_charuco(image: &GrayImage, board: &Checkerboard) -> Option<BoardCorners> {
+    let marker_size = board.marker_size?;
+    if board.columns < 2 || board.rows < 2 || marker_size <= 0.0 || marker_size >= board.square_size {
+        return None;
+    }
+
+    let factor = image.width().max(image.height()).div_ceil(DETECTION_SIZE).max(1);
+    let plane = Plane::downsampled(image, factor).blurred(SMOOTHING_SIGMA);
+
+    let squares = marker_squares(board);
+    let mut markers = detect_markers(&plane).into_iter()
+        .filter(|(id, _)| *id < squares.len())
+        .collect::<Vec<_>>();
+
+    // misread markers are off the board the other markers agree on
+    if markers.len() >= 3 {
+        let (board_points, image_points): (Vec<_>, Vec<_>) = markers.iter()
+            .flat_map(|(id, corners)| marker_corners(board, squares[*id]).into_iter().zip(*corners))
+            .unzip();
+        let homography = estimate_homography(&board_points, &image_points).ok()?;
+
+        markers.retain(|(id, corners)| {
+            let edge = marker_edge(corners);
+
+            marker_corners(board, squares[*id]).iter()
+                .zip(corners)
+                .all(|(board_point, corner)| distance(transform(&homography, *board_point), *corner) < MARKER_TOLERANCE * edge)
+        });
+    }
+
+    if markers.is_empty() {
+        return None;
+    }
+
+    let markers = markers.into_iter()
+        .map(|(id, corners)| (squares[id], corners))
+        .collect::<HashMap<_, _>>();
+
+    // the light margin around the markers, which the refinement window has to stay within
+    let margin = (board.square_size - marker_size) / 2.0;
+
+    // the margin makes for smaller windows than on a checkerboard, smoothing evens out their fewer gradients
+    let full = Plane::downsampled(image, 1).blurred(SMOOTHING_SIGMA);
+    let mut corners = vec![];
+
+    for row in 0..board.rows {
+        for column in 0..board.columns {
+            // the squares touching the inner corner, of which the light ones hold markers
+            let adjacent = [(column, row), (column + 1, row), (column, row + 1), (column + 1, row + 1)]
+                .into_iter()
+                .filter_map(|square| Some((square, markers.get(&square)?)))
+                .collect::<Vec<_>>();
+            if adjacent.is_empty() {
+                continue;
+            }
+
+            let (board_points, image_points): (Vec<_>, Vec<_>) = adjacent.iter()
+                .flat_map(|(square, corners)| marker_corners(board, *square).into_iter().zip(**corners))
+                .unzip();
+            let Ok(homography) = estimate_homography(&board_points, &image_points) else {
+                continue;
+            };
+
+            let object_point = [column as f64 * board.square_size, row as f64 * board.square_size, 0.0];
+            let [x, y] = transform(&homography, object_point);
+            if !is_saddle(&plane, x, y) {
+                continue;
+            }
+
+            let pixels_per_meter = adjacent.iter()
+                .map(|(_, corners)| marker_edge(corners))
+                .sum::<f64>() / adjacent.len() as f64 / marker_size * factor as f64;
+            let half_window = ((margin * pixels_per_meter * 0.7) as i32).clamp(2, 3 + 2 * factor as i32);
+
+            let corner = [
+                (x + 0.5) * factor as f64 - 0.5,
+                (y + 0.5) * factor as f64 - 0.5,
+            ];
+            corners.push((row * board.columns + column, refine_corner(&full, corner, half_window)));
+        }
+    }
+
+    // corners along a single row or column do not span the board plane
+    let first = corners.first()?.0;
+    let spans_rows = corners.iter().any(|(corner, _)| corner / board.columns != first / board.columns);
+    let spans_columns = corners.iter().any(|(corner, _)| corner % board.columns != first % board.columns);
+
+    (corners.len() >= MIN_CORNERS && spans_rows && spans_columns).then_some(corners)
+}
+
+
+/// the id of a marker, `None` if a row is not one of `MARKER_WORDS`
+fn marker_id(bits: &[[bool; 5]; 5]) -> Option<usize> {
+    bits.iter()
+        .try_fold(0, |id, row| {
+            let word = row.iter().fold(0u8, |word, &bit| (word << 1) | bit as u8);
+            let index = MARKER_WORDS.iter().position(|&candidate| candidate == word)?;
+
+            Some((id << 2) | index)
+        })
+}
+
+/// the `(column, row)` square of each marker id, the light squares row by row
+fn marker_squares(board: &Checkerboard) -> Vec<(usize, usize)> {
+    (0..=board.rows)
+        .flat_map(|row| (0..=board.columns).map(move |column| (column, row)))
+        .filter(|(column, row)| (column + row) % 2 == 1)
+        .take(MARKER_IDS)
+        .collect()
+}
+
+/// board coordinates of the top left, top right, bottom right and bottom left corner of the marker in `square`, in the
+/// frame of `Checkerboard::object_points` whose origin is the first inner corner
+fn marker_corners(board: &Checkerboard, (column, row): (usize, usize)) -> [[f64; 3]; 4] {
+    let marker_size = board.marker_size.unwrap_or_default();
+    let center_x = (column as f64 - 0.5) * board.square_size;
+    let center_y = (row as f64 - 0.5) * board.square_size;
+    let half = marker_size / 2.0;
+
+    [
+        [center_x - half, center_y - half, 0.0],
+        [center_x + half, center_y - half, 0.0],
+        [center_x + half, center_y + half, 0.0],
+        [center_x - half, center_y + half, 0.0],
+    ]
+}
+
+/// mean edge length of a marker in the image
+fn marker_edge(corners: &[[f64; 2]; 4]) -> f64 {
+    (0..4).map(|i| distance(corners[i], corners[(i + 1) % 4])).sum::<f64>() / 4.0
+}
+
+
+/// the decoded markers by id with their top left, top right, bottom right and bottom left corners in the image
+fn detect_markers(plane: &Plane) -> HashMap<usize, [[f64; 2]; 4]> {
+    let integral = integral_image(plane);
+    let mut markers = HashMap::new();
+
+    for radius in THRESHOLD_RADII {
+        let dark = threshold(plane, &integral, radius);
+
+        for component in dark_components(&dark, plane.width, plane.height) {
+            let Some(quad) = fit_quad(&convex_hull(component)) else {
+                continue;
+            };
+
+            if let Some((id, corners)) = decode_marker(plane, &quad) {
+                markers.entry(id).or_insert(corners);
+            }
+        }
+    }
+
+    markers
+}
+
+/// sums of the plane above and left of each position, with a leading row and column of zeros
+fn integral_image(plane: &Plane) -> Vec<f64> {
+    let stride = plane.width + 1;
+    let mut integral = vec![0.0; stride * (plane.height + 1)];
+
+    for y in 0..plane.height {
+        let mut row_sum = 0.0;
+        for x in 0..plane.width {
+            row_sum += plane.data[y * plane.width + x] as f64;
+            integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    integral
+}
+
+/// pixels darker than the mean of the window around them
+fn threshold(plane: &Plane, integral: &[f64], radius: usize) -> Vec<bool> {
+    let stride = plane.width + 1;
+
+    (0..plane.height)
+        .flat_map(|y| (0..plane.width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let (left, right) = (x.saturating_sub(radius), (x + radius + 1).min(plane.width));
+            let (top, bottom) = (y.saturating_sub(radius), (y + radius + 1).min(plane.height));
+
+            let sum = integral[bottom * stride + right] - integral[top * stride + right]
+                - integral[bottom * stride + left] + integral[top * stride + left];
+            let mean = sum / ((right - left) * (bottom - top)) as f64;
+
+            plane.data[y * plane.width + x] < mean as f32 - THRESHOLD_OFFSET
+        })
+        .collect()
+}
+
+/// the pixels of each 4-connected dark region large enough to be a marker
+fn dark_components(dark: &[bool], width: usize, height: usize) -> Vec<Vec<[f64; 2]>> {
+    let min_pixels = (MIN_MARKER_SIZE * MIN_MARKER_SIZE / 2.0) as usize;
+    let max_pixels = width * height / 4;
+
+    let mut visited = vec![false; dark.len()];
+    let mut components = vec![];
+    let mut stack = vec![];
+
+    for start in 0..dark.len() {
+        if !dark[start] || visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        stack.push(start);
+        let mut pixels = vec![];
+
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            pixels.push([x as f64, y as f64]);
+
+            let neighbors = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then(|| index + 1),
+                (y > 0).then(|| index - width),
+                (y + 1 < height).then(|| index + width),
+            ];
+            for neighbor in neighbors.into_iter().flatten() {
+                if dark[neighbor] && !visited[neighbor] {
+                    visited[neighbor] = true;
+                    stack.push(neighbor);
+                }
+            }
+        }
+
+        if (min_pixels..=max_pixels).contains(&pixels.len()) {
+            components.push(pixels);
+        }
+    }
+
+    components
+}
+
+/// monotone chain, counterclockwise with y up
+fn convex_hull(mut points: Vec<[f64; 2]>) -> Vec<[f64; 2]> {
+    points.sort_by(|a, b| a[0].total_cmp(&b[0]).then(a[1].total_cmp(&b[1])));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+
+    let mut hull: Vec<[f64; 2]> = vec![];
+    for pass in 0..2 {
+        let start = hull.len();
+        let ordered: Box<dyn Iterator<Item = &[f64; 2]>> = if pass == 0 {
+            Box::new(points.iter())
+        } else {
+            Box::new(points.iter().rev())
+        };
+
+        for &point in ordered {
+            while hull.len() >= start + 2 && cross(sub(hull[hull.len() - 1], hull[hull.len() - 2]), sub(point, hull[hull.len() - 2])) <= 0.0 {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+
+        // the last point starts the other chain
+        hull.pop();
+    }
+
+    hull
+}
+
+/// the quadrilateral spanned by the hull, clockwise in the image, `None` unless the hull is close to one
+fn fit_quad(hull: &[[f64; 2]]) -> Option<[[f64; 2]; 4]> {
+    if hull.len() < 4 {
+        return None;
+    }
+
+    let (a, b) = (0..hull.len())
+        .flat_map(|i| (i + 1..hull.len()).map(move |j| (i, j)))
+        .max_by(|&(i, j), &(k, l)| distance(hull[i], hull[j]).total_cmp(&distance(hull[k], hull[l])))?;
+    let diagonal = sub(hull[b], hull[a]);
+    let side = |point: &[f64; 2]| cross(diagonal, sub(*point, hull[a]));
+
+    let c = *hull.iter().max_by(|p, q| side(p).total_cmp(&side(q)))?;
+    let d = *hull.iter().min_by(|p, q| side(p).total_cmp(&side(q)))?;
+    if side(&c) <= 0.0 || side(&d) >= 0.0 {
+        return None;
+    }
+
+    let mut quad = [hull[a], c, hull[b], d];
+    if cross(sub(quad[1], quad[0]), sub(quad[3], quad[0])) < 0.0 {
+        quad = [quad[0], quad[3], quad[2], quad[1]];
+    }
+
+    if (0..4).any(|i| distance(quad[i], quad[(i + 1) % 4]) < MIN_MARKER_SIZE) {
+        return None;
+    }
+
+    // the hull of a marker is its outline, other shapes stick out of the quadrilateral
+    (polygon_area(&quad) > 0.9 * polygon_area(hull)).then_some(quad)
+}
+
+fn polygon_area(points: &[[f64; 2]]) -> f64 {
+    (0..points.len())
+        .map(|i| cross(points[i], points[(i + 1) % points.len()]))
+        .sum::<f64>()
+        .abs() / 2.0
+}
+
+/// the id and the corners of the marker outlined by `quad`, starting at its top left corner. quads which decode in
+/// more than one rotation are ambiguous and dropped
+fn decode_marker(plane: &Plane, quad: &[[f64; 2]; 4]) -> Option<(usize, [[f64; 2]; 4])> {
+    let cells = MARKER_CELLS as f64;
+    let cell_points = [[0.0, 0.0, 0.0], [cells, 0.0, 0.0], [cells, cells, 0.0], [0.0, cells, 0.0]];
+
+    let mut decoded = (0..4).filter_map(|rotation| {
+        let corners: [[f64; 2]; 4] = std::array::from_fn(|i| quad[(i + rotation) % 4]);
+        let homography = estimate_homography(&cell_points, &corners).ok()?;
+
+        // the mean of the inner samples of each cell
+        let means: [[f32; MARKER_CELLS]; MARKER_CELLS] = std::array::from_fn(|row| std::array::from_fn(|column| {
+            let offsets = [-0.2, 0.0, 0.2];
+            offsets.iter()
+                .flat_map(|dy| offsets.iter().map(move |dx| (dx, dy)))
+                .map(|(dx, dy)| {
+                    let [x, y] = transform(&homography, [column as f64 + 0.5 + dx, row as f64 + 0.5 + dy, 0.0]);
+                    plane.sample(x, y)
+                })
+                .sum::<f32>() / 9.0
+        }));
+
+        let min = means.iter().flatten().copied().fold(f32::INFINITY, f32::min);
+        let max = means.iter().flatten().copied().fold(f32::NEG_INFINITY, f32::max);
+        if max - min < MIN_CONTRAST {
+            return None;
+        }
+
+        let middle = (min + max) / 2.0;
+        let is_border = |index: usize| index == 0 || index == MARKER_CELLS - 1;
+        let dark_border = (0..MARKER_CELLS)
+            .flat_map(|row| (0..MARKER_CELLS).map(move |column| (row, column)))
+            .filter(|&(row, column)| is_border(row) || is_border(column))
+            .all(|(row, column)| means[row][column] < middle);
+        if !dark_border {
+            return None;
+        }
+
+        let bits = std::array::from_fn(|row| std::array::from_fn(|column| means[row + 1][column + 1] > middle));
+        Some((marker_id(&bits)?, corners))
+    });
+
+    let marker = decoded.next()?;
+    decoded.next().is_none().then_some(marker)
+}
+
+/// pixel coordinates of a board point under a homography of the board plane
+fn transform(homography: &Matrix3<f64>, [x, y, _]: [f64; 3]) -> [f64; 2] {
+    let point = homography * Vector3::new(x, y, 1.0);
+
+    [point.x / point.z, point.y / point.z]
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nalgebra::{Isometry3, Point3, Vector3};
+
+    use crate::calibration::{
+        checkerboard::tests::{board_pose, camera, render as render_checkerboard},
+        intrinsics::CameraIntrinsics,
+    };
+
+
+    /// the 5x5 bits of a marker, white bits are set
+    fn marker_bits(id: usize) -> [[bool; 5]; 5] {
+        std::array::from_fn(|row| {
+            let word = MARKER_WORDS[(id >> (2 * (4 - row))) & 3];
+            std::array::from_fn(|column| (word >> (4 - column)) & 1 == 1)
+        })
+    }
+
+    fn charuco_board() -> Checkerboard {
+        Checkerboard {
+            columns: 7,
+            rows: 5,
+            square_size: 0.04,
+            marker_size: Some(0.025),
+        }
+    }
+
+    /// renders the board with a white margin of one square, supersampled 4 times
+    fn render(
+        board: &Checkerboard,
+        intrinsics: &CameraIntrinsics,
+        pose: &Isometry3<f64>,
+        (width, height): (u32, u32),
+    ) -> GrayImage {
+        let inverse = pose.inverse();
+        let origin = inverse * Point3::origin();
+
+        let marker_size = board.marker_size.unwrap();
+        let markers = marker_squares(board).into_iter()
+            .enumerate()
+            .map(|(id, square)| (square, marker_bits(id)))
+            .collect::<HashMap<_, _>>();
+
+        GrayImage::from_fn(width, height, |u, v| {
+            let value = [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+                .iter()
+                .map(|(dx, dy)| {
+                    let [x, y] = intrinsics.undistort([u as f64 + dx - 0.5, v as f64 + dy - 0.5]);
+                    let direction = inverse * Vector3::new(x, y, 1.0);
+
+                    let distance = -origin.z / direction.z;
+                    if distance <= 0.0 {
+                        return 128.0;
+                    }
+
+                    let point = origin + direction * distance;
+                    let i = (point.x / board.square_size).floor() as i64;
+                    let j = (point.y / board.square_size).floor() as i64;
+
+                    let (columns, rows) = (board.columns as i64, board.rows as i64);
+                    if (-1..columns).contains(&i) && (-1..rows).contains(&j) {
+                        if (i + j).rem_euclid(2) == 0 {
+                            return 20.0;
+                        }
+
+                        // the marker cell under the point, or the light margin around the marker
+                        let square = ((i + 1) as usize, (j + 1) as usize);
+                        let margin = (board.square_size - marker_size) / 2.0;
+                        let cell_size = marker_size / MARKER_CELLS as f64;
+                        let cell_x = ((point.x - i as f64 * board.square_size - margin) / cell_size).floor();
+                        let cell_y = ((point.y - j as f64 * board.square_size - margin) / cell_size).floor();
+
+                        let cells = 0.0..MARKER_CELLS as f64;
+                        match markers.get(&square) {
+                            Some(bits) if cells.contains(&cell_x) && cells.contains(&cell_y) => {
+                                let (cell_x, cell_y) = (cell_x as usize, cell_y as usize);
+                                let border = cell_x == 0 || cell_y == 0 || cell_x == MARKER_CELLS - 1 || cell_y == MARKER_CELLS - 1;
+
+                                if !border && bits[cell_y - 1][cell_x - 1] { 235.0 } else { 20.0 }
+                            },
+                            _ => 235.0,
+                        }
+                    } else if (-2..=columns).contains(&i) && (-2..=rows).contains(&j) {
+                        235.0
+                    } else {
+                        128.0
+                    }
+                })
+                .sum::<f64>() / 4.0;
+
+            image::Luma([value.round() as u8])
+        })
+    }
+
+
+    #[test]
+    fn test_marker_ids_round_trip() {
+        for id in 0..MARKER_IDS {
+            assert_eq!(marker_id(&marker_bits(id)), Some(id));
+        }
+
+        assert_eq!(marker_id(&[[false; 5]; 5]), None, "a dark square is no marker");
+    }
+
+    #[test]
+    fn test_detect_charuco() {
+        let board = charuco_board();
+        let intrinsics = camera();
+        let object_points = board.object_points();
+
+        let poses = [
+            ((0.25, -0.2, 0.1), Vector3::new(0.0, 0.0, 0.45)),
+
+            // upside down, the markers label the corners regardless
+            ((-0.15, 0.3, std::f64::consts::PI + 0.2), Vector3::new(0.01, -0.01, 0.5)),
+
+            // partially out of the frame
+            ((0.1, 0.2, -0.1), Vector3::new(0.16, 0.03, 0.45)),
+        ];
+
+        for (view, (angles, offset)) in poses.into_iter().enumerate() {
+            let pose = board_pose(&board, angles, offset);
+            let image = render(&board, &intrinsics, &pose, (640, 480));
+
+            let corners = detect_charuco(&image, &board).expect("the board is detected");
+
+            let visible = object_points.iter()
+                .filter(|&&object| {
+                    let [u, v] = intrinsics.project(&(pose * Point3::from(object)));
+                    (20.0..620.0).contains(&u) && (20.0..460.0).contains(&v)
+                })
+                .count();
+            if view < 2 {
+                assert_eq!(corners.len(), board.corner_count(), "every corner of view {} is detected", view);
+            } else {
+                assert!(corners.len() < board.corner_count() && corners.len() + 4 >= visible, "{} of {} visible corners", corners.len(), visible);
+            }
+
+            for (corner, point) in &corners {
+                let expected = intrinsics.project(&(pose * Point3::from(object_points[*corner])));
+                assert!(distance(*point, expected) < 0.25, "corner {} at {:?} is far from {:?} in view {}", corner, point, expected, view);
+            }
+        }
+
+        let blank = GrayImage::from_pixel(640, 480, image::Luma([128]));
+        assert!(detect_charuco(&blank, &board).is_none());
+
+        // a plain checkerboard has no markers
+        let checkerboard = Checkerboard {
+            marker_size: None,
+            ..board.clone()
+        };
+        let pose = board_pose(&checkerboard, (0.1, 0.1, 0.0), Vector3::new(0.0, 0.0, 0.45));
+        let image = render_checkerboard(&checkerboard, &intrinsics, &pose, (640, 480));
+        assert!(detect_charuco(&image, &board).is_none());
+    }
+}
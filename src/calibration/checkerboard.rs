@@ -0,0 +1,632 @@
+use std::collections::{HashMap, HashSet};
+
+use image::GrayImage;
+
+use super::Checkerboard;
+
+
+/// corners are detected on a copy downsampled below this size and refined at full resolution
+pub(super) const DETECTION_SIZE: u32 = 1024;
+
+const SMOOTHING_SIGMA: f32 = 1.5;
+
+/// radius of the circle sampled around saddle points, in detection pixels
+const SADDLE_RADIUS: f32 = 4.0;
+
+/// minimum intensity range around a corner
+const MIN_CONTRAST: f32 = 20.0;
+
+/// candidates considered as grid neighbors of a corner
+const NEIGHBORS: usize = 12;
+
+/// accepted distance of a grid corner from its predicted position, relative to the grid spacing
+const GRID_TOLERANCE: f64 = 0.3;
+
+
+/// finds the inner corners of `board` with subpixel accuracy
+///
+/// corners are returned row by row, matching `Checkerboard::object_points`. the first row runs along the columns of
+/// the board, the rows advance clockwise of it in the image and, for boards with `is_oriented`, the square diagonally
+/// outside the first corner is dark, so every camera seeing the board labels its corners the same way
+pub fn detect_checkerboard(image: &GrayImage, board: &Checkerboard) -> Option<Vec<[f64; 2]>> {
+    if board.columns < 2 || board.rows < 2 {
+        return None;
+    }
+
+    let factor = image.width().max(image.height()).div_ceil(DETECTION_SIZE).max(1);
+    let plane = Plane::downsampled(image, factor).blurred(SMOOTHING_SIGMA);
+
+    let candidates = saddle_points(&plane);
+    if candidates.len() < board.corner_count() {
+        return None;
+    }
+
+    let grid = assemble_grid(&candidates, board)?;
+    let corners = order_corners(&grid, &candidates, &plane, board)?;
+
+    // grid spacing limits the refinement window, so neighboring corners stay outside of it
+    let spacing = corners.windows(2)
+        .enumerate()
+        .filter(|(i, _)| (i + 1) % board.columns != 0)
+        .map(|(_, pair)| distance(pair[0], pair[1]))
+        .fold(f64::INFINITY, f64::min) * factor as f64;
+    let half_window = ((spacing * 0.35) as i32).clamp(2, 3 + 2 * factor as i32);
+
+    let full = Plane::downsampled(image, 1);
+    let corners = corners.into_iter()
+        .map(|[x, y]| {
+            let corner = [
+                (x + 0.5) * factor as f64 - 0.5,
+                (y + 0.5) * factor as f64 - 0.5,
+            ];
+
+            refine_corner(&full, corner, half_window)
+        })
+        .collect();
+
+    Some(corners)
+}
+
+
+pub(super) struct Plane {
+    pub(super) width: usize,
+    pub(super) height: usize,
+    pub(super) data: Vec<f32>,
+}
+
+impl Plane {
+    /// box filtered by `factor`
+    pub(super) fn downsampled(image: &GrayImage, factor: u32) -> Self {
+        let factor = factor as usize;
+        let width = (image.width() as usize / factor).max(1);
+        let height = (image.height() as usize / factor).max(1);
+
+        let mut data = vec![0.0; width * height];
+        for (y, row) in data.chunks_mut(width).enumerate() {
+            for (x, value) in row.iter_mut().enumerate() {
+                let mut sum = 0.0;
+                for dy in 0..factor {
+                    for dx in 0..factor {
+                        let source_x = ((x * factor + dx) as u32).min(image.width() - 1);
+                        let source_y = ((y * factor + dy) as u32).min(image.height() - 1);
+                        sum += image.get_pixel(source_x, source_y).0[0] as f32;
+                    }
+                }
+
+                *value = sum / (factor * factor) as f32;
+            }
+        }
+
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub(super) fn blurred(&self, sigma: f32) -> Self {
+        let radius = (sigma * 3.0).ceil() as i32;
+        let kernel = (-radius..=radius)
+            .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+            .collect::<Vec<_>>();
+        let kernel_sum = kernel.iter().sum::<f32>();
+
+        let convolve = |get: &dyn Fn(i32) -> f32| -> f32 {
+            kernel.iter()
+                .enumerate()
+                .map(|(i, weight)| weight * get(i as i32 - radius))
+                .sum::<f32>() / kernel_sum
+        };
+
+        let mut horizontal = vec![0.0; self.data.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                horizontal[y * self.width + x] = convolve(&|offset| self.get(x as i32 + offset, y as i32));
+            }
+        }
+
+        let horizontal = Self {
+            data: horizontal,
+            ..*self
+        };
+
+        let mut data = vec![0.0; self.data.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                data[y * self.width + x] = convolve(&|offset| horizontal.get(x as i32, y as i32 + offset));
+            }
+        }
+
+        Self {
+            data,
+            ..horizontal
+        }
+    }
+
+    /// clamped to the border
+    pub(super) fn get(&self, x: i32, y: i32) -> f32 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+
+        self.data[y * self.width + x]
+    }
+
+    /// bilinear
+    pub(super) fn sample(&self, x: f64, y: f64) -> f32 {
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = (x - x0) as f32;
+        let fy = (y - y0) as f32;
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self.get(x0, y0) * (1.0 - fx) + self.get(x0 + 1, y0) * fx;
+        let bottom = self.get(x0, y0 + 1) * (1.0 - fx) + self.get(x0 + 1, y0 + 1) * fx;
+
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+
+/// local maxima of the hessian saddle response which look like the meeting point of four squares
+fn saddle_points(plane: &Plane) -> Vec<[f64; 2]> {
+    let (width, height) = (plane.width as i32, plane.height as i32);
+    let mut response = vec![0.0f32; plane.data.len()];
+
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let center = plane.get(x, y);
+            let ixx = plane.get(x + 1, y) - 2.0 * center + plane.get(x - 1, y);
+            let iyy = plane.get(x, y + 1) - 2.0 * center + plane.get(x, y - 1);
+            let ixy = (plane.get(x + 1, y + 1) - plane.get(x + 1, y - 1) - plane.get(x - 1, y + 1) + plane.get(x - 1, y - 1)) / 4.0;
+
+            response[(y * width + x) as usize] = ixy * ixy - ixx * iyy;
+        }
+    }
+
+    let max_response = response.iter().copied().fold(0.0, f32::max);
+    if max_response <= 0.0 {
+        return vec![];
+    }
+    let threshold = max_response * 0.02;
+
+    let border = SADDLE_RADIUS.ceil() as i32 + 1;
+    let mut points = vec![];
+
+    for y in border..height - border {
+        for x in border..width - border {
+            let index = (y * width + x) as usize;
+            let value = response[index];
+            if value <= threshold {
+                continue;
+            }
+
+            let is_maximum = (-2..=2)
+                .flat_map(|dy| (-2..=2).map(move |dx| (dx, dy)))
+                .filter(|&offset| offset != (0, 0))
+                .all(|(dx, dy)| {
+                    let neighbor_index = ((y + dy) * width + x + dx) as usize;
+                    let neighbor = response[neighbor_index];
+
+                    neighbor < value || (neighbor == value && neighbor_index > index)
+                });
+
+            if is_maximum && is_saddle(plane, x as f64, y as f64) {
+                points.push(refine_corner(plane, [x as f64, y as f64], 3));
+            }
+        }
+    }
+
+    points
+}
+
+/// a checkerboard corner alternates between dark and bright four times around its center
+pub(super) fn is_saddle(plane: &Plane, x: f64, y: f64) -> bool {
+    const SAMPLES: usize = 24;
+
+    let samples = (0..SAMPLES)
+        .map(|i| {
+            let angle = i as f64 / SAMPLES as f64 * std::f64::consts::TAU;
+            plane.sample(
+                x + angle.cos() * SADDLE_RADIUS as f64,
+                y + angle.sin() * SADDLE_RADIUS as f64,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let min = samples.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = samples.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    if max - min < MIN_CONTRAST {
+        return false;
+    }
+
+    // samples close to the middle keep the previous side, so noise does not add transitions
+    let middle = (min + max) / 2.0;
+    let hysteresis = (max - min) * 0.1;
+
+    let Some(start) = samples.iter().position(|sample| (sample - middle).abs() > hysteresis) else {
+        return false;
+    };
+
+    let mut bright = samples[start] > middle;
+    let mut transitions = 0;
+    for i in 1..=SAMPLES {
+        let sample = samples[(start + i) % SAMPLES];
+
+        if bright && sample < middle - hysteresis {
+            bright = false;
+            transitions += 1;
+        } else if !bright && sample > middle + hysteresis {
+            bright = true;
+            transitions += 1;
+        }
+    }
+
+    transitions == 4
+}
+
+/// moves `corner` to where the image gradients in the window around it are orthogonal to their offset from it
+pub(super) fn refine_corner(plane: &Plane, corner: [f64; 2], half_window: i32) -> [f64; 2] {
+    let sigma = half_window as f64;
+    let mut refined = corner;
+
+    for _ in 0..10 {
+        let center_x = refined[0].round() as i32;
+        let center_y = refined[1].round() as i32;
+
+        let (mut a00, mut a01, mut a11) = (0.0, 0.0, 0.0);
+        let (mut b0, mut b1) = (0.0, 0.0);
+
+        for dy in -half_window..=half_window {
+            for dx in -half_window..=half_window {
+                let (x, y) = (center_x + dx, center_y + dy);
+                if x < 1 || y < 1 || x >= plane.width as i32 - 1 || y >= plane.height as i32 - 1 {
+                    continue;
+                }
+
+                let gx = ((plane.get(x + 1, y) - plane.get(x - 1, y)) / 2.0) as f64;
+                let gy = ((plane.get(x, y + 1) - plane.get(x, y - 1)) / 2.0) as f64;
+                let weight = (-((dx * dx + dy * dy) as f64) / (2.0 * sigma * sigma)).exp();
+
+                let (gxx, gxy, gyy) = (weight * gx * gx, weight * gx * gy, weight * gy * gy);
+                a00 += gxx;
+                a01 += gxy;
+                a11 += gyy;
+                b0 += gxx * x as f64 + gxy * y as f64;
+                b1 += gxy * x as f64 + gyy * y as f64;
+            }
+        }
+
+        let determinant = a00 * a11 - a01 * a01;
+        if determinant.abs() < 1e-9 {
+            break;
+        }
+
+        let next = [
+            (a11 * b0 - a01 * b1) / determinant,
+            (a00 * b1 - a01 * b0) / determinant,
+        ];
+
+        if distance(next, corner) > half_window as f64 {
+            return corner;
+        }
+
+        let shift = distance(next, refined);
+        refined = next;
+
+        if shift < 0.01 {
+            break;
+        }
+    }
+
+    refined
+}
+
+
+type Grid = HashMap<(i32, i32), usize>;
+
+/// grows grids of neighboring corners from seed corners until one has the dimensions of the board
+fn assemble_grid(points: &[[f64; 2]], board: &Checkerboard) -> Option<Grid> {
+    let neighbors = points.iter()
+        .enumerate()
+        .map(|(i, &point)| {
+            let mut neighbors = (0..points.len())
+                .filter(|&j| j != i)
+                .collect::<Vec<_>>();
+            neighbors.sort_by(|&a, &b| distance(points[a], point).total_cmp(&distance(points[b], point)));
+            neighbors.truncate(NEIGHBORS);
+            neighbors
+        })
+        .collect::<Vec<_>>();
+
+    for seed in 0..points.len() {
+        let closest = &neighbors[seed][..neighbors[seed].len().min(4)];
+
+        for (k, &a) in closest.iter().enumerate() {
+            for &b in &closest[k + 1..] {
+                let u = sub(points[a], points[seed]);
+                let v = sub(points[b], points[seed]);
+
+                let (u_length, v_length) = (norm(u), norm(v));
+                let ratio = u_length / v_length;
+                if cross(u, v).abs() < 0.5 * u_length * v_length || !(0.5..=2.0).contains(&ratio) {
+                    continue;
+                }
+
+                let grid = grow_grid(points, &neighbors, seed, a, b, board);
+                if grid.len() == board.corner_count() {
+                    return Some(grid);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+fn grow_grid(
+    points: &[[f64; 2]],
+    neighbors: &[Vec<usize>],
+    seed: usize,
+    a: usize,
+    b: usize,
+    board: &Checkerboard,
+) -> Grid {
+    let base_steps = [
+        sub(points[a], points[seed]),
+        sub(points[b], points[seed]),
+    ];
+
+    let mut grid = Grid::from([
+        ((0, 0), seed),
+        ((1, 0), a),
+        ((0, 1), b),
+    ]);
+    let mut used = HashSet::from([seed, a, b]);
+
+    let fits_board = |grid: &Grid, cell: (i32, i32)| {
+        let (mut min_i, mut max_i, mut min_j, mut max_j) = (cell.0, cell.0, cell.1, cell.1);
+        for &(i, j) in grid.keys() {
+            (min_i, max_i) = (min_i.min(i), max_i.max(i));
+            (min_j, max_j) = (min_j.min(j), max_j.max(j));
+        }
+
+        let (width, height) = ((max_i - min_i + 1) as usize, (max_j - min_j + 1) as usize);
+        (width <= board.columns && height <= board.rows) || (width <= board.rows && height <= board.columns)
+    };
+
+    loop {
+        let mut grown = false;
+
+        let cells = grid.iter().map(|(&cell, &index)| (cell, index)).collect::<Vec<_>>();
+        for ((i, j), index) in cells {
+            for (di, dj) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let target = (i + di, j + dj);
+                if grid.contains_key(&target) || !fits_board(&grid, target) {
+                    continue;
+                }
+
+                // extrapolate along the row or column, or copy the step of a parallel row or column
+                let step = if let Some(&previous) = grid.get(&(i - di, j - dj)) {
+                    sub(points[index], points[previous])
+                } else {
+                    [(dj, di), (-dj, -di)].iter()
+                        .find_map(|&(pi, pj)| {
+                            let side = grid.get(&(i + pi, j + pj))?;
+                            let side_target = grid.get(&(i + pi + di, j + pj + dj))?;
+                            Some(sub(points[*side_target], points[*side]))
+                        })
+                        .unwrap_or_else(|| {
+                            let base = base_steps[if di != 0 { 0 } else { 1 }];
+                            let sign = (di + dj) as f64;
+                            [base[0] * sign, base[1] * sign]
+                        })
+                };
+
+                let predicted = add(points[index], step);
+                let tolerance = GRID_TOLERANCE * norm(step);
+
+                let closest = neighbors[index].iter()
+                    .copied()
+                    .filter(|candidate| !used.contains(candidate))
+                    .map(|candidate| (candidate, distance(points[candidate], predicted)))
+                    .filter(|&(_, distance)| distance < tolerance)
+                    .min_by(|a, b| a.1.total_cmp(&b.1));
+
+                if let Some((candidate, _)) = closest {
+                    grid.insert(target, candidate);
+                    used.insert(candidate);
+                    grown = true;
+                }
+            }
+        }
+
+        if !grown {
+            return grid;
+        }
+    }
+}
+
+/// labels a complete grid as described on `detect_checkerboard`
+fn order_corners(
+    grid: &Grid,
+    points: &[[f64; 2]],
+    plane: &Plane,
+    board: &Checkerboard,
+) -> Option<Vec<[f64; 2]>> {
+    let min_i = grid.keys().map(|cell| cell.0).min()?;
+    let min_j = grid.keys().map(|cell| cell.1).min()?;
+    let width = grid.keys().map(|cell| cell.0).max()? - min_i + 1;
+
+    let mut cells: HashMap<(usize, usize), [f64; 2]> = grid.iter()
+        .map(|(&(i, j), &index)| {
+            let cell = ((i - min_i) as usize, (j - min_j) as usize);
+            if width as usize == board.columns {
+                (cell, points[index])
+            } else {
+                ((cell.1, cell.0), points[index])
+            }
+        })
+        .collect();
+
+    let (columns, rows) = (board.columns, board.rows);
+    let at = |cells: &HashMap<(usize, usize), [f64; 2]>, i: usize, j: usize| cells.get(&(i, j)).copied();
+
+    let origin = at(&cells, 0, 0)?;
+    let along_row = sub(at(&cells, 1, 0)?, origin);
+    let along_column = sub(at(&cells, 0, 1)?, origin);
+    if cross(along_row, along_column) < 0.0 {
+        cells = cells.into_iter()
+            .map(|((i, j), point)| ((i, rows - 1 - j), point))
+            .collect();
+    }
+
+    if board.is_oriented() {
+        let origin = at(&cells, 0, 0)?;
+        let along_row = sub(at(&cells, 1, 0)?, origin);
+        let along_column = sub(at(&cells, 0, 1)?, origin);
+
+        let sample = |row_offset: f64, column_offset: f64| plane.sample(
+            origin[0] + along_row[0] * row_offset + along_column[0] * column_offset,
+            origin[1] + along_row[1] * row_offset + along_column[1] * column_offset,
+        );
+
+        // the diagonal square and an adjacent square of the other color
+        if sample(-0.5, -0.5) > sample(0.5, -0.5) {
+            cells = cells.into_iter()
+                .map(|((i, j), point)| ((columns - 1 - i, rows - 1 - j), point))
+                .collect();
+        }
+    }
+
+    (0..rows)
+        .flat_map(|j| (0..columns).map(move |i| (i, j)))
+        .map(|(i, j)| at(&cells, i, j))
+        .collect()
+}
+
+
+fn add(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+pub(super) fn sub(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+pub(super) fn cross(a: [f64; 2], b: [f64; 2]) -> f64 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn norm(a: [f64; 2]) -> f64 {
+    a[0].hypot(a[1])
+}
+
+pub(super) fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    norm(sub(a, b))
+}
+
+
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion, Vector3};
+
+    use crate::calibration::intrinsics::CameraIntrinsics;
+
+
+    pub(in crate::calibration) fn camera() -> CameraIntrinsics {
+        CameraIntrinsics {
+            fx: 520.0,
+            fy: 515.0,
+            cx: 322.0,
+            cy: 238.0,
+            distortion: [-0.12, 0.05, 0.001, -0.0005, 0.0],
+        }
+    }
+
+    /// the board centered `distance` in front of the camera, rotated by the euler angles
+    pub(in crate::calibration) fn board_pose(board: &Checkerboard, (roll, pitch, yaw): (f64, f64, f64), offset: Vector3<f64>) -> Isometry3<f64> {
+        let rotation = UnitQuaternion::from_euler_angles(roll, pitch, yaw);
+        let center = Vector3::new(
+            (board.columns - 1) as f64 * board.square_size / 2.0,
+            (board.rows - 1) as f64 * board.square_size / 2.0,
+            0.0,
+        );
+
+        Isometry3::from_parts(Translation3::from(offset - rotation * center), rotation)
+    }
+
+    /// renders the board with a white margin of one square, supersampled 4 times
+    pub(in crate::calibration) fn render(
+        board: &Checkerboard,
+        intrinsics: &CameraIntrinsics,
+        pose: &Isometry3<f64>,
+        (width, height): (u32, u32),
+    ) -> GrayImage {
+        let inverse = pose.inverse();
+        let origin = inverse * Point3::origin();
+
+        GrayImage::from_fn(width, height, |u, v| {
+            let value = [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+                .iter()
+                .map(|(dx, dy)| {
+                    let [x, y] = intrinsics.undistort([u as f64 + dx - 0.5, v as f64 + dy - 0.5]);
+                    let direction = inverse * Vector3::new(x, y, 1.0);
+
+                    let distance = -origin.z / direction.z;
+                    if distance <= 0.0 {
+                        return 128.0;
+                    }
+
+                    let point = origin + direction * distance;
+                    let i = (point.x / board.square_size).floor() as i64;
+                    let j = (point.y / board.square_size).floor() as i64;
+
+                    let (columns, rows) = (board.columns as i64, board.rows as i64);
+                    if (-1..columns).contains(&i) && (-1..rows).contains(&j) {
+                        if (i + j).rem_euclid(2) == 0 { 20.0 } else { 235.0 }
+                    } else if (-2..=columns).contains(&i) && (-2..=rows).contains(&j) {
+                        235.0
+                    } else {
+                        128.0
+                    }
+                })
+                .sum::<f64>() / 4.0;
+
+            image::Luma([value.round() as u8])
+        })
+    }
+
+
+    #[test]
+    fn test_detect_checkerboard() {
+        let board = Checkerboard::default();
+        let intrinsics = camera();
+
+        let poses = [
+            ((0.3, -0.2, 0.1), Vector3::new(0.02, -0.01, 0.5)),
+            ((-0.1, 0.35, -0.2), Vector3::new(-0.03, 0.02, 0.55)),
+
+            // upside down and seen from the side, the labels follow the board rather than the image
+            ((0.2, 0.1, std::f64::consts::PI + 0.3), Vector3::new(0.0, 0.0, 0.5)),
+        ];
+
+        for (angles, offset) in poses {
+            let pose = board_pose(&board, angles, offset);
+            let image = render(&board, &intrinsics, &pose, (640, 480));
+
+            let corners = detect_checkerboard(&image, &board).expect("the board is detected");
+            assert_eq!(corners.len(), board.corner_count());
+
+            for (corner, object) in corners.iter().zip(board.object_points()) {
+                let expected = intrinsics.project(&(pose * Point3::from(object)));
+                assert!(distance(*corner, expected) < 0.25, "corner {:?} is far from {:?}", corner, expected);
+            }
+        }
+
+        let blank = GrayImage::from_pixel(640, 480, image::Luma([128]));
+        assert!(detect_checkerboard(&blank, &board).is_none());
+    }
+}
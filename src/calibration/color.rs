@@ -0,0 +1,758 @@
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::{bail, Error};
+use bevy::prelude::*;
+use image::{GrayImage, ImageBuffer, Pixel, RgbImage};
+use nalgebra::{DMatrix, Matrix3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    checkerboard::{Plane, DETECTION_SIZE},
+    intrinsics::estimate_homography,
+};
+
+
+pub const COLOR_CHECKER_COLUMNS: usize = 6;
+pub const COLOR_CHECKER_ROWS: usize = 4;
+pub const COLOR_CHECKER_PATCHES: usize = COLOR_CHECKER_COLUMNS * COLOR_CHECKER_ROWS;
+
+/// srgb values of the classic 24 patch color checker, row by row from the dark skin patch in the top left corner
+pub const COLOR_CHECKER_SRGB: [[u8; 3]; COLOR_CHECKER_PATCHES] = [
+    [115, 82, 68],
+    [194, 150, 130],
+    [98, 122, 157],
+    [87, 108, 67],
+    [133, 128, 177],
+    [103, 189, 170],
+    [214, 126, 44],
+    [80, 91, 166],
+    [193, 90, 99],
+    [94, 60, 108],
+    [157, 188, 64],
+    [224, 163, 46],
+    [56, 61, 150],
+    [70, 148, 73],
+    [175, 54, 60],
+    [231, 199, 31],
+    [187, 86, 149],
+    [8, 133, 161],
+    [243, 243, 242],
+    [200, 200, 200],
+    [160, 160, 160],
+    [122, 122, 121],
+    [85, 85, 85],
+    [52, 52, 52],
+];
+
+/// the neutral patches of the bottom row
+const GRAY_PATCHES: std::ops::Range<usize> = 18..24;
+
+const SMOOTHING_SIGMA: f32 = 1.0;
+
+/// largest gradient inside a patch, in 8-bit levels per pixel after smoothing
+const FLAT_GRADIENT: f32 = 6.0;
+
+/// smallest patch in detection pixels
+const MIN_PATCH_AREA: usize = 30;
+
+/// accepted distance of a patch from its lattice position, in patches
+const LATTICE_TOLERANCE: f64 = 0.25;
+
+/// patches of the chart which need to be segmented, the others are sampled at their lattice position
+const MIN_PATCHES: usize = 16;
+
+/// rms error of the chart colors after an affine fit, relative to the spread of the reference colors
+const MAX_FIT_ERROR: f64 = 0.2;
+
+/// camera values outside of this range are clipped and not used in the fit
+const VALID_RANGE: std::ops::Range<f64> = 0.02..0.98;
+
+
+/// maps the colors of one camera to srgb, fitted to a color checker
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct ColorCorrection {
+    /// per channel exponent of the tone curve, which linearizes the camera values
+    pub gamma: [f64; 3],
+
+    /// linear camera rgb to linear srgb, row-major
+    pub matrix: [[f64; 3]; 3],
+
+    /// root mean square error of the corrected chart colors in 8-bit srgb levels
+    pub rms_error: f64,
+
+    /// frames the color checker was detected in
+    pub frames: usize,
+}
+
+impl ColorCorrection {
+    /// corrected srgb of a camera color, both normalized to 0..1
+    pub fn apply(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let linear = Vector3::from_fn(|channel, _| rgb[channel].clamp(0.0, 1.0).powf(self.gamma[channel]));
+        let corrected = Matrix3::from_fn(|row, column| self.matrix[row][column]) * linear;
+
+        std::array::from_fn(|channel| linear_to_srgb(corrected[channel].clamp(0.0, 1.0)))
+    }
+
+    /// corrects the color channels of an 8-bit rgb or rgba frame in place, alpha is kept
+    pub fn correct_image<P: Pixel<Subpixel = u8>>(&self, image: &mut ImageBuffer<P, Vec<u8>>) {
+        let channels = P::CHANNEL_COUNT as usize;
+        if channels < 3 {
+            return;
+        }
+
+        let decode: [[f32; 256]; 3] = std::array::from_fn(|channel| {
+            std::array::from_fn(|value| (value as f64 / 255.0).powf(self.gamma[channel]) as f32)
+        });
+
+        // linear values are quantized finer than 8 bits, as the srgb curve is steep near black
+        const ENCODE_STEPS: usize = 4096;
+        let encode = (0..ENCODE_STEPS)
+            .map(|i| (linear_to_srgb(i as f64 / (ENCODE_STEPS - 1) as f64) * 255.0).round() as u8)
+            .collect::<Vec<_>>();
+
+        let matrix = self.matrix.map(|row| row.map(|value| value as f32));
+
+        for pixel in image.chunks_exact_mut(channels) {
+            let linear: [f32; 3] = std::array::from_fn(|channel| decode[channel][pixel[channel] as usize]);
+
+            for (channel, row) in matrix.iter().enumerate() {
+                let corrected = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                pixel[channel] = encode[(corrected.clamp(0.0, 1.0) * (ENCODE_STEPS - 1) as f32).round() as usize];
+            }
+        }
+    }
+}
+
+
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+
+/// mean color of each patch of a color checker, normalized to 0..1 in the order of `COLOR_CHECKER_SRGB`
+///
+/// the chart has to face the camera, seen from behind (e.g. in a mirror) the patches are mislabeled
+pub fn detect_color_checker(image: &RgbImage) -> Option<[[f64; 3]; COLOR_CHECKER_PATCHES]> {
+    let factor = image.width().max(image.height()).div_ceil(DETECTION_SIZE).max(1);
+    let planes: [Plane; 3] = std::array::from_fn(|channel| {
+        let channel_image = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            image::Luma([image.get_pixel(x, y).0[channel]])
+        });
+
+        Plane::downsampled(&channel_image, factor).blurred(SMOOTHING_SIGMA)
+    });
+
+    let patches = flat_patches(&planes);
+    if patches.len() < MIN_PATCHES {
+        return None;
+    }
+
+    // other grids of uniform squares, like tiles or checkerboards, are told apart by their colors
+    let (colors, error) = (0..patches.len())
+        .filter_map(|seed| Lattice::grow(&patches, seed))
+        .filter_map(|lattice| {
+            let window = lattice.chart_window()?;
+            lattice.chart_colors(image, factor, &window)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+
+    if error > MAX_FIT_ERROR {
+        return None;
+    }
+
+    colors.try_into().ok()
+}
+
+
+/// fits the tone curves to the neutral patches and the color matrix to every patch
+///
+/// `samples` are the patch colors of each frame the chart was detected in, their median is fitted
+pub fn calibrate_color(samples: &[[[f64; 3]; COLOR_CHECKER_PATCHES]]) -> Result<ColorCorrection, Error> {
+    if samples.is_empty() {
+        bail!("the color checker was not detected");
+    }
+
+    let measured: [[f64; 3]; COLOR_CHECKER_PATCHES] = std::array::from_fn(|patch| {
+        std::array::from_fn(|channel| {
+            let mut values = samples.iter().map(|sample| sample[patch][channel]).collect::<Vec<_>>();
+            values.sort_by(f64::total_cmp);
+            values[values.len() / 2]
+        })
+    });
+    let reference = reference_linear();
+
+    // camera value = (k * linear)^(1 / gamma), a line through the neutral patches in log space
+    let mut gamma = [0.0; 3];
+    for (channel, gamma) in gamma.iter_mut().enumerate() {
+        let points = GRAY_PATCHES
+            .filter(|&patch| VALID_RANGE.contains(&measured[patch][channel]))
+            .map(|patch| (reference[patch][channel].ln(), measured[patch][channel].ln()))
+            .collect::<Vec<_>>();
+
+        if points.len() < 3 {
+            bail!("{} of the neutral patches are clipped, adjust the exposure", GRAY_PATCHES.len() - points.len());
+        }
+
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let slope = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum::<f64>()
+            / points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum::<f64>();
+
+        if !(0.1..=2.0).contains(&slope) {
+            bail!("implausible tone curve with exponent {:.2}", 1.0 / slope);
+        }
+
+        *gamma = 1.0 / slope;
+    }
+
+    let used = (0..COLOR_CHECKER_PATCHES)
+        .filter(|&patch| measured[patch].iter().all(|&value| value < VALID_RANGE.end))
+        .collect::<Vec<_>>();
+
+    if used.len() < 12 {
+        bail!("{} of the patches are clipped, adjust the exposure", COLOR_CHECKER_PATCHES - used.len());
+    }
+
+    let linear = DMatrix::from_fn(used.len(), 3, |i, channel| measured[used[i]][channel].max(0.0).powf(gamma[channel]));
+    let target = DMatrix::from_fn(used.len(), 3, |i, channel| reference[used[i]][channel]);
+
+    let Some(normal) = (linear.transpose() * &linear).try_inverse() else {
+        bail!("the patch colors are degenerate");
+    };
+    let transposed = normal * linear.transpose() * target;
+
+    let mut correction = ColorCorrection {
+        gamma,
+        matrix: std::array::from_fn(|row| std::array::from_fn(|column| transposed[(column, row)])),
+        rms_error: 0.0,
+        frames: samples.len(),
+    };
+
+    let squared_error = used.iter()
+        .map(|&patch| {
+            let corrected = correction.apply(measured[patch]);
+
+            (0..3)
+                .map(|channel| (corrected[channel] * 255.0 - COLOR_CHECKER_SRGB[patch][channel] as f64).powi(2))
+                .sum::<f64>()
+        })
+        .sum::<f64>();
+    correction.rms_error = (squared_error / (used.len() * 3) as f64).sqrt();
+
+    Ok(correction)
+}
+
+
+fn reference_linear() -> [[f64; 3]; COLOR_CHECKER_PATCHES] {
+    COLOR_CHECKER_SRGB.map(|color| color.map(|value| srgb_to_linear(value as f64 / 255.0)))
+}
+
+
+/// rms error of an affine map from the roughly linearized colors to the reference, relative to the reference spread
+///
+/// distinguishes the orientations of the chart and rejects grids of other uniform squares
+fn affine_fit_error(colors: &[[f64; 3]]) -> f64 {
+    let reference = reference_linear();
+
+    let source = DMatrix::from_fn(colors.len(), 4, |i, column| match column {
+        3 => 1.0,
+        channel => srgb_to_linear(colors[i][channel]),
+    });
+    let target = DMatrix::from_fn(colors.len(), 3, |i, channel| reference[i][channel]);
+
+    let Some(normal) = (source.transpose() * &source).try_inverse() else {
+        return f64::INFINITY;
+    };
+    let residuals = &source * (normal * source.transpose() * &target) - &target;
+
+    let mean = target.row_mean();
+    let spread = target.row_iter().map(|row| (row - &mean).norm_squared()).sum::<f64>();
+
+    (residuals.norm_squared() / spread).sqrt()
+}
+
+
+fn mean_color(image: &RgbImage, [x, y]: [f64; 2], radius: f64) -> Option<[f64; 3]> {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let min_x = (x - radius).round() as i64;
+    let max_x = (x + radius).round() as i64;
+    let min_y = (y - radius).round() as i64;
+    let max_y = (y + radius).round() as i64;
+
+    if min_x < 0 || min_y < 0 || max_x >= width || max_y >= height {
+        return None;
+    }
+
+    let mut sum = [0.0; 3];
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let pixel = image.get_pixel(x as u32, y as u32).0;
+            for channel in 0..3 {
+                sum[channel] += pixel[channel] as f64;
+            }
+        }
+    }
+
+    let count = ((max_x - min_x + 1) * (max_y - min_y + 1)) as f64;
+    Some(sum.map(|sum| sum / count / 255.0))
+}
+
+
+/// uniformly colored, roughly square region of the detection image
+struct Patch {
+    center: [f64; 2],
+    area: f64,
+}
+
+/// connected regions without color edges, which are square like the chart patches
+fn flat_patches(planes: &[Plane; 3]) -> Vec<Patch> {
+    let (width, height) = (planes[0].width, planes[0].height);
+
+    let flat = (0..width * height)
+        .map(|i| {
+            let (x, y) = ((i % width) as i32, (i / width) as i32);
+
+            planes.iter()
+                .map(|plane| {
+                    let dx = (plane.get(x + 1, y) - plane.get(x - 1, y)) / 2.0;
+                    let dy = (plane.get(x, y + 1) - plane.get(x, y - 1)) / 2.0;
+                    dx.abs().max(dy.abs())
+                })
+                .fold(0.0, f32::max) < FLAT_GRADIENT
+        })
+        .collect::<Vec<_>>();
+
+    let max_area = width * height / COLOR_CHECKER_PATCHES;
+    let mut visited = vec![false; width * height];
+    let mut patches = vec![];
+
+    for start in 0..width * height {
+        if !flat[start] || visited[start] {
+            continue;
+        }
+
+        // moments of the region
+        let (mut count, mut sum_x, mut sum_y, mut sum_xx, mut sum_yy, mut sum_xy) = (0usize, 0.0, 0.0, 0.0, 0.0, 0.0);
+
+        visited[start] = true;
+        let mut queue = VecDeque::from([start]);
+        while let Some(i) = queue.pop_front() {
+            let (x, y) = (i % width, i / width);
+            let (fx, fy) = (x as f64, y as f64);
+            count += 1;
+            sum_x += fx;
+            sum_y += fy;
+            sum_xx += fx * fx;
+            sum_yy += fy * fy;
+            sum_xy += fx * fy;
+
+            let neighbors = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            for neighbor in neighbors.into_iter().flatten() {
+                if flat[neighbor] && !visited[neighbor] {
+                    visited[neighbor] = true;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        if count < MIN_PATCH_AREA || count > max_area {
+            continue;
+        }
+
+        let n = count as f64;
+        let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+        let xx = sum_xx / n - mean_x * mean_x;
+        let yy = sum_yy / n - mean_y * mean_y;
+        let xy = sum_xy / n - mean_x * mean_y;
+
+        // a filled square of area a has a variance of a / 12 along every axis
+        let trace = xx + yy;
+        let root = ((xx - yy).powi(2) / 4.0 + xy * xy).sqrt();
+        let (major, minor) = (trace / 2.0 + root, trace / 2.0 - root);
+        if minor <= 0.0 || major / minor > 2.5 {
+            continue;
+        }
+
+        let compactness = n / (12.0 * (major * minor).sqrt());
+        if !(0.75..=1.25).contains(&compactness) {
+            continue;
+        }
+
+        patches.push(Patch {
+            center: [mean_x, mean_y],
+            area: n,
+        });
+    }
+
+    patches
+}
+
+
+/// patches on a regular grid, with the homography from lattice coordinates to the detection image
+struct Lattice {
+    cells: HashMap<[i32; 2], usize>,
+    homography: Matrix3<f64>,
+}
+
+/// the chart sized window of a lattice with the most patches
+struct ChartWindow {
+    origin: [i32; 2],
+
+    /// whether the rows of the chart run along the second lattice axis
+    transposed: bool,
+
+    patches: usize,
+}
+
+impl ChartWindow {
+    /// lattice coordinates of a chart patch, keeping the handedness of the lattice
+    fn cell(&self, row: i32, column: i32) -> [i32; 2] {
+        let [a, b] = self.origin;
+
+        if self.transposed {
+            [a + COLOR_CHECKER_ROWS as i32 - 1 - row, b + column]
+        } else {
+            [a + column, b + row]
+        }
+    }
+}
+
+impl Lattice {
+    /// lattice spanned by the nearest neighbors of `seed`, refined with a homography as it grows
+    fn grow(patches: &[Patch], seed: usize) -> Option<Self> {
+        let origin = patches[seed].center;
+        let similar = |patch: &Patch| (0.5..=2.0).contains(&(patch.area / patches[seed].area));
+
+        let mut neighbors = patches.iter()
+            .enumerate()
+            .filter(|(i, patch)| *i != seed && similar(patch))
+            .map(|(_, patch)| [patch.center[0] - origin[0], patch.center[1] - origin[1]])
+            .filter(|offset| {
+                let distance = offset[0].hypot(offset[1]);
+                let side = patches[seed].area.sqrt();
+                distance > side && distance < 2.5 * side
+            })
+            .collect::<Vec<_>>();
+        neighbors.sort_by(|a, b| a[0].hypot(a[1]).total_cmp(&b[0].hypot(b[1])));
+
+        let u = *neighbors.first()?;
+        let length = u[0].hypot(u[1]);
+        let mut v = *neighbors.iter().find(|v| {
+            let v_length = v[0].hypot(v[1]);
+            let cos = (u[0] * v[0] + u[1] * v[1]) / (length * v_length);
+            cos.abs() < 0.35 && (0.7..=1.4).contains(&(v_length / length))
+        })?;
+
+        // image coordinates point down, so this keeps the chart rows below each other as seen from the front
+        if u[0] * v[1] - u[1] * v[0] < 0.0 {
+            v = [-v[0], -v[1]];
+        }
+
+        let mut homography = Matrix3::new(
+            u[0], v[0], origin[0],
+            u[1], v[1], origin[1],
+            0.0, 0.0, 1.0,
+        );
+
+        let mut cells = HashMap::new();
+        for radius in [2.0, 4.0, f64::INFINITY] {
+            let inverse = homography.try_inverse()?;
+
+            // nearest patch of each cell
+            let mut assigned: HashMap<[i32; 2], (usize, f64)> = HashMap::new();
+            for (i, patch) in patches.iter().enumerate().filter(|(_, patch)| similar(patch)) {
+                let point = inverse * Vector3::new(patch.center[0], patch.center[1], 1.0);
+                let [a, b] = [point.x / point.z, point.y / point.z];
+                let cell = [a.round() as i32, b.round() as i32];
+                let offset = (a - cell[0] as f64).hypot(b - cell[1] as f64);
+
+                if offset > LATTICE_TOLERANCE || (cell[0] as f64).hypot(cell[1] as f64) > radius {
+                    continue;
+                }
+
+                if !matches!(assigned.get(&cell), Some(&(_, best)) if best <= offset) {
+                    assigned.insert(cell, (i, offset));
+                }
+            }
+
+            cells = assigned.into_iter().map(|(cell, (i, _))| (cell, i)).collect();
+            if cells.len() < 4 {
+                return None;
+            }
+
+            let (lattice_points, image_points): (Vec<_>, Vec<_>) = cells.iter()
+                .map(|(&[a, b], &i)| ([a as f64, b as f64, 0.0], patches[i].center))
+                .unzip();
+
+            homography = estimate_homography(&lattice_points, &image_points).ok()?;
+        }
+
+        Some(Self {
+            cells,
+            homography,
+        })
+    }
+
+    /// patch colors of the better fitting of the two orientations of the window, with the relative fit error
+    fn chart_colors(&self, image: &RgbImage, factor: u32, window: &ChartWindow) -> Option<(Vec<[f64; 3]>, f64)> {
+        // lattice coordinates to full resolution
+        let to_image = |a: f64, b: f64| {
+            let point = self.homography * Vector3::new(a, b, 1.0);
+            [
+                (point.x / point.z + 0.5) * factor as f64 - 0.5,
+                (point.y / point.z + 0.5) * factor as f64 - 0.5,
+            ]
+        };
+
+        let sample = |[a, b]: [i32; 2]| {
+            let (a, b) = (a as f64, b as f64);
+            let center = to_image(a, b);
+            let right = to_image(a + 1.0, b);
+            let below = to_image(a, b + 1.0);
+            let pitch = (right[0] - center[0]).hypot(right[1] - center[1])
+                .min((below[0] - center[0]).hypot(below[1] - center[1]));
+
+            mean_color(image, center, (pitch * 0.2).max(1.0))
+        };
+
+        [false, true].into_iter()
+            .filter_map(|flipped| {
+                (0..COLOR_CHECKER_PATCHES)
+                    .map(|patch| {
+                        let row = (patch / COLOR_CHECKER_COLUMNS) as i32;
+                        let column = (patch % COLOR_CHECKER_COLUMNS) as i32;
+
+                        if flipped {
+                            window.cell(COLOR_CHECKER_ROWS as i32 - 1 - row, COLOR_CHECKER_COLUMNS as i32 - 1 - column)
+                        } else {
+                            window.cell(row, column)
+                        }
+                    })
+                    .map(sample)
+                    .collect::<Option<Vec<_>>>()
+            })
+            .map(|colors| {
+                let error = affine_fit_error(&colors);
+                (colors, error)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    fn chart_window(&self) -> Option<ChartWindow> {
+        let min_a = self.cells.keys().map(|cell| cell[0]).min()?;
+        let max_a = self.cells.keys().map(|cell| cell[0]).max()?;
+        let min_b = self.cells.keys().map(|cell| cell[1]).min()?;
+        let max_b = self.cells.keys().map(|cell| cell[1]).max()?;
+
+        let (long, short) = (COLOR_CHECKER_COLUMNS as i32, COLOR_CHECKER_ROWS as i32);
+
+        [(long, short, false), (short, long, true)].into_iter()
+            .flat_map(|(columns, rows, transposed)| {
+                (min_a - columns + 1..=max_a)
+                    .flat_map(move |a| (min_b - rows + 1..=max_b).map(move |b| ([a, b], columns, rows, transposed)))
+            })
+            .map(|(origin, columns, rows, transposed)| {
+                let patches = self.cells.keys()
+                    .filter(|cell| (origin[0]..origin[0] + columns).contains(&cell[0]) && (origin[1]..origin[1] + rows).contains(&cell[1]))
+                    .count();
+
+                ChartWindow {
+                    origin,
+                    transposed,
+                    patches,
+                }
+            })
+            .filter(|window| window.patches >= MIN_PATCHES)
+            .max_by_key(|window| window.patches)
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use image::Rgb;
+
+
+    /// camera response with crosstalk between channels and per channel tone curves
+    pub(crate) struct Camera {
+        matrix: Matrix3<f64>,
+        gamma: [f64; 3],
+    }
+
+    impl Camera {
+        fn capture(&self, srgb: [u8; 3]) -> [f64; 3] {
+            let linear = Vector3::from_fn(|channel, _| srgb_to_linear(srgb[channel] as f64 / 255.0));
+            let response = self.matrix * linear;
+
+            std::array::from_fn(|channel| response[channel].clamp(0.0, 1.0).powf(1.0 / self.gamma[channel]))
+        }
+    }
+
+    pub(crate) fn camera() -> Camera {
+        Camera {
+            matrix: Matrix3::new(
+                0.82, 0.12, 0.03,
+                0.06, 0.95, 0.02,
+                0.02, 0.15, 0.75,
+            ),
+            gamma: [1.9, 2.2, 2.5],
+        }
+    }
+
+    /// the chart on a textured background, `corners` are the image positions of its top left, top right, bottom
+    /// right and bottom left corners
+    pub(crate) fn render_chart(camera: &Camera, corners: [[f64; 2]; 4], (width, height): (u32, u32)) -> RgbImage {
+        // chart coordinates in patches, with gaps and a black frame
+        let (gap, border) = (0.2, 0.4);
+        let chart_width = COLOR_CHECKER_COLUMNS as f64 * (1.0 + gap) - gap + 2.0 * border;
+        let chart_height = COLOR_CHECKER_ROWS as f64 * (1.0 + gap) - gap + 2.0 * border;
+
+        let chart_points = [
+            [0.0, 0.0, 0.0],
+            [chart_width, 0.0, 0.0],
+            [chart_width, chart_height, 0.0],
+            [0.0, chart_height, 0.0],
+        ];
+        let to_image = estimate_homography(&chart_points, &corners).unwrap();
+        let to_chart = to_image.try_inverse().unwrap();
+
+        let chart_color = |x: f64, y: f64| -> Option<[u8; 3]> {
+            let point = to_chart * Vector3::new(x, y, 1.0);
+            let (u, v) = (point.x / point.z, point.y / point.z);
+            if !(0.0..chart_width).contains(&u) || !(0.0..chart_height).contains(&v) {
+                return None;
+            }
+
+            let (u, v) = (u - border, v - border);
+            let (column, row) = ((u / (1.0 + gap)).floor(), (v / (1.0 + gap)).floor());
+            let inside = u >= 0.0 && v >= 0.0
+                && u - column * (1.0 + gap) < 1.0
+                && v - row * (1.0 + gap) < 1.0
+                && (column as usize) < COLOR_CHECKER_COLUMNS
+                && (row as usize) < COLOR_CHECKER_ROWS;
+
+            Some(if inside {
+                COLOR_CHECKER_SRGB[row as usize * COLOR_CHECKER_COLUMNS + column as usize]
+            } else {
+                [22, 22, 24]
+            })
+        };
+
+        let mut noise = 0x2545_f491_u32;
+        RgbImage::from_fn(width, height, |x, y| {
+            // 2x2 supersampling for antialiased edges
+            let mut sum = [0.0; 3];
+            for (dx, dy) in [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)] {
+                let (sx, sy) = (x as f64 + dx - 0.5, y as f64 + dy - 0.5);
+                let srgb = chart_color(sx, sy).unwrap_or_else(|| {
+                    let stripe = if ((sx / 37.0).floor() as i64 + (sy / 53.0).floor() as i64) % 2 == 0 { 150 } else { 110 };
+                    [stripe, stripe - 10, stripe - 25]
+                });
+
+                let captured = camera.capture(srgb);
+                for channel in 0..3 {
+                    sum[channel] += captured[channel] / 4.0;
+                }
+            }
+
+            Rgb(std::array::from_fn(|channel| {
+                noise ^= noise << 13;
+                noise ^= noise >> 17;
+                noise ^= noise << 5;
+                let jitter = (noise % 5) as f64 - 2.0;
+
+                (sum[channel] * 255.0 + jitter).round().clamp(0.0, 255.0) as u8
+            }))
+        })
+    }
+
+
+    #[test]
+    fn test_calibrate_color_checker() {
+        let camera = camera();
+
+        let views = [
+            [[210.0, 140.0], [590.0, 160.0], [575.0, 420.0], [200.0, 395.0]],
+            [[160.0, 120.0], [520.0, 90.0], [560.0, 350.0], [190.0, 380.0]],
+            // upside down
+            [[560.0, 400.0], [190.0, 380.0], [205.0, 130.0], [575.0, 150.0]],
+        ];
+
+        let samples = views.iter()
+            .map(|&corners| {
+                let image = render_chart(&camera, corners, (800, 540));
+                detect_color_checker(&image).expect("color checker not detected")
+            })
+            .collect::<Vec<_>>();
+
+        for (patch, reference) in COLOR_CHECKER_SRGB.iter().enumerate() {
+            let expected = camera.capture(*reference);
+            for sample in &samples {
+                for channel in 0..3 {
+                    assert!(
+                        (sample[patch][channel] - expected[channel]).abs() < 0.02,
+                        "patch {} was sampled as {:?} instead of {:?}",
+                        patch,
+                        sample[patch],
+                        expected,
+                    );
+                }
+            }
+        }
+
+        let correction = calibrate_color(&samples).unwrap();
+        assert!(correction.rms_error < 2.0, "rms error {}", correction.rms_error);
+
+        for (channel, gamma) in camera.gamma.iter().enumerate() {
+            assert!((correction.gamma[channel] - gamma).abs() < 0.1, "gamma {:?}", correction.gamma);
+        }
+
+        let mut image = RgbImage::from_fn(6, 4, |x, y| {
+            let captured = camera.capture(COLOR_CHECKER_SRGB[(y * 6 + x) as usize]);
+            Rgb(captured.map(|value| (value * 255.0).round() as u8))
+        });
+        correction.correct_image(&mut image);
+
+        // the fit is in linear space, so the error of dark channels is larger in srgb levels
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let reference = COLOR_CHECKER_SRGB[(y * 6 + x) as usize];
+            for channel in 0..3 {
+                assert!(
+                    (pixel.0[channel] as i32 - reference[channel] as i32).abs() <= 8,
+                    "patch {} was corrected to {:?} instead of {:?}",
+                    y * 6 + x,
+                    pixel.0,
+                    reference,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_color_checker() {
+        let camera = camera();
+        let image = render_chart(&camera, [[-10.0, -10.0], [-5.0, -10.0], [-5.0, -5.0], [-10.0, -5.0]], (640, 480));
+
+        assert!(detect_color_checker(&image).is_none());
+    }
+}
@@ -0,0 +1,736 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{bail, Error};
+use bevy::prelude::*;
+use nalgebra::{
+    DMatrix,
+    DVector,
+    Isometry3,
+    Matrix6,
+    MatrixXx6,
+    Point3,
+    Translation3,
+    UnitQuaternion,
+    Vector3,
+    Vector6,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    corresponding_points,
+    intrinsics::{
+        estimate_homography,
+        pose_from_homography,
+        CameraIntrinsics,
+    },
+    BoardCorners,
+};
+
+
+/// pose of a camera in the shared world frame, which is the frame of the reference camera of the calibration
+#[derive(Debug, Default, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct CameraExtrinsics {
+    /// axis-angle rotation from world to camera coordinates in radians
+    pub rotation: [f64; 3],
+
+    /// world origin in camera coordinates, in board units (meters)
+    pub translation: [f64; 3],
+
+    /// root mean square reprojection error of the board corners seen by this camera after bundle adjustment, in pixels
+    pub rms_error: f64,
+
+    /// frames in which this camera saw the board together with another camera
+    pub frames: usize,
+
+    /// the residual is far above the other cameras, e.g. a loose mount or a camera moved during the capture
+    pub flagged: bool,
+}
+
+impl CameraExtrinsics {
+    /// camera from world transform
+    pub fn isometry(&self) -> Isometry3<f64> {
+        pose_from_parameters(&[
+            self.rotation[0],
+            self.rotation[1],
+            self.rotation[2],
+            self.translation[0],
+            self.translation[1],
+            self.translation[2],
+        ])
+    }
+
+    /// camera position in the world frame
+    pub fn center(&self) -> [f64; 3] {
+        let center = self.isometry().inverse().translation.vector;
+
+        [center.x, center.y, center.z]
+    }
+}
+
+
+/// the board detections of one camera
+#[derive(Debug, Clone)]
+pub struct CameraObservations {
+    pub intrinsics: CameraIntrinsics,
+
+    /// the board corners by frame index, frames with the same index are captured at the same time
+    pub detections: BTreeMap<usize, BoardCorners>,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ExtrinsicCalibration {
+    /// index of the camera defining the world frame
+    pub reference: usize,
+
+    /// one entry per camera, `None` for cameras which never saw the board together with a calibrated camera
+    pub cameras: Vec<Option<CameraExtrinsics>>,
+
+    /// root mean square reprojection error of all observations in pixels
+    pub rms_error: f64,
+}
+
+
+/// settings of the extrinsic calibration
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtrinsicConfig {
+    /// frames two cameras have to share to be connected in the pose graph
+    pub min_shared_frames: usize,
+
+    /// cameras are flagged when their reprojection error exceeds this multiple of the median camera error
+    pub flag_factor: f64,
+}
+
+impl Default for ExtrinsicConfig {
+    fn default() -> Self {
+        Self {
+            min_shared_frames: 3,
+            flag_factor: 2.0,
+        }
+    }
+}
+
+
+/// poses of cameras with overlapping views of a moving calibration board
+///
+/// the board pose of each detection is estimated from the intrinsics, the relative poses of camera pairs sharing frames
+/// are chained along a maximum spanning tree of the pose graph, and a bundle adjustment refines the camera and board
+/// poses together. intrinsics are kept fixed
+pub fn calibrate_extrinsics(
+    cameras: &[CameraObservations],
+    object_points: &[[f64; 3]],
+    config: &ExtrinsicConfig,
+) -> Result<ExtrinsicCalibration, Error> {
+    let board_poses = cameras.iter()
+        .map(|camera| {
+            camera.detections.iter()
+                .filter_map(|(&frame, corners)| {
+                    let pose = estimate_board_pose(&camera.intrinsics, object_points, corners)?;
+                    Some((frame, pose))
+                })
+                .collect::<BTreeMap<_, _>>()
+        })
+        .collect::<Vec<_>>();
+
+    // frames seen by a single camera do not constrain the relative poses
+    let mut frame_cameras: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (camera, poses) in board_poses.iter().enumerate() {
+        for &frame in poses.keys() {
+            frame_cameras.entry(frame).or_default().push(camera);
+        }
+    }
+    frame_cameras.retain(|_, cameras| cameras.len() > 1);
+
+    let mut shared = vec![vec![vec![]; cameras.len()]; cameras.len()];
+    for (&frame, frame_cameras) in &frame_cameras {
+        for &a in frame_cameras {
+            for &b in frame_cameras {
+                if a != b {
+                    shared[a][b].push(frame);
+                }
+            }
+        }
+    }
+
+    let connected = |a: usize, b: usize| shared[a][b].len() >= config.min_shared_frames.max(1);
+
+    let Some(reference) = (0..cameras.len())
+        .filter(|&camera| (0..cameras.len()).any(|other| connected(camera, other)))
+        .max_by_key(|&camera| (
+            (0..cameras.len()).filter(|&other| connected(camera, other)).map(|other| shared[camera][other].len()).sum::<usize>(),
+            std::cmp::Reverse(camera),
+        ))
+    else {
+        bail!("no two cameras saw the board in {} common frames", config.min_shared_frames);
+    };
+
+    // prim's algorithm, preferring the pairs with the most shared frames
+    let mut camera_poses: Vec<Option<Isometry3<f64>>> = vec![None; cameras.len()];
+    camera_poses[reference] = Some(Isometry3::identity());
+
+    loop {
+        let edge = (0..cameras.len())
+            .filter(|&parent| camera_poses[parent].is_some())
+            .flat_map(|parent| (0..cameras.len()).map(move |child| (parent, child)))
+            .filter(|&(parent, child)| camera_poses[child].is_none() && connected(parent, child))
+            .max_by_key(|&(parent, child)| shared[parent][child].len());
+
+        let Some((parent, child)) = edge else {
+            break;
+        };
+
+        // child from parent, the frame most consistent with the others
+        let relative_poses = shared[parent][child].iter()
+            .map(|frame| board_poses[child][frame] * board_poses[parent][frame].inverse())
+            .collect::<Vec<_>>();
+        let relative = medoid(&relative_poses);
+
+        camera_poses[child] = Some(relative * camera_poses[parent].unwrap());
+    }
+
+    // world from board of each frame, from its first calibrated camera
+    let frames = frame_cameras.iter()
+        .filter(|(_, frame_cameras)| frame_cameras.iter().filter(|&&camera| camera_poses[camera].is_some()).count() > 1)
+        .map(|(&frame, frame_cameras)| {
+            let camera = *frame_cameras.iter().find(|&&camera| camera_poses[camera].is_some()).unwrap();
+            (frame, camera_poses[camera].unwrap().inverse() * board_poses[camera][&frame])
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let observations = frames.keys()
+        .enumerate()
+        .flat_map(|(frame_index, frame)| {
+            frame_cameras[frame].iter()
+                .filter(|&&camera| camera_poses[camera].is_some())
+                .map(move |&camera| Observation {
+                    camera,
+                    frame: frame_index,
+                    frame_id: *frame,
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let mut adjustment = BundleAdjustment {
+        cameras,
+        object_points,
+        reference,
+        calibrated: camera_poses.iter().map(Option::is_some).collect(),
+        camera_poses: camera_poses.iter().map(|pose| pose.unwrap_or_else(Isometry3::identity)).collect(),
+        board_poses: frames.values().copied().collect(),
+        observations,
+    };
+    adjustment.optimize();
+
+    let mut squared_errors = vec![0.0; cameras.len()];
+    let mut observed_frames = vec![BTreeSet::new(); cameras.len()];
+    let mut point_counts = vec![0; cameras.len()];
+
+    for observation in &adjustment.observations {
+        let residuals = adjustment.residuals(observation, &adjustment.camera_poses[observation.camera], &adjustment.board_poses[observation.frame]);
+
+        squared_errors[observation.camera] += residuals.norm_squared();
+        point_counts[observation.camera] += residuals.len() / 2;
+        observed_frames[observation.camera].insert(observation.frame_id);
+    }
+
+    let rms_errors = (0..cameras.len())
+        .map(|camera| (squared_errors[camera] / point_counts[camera].max(1) as f64).sqrt())
+        .collect::<Vec<_>>();
+
+    let mut calibrated_errors = (0..cameras.len())
+        .filter(|&camera| camera_poses[camera].is_some())
+        .map(|camera| rms_errors[camera])
+        .collect::<Vec<_>>();
+    calibrated_errors.sort_by(f64::total_cmp);
+    let flag_threshold = (calibrated_errors[calibrated_errors.len() / 2] * config.flag_factor).max(1.0);
+
+    let extrinsics = (0..cameras.len())
+        .map(|camera| {
+            camera_poses[camera]?;
+
+            let [rx, ry, rz, tx, ty, tz] = pose_parameters(&adjustment.camera_poses[camera]);
+
+            Some(CameraExtrinsics {
+                rotation: [rx, ry, rz],
+                translation: [tx, ty, tz],
+                rms_error: rms_errors[camera],
+                frames: observed_frames[camera].len(),
+                flagged: rms_errors[camera] > flag_threshold,
+            })
+        })
+        .collect();
+
+    let point_count = point_counts.iter().sum::<usize>();
+
+    Ok(ExtrinsicCalibration {
+        reference,
+        cameras: extrinsics,
+        rms_error: (squared_errors.iter().sum::<f64>() / point_count.max(1) as f64).sqrt(),
+    })
+}
+
+
+/// board to camera transform of a single detection
+pub fn estimate_board_pose(
+    intrinsics: &CameraIntrinsics,
+    object_points: &[[f64; 3]],
+    corners: &[(usize, [f64; 2])],
+) -> Option<Isometry3<f64>> {
+    let (board_points, image_points) = corresponding_points(object_points, corners);
+
+    let normalized = image_points.iter()
+        .map(|&point| intrinsics.undistort(point))
+        .collect::<Vec<_>>();
+
+    let homography = estimate_homography(&board_points, &normalized).ok()?;
+    let mut parameters = Vector6::from(pose_parameters(&pose_from_homography(&homography)));
+
+    // gauss-newton on the pixel reprojection error
+    for _ in 0..20 {
+        let residuals = |parameters: &Vector6<f64>| reprojection_residuals(
+            intrinsics,
+            &pose_from_parameters(parameters.as_slice()),
+            object_points,
+            corners.iter().copied(),
+        );
+
+        let current = residuals(&parameters);
+        let jacobian = numeric_jacobian(&parameters, residuals);
+
+        let step = (jacobian.transpose() * &jacobian).cholesky()?.solve(&(-jacobian.transpose() * &current));
+        parameters += step;
+
+        if step.norm() < 1e-10 {
+            break;
+        }
+    }
+
+    Some(pose_from_parameters(parameters.as_slice()))
+}
+
+
+/// reprojection errors above this many pixels are down weighted, so a single loose camera does not bend the others
+const HUBER_THRESHOLD: f64 = 1.0;
+
+
+struct Observation {
+    camera: usize,
+
+    /// index into the board poses
+    frame: usize,
+    frame_id: usize,
+}
+
+struct BundleAdjustment<'a> {
+    cameras: &'a [CameraObservations],
+    object_points: &'a [[f64; 3]],
+
+    /// fixed to the identity, defining the world frame
+    reference: usize,
+
+    /// cameras connected to the reference, the others have no observations or parameters
+    calibrated: Vec<bool>,
+
+    /// camera from world
+    camera_poses: Vec<Isometry3<f64>>,
+
+    /// world from board
+    board_poses: Vec<Isometry3<f64>>,
+
+    observations: Vec<Observation>,
+}
+
+impl BundleAdjustment<'_> {
+    fn residuals(&self, observation: &Observation, camera_pose: &Isometry3<f64>, board_pose: &Isometry3<f64>) -> DVector<f64> {
+        let camera = &self.cameras[observation.camera];
+
+        reprojection_residuals(
+            &camera.intrinsics,
+            &(camera_pose * board_pose),
+            self.object_points,
+            camera.detections[&observation.frame_id].iter().copied(),
+        )
+    }
+
+    fn cost(&self, camera_poses: &[Isometry3<f64>], board_poses: &[Isometry3<f64>]) -> f64 {
+        self.observations.iter()
+            .map(|observation| {
+                let residuals = self.residuals(observation, &camera_poses[observation.camera], &board_poses[observation.frame]);
+
+                residuals.as_slice()
+                    .chunks(2)
+                    .map(|point| {
+                        let error = point[0].hypot(point[1]);
+                        if error <= HUBER_THRESHOLD {
+                            error * error
+                        } else {
+                            2.0 * HUBER_THRESHOLD * error - HUBER_THRESHOLD * HUBER_THRESHOLD
+                        }
+                    })
+                    .sum::<f64>()
+            })
+            .sum()
+    }
+
+    /// levenberg-marquardt on the huber loss, solving the normal equations with the schur complement of the board poses
+    ///
+    /// each observation only couples one camera with one board pose, so the board blocks are eliminated independently
+    /// and only the reduced camera system is solved densely
+    fn optimize(&mut self) {
+        let camera_count = self.camera_poses.len();
+        let board_count = self.board_poses.len();
+
+        // parameter block of each camera in the reduced system, the reference camera has no parameters
+        let mut block_count = 0;
+        let camera_blocks = (0..camera_count)
+            .map(|camera| (self.calibrated[camera] && camera != self.reference).then(|| {
+                block_count += 1;
+                block_count - 1
+            }))
+            .collect::<Vec<_>>();
+        let camera_block = |camera: usize| camera_blocks[camera];
+        let reduced_size = 6 * block_count;
+
+        let mut cost = self.cost(&self.camera_poses, &self.board_poses);
+        let mut damping = 1e-3;
+
+        for _ in 0..100 {
+            let mut camera_hessian = vec![Matrix6::<f64>::zeros(); camera_count];
+            let mut camera_gradient = vec![Vector6::<f64>::zeros(); camera_count];
+            let mut board_hessian = vec![Matrix6::<f64>::zeros(); board_count];
+            let mut board_gradient = vec![Vector6::<f64>::zeros(); board_count];
+            let mut coupling = BTreeMap::<(usize, usize), Matrix6<f64>>::new();
+
+            for observation in &self.observations {
+                let camera_parameters = Vector6::from(pose_parameters(&self.camera_poses[observation.camera]));
+                let board_parameters = Vector6::from(pose_parameters(&self.board_poses[observation.frame]));
+                let board_pose = self.board_poses[observation.frame];
+                let camera_pose = self.camera_poses[observation.camera];
+
+                // iteratively reweighted least squares of the huber loss
+                let mut residuals = self.residuals(observation, &camera_pose, &board_pose);
+                let weights = residuals.as_slice()
+                    .chunks(2)
+                    .flat_map(|point| {
+                        let error = point[0].hypot(point[1]);
+                        let weight = if error <= HUBER_THRESHOLD { 1.0 } else { (HUBER_THRESHOLD / error).sqrt() };
+                        [weight, weight]
+                    })
+                    .collect::<Vec<_>>();
+                let reweight = |mut jacobian: MatrixXx6<f64>| {
+                    for (mut row, weight) in jacobian.row_iter_mut().zip(&weights) {
+                        row *= *weight;
+                    }
+                    jacobian
+                };
+                residuals.iter_mut().zip(&weights).for_each(|(residual, weight)| *residual *= weight);
+
+                let board_jacobian = reweight(numeric_jacobian(&board_parameters, |parameters| {
+                    self.residuals(observation, &camera_pose, &pose_from_parameters(parameters.as_slice()))
+                }));
+                board_hessian[observation.frame] += board_jacobian.transpose() * &board_jacobian;
+                board_gradient[observation.frame] += board_jacobian.transpose() * &residuals;
+
+                if camera_block(observation.camera).is_none() {
+                    continue;
+                }
+
+                let camera_jacobian = reweight(numeric_jacobian(&camera_parameters, |parameters| {
+                    self.residuals(observation, &pose_from_parameters(parameters.as_slice()), &board_pose)
+                }));
+                camera_hessian[observation.camera] += camera_jacobian.transpose() * &camera_jacobian;
+                camera_gradient[observation.camera] += camera_jacobian.transpose() * &residuals;
+                *coupling.entry((observation.frame, observation.camera)).or_insert_with(Matrix6::zeros) += camera_jacobian.transpose() * &board_jacobian;
+            }
+
+            let mut improved = false;
+            while damping < 1e10 {
+                let damped = |hessian: &Matrix6<f64>| {
+                    let mut hessian = *hessian;
+                    for k in 0..6 {
+                        hessian[(k, k)] += damping * hessian[(k, k)].max(1e-12);
+                    }
+                    hessian
+                };
+
+                let Some(board_inverses) = board_hessian.iter()
+                    .map(|hessian| damped(hessian).try_inverse())
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    damping *= 10.0;
+                    continue;
+                };
+
+                let mut reduced = DMatrix::<f64>::zeros(reduced_size, reduced_size);
+                let mut reduced_gradient = DVector::<f64>::zeros(reduced_size);
+
+                for camera in 0..camera_count {
+                    let Some(block) = camera_block(camera) else {
+                        continue;
+                    };
+
+                    reduced.fixed_view_mut::<6, 6>(6 * block, 6 * block).copy_from(&damped(&camera_hessian[camera]));
+                    reduced_gradient.fixed_rows_mut::<6>(6 * block).copy_from(&camera_gradient[camera]);
+                }
+
+                for frame in 0..board_count {
+                    let frame_couplings = coupling.range((frame, 0)..(frame + 1, 0))
+                        .map(|(&(_, camera), w)| (camera_block(camera).unwrap(), w))
+                        .collect::<Vec<_>>();
+
+                    for &(a, w_a) in &frame_couplings {
+                        let w_a_inverse = w_a * board_inverses[frame];
+
+                        let mut gradient_block = reduced_gradient.fixed_rows_mut::<6>(6 * a);
+                        gradient_block -= w_a_inverse * board_gradient[frame];
+
+                        for &(b, w_b) in &frame_couplings {
+                            let mut block = reduced.fixed_view_mut::<6, 6>(6 * a, 6 * b);
+                            block -= w_a_inverse * w_b.transpose();
+                        }
+                    }
+                }
+
+                let camera_steps = if reduced_size == 0 {
+                    Some(DVector::zeros(0))
+                } else {
+                    reduced.cholesky().map(|cholesky| cholesky.solve(&-&reduced_gradient))
+                };
+                let Some(camera_steps) = camera_steps else {
+                    damping *= 10.0;
+                    continue;
+                };
+
+                let camera_poses = (0..camera_count)
+                    .map(|camera| match camera_block(camera) {
+                        Some(block) => {
+                            let parameters = Vector6::from(pose_parameters(&self.camera_poses[camera])) + camera_steps.fixed_rows::<6>(6 * block);
+                            pose_from_parameters(parameters.as_slice())
+                        },
+                        None => self.camera_poses[camera],
+                    })
+                    .collect::<Vec<_>>();
+
+                let board_poses = (0..board_count)
+                    .map(|frame| {
+                        let mut rhs = -board_gradient[frame];
+                        for (&(_, camera), w) in coupling.range((frame, 0)..(frame + 1, 0)) {
+                            rhs -= w.transpose() * camera_steps.fixed_rows::<6>(6 * camera_block(camera).unwrap());
+                        }
+
+                        let parameters = Vector6::from(pose_parameters(&self.board_poses[frame])) + board_inverses[frame] * rhs;
+                        pose_from_parameters(parameters.as_slice())
+                    })
+                    .collect::<Vec<_>>();
+
+                let candidate_cost = self.cost(&camera_poses, &board_poses);
+                if candidate_cost < cost {
+                    let reduction = (cost - candidate_cost) / cost.max(f64::MIN_POSITIVE);
+
+                    self.camera_poses = camera_poses;
+                    self.board_poses = board_poses;
+                    cost = candidate_cost;
+                    damping = (damping / 10.0).max(1e-12);
+                    improved = reduction > 1e-12;
+                    break;
+                }
+
+                damping *= 10.0;
+            }
+
+            if !improved {
+                break;
+            }
+        }
+    }
+}
+
+
+/// pixel offsets of the projected object points from the `corners` indexing them, corners outside of `object_points`
+/// are skipped
+fn reprojection_residuals(
+    intrinsics: &CameraIntrinsics,
+    board_to_camera: &Isometry3<f64>,
+    object_points: &[[f64; 3]],
+    corners: impl Iterator<Item = (usize, [f64; 2])>,
+) -> DVector<f64> {
+    let residuals = corners
+        .filter_map(|(corner, image)| {
+            let [u, v] = intrinsics.project(&(board_to_camera * Point3::from(*object_points.get(corner)?)));
+            Some([u - image[0], v - image[1]])
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+
+    DVector::from_vec(residuals)
+}
+
+/// central differences of `residuals` with respect to 6 pose parameters
+fn numeric_jacobian(
+    parameters: &Vector6<f64>,
+    residuals: impl Fn(&Vector6<f64>) -> DVector<f64>,
+) -> MatrixXx6<f64> {
+    let columns = (0..6)
+        .map(|k| {
+            let step = 1e-6 * parameters[k].abs().max(1e-2);
+
+            let mut forward = *parameters;
+            forward[k] += step;
+            let mut backward = *parameters;
+            backward[k] -= step;
+
+            (residuals(&forward) - residuals(&backward)) / (2.0 * step)
+        })
+        .collect::<Vec<_>>();
+
+    MatrixXx6::from_columns(&columns)
+}
+
+/// the pose with the smallest summed rotation and translation distance to the others
+fn medoid(poses: &[Isometry3<f64>]) -> Isometry3<f64> {
+    let scale = poses.iter()
+        .map(|pose| pose.translation.vector.norm())
+        .fold(f64::EPSILON, f64::max);
+
+    *poses.iter()
+        .min_by(|a, b| {
+            let distance = |pose: &Isometry3<f64>| poses.iter()
+                .map(|other| pose.rotation.angle_to(&other.rotation) + (pose.translation.vector - other.translation.vector).norm() / scale)
+                .sum::<f64>();
+
+            distance(a).total_cmp(&distance(b))
+        })
+        .unwrap()
+}
+
+fn pose_parameters(pose: &Isometry3<f64>) -> [f64; 6] {
+    let rotation = pose.rotation.scaled_axis();
+    let translation = pose.translation.vector;
+
+    [rotation.x, rotation.y, rotation.z, translation.x, translation.y, translation.z]
+}
+
+/// axis-angle rotation followed by the translation
+fn pose_from_parameters(parameters: &[f64]) -> Isometry3<f64> {
+    Isometry3::from_parts(
+        Translation3::new(parameters[3], parameters[4], parameters[5]),
+        UnitQuaternion::from_scaled_axis(Vector3::new(parameters[0], parameters[1], parameters[2])),
+    )
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::calibration::Checkerboard;
+
+
+    #[test]
+    fn test_calibrate_extrinsics() {
+        let board = Checkerboard::default();
+        let object_points = board.object_points();
+
+        let intrinsics = |fx: f64, k1: f64| CameraIntrinsics {
+            fx,
+            fy: fx,
+            cx: 960.0,
+            cy: 540.0,
+            distortion: [k1, 0.03, 0.0, 0.0, 0.0],
+        };
+
+        // an arc of cameras 0.3 m apart, turned towards a point 1.5 m ahead of the middle camera
+        let camera_poses = (0..4)
+            .map(|i| {
+                let x = (i as f64 - 1.5) * 0.3;
+                let yaw = (-x / 1.5).atan();
+                let rotation = UnitQuaternion::from_euler_angles(0.0, yaw, 0.0);
+
+                // camera from world of a camera at `x` with the rotation applied in world coordinates
+                Isometry3::from_parts(Translation3::new(x, 0.0, 0.0), rotation).inverse()
+            })
+            .collect::<Vec<_>>();
+
+        let camera_intrinsics = [intrinsics(1400.0, -0.1), intrinsics(1420.0, -0.12), intrinsics(1380.0, -0.08), intrinsics(1410.0, -0.1)];
+
+        let mut seed = 3u64;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) as f64 / (1u64 << 31) as f64 - 0.5) * 0.2
+        };
+
+        let center = Vector3::new(
+            (board.columns - 1) as f64 * board.square_size / 2.0,
+            (board.rows - 1) as f64 * board.square_size / 2.0,
+            0.0,
+        );
+
+        let mut cameras = camera_intrinsics.iter()
+            .map(|intrinsics| CameraObservations {
+                intrinsics: intrinsics.clone(),
+                detections: BTreeMap::new(),
+            })
+            .collect::<Vec<_>>();
+
+        for frame in 0..24 {
+            let t = frame as f64 / 24.0;
+            let rotation = UnitQuaternion::from_euler_angles(
+                std::f64::consts::PI + 0.4 * (t * 9.0).sin(),
+                0.5 * (t * 7.0).cos(),
+                0.3 * (t * 5.0).sin(),
+            );
+            let position = Vector3::new(0.25 * (t * 6.0).sin(), 0.15 * (t * 4.0).cos(), 1.4 + 0.3 * t);
+
+            // world from board, facing the cameras
+            let board_pose = Isometry3::from_parts(Translation3::from(position - rotation * center), rotation);
+
+            for (camera, observations) in cameras.iter_mut().enumerate() {
+                let pose = camera_poses[camera] * board_pose;
+
+                let image_points = object_points.iter()
+                    .map(|&point| {
+                        let point = pose * Point3::from(point);
+                        let [u, v] = observations.intrinsics.project(&point);
+                        (point.z > 0.0 && (0.0..1920.0).contains(&u) && (0.0..1080.0).contains(&v)).then_some([u, v])
+                    })
+                    .collect::<Option<Vec<_>>>();
+
+                if let Some(mut image_points) = image_points {
+                    // the last camera vibrates on a loose mount
+                    let amplitude = if camera == 3 { 30.0 } else { 1.0 };
+
+                    for point in image_points.iter_mut() {
+                        point[0] += noise() * amplitude;
+                        point[1] += noise() * amplitude;
+                    }
+
+                    observations.detections.insert(frame, image_points.into_iter().enumerate().collect());
+                }
+            }
+        }
+
+        assert!(cameras.iter().all(|camera| camera.detections.len() >= 12), "every camera sees the board");
+
+        let calibration = calibrate_extrinsics(&cameras, &object_points, &ExtrinsicConfig::default()).unwrap();
+
+        let reference = calibration.reference;
+        let extrinsics = calibration.cameras.iter()
+            .map(|camera| camera.as_ref().expect("every camera is calibrated"))
+            .collect::<Vec<_>>();
+
+        // poses are relative to the reference camera
+        for camera in 0..3 {
+            let expected = camera_poses[camera] * camera_poses[reference].inverse();
+            let estimated = extrinsics[camera].isometry();
+
+            assert!(expected.rotation.angle_to(&estimated.rotation) < 0.002, "camera {} rotation", camera);
+            assert!((expected.translation.vector - estimated.translation.vector).norm() < 0.002, "camera {} translation", camera);
+            assert!(extrinsics[camera].rms_error < 0.2, "camera {} rms error {}", camera, extrinsics[camera].rms_error);
+            assert!(!extrinsics[camera].flagged);
+        }
+
+        assert!(extrinsics[3].flagged, "the moved camera is flagged, rms error {}", extrinsics[3].rms_error);
+    }
+}
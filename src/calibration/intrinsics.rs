@@ -0,0 +1,624 @@
+use anyhow::{bail, Error};
+use bevy::prelude::*;
+use nalgebra::{
+    DMatrix,
+    DVector,
+    Isometry3,
+    Matrix3,
+    Point3,
+    Rotation3,
+    SMatrix,
+    SymmetricEigen,
+    Translation3,
+    UnitQuaternion,
+    Vector3,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{corresponding_points, BoardCorners};
+
+
+/// pinhole camera with opencv's radial/tangential distortion model
+#[derive(Debug, Default, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct CameraIntrinsics {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+
+    /// `[k1, k2, p1, p2, k3]`
+    pub distortion: [f64; 5],
+}
+
+impl CameraIntrinsics {
+    pub fn matrix(&self) -> Matrix3<f64> {
+        Matrix3::new(
+            self.fx, 0.0, self.cx,
+            0.0, self.fy, self.cy,
+            0.0, 0.0, 1.0,
+        )
+    }
+
+    /// applies the lens distortion to normalized image coordinates
+    pub fn distort(&self, [x, y]: [f64; 2]) -> [f64; 2] {
+        let [k1, k2, p1, p2, k3] = self.distortion;
+
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+
+        [
+            x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+            y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+        ]
+    }
+
+    /// pixel coordinates of a point in camera space
+    pub fn project(&self, point: &Point3<f64>) -> [f64; 2] {
+        let [x, y] = self.distort([point.x / point.z, point.y / point.z]);
+
+        [
+            self.fx * x + self.cx,
+            self.fy * y + self.cy,
+        ]
+    }
+
+    /// normalized image coordinates of a pixel with the lens distortion removed
+    pub fn undistort(&self, [u, v]: [f64; 2]) -> [f64; 2] {
+        let distorted = [(u - self.cx) / self.fx, (v - self.cy) / self.fy];
+
+        // fixed point iteration, which converges for the distortion of calibrated lenses
+        let mut point = distorted;
+        for _ in 0..20 {
+            let [x, y] = self.distort(point);
+            point = [
+                point[0] + distorted[0] - x,
+                point[1] + distorted[1] - y,
+            ];
+        }
+
+        point
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct IntrinsicCalibration {
+    pub intrinsics: CameraIntrinsics,
+
+    /// root mean square reprojection error of the used views in pixels
+    pub rms_error: f64,
+
+    /// indices of the views the intrinsics were estimated from, outliers are excluded
+    pub views: Vec<usize>,
+
+    /// board to camera transform of each used view
+    pub poses: Vec<Isometry3<f64>>,
+}
+
+
+/// the object points of a view on the board plane (z = 0) and their image points
+type PlanarView = (Vec<[f64; 3]>, Vec<[f64; 2]>);
+
+
+/// Zhang's method followed by a levenberg-marquardt refinement of the intrinsics, distortion and board poses
+///
+/// `views` are the detected corners of each view, indexing the planar `object_points` (z = 0). views with a reprojection
+/// error above `outlier_factor` times the median view error are dropped and the rest refined again
+pub fn calibrate_intrinsics(
+    views: &[BoardCorners],
+    object_points: &[[f64; 3]],
+    image_size: (u32, u32),
+    outlier_factor: f64,
+) -> Result<IntrinsicCalibration, Error> {
+    if views.len() < 3 {
+        bail!("{} views are not enough for calibration, at least 3 are required", views.len());
+    }
+
+    if views.iter().any(|view| view.len() < 4 || view.iter().any(|&(corner, _)| corner >= object_points.len())) {
+        bail!("every view needs at least 4 corners of the board");
+    }
+
+    let views = views.iter()
+        .map(|view| corresponding_points(object_points, view))
+        .collect::<Vec<PlanarView>>();
+
+    let mut used = (0..views.len()).collect::<Vec<_>>();
+    let used_views = |used: &[usize]| used.iter().map(|&i| views[i].clone()).collect::<Vec<_>>();
+
+    let (intrinsics, poses) = initial_estimate(&used_views(&used), image_size)?;
+    let mut refinement = refine(&used_views(&used), intrinsics, poses);
+
+    let mut view_errors = refinement.view_errors.clone();
+    view_errors.sort_by(f64::total_cmp);
+    let threshold = (view_errors[view_errors.len() / 2] * outlier_factor).max(1.0);
+
+    let inliers = refinement.view_errors.iter()
+        .zip(&used)
+        .filter(|(&error, _)| error <= threshold)
+        .map(|(_, &view)| view)
+        .collect::<Vec<_>>();
+
+    if inliers.len() < used.len() && inliers.len() >= 3 {
+        let poses = inliers.iter()
+            .map(|view| refinement.poses[used.iter().position(|used| used == view).unwrap()])
+            .collect();
+
+        used = inliers;
+        refinement = refine(&used_views(&used), refinement.intrinsics, poses);
+    }
+
+    Ok(IntrinsicCalibration {
+        intrinsics: refinement.intrinsics,
+        rms_error: refinement.rms_error,
+        views: used,
+        poses: refinement.poses,
+    })
+}
+
+
+/// homography from the board plane to the image, estimated with the normalized direct linear transform
+pub fn estimate_homography(object_points: &[[f64; 3]], image_points: &[[f64; 2]]) -> Result<Matrix3<f64>, Error> {
+    if object_points.len() < 4 || object_points.len() != image_points.len() {
+        bail!("a homography needs at least 4 point correspondences");
+    }
+
+    let object_points = object_points.iter().map(|point| [point[0], point[1]]).collect::<Vec<_>>();
+    let object_normalization = normalization(&object_points);
+    let image_normalization = normalization(image_points);
+
+    let mut ata = SMatrix::<f64, 9, 9>::zeros();
+    for (object, image) in object_points.iter().zip(image_points) {
+        let [x, y] = transform(&object_normalization, *object);
+        let [u, v] = transform(&image_normalization, *image);
+
+        let rows = [
+            SMatrix::<f64, 1, 9>::from_row_slice(&[x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, -u]),
+            SMatrix::<f64, 1, 9>::from_row_slice(&[0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, -v]),
+        ];
+        for row in rows {
+            ata += row.transpose() * row;
+        }
+    }
+
+    let h = smallest_eigenvector(DMatrix::from_column_slice(9, 9, ata.as_slice()));
+    let normalized = Matrix3::from_row_slice(h.as_slice());
+
+    let Some(image_denormalization) = image_normalization.try_inverse() else {
+        bail!("degenerate image points");
+    };
+    let homography = image_denormalization * normalized * object_normalization;
+
+    Ok(homography / homography[(2, 2)])
+}
+
+
+/// closed form intrinsics (zero skew, no distortion) and board poses from the view homographies
+fn initial_estimate(
+    views: &[PlanarView],
+    (width, height): (u32, u32),
+) -> Result<(CameraIntrinsics, Vec<Isometry3<f64>>), Error> {
+    // pixel coordinates are scaled to about [-1, 1] to condition the linear system
+    let scale = 2.0 / (width + height) as f64;
+    let conditioning = Matrix3::new(
+        scale, 0.0, -scale * width as f64 / 2.0,
+        0.0, scale, -scale * height as f64 / 2.0,
+        0.0, 0.0, 1.0,
+    );
+
+    let homographies = views.iter()
+        .map(|(object_points, image_points)| estimate_homography(object_points, image_points))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let v = |h: &Matrix3<f64>, i: usize, j: usize| [
+        h[(0, i)] * h[(0, j)],
+        h[(0, i)] * h[(1, j)] + h[(1, i)] * h[(0, j)],
+        h[(1, i)] * h[(1, j)],
+        h[(2, i)] * h[(0, j)] + h[(0, i)] * h[(2, j)],
+        h[(2, i)] * h[(1, j)] + h[(1, i)] * h[(2, j)],
+        h[(2, i)] * h[(2, j)],
+    ];
+
+    let mut vtv = SMatrix::<f64, 6, 6>::zeros();
+    let mut add_constraint = |row: [f64; 6]| {
+        let row = SMatrix::<f64, 1, 6>::from_row_slice(&row);
+        vtv += row.transpose() * row;
+    };
+
+    for homography in &homographies {
+        let h = conditioning * homography;
+        let h = h / h.column(0).norm();
+
+        let v11 = v(&h, 0, 0);
+        let v22 = v(&h, 1, 1);
+        add_constraint(v(&h, 0, 1));
+        add_constraint(std::array::from_fn(|k| v11[k] - v22[k]));
+    }
+
+    // zero skew
+    add_constraint([0.0, views.len() as f64, 0.0, 0.0, 0.0, 0.0]);
+
+    let b = smallest_eigenvector(DMatrix::from_column_slice(6, 6, vtv.as_slice()));
+    let b = if b[0] < 0.0 { -b } else { b };
+    let (b11, b12, b22, b13, b23, b33) = (b[0], b[1], b[2], b[3], b[4], b[5]);
+
+    let denominator = b11 * b22 - b12 * b12;
+    if b11 <= 0.0 || denominator <= 0.0 {
+        bail!("the views are degenerate, tilt the board in different directions");
+    }
+
+    let cy = (b12 * b13 - b11 * b23) / denominator;
+    let lambda = b33 - (b13 * b13 + cy * (b12 * b13 - b11 * b23)) / b11;
+    if lambda / b11 <= 0.0 {
+        bail!("the views are degenerate, tilt the board in different directions");
+    }
+
+    let fx = (lambda / b11).sqrt();
+    let fy = (lambda * b11 / denominator).sqrt();
+    let cx = -b13 * fx * fx / lambda;
+
+    let conditioned = Matrix3::new(
+        fx, 0.0, cx,
+        0.0, fy, cy,
+        0.0, 0.0, 1.0,
+    );
+    let k = conditioning.try_inverse().unwrap() * conditioned;
+
+    let intrinsics = CameraIntrinsics {
+        fx: k[(0, 0)],
+        fy: k[(1, 1)],
+        cx: k[(0, 2)],
+        cy: k[(1, 2)],
+        distortion: [0.0; 5],
+    };
+
+    let k_inverse = intrinsics.matrix().try_inverse().unwrap();
+    let poses = homographies.iter()
+        .map(|homography| pose_from_homography(&(k_inverse * homography)))
+        .collect();
+
+    Ok((intrinsics, poses))
+}
+
+
+/// board to camera transform from a homography between the board plane and normalized image coordinates
+pub fn pose_from_homography(homography: &Matrix3<f64>) -> Isometry3<f64> {
+    // the board is in front of the camera
+    let mut lambda = 1.0 / homography.column(0).norm();
+    if homography[(2, 2)] * lambda < 0.0 {
+        lambda = -lambda;
+    }
+
+    let r1 = homography.column(0) * lambda;
+    let r2 = homography.column(1) * lambda;
+    let t = homography.column(2) * lambda;
+    let rotation = Matrix3::from_columns(&[r1, r2, r1.cross(&r2)]);
+
+    Isometry3::from_parts(
+        Translation3::from(t.into_owned()),
+        UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix(&rotation)),
+    )
+}
+
+
+struct Refinement {
+    intrinsics: CameraIntrinsics,
+    poses: Vec<Isometry3<f64>>,
+    rms_error: f64,
+    view_errors: Vec<f64>,
+}
+
+const INTRINSIC_PARAMETERS: usize = 9;
+const POSE_PARAMETERS: usize = 6;
+
+/// levenberg-marquardt over `[fx, fy, cx, cy, k1, k2, p1, p2, k3]` and the axis-angle and translation of each pose
+fn refine(
+    views: &[PlanarView],
+    intrinsics: CameraIntrinsics,
+    poses: Vec<Isometry3<f64>>,
+) -> Refinement {
+    let parameter_count = INTRINSIC_PARAMETERS + POSE_PARAMETERS * views.len();
+
+    let mut parameters = DVector::zeros(parameter_count);
+    parameters.rows_mut(0, INTRINSIC_PARAMETERS).copy_from_slice(&[
+        intrinsics.fx,
+        intrinsics.fy,
+        intrinsics.cx,
+        intrinsics.cy,
+        intrinsics.distortion[0],
+        intrinsics.distortion[1],
+        intrinsics.distortion[2],
+        intrinsics.distortion[3],
+        intrinsics.distortion[4],
+    ]);
+    for (i, pose) in poses.iter().enumerate() {
+        let offset = INTRINSIC_PARAMETERS + POSE_PARAMETERS * i;
+        parameters.rows_mut(offset, 3).copy_from(&pose.rotation.scaled_axis());
+        parameters.rows_mut(offset + 3, 3).copy_from(&pose.translation.vector);
+    }
+
+    let view_parameters = |parameters: &DVector<f64>, view: usize| -> [f64; INTRINSIC_PARAMETERS + POSE_PARAMETERS] {
+        let offset = INTRINSIC_PARAMETERS + POSE_PARAMETERS * view;
+        std::array::from_fn(|k| if k < INTRINSIC_PARAMETERS {
+            parameters[k]
+        } else {
+            parameters[offset + k - INTRINSIC_PARAMETERS]
+        })
+    };
+
+    let cost = |parameters: &DVector<f64>| -> f64 {
+        views.iter()
+            .enumerate()
+            .map(|(i, (object_points, image_points))| {
+                view_residuals(&view_parameters(parameters, i), object_points, image_points).iter().map(|r| r * r).sum::<f64>()
+            })
+            .sum()
+    };
+
+    let mut current_cost = cost(&parameters);
+    let mut damping = 1e-3;
+
+    for _ in 0..100 {
+        let mut jtj = DMatrix::<f64>::zeros(parameter_count, parameter_count);
+        let mut jtr = DVector::<f64>::zeros(parameter_count);
+
+        for (i, (object_points, image_points)) in views.iter().enumerate() {
+            let local = view_parameters(&parameters, i);
+            let residuals = view_residuals(&local, object_points, image_points);
+
+            // central differences, each view only depends on the intrinsics and its own pose
+            let mut jacobian = DMatrix::zeros(residuals.len(), local.len());
+            for k in 0..local.len() {
+                let step = 1e-6 * local[k].abs().max(1e-2);
+
+                let mut forward = local;
+                forward[k] += step;
+                let mut backward = local;
+                backward[k] -= step;
+
+                let derivative = (view_residuals(&forward, object_points, image_points) - view_residuals(&backward, object_points, image_points)) / (2.0 * step);
+                jacobian.set_column(k, &derivative);
+            }
+
+            let local_jtj = jacobian.transpose() * &jacobian;
+            let local_jtr = jacobian.transpose() * &residuals;
+
+            let global = |k: usize| if k < INTRINSIC_PARAMETERS {
+                k
+            } else {
+                INTRINSIC_PARAMETERS + POSE_PARAMETERS * i + k - INTRINSIC_PARAMETERS
+            };
+
+            for row in 0..local.len() {
+                jtr[global(row)] += local_jtr[row];
+                for column in 0..local.len() {
+                    jtj[(global(row), global(column))] += local_jtj[(row, column)];
+                }
+            }
+        }
+
+        let mut improved = false;
+        while damping < 1e10 {
+            let mut damped = jtj.clone();
+            for k in 0..parameter_count {
+                damped[(k, k)] += damping * jtj[(k, k)].max(1e-12);
+            }
+
+            let Some(step) = damped.cholesky().map(|cholesky| cholesky.solve(&-&jtr)) else {
+                damping *= 10.0;
+                continue;
+            };
+
+            let candidate = &parameters + &step;
+            let candidate_cost = cost(&candidate);
+
+            if candidate_cost < current_cost {
+                let reduction = (current_cost - candidate_cost) / current_cost.max(f64::MIN_POSITIVE);
+
+                parameters = candidate;
+                current_cost = candidate_cost;
+                damping = (damping / 10.0).max(1e-12);
+                improved = reduction > 1e-12;
+                break;
+            }
+
+            damping *= 10.0;
+        }
+
+        if !improved {
+            break;
+        }
+    }
+
+    let intrinsics = intrinsics_from(&view_parameters(&parameters, 0));
+    let poses = (0..views.len())
+        .map(|i| pose_from(&view_parameters(&parameters, i)))
+        .collect();
+
+    let view_errors = views.iter()
+        .enumerate()
+        .map(|(i, (object_points, image_points))| {
+            let residuals = view_residuals(&view_parameters(&parameters, i), object_points, image_points);
+            (residuals.norm_squared() / image_points.len() as f64).sqrt()
+        })
+        .collect();
+
+    let point_count = views.iter().map(|(_, image_points)| image_points.len()).sum::<usize>();
+
+    Refinement {
+        intrinsics,
+        poses,
+        rms_error: (current_cost / point_count as f64).sqrt(),
+        view_errors,
+    }
+}
+
+fn intrinsics_from(parameters: &[f64]) -> CameraIntrinsics {
+    CameraIntrinsics {
+        fx: parameters[0],
+        fy: parameters[1],
+        cx: parameters[2],
+        cy: parameters[3],
+        distortion: [parameters[4], parameters[5], parameters[6], parameters[7], parameters[8]],
+    }
+}
+
+fn pose_from(parameters: &[f64]) -> Isometry3<f64> {
+    let pose = &parameters[INTRINSIC_PARAMETERS..];
+
+    Isometry3::from_parts(
+        Translation3::new(pose[3], pose[4], pose[5]),
+        UnitQuaternion::from_scaled_axis(Vector3::new(pose[0], pose[1], pose[2])),
+    )
+}
+
+/// pixel offsets between the projected object points and the detections of one view, interleaved x and y
+fn view_residuals(
+    parameters: &[f64; INTRINSIC_PARAMETERS + POSE_PARAMETERS],
+    object_points: &[[f64; 3]],
+    image_points: &[[f64; 2]],
+) -> DVector<f64> {
+    let intrinsics = intrinsics_from(parameters);
+    let pose = pose_from(parameters);
+
+    let mut residuals = DVector::zeros(image_points.len() * 2);
+    for (i, (object, image)) in object_points.iter().zip(image_points).enumerate() {
+        let [u, v] = intrinsics.project(&(pose * Point3::from(*object)));
+
+        residuals[2 * i] = u - image[0];
+        residuals[2 * i + 1] = v - image[1];
+    }
+
+    residuals
+}
+
+
+/// similarity moving the centroid of `points` to the origin at an average distance of √2
+fn normalization(points: &[[f64; 2]]) -> Matrix3<f64> {
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|point| point[0]).sum::<f64>() / count;
+    let mean_y = points.iter().map(|point| point[1]).sum::<f64>() / count;
+
+    let mean_distance = points.iter()
+        .map(|point| (point[0] - mean_x).hypot(point[1] - mean_y))
+        .sum::<f64>() / count;
+    let scale = std::f64::consts::SQRT_2 / mean_distance.max(f64::EPSILON);
+
+    Matrix3::new(
+        scale, 0.0, -scale * mean_x,
+        0.0, scale, -scale * mean_y,
+        0.0, 0.0, 1.0,
+    )
+}
+
+fn transform(matrix: &Matrix3<f64>, [x, y]: [f64; 2]) -> [f64; 2] {
+    let point = matrix * Vector3::new(x, y, 1.0);
+
+    [point.x / point.z, point.y / point.z]
+}
+
+fn smallest_eigenvector(matrix: DMatrix<f64>) -> DVector<f64> {
+    let eigen = SymmetricEigen::new(matrix);
+    let smallest = eigen.eigenvalues.argmin().0;
+
+    eigen.eigenvectors.column(smallest).into_owned()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::calibration::Checkerboard;
+
+
+    fn board_pose(board: &Checkerboard, (roll, pitch, yaw): (f64, f64, f64), offset: Vector3<f64>) -> Isometry3<f64> {
+        let rotation = UnitQuaternion::from_euler_angles(roll, pitch, yaw);
+        let center = Vector3::new(
+            (board.columns - 1) as f64 * board.square_size / 2.0,
+            (board.rows - 1) as f64 * board.square_size / 2.0,
+            0.0,
+        );
+
+        Isometry3::from_parts(Translation3::from(offset - rotation * center), rotation)
+    }
+
+
+    #[test]
+    fn test_calibrate_intrinsics() {
+        let board = Checkerboard::default();
+        let object_points = board.object_points();
+
+        let camera = CameraIntrinsics {
+            fx: 1210.0,
+            fy: 1195.0,
+            cx: 968.0,
+            cy: 531.0,
+            distortion: [-0.21, 0.08, 0.0012, -0.0008, -0.01],
+        };
+
+        let poses = [
+            ((0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.6)),
+            ((0.4, 0.0, 0.1), Vector3::new(-0.15, 0.08, 0.7)),
+            ((-0.35, 0.2, -0.1), Vector3::new(0.18, -0.1, 0.65)),
+            ((0.1, -0.45, 0.3), Vector3::new(0.2, 0.12, 0.8)),
+            ((-0.2, 0.4, 1.2), Vector3::new(-0.2, -0.12, 0.75)),
+            ((0.3, 0.3, -0.4), Vector3::new(0.0, 0.15, 0.55)),
+            ((-0.4, -0.3, 0.2), Vector3::new(-0.1, -0.05, 0.9)),
+            ((0.25, -0.2, 3.0), Vector3::new(0.1, 0.0, 0.6)),
+        ];
+
+        // deterministic detection noise of up to a tenth of a pixel
+        let mut seed = 7u64;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) as f64 / (1u64 << 31) as f64 - 0.5) * 0.2
+        };
+
+        let mut views = poses.iter()
+            .map(|&(angles, offset)| {
+                let pose = board_pose(&board, angles, offset);
+
+                object_points.iter()
+                    .map(|&point| {
+                        let [u, v] = camera.project(&(pose * Point3::from(point)));
+                        [u + noise(), v + noise()]
+                    })
+                    .enumerate()
+                    .collect::<BoardCorners>()
+            })
+            .collect::<Vec<_>>();
+
+        // a mislabeled detection
+        let mut mislabeled = views[1].clone();
+        mislabeled.swap(0, 20);
+        mislabeled.swap(3, 40);
+        mislabeled.iter_mut().enumerate().for_each(|(i, (corner, _))| *corner = i);
+        views.push(mislabeled);
+
+        // a partially visible board, as detected on ChArUco boards
+        let partial = views[2].iter()
+            .filter(|(corner, _)| corner % board.columns < 5)
+            .copied()
+            .collect::<BoardCorners>();
+        views.push(partial);
+
+        let calibration = calibrate_intrinsics(&views, &object_points, (1920, 1080), 3.0).unwrap();
+        let intrinsics = &calibration.intrinsics;
+
+        assert!(!calibration.views.contains(&poses.len()), "the mislabeled view is an outlier");
+        assert!(calibration.views.contains(&(poses.len() + 1)), "the partial view is used");
+        assert!(calibration.rms_error < 0.1, "rms error {}", calibration.rms_error);
+
+        assert!((intrinsics.fx - camera.fx).abs() < 2.0, "fx {}", intrinsics.fx);
+        assert!((intrinsics.fy - camera.fy).abs() < 2.0, "fy {}", intrinsics.fy);
+        assert!((intrinsics.cx - camera.cx).abs() < 2.0, "cx {}", intrinsics.cx);
+        assert!((intrinsics.cy - camera.cy).abs() < 2.0, "cy {}", intrinsics.cy);
+        assert!((intrinsics.distortion[0] - camera.distortion[0]).abs() < 0.01, "k1 {}", intrinsics.distortion[0]);
+
+        let pixel = [1700.0, 950.0];
+        let [x, y] = intrinsics.undistort(pixel);
+        let reprojected = intrinsics.project(&Point3::new(x, y, 1.0));
+        assert!((reprojected[0] - pixel[0]).abs() < 1e-6 && (reprojected[1] - pixel[1]).abs() < 1e-6);
+    }
+}
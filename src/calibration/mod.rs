@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+
+use extrinsics::ExtrinsicConfig;
+
+pub mod charuco;
+pub mod checkerboard;
+pub mod color;
+pub mod extrinsics;
+pub mod intrinsics;
+pub mod undistort;
+
+
+/// the corners of the board found in one view, as indices into `Checkerboard::object_points` with their image points.
+/// a checkerboard view has every corner, a ChArUco view the corners next to its visible markers
+pub type BoardCorners = Vec<(usize, [f64; 2])>;
+
+
+/// a printed checkerboard, or a ChArUco board with ArUco markers in its light squares
+///
+/// views of a checkerboard only count when the whole board is visible (see `detect_checkerboard`), the markers of a
+/// ChArUco board identify its corners, so partially visible or occluded boards count too (see `detect_charuco`)
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Checkerboard {
+    /// inner corners along a row, one less than the number of squares
+    pub columns: usize,
+
+    /// inner corners along a column
+    pub rows: usize,
+
+    /// square edge length in meters
+    pub square_size: f64,
+
+    /// marker edge length in meters, which makes the board a ChArUco board of the original ArUco dictionary
+    pub marker_size: Option<f64>,
+}
+
+impl Default for Checkerboard {
+    fn default() -> Self {
+        Self {
+            columns: 9,
+            rows: 6,
+            square_size: 0.025,
+            marker_size: None,
+        }
+    }
+}
+
+impl Checkerboard {
+    /// the corners of the board found in `image`, with their indices into `object_points`
+    pub fn detect(&self, image: &GrayImage) -> Option<BoardCorners> {
+        match self.marker_size {
+            Some(_) => charuco::detect_charuco(image, self),
+            None => checkerboard::detect_checkerboard(image, self)
+                .map(|corners| corners.into_iter().enumerate().collect()),
+        }
+    }
+
+    pub fn corner_count(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    /// board coordinates of the inner corners in the order of `detect_checkerboard`, the board lies in the z = 0 plane
+    pub fn object_points(&self) -> Vec<[f64; 3]> {
+        (0..self.rows)
+            .flat_map(|row| (0..self.columns).map(move |column| [
+                column as f64 * self.square_size,
+                row as f64 * self.square_size,
+                0.0,
+            ]))
+            .collect()
+    }
+
+    /// whether the board looks different when rotated by 180°, which is required to label corners consistently across cameras
+    pub fn is_oriented(&self) -> bool {
+        (self.columns + self.rows) % 2 == 1
+    }
+}
+
+
+/// the object points of `corners` paired with their image points, corners outside of `object_points` are skipped
+pub fn corresponding_points(object_points: &[[f64; 3]], corners: &[(usize, [f64; 2])]) -> (Vec<[f64; 3]>, Vec<[f64; 2]>) {
+    corners.iter()
+        .filter_map(|&(corner, image_point)| Some((*object_points.get(corner)?, image_point)))
+        .unzip()
+}
+
+
+/// settings of the calibration node
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CalibrationConfig {
+    pub checkerboard: Checkerboard,
+
+    /// the detections of each stream are subsampled evenly to at most this many views
+    pub max_views: usize,
+
+    /// views are dropped when their reprojection error exceeds this multiple of the median view error
+    pub outlier_factor: f64,
+
+    /// the color checker is searched in at most this many evenly spaced frames of each stream by the
+    /// `color_calibration` node, 0 skips the color calibration
+    pub max_color_frames: usize,
+
+    pub extrinsics: ExtrinsicConfig,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            checkerboard: Checkerboard::default(),
+            max_views: 40,
+            outlier_factor: 3.0,
+            max_color_frames: 20,
+            extrinsics: ExtrinsicConfig::default(),
+        }
+    }
+}
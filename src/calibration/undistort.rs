@@ -0,0 +1,267 @@
+use anyhow::{anyhow, bail, Error};
+use bevy::prelude::*;
+use image::{ImageBuffer, Pixel};
+use serde::{Deserialize, Serialize};
+
+use super::intrinsics::CameraIntrinsics;
+
+
+/// border pixels sampled along each image edge to bound the undistorted image
+const EDGE_SAMPLES: usize = 32;
+
+
+/// settings of the undistortion nodes
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UndistortionConfig {
+    /// 0 crops the undistorted frames to valid pixels, 1 keeps every source pixel with black borders, values in
+    /// between interpolate the two
+    pub alpha: f64,
+}
+
+impl Default for UndistortionConfig {
+    fn default() -> Self {
+        Self {
+            alpha: 0.0,
+        }
+    }
+}
+
+
+/// distortion free intrinsics of the undistorted frames, which keep the size of the source frames
+///
+/// like opencv's `getOptimalNewCameraMatrix`, `alpha` blends between the largest rectangle of valid pixels and the
+/// bounds of every undistorted source pixel
+pub fn undistorted_intrinsics(intrinsics: &CameraIntrinsics, (width, height): (u32, u32), alpha: f64) -> CameraIntrinsics {
+    let right = (width - 1) as f64;
+    let bottom = (height - 1) as f64;
+
+    let edge = |from: [f64; 2], to: [f64; 2]| (0..EDGE_SAMPLES)
+        .map(move |i| {
+            let t = i as f64 / (EDGE_SAMPLES - 1) as f64;
+            intrinsics.undistort([
+                from[0] + (to[0] - from[0]) * t,
+                from[1] + (to[1] - from[1]) * t,
+            ])
+        });
+
+    let left_edge = edge([0.0, 0.0], [0.0, bottom]).collect::<Vec<_>>();
+    let right_edge = edge([right, 0.0], [right, bottom]).collect::<Vec<_>>();
+    let top_edge = edge([0.0, 0.0], [right, 0.0]).collect::<Vec<_>>();
+    let bottom_edge = edge([0.0, bottom], [right, bottom]).collect::<Vec<_>>();
+
+    let min = |points: &[[f64; 2]], axis: usize| points.iter().map(|point| point[axis]).fold(f64::INFINITY, f64::min);
+    let max = |points: &[[f64; 2]], axis: usize| points.iter().map(|point| point[axis]).fold(f64::NEG_INFINITY, f64::max);
+
+    // normalized bounds as `[left, top, right, bottom]`
+    let inner = [
+        max(&left_edge, 0),
+        max(&top_edge, 1),
+        min(&right_edge, 0),
+        min(&bottom_edge, 1),
+    ];
+
+    let border = [left_edge, right_edge, top_edge, bottom_edge].concat();
+    let outer = [
+        min(&border, 0),
+        min(&border, 1),
+        max(&border, 0),
+        max(&border, 1),
+    ];
+
+    let fit = |[left, top, right_bound, bottom_bound]: [f64; 4]| {
+        let fx = right / (right_bound - left);
+        let fy = bottom / (bottom_bound - top);
+
+        [fx, fy, -fx * left, -fy * top]
+    };
+
+    let inner = fit(inner);
+    let outer = fit(outer);
+    let alpha = alpha.clamp(0.0, 1.0);
+    let [fx, fy, cx, cy] = std::array::from_fn(|i| inner[i] + (outer[i] - inner[i]) * alpha);
+
+    CameraIntrinsics {
+        fx,
+        fy,
+        cx,
+        cy,
+        distortion: [0.0; 5],
+    }
+}
+
+
+/// source pixel of every pixel of an undistorted frame
+#[derive(Debug, Clone)]
+pub struct UndistortMap {
+    pub width: u32,
+    pub height: u32,
+
+    /// row-major source coordinates
+    coordinates: Vec<[f32; 2]>,
+}
+
+impl UndistortMap {
+    pub fn new(intrinsics: &CameraIntrinsics, undistorted: &CameraIntrinsics, (width, height): (u32, u32)) -> Self {
+        let coordinates = (0..height)
+            .flat_map(|v| (0..width).map(move |u| (u, v)))
+            .map(|(u, v)| {
+                let [x, y] = intrinsics.distort([
+                    (u as f64 - undistorted.cx) / undistorted.fx,
+                    (v as f64 - undistorted.cy) / undistorted.fy,
+                ]);
+
+                [
+                    (intrinsics.fx * x + intrinsics.cx) as f32,
+                    (intrinsics.fy * y + intrinsics.cy) as f32,
+                ]
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            coordinates,
+        }
+    }
+
+    /// bilinear resampling of a frame of the calibrated size, pixels mapped outside of the source frame are zero
+    pub fn remap<P: Pixel<Subpixel = u8>>(&self, image: &ImageBuffer<P, Vec<u8>>) -> Result<ImageBuffer<P, Vec<u8>>, Error> {
+        if image.dimensions() != (self.width, self.height) {
+            bail!(
+                "the {}x{} frame does not match the {}x{} calibration",
+                image.width(),
+                image.height(),
+                self.width,
+                self.height,
+            );
+        }
+
+        let channels = P::CHANNEL_COUNT as usize;
+        let source = image.as_raw();
+        let width = self.width as usize;
+        let height = self.height as usize;
+
+        let mut output = vec![0u8; source.len()];
+        for (pixel, &[x, y]) in output.chunks_exact_mut(channels).zip(&self.coordinates) {
+            // the outer half of the border pixels is sampled from the border
+            if !(-0.5..=width as f32 - 0.5).contains(&x) || !(-0.5..=height as f32 - 0.5).contains(&y) {
+                continue;
+            }
+            let x = x.clamp(0.0, (width - 1) as f32);
+            let y = y.clamp(0.0, (height - 1) as f32);
+
+            let x0 = (x.floor() as usize).min(width - 1);
+            let y0 = (y.floor() as usize).min(height - 1);
+            let x1 = (x0 + 1).min(width - 1);
+            let y1 = (y0 + 1).min(height - 1);
+            let tx = x - x0 as f32;
+            let ty = y - y0 as f32;
+
+            for (channel, value) in pixel.iter_mut().enumerate() {
+                let sample = |x: usize, y: usize| source[(y * width + x) * channels + channel] as f32;
+
+                let top = sample(x0, y0) * (1.0 - tx) + sample(x1, y0) * tx;
+                let bottom = sample(x0, y1) * (1.0 - tx) + sample(x1, y1) * tx;
+
+                *value = (top * (1.0 - ty) + bottom * ty).round() as u8;
+            }
+        }
+
+        ImageBuffer::from_raw(self.width, self.height, output)
+            .ok_or_else(|| anyhow!("invalid undistorted frame"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{GrayImage, Luma};
+    use nalgebra::Point3;
+
+
+    fn camera() -> CameraIntrinsics {
+        CameraIntrinsics {
+            fx: 610.0,
+            fy: 600.0,
+            cx: 322.0,
+            cy: 236.0,
+            distortion: [-0.28, 0.09, 0.001, -0.0005, -0.01],
+        }
+    }
+
+
+    #[test]
+    fn test_undistorted_intrinsics_alpha() {
+        let camera = camera();
+        let size = (640, 480);
+
+        let cropped = undistorted_intrinsics(&camera, size, 0.0);
+        let full = undistorted_intrinsics(&camera, size, 1.0);
+
+        // barrel distortion, the cropped frames zoom in
+        assert!(cropped.fx > full.fx && cropped.fy > full.fy);
+
+        let cropped_map = UndistortMap::new(&camera, &cropped, size);
+        let valid = cropped_map.coordinates.iter()
+            .all(|&[x, y]| (-0.5..=640.5).contains(&x) && (-0.5..=480.5).contains(&y));
+        assert!(valid, "alpha 0 samples outside of the source frame");
+
+        // every source border pixel is inside the full undistorted frame
+        for u in (0..640).step_by(16) {
+            for v in [0.0, 479.0] {
+                let [x, y] = camera.undistort([u as f64, v]);
+                let (x, y) = (full.fx * x + full.cx, full.fy * y + full.cy);
+                assert!((-0.5..=639.5).contains(&x) && (-0.5..=479.5).contains(&y), "({}, {}) is cropped", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_remap_moves_points_to_pinhole_projection() {
+        let camera = camera();
+        let size = (640, 480);
+        let undistorted = undistorted_intrinsics(&camera, size, 0.0);
+        let map = UndistortMap::new(&camera, &undistorted, size);
+
+        let points = [
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::new(0.35, 0.2, 1.0),
+            Point3::new(-0.38, -0.25, 1.0),
+            Point3::new(0.3, -0.28, 1.0),
+        ];
+
+        for point in points {
+            // gaussian blob at the distorted projection
+            let [u, v] = camera.project(&point);
+            let image = GrayImage::from_fn(640, 480, |x, y| {
+                let distance = (x as f64 - u).powi(2) + (y as f64 - v).powi(2);
+                Luma([(255.0 * (-distance / 8.0).exp()).round() as u8])
+            });
+
+            let remapped = map.remap(&image).unwrap();
+
+            let (mut sum, mut sum_x, mut sum_y) = (0.0, 0.0, 0.0);
+            for (x, y, pixel) in remapped.enumerate_pixels() {
+                let weight = pixel.0[0] as f64;
+                sum += weight;
+                sum_x += weight * x as f64;
+                sum_y += weight * y as f64;
+            }
+
+            let expected = undistorted.project(&point);
+            let error = ((sum_x / sum - expected[0]).powi(2) + (sum_y / sum - expected[1]).powi(2)).sqrt();
+            assert!(error < 0.25, "blob at ({}, {}) is {} px off", expected[0], expected[1], error);
+        }
+    }
+
+    #[test]
+    fn test_remap_rejects_other_sizes() {
+        let camera = camera();
+        let map = UndistortMap::new(&camera, &undistorted_intrinsics(&camera, (640, 480), 0.0), (640, 480));
+
+        assert!(map.remap(&GrayImage::new(512, 512)).is_err());
+    }
+}
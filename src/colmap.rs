@@ -0,0 +1,225 @@
+use std::{
+    fmt::Write as _,
+    path::Path,
+};
+
+use anyhow::Error;
+use bevy::prelude::*;
+use nalgebra::Isometry3;
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::{
+    extrinsics::CameraExtrinsics,
+    intrinsics::CameraIntrinsics,
+};
+
+
+/// settings of the COLMAP export node
+#[derive(Debug, Clone, Default, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColmapConfig {
+    /// frame index to export, every frame index gets its own project if `None`
+    pub frame: Option<usize>,
+}
+
+
+/// a camera of a COLMAP text model, `PINHOLE` without distortion (e.g. undistorted frames), `OPENCV` with radial and
+/// tangential distortion and `FULL_OPENCV` if the distortion has a third radial coefficient
+#[derive(Debug, Clone)]
+pub struct ColmapCamera {
+    pub id: usize,
+    pub width: u32,
+    pub height: u32,
+    pub intrinsics: CameraIntrinsics,
+}
+
+impl ColmapCamera {
+    fn line(&self) -> String {
+        let CameraIntrinsics { fx, fy, cx, cy, distortion } = &self.intrinsics;
+        let [k1, k2, p1, p2, k3] = distortion;
+
+        if distortion.iter().all(|&coefficient| coefficient == 0.0) {
+            format!("{} PINHOLE {} {} {} {} {} {}", self.id, self.width, self.height, fx, fy, cx, cy)
+        } else if *k3 == 0.0 {
+            format!(
+                "{} OPENCV {} {} {} {} {} {} {} {} {} {}",
+                self.id, self.width, self.height, fx, fy, cx, cy, k1, k2, p1, p2,
+            )
+        } else {
+            format!(
+                "{} FULL_OPENCV {} {} {} {} {} {} {} {} {} {} {} 0 0 0",
+                self.id, self.width, self.height, fx, fy, cx, cy, k1, k2, p1, p2, k3,
+            )
+        }
+    }
+}
+
+
+/// a posed image of a COLMAP text model, `name` is relative to the `images` directory of the project
+#[derive(Debug, Clone)]
+pub struct ColmapImage {
+    pub id: usize,
+    pub camera_id: usize,
+    pub name: String,
+    pub extrinsics: CameraExtrinsics,
+}
+
+impl ColmapImage {
+    fn line(&self) -> String {
+        // world to camera, like the extrinsics
+        let Isometry3 { rotation, translation } = self.extrinsics.isometry();
+
+        format!(
+            "{} {} {} {} {} {} {} {} {} {}",
+            self.id,
+            rotation.w,
+            rotation.i,
+            rotation.j,
+            rotation.k,
+            translation.x,
+            translation.y,
+            translation.z,
+            self.camera_id,
+            self.name,
+        )
+    }
+}
+
+
+/// writes `cameras.txt`, `images.txt` and an empty `points3D.txt` to `directory`, typically `<project>/sparse/0`
+///
+/// the images have no 2d points, run `colmap point_triangulator` on the model to add them with the known poses
+pub fn write_text_model(
+    directory: impl AsRef<Path>,
+    cameras: &[ColmapCamera],
+    images: &[ColmapImage],
+) -> Result<(), Error> {
+    let directory = directory.as_ref();
+    std::fs::create_dir_all(directory)?;
+
+    let mut cameras_txt = String::new();
+    writeln!(cameras_txt, "# Camera list with one line of data per camera:")?;
+    writeln!(cameras_txt, "#   CAMERA_ID, MODEL, WIDTH, HEIGHT, PARAMS[]")?;
+    writeln!(cameras_txt, "# Number of cameras: {}", cameras.len())?;
+    for camera in cameras {
+        writeln!(cameras_txt, "{}", camera.line())?;
+    }
+
+    let mut images_txt = String::new();
+    writeln!(images_txt, "# Image list with two lines of data per image:")?;
+    writeln!(images_txt, "#   IMAGE_ID, QW, QX, QY, QZ, TX, TY, TZ, CAMERA_ID, NAME")?;
+    writeln!(images_txt, "#   POINTS2D[] as (X, Y, POINT3D_ID)")?;
+    writeln!(images_txt, "# Number of images: {}, mean observations per image: 0", images.len())?;
+    for image in images {
+        writeln!(images_txt, "{}", image.line())?;
+        writeln!(images_txt)?;
+    }
+
+    let mut points_txt = String::new();
+    writeln!(points_txt, "# 3D point list with one line of data per point:")?;
+    writeln!(points_txt, "#   POINT3D_ID, X, Y, Z, R, G, B, ERROR, TRACK[] as (IMAGE_ID, POINT2D_IDX)")?;
+    writeln!(points_txt, "# Number of points: 0, mean track length: 0")?;
+
+    std::fs::write(directory.join("cameras.txt"), cameras_txt)?;
+    std::fs::write(directory.join("images.txt"), images_txt)?;
+    std::fs::write(directory.join("points3D.txt"), points_txt)?;
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nalgebra::{Point3, Quaternion, UnitQuaternion, Vector3};
+
+
+    #[test]
+    fn test_write_text_model() {
+        let directory = std::env::temp_dir().join(format!("bevy_light_field_colmap_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let extrinsics = CameraExtrinsics {
+            rotation: [0.1, -0.4, 0.05],
+            translation: [0.3, -0.1, 1.2],
+            ..Default::default()
+        };
+
+        let cameras = [
+            ColmapCamera {
+                id: 1,
+                width: 1920,
+                height: 1080,
+                intrinsics: CameraIntrinsics {
+                    fx: 1400.0,
+                    fy: 1390.0,
+                    cx: 960.0,
+                    cy: 540.0,
+                    distortion: [0.0; 5],
+                },
+            },
+            ColmapCamera {
+                id: 2,
+                width: 1920,
+                height: 1080,
+                intrinsics: CameraIntrinsics {
+                    fx: 1400.0,
+                    fy: 1390.0,
+                    cx: 960.0,
+                    cy: 540.0,
+                    distortion: [-0.2, 0.05, 0.001, 0.002, 0.0],
+                },
+            },
+            ColmapCamera {
+                id: 3,
+                width: 1920,
+                height: 1080,
+                intrinsics: CameraIntrinsics {
+                    fx: 1400.0,
+                    fy: 1390.0,
+                    cx: 960.0,
+                    cy: 540.0,
+                    distortion: [-0.2, 0.05, 0.001, 0.002, 0.01],
+                },
+            },
+        ];
+        let images = [
+            ColmapImage {
+                id: 1,
+                camera_id: 1,
+                name: "0.png".to_string(),
+                extrinsics: extrinsics.clone(),
+            },
+        ];
+
+        write_text_model(&directory, &cameras, &images).unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(directory.join(name)).unwrap();
+        let data_lines = |text: &str| text.lines().filter(|line| !line.starts_with('#')).map(str::to_string).collect::<Vec<_>>();
+
+        let cameras_txt = data_lines(&read("cameras.txt"));
+        assert_eq!(cameras_txt[0], "1 PINHOLE 1920 1080 1400 1390 960 540");
+        assert_eq!(cameras_txt[1], "2 OPENCV 1920 1080 1400 1390 960 540 -0.2 0.05 0.001 0.002");
+        assert_eq!(cameras_txt[2], "3 FULL_OPENCV 1920 1080 1400 1390 960 540 -0.2 0.05 0.001 0.002 0.01 0 0 0");
+
+        let images_txt = data_lines(&read("images.txt"));
+        assert_eq!(images_txt.len(), 2, "every image is followed by a line of 2d points");
+        assert!(images_txt[1].is_empty());
+
+        let values = images_txt[0].split(' ').collect::<Vec<_>>();
+        assert_eq!(values[8..], ["1", "0.png"]);
+
+        // the pose transforms world points like the extrinsics
+        let number = |i: usize| values[i].parse::<f64>().unwrap();
+        let rotation = UnitQuaternion::from_quaternion(Quaternion::new(number(1), number(2), number(3), number(4)));
+        let point = Point3::new(0.2, -0.3, 2.0);
+        let expected = extrinsics.isometry() * point;
+        let actual = rotation * point + Vector3::new(number(5), number(6), number(7));
+        assert!((expected - actual).norm() < 1e-9);
+
+        assert!(data_lines(&read("points3D.txt")).is_empty());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
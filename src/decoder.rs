@@ -0,0 +1,632 @@
+use anyhow::{anyhow, bail, Error};
+use bevy::prelude::*;
+use openh264::{
+    decoder::Decoder,
+    nal_units,
+    OpenH264API,
+};
+use serde::{Deserialize, Serialize};
+
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    H265,
+}
+
+impl VideoCodec {
+    pub fn from_encoding_name(encoding_name: &str) -> Option<Self> {
+        match encoding_name.to_uppercase().as_str() {
+            "H264" => Some(VideoCodec::H264),
+            "H265" | "HEVC" => Some(VideoCodec::H265),
+            _ => None,
+        }
+    }
+
+    pub fn from_rfc6381_codec(codec: &str) -> Option<Self> {
+        match codec.get(..4) {
+            Some("avc1") | Some("avc3") => Some(VideoCodec::H264),
+            Some("hvc1") | Some("hev1") => Some(VideoCodec::H265),
+            _ => None,
+        }
+    }
+}
+
+
+/// receives the `pts` of the access unit, the dimensions and an rgba8 writer of each decoded picture
+pub type FrameCallback<'a> = dyn FnMut(i64, (usize, usize), &dyn Fn(&mut [u8])) + 'a;
+
+/// decodes length-converted (annex b) access units into rgba8 pictures
+pub trait VideoDecoder: Send {
+    /// calls `on_frame` for each decoded picture, which may belong to an earlier access unit if the decoder reorders
+    fn decode(
+        &mut self,
+        access_unit: &[u8],
+        pts: i64,
+        on_frame: &mut FrameCallback,
+    ) -> Result<(), Error>;
+
+    /// emits the pictures still held back by the decoder, e.g. at the end of a recording
+    fn flush(
+        &mut self,
+        _on_frame: &mut FrameCallback,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub fn create_decoder(codec: VideoCodec) -> Result<Box<dyn VideoDecoder>, Error> {
+    match codec {
+        VideoCodec::H264 => Ok(Box::new(H264Decoder::new()?)),
+        #[cfg(feature = "hevc")]
+        VideoCodec::H265 => Ok(Box::new(HevcDecoder::new()?)),
+        #[cfg(not(feature = "hevc"))]
+        VideoCodec::H265 => bail!("h.265 decoding requires building with the `hevc` feature (libavcodec)"),
+    }
+}
+
+
+pub struct H264Decoder {
+    decoder: Decoder,
+}
+
+impl H264Decoder {
+    pub fn new() -> Result<Self, Error> {
+        let api = OpenH264API::from_source();
+        let decoder = Decoder::new(api)?;
+
+        Ok(Self {
+            decoder,
+        })
+    }
+}
+
+impl VideoDecoder for H264Decoder {
+    // openh264 decodes the constrained baseline profile, which has no frame reordering
+    fn decode(
+        &mut self,
+        access_unit: &[u8],
+        pts: i64,
+        on_frame: &mut FrameCallback,
+    ) -> Result<(), Error> {
+        for packet in nal_units(access_unit) {
+            if let Some(frame) = self.decoder.decode(packet)? {
+                let image_size = frame.dimension_rgb();
+                on_frame(pts, image_size, &|data| frame.write_rgba8(data));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/// decodes h.265 in process with libavcodec, as openh264 does not support it (requires the `hevc` feature)
+///
+/// pictures keep the `pts` of their access unit, libavcodec may emit them several access units later in presentation
+/// order. access units before the first SPS are skipped, as they can't be decoded
+#[cfg(feature = "hevc")]
+pub struct HevcDecoder {
+    decoder: ffmpeg_next::decoder::Video,
+    scaler: Option<(ffmpeg_next::software::scaling::Context, (u32, u32, ffmpeg_next::format::Pixel))>,
+    started: bool,
+}
+
+#[cfg(feature = "hevc")]
+impl HevcDecoder {
+    pub fn new() -> Result<Self, Error> {
+        ffmpeg_next::init()?;
+
+        let codec = ffmpeg_next::decoder::find(ffmpeg_next::codec::Id::HEVC)
+            .ok_or_else(|| anyhow!("libavcodec was built without an h.265 decoder"))?;
+        let decoder = ffmpeg_next::codec::Context::new_with_codec(codec)
+            .decoder()
+            .video()?;
+
+        Ok(Self {
+            decoder,
+            scaler: None,
+            started: false,
+        })
+    }
+
+    /// emits the pictures libavcodec has finished
+    fn receive(&mut self, on_frame: &mut FrameCallback) -> Result<(), Error> {
+        use ffmpeg_next::{format::Pixel, frame, software::scaling};
+
+        let mut picture = frame::Video::empty();
+        while self.decoder.receive_frame(&mut picture).is_ok() {
+            let (width, height, format) = (picture.width(), picture.height(), picture.format());
+
+            if self.scaler.as_ref().map(|(_, key)| *key) != Some((width, height, format)) {
+                let scaler = scaling::Context::get(
+                    format,
+                    width,
+                    height,
+                    Pixel::RGBA,
+                    width,
+                    height,
+                    scaling::Flags::BILINEAR,
+                )?;
+                self.scaler = Some((scaler, (width, height, format)));
+            }
+
+            let mut rgba = frame::Video::empty();
+            self.scaler.as_mut().unwrap().0.run(&picture, &mut rgba)?;
+
+            let row_size = width as usize * 4;
+            let stride = rgba.stride(0);
+            let rows = rgba.data(0);
+            on_frame(
+                picture.pts().unwrap_or_default(),
+                (width as usize, height as usize),
+                &|data| {
+                    for (y, row) in data.chunks_exact_mut(row_size).enumerate() {
+                        row.copy_from_slice(&rows[y * stride..y * stride + row_size]);
+                    }
+                },
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "hevc")]
+impl VideoDecoder for HevcDecoder {
+    fn decode(
+        &mut self,
+        access_unit: &[u8],
+        pts: i64,
+        on_frame: &mut FrameCallback,
+    ) -> Result<(), Error> {
+        self.started |= hevc_sps_dimensions(access_unit)?.is_some();
+        if !self.started {
+            return Ok(());
+        }
+
+        let mut packet = ffmpeg_next::Packet::copy(access_unit);
+        packet.set_pts(Some(pts));
+        self.decoder.send_packet(&packet)?;
+
+        self.receive(on_frame)
+    }
+
+    fn flush(
+        &mut self,
+        on_frame: &mut FrameCallback,
+    ) -> Result<(), Error> {
+        if !self.started {
+            return Ok(());
+        }
+
+        self.decoder.send_eof()?;
+        self.receive(on_frame)?;
+
+        // the decoder accepts access units again, e.g. of the next loop of a replayed recording
+        self.decoder.flush();
+        self.started = false;
+
+        Ok(())
+    }
+}
+
+
+/// the NAL units of an annex b byte stream, without their start codes
+pub fn annex_b_nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i..i + 3] == [0, 0, 1] {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    let ends = starts.iter()
+        .skip(1)
+        .map(|&start| start - 3)
+        .chain(std::iter::once(data.len()))
+        .collect::<Vec<_>>();
+
+    starts.into_iter()
+        .zip(ends)
+        .map(move |(start, end)| {
+            // the zero byte of a 4-byte start code belongs to the next NAL unit
+            let end = data[start..end].iter().rposition(|&byte| byte != 0).map_or(start, |last| start + last + 1);
+            &data[start..end]
+        })
+}
+
+/// the fields of an h.265 SPS which describe the stream in a decoder configuration record (`hvcC`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HevcSps {
+    /// cropped picture size
+    pub width: usize,
+    pub height: usize,
+
+    pub max_sub_layers: u8,
+    pub temporal_id_nesting: bool,
+
+    /// general profile space, tier, profile, compatibility flags, constraint flags and level, as coded
+    pub general_profile: [u8; 12],
+
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+}
+
+impl HevcSps {
+    /// parses an SPS NAL unit, including its 2-byte header
+    pub fn parse(nal: &[u8]) -> Result<Self, Error> {
+        if nal.len() < 2 {
+            bail!("truncated h.265 SPS");
+        }
+
+        let rbsp = remove_emulation_prevention(&nal[2..]);
+        let mut bits = BitReader::new(&rbsp);
+
+        bits.skip(4)?; // sps_video_parameter_set_id
+        let max_sub_layers_minus1 = bits.bits(3)? as usize;
+        let temporal_id_nesting = bits.flag()?;
+
+        // profile_tier_level: the general profile (88 bits) and level, then the optional sub-layer profiles and levels
+        bits.skip(88 + 8)?;
+        let general_profile = rbsp[1..13].try_into()?;
+
+        let mut sub_layers = vec![];
+        for _ in 0..max_sub_layers_minus1 {
+            sub_layers.push((bits.flag()?, bits.flag()?));
+        }
+        if max_sub_layers_minus1 > 0 {
+            bits.skip(2 * (8 - max_sub_layers_minus1))?;
+        }
+        for (profile_present, level_present) in sub_layers {
+            if profile_present {
+                bits.skip(88)?;
+            }
+            if level_present {
+                bits.skip(8)?;
+            }
+        }
+
+        bits.exp_golomb()?; // sps_seq_parameter_set_id
+        let chroma_format_idc = bits.exp_golomb()?;
+        let separate_colour_plane = chroma_format_idc == 3 && bits.flag()?;
+        let width = bits.exp_golomb()? as usize;
+        let height = bits.exp_golomb()? as usize;
+
+        let (crop_width, crop_height) = if bits.flag()? {
+            let left = bits.exp_golomb()? as usize;
+            let right = bits.exp_golomb()? as usize;
+            let top = bits.exp_golomb()? as usize;
+            let bottom = bits.exp_golomb()? as usize;
+
+            // the offsets are in chroma samples
+            let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc };
+            let (sub_width, sub_height) = match chroma_array_type {
+                1 => (2, 2),
+                2 => (2, 1),
+                _ => (1, 1),
+            };
+
+            (sub_width * (left + right), sub_height * (top + bottom))
+        } else {
+            (0, 0)
+        };
+
+        let bit_depth_luma = bits.exp_golomb()? + 8;
+        let bit_depth_chroma = bits.exp_golomb()? + 8;
+
+        let (width, height) = match (width.checked_sub(crop_width), height.checked_sub(crop_height)) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+            _ => bail!("invalid h.265 SPS picture size {}x{}", width, height),
+        };
+
+        if bit_depth_luma > 16 || bit_depth_chroma > 16 {
+            bail!("invalid h.265 SPS bit depth {}/{}", bit_depth_luma, bit_depth_chroma);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            max_sub_layers: max_sub_layers_minus1 as u8 + 1,
+            temporal_id_nesting,
+            general_profile,
+            chroma_format_idc: chroma_format_idc as u8,
+            bit_depth_luma: bit_depth_luma as u8,
+            bit_depth_chroma: bit_depth_chroma as u8,
+        })
+    }
+}
+
+/// the NAL unit type of an h.265 NAL unit header
+pub fn hevc_nal_type(nal: &[u8]) -> Option<u8> {
+    nal.first().map(|header| (header >> 1) & 0x3f)
+}
+
+pub const HEVC_VPS_NUT: u8 = 32;
+pub const HEVC_SPS_NUT: u8 = 33;
+pub const HEVC_PPS_NUT: u8 = 34;
+
+/// the cropped picture size of the first h.265 SPS of an annex b access unit
+pub fn hevc_sps_dimensions(access_unit: &[u8]) -> Result<Option<(usize, usize)>, Error> {
+    annex_b_nal_units(access_unit)
+        .find(|nal| nal.len() > 2 && hevc_nal_type(nal) == Some(HEVC_SPS_NUT))
+        .map(|sps| HevcSps::parse(sps).map(|sps| (sps.width, sps.height)))
+        .transpose()
+}
+
+/// the RBSP of a NAL unit payload, without the emulation prevention bytes of `00 00 03` sequences
+fn remove_emulation_prevention(payload: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(payload.len());
+    let mut zeros = 0;
+
+    for &byte in payload {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+
+    rbsp
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+        }
+    }
+
+    fn flag(&mut self) -> Result<bool, Error> {
+        let byte = self.data.get(self.position / 8).ok_or_else(|| anyhow!("truncated h.265 SPS"))?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+
+        Ok(bit == 1)
+    }
+
+    fn bits(&mut self, count: usize) -> Result<u32, Error> {
+        (0..count).try_fold(0, |value, _| Ok((value << 1) | self.flag()? as u32))
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), Error> {
+        if self.position + count > self.data.len() * 8 {
+            bail!("truncated h.265 SPS");
+        }
+        self.position += count;
+
+        Ok(())
+    }
+
+    fn exp_golomb(&mut self) -> Result<u32, Error> {
+        let mut leading_zeros = 0;
+        while !self.flag()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                bail!("invalid exp-golomb code in h.265 SPS");
+            }
+        }
+
+        Ok(((1u64 << leading_zeros) - 1 + self.bits(leading_zeros)? as u64) as u32)
+    }
+}
+
+
+/// replaces the 4-byte length prefix of each NAL (avcC/hvcC framing) with the annex b start code, in place
+pub fn convert_annex_b(data: &mut [u8]) -> Result<(), Error> {
+    let mut i = 0;
+    while i + 3 < data.len() {
+        // Replace each NAL's length with the Annex B start code b"\x00\x00\x00\x01".
+        let len = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+        data[i] = 0;
+        data[i + 1] = 0;
+        data[i + 2] = 0;
+        data[i + 3] = 1;
+        i += 4 + len;
+        if i > data.len() {
+            bail!("partial NAL body");
+        }
+    }
+
+    if i < data.len() {
+        bail!("partial NAL length");
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[derive(Default)]
+    struct BitWriter {
+        bits: Vec<bool>,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u64, count: usize) {
+            self.bits.extend((0..count).rev().map(|i| (value >> i) & 1 == 1));
+        }
+
+        fn exp_golomb(&mut self, value: u32) {
+            let code = value as u64 + 1;
+            let length = 64 - code.leading_zeros() as usize;
+            self.bits(0, length - 1);
+            self.bits(code, length);
+        }
+
+        /// the NAL unit payload with rbsp trailing bits and emulation prevention
+        fn payload(mut self) -> Vec<u8> {
+            self.bits.push(true);
+            while self.bits.len() % 8 != 0 {
+                self.bits.push(false);
+            }
+
+            let rbsp = self.bits
+                .chunks(8)
+                .map(|byte| byte.iter().fold(0u8, |value, &bit| (value << 1) | bit as u8));
+
+            let mut payload = vec![];
+            let mut zeros = 0;
+            for byte in rbsp {
+                if zeros == 2 && byte <= 3 {
+                    payload.push(3);
+                    zeros = 0;
+                }
+                zeros = if byte == 0 { zeros + 1 } else { 0 };
+                payload.push(byte);
+            }
+            payload
+        }
+    }
+
+    fn hevc_sps(width: u32, height: u32, crop_bottom: u32, sub_layers: &[(bool, bool)]) -> Vec<u8> {
+        let mut sps = BitWriter::default();
+        sps.bits(0, 4); // sps_video_parameter_set_id
+        sps.bits(sub_layers.len() as u64, 3); // sps_max_sub_layers_minus1
+        sps.bits(1, 1); // sps_temporal_id_nesting_flag
+
+        // main profile, level 4
+        let general_profile = |sps: &mut BitWriter| {
+            sps.bits(1, 8); // profile space, tier, profile idc
+            sps.bits(0x6000_0000, 32); // profile compatibility flags
+            sps.bits(0b1001, 4); // progressive, interlaced, non-packed, frame-only
+            sps.bits(0, 44);
+        };
+        general_profile(&mut sps);
+        sps.bits(120, 8); // general_level_idc
+
+        for &(profile_present, level_present) in sub_layers {
+            sps.bits(profile_present as u64, 1);
+            sps.bits(level_present as u64, 1);
+        }
+        if !sub_layers.is_empty() {
+            sps.bits(0, 2 * (8 - sub_layers.len()));
+        }
+        for &(profile_present, level_present) in sub_layers {
+            if profile_present {
+                general_profile(&mut sps);
+            }
+            if level_present {
+                sps.bits(90, 8);
+            }
+        }
+
+        sps.exp_golomb(0); // sps_seq_parameter_set_id
+        sps.exp_golomb(1); // chroma_format_idc 4:2:0
+        sps.exp_golomb(width);
+        sps.exp_golomb(height);
+        if crop_bottom > 0 {
+            sps.bits(1, 1);
+            sps.exp_golomb(0);
+            sps.exp_golomb(0);
+            sps.exp_golomb(0);
+            sps.exp_golomb(crop_bottom);
+        } else {
+            sps.bits(0, 1);
+        }
+        sps.exp_golomb(0); // bit_depth_luma_minus8
+
+        // nal_unit_type 33
+        [&[0x42, 0x01][..], &sps.payload()].concat()
+    }
+
+
+    #[test]
+    fn test_hevc_sps_dimensions() {
+        let vps = [0x40, 0x01, 0x0c, 0x01];
+        let pps = [0x44, 0x01, 0xc1, 0x72];
+        let slice = [0x26, 0x01, 0xaf, 0x09];
+
+        // 1080p is coded as 1088 lines and cropped by 4 chroma lines
+        let sps = hevc_sps(1920, 1088, 4, &[]);
+        assert!(sps.windows(3).any(|window| window == [0, 0, 3]), "the SPS exercises emulation prevention");
+
+        let access_unit = [
+            &[0, 0, 0, 1][..], &vps,
+            &[0, 0, 0, 1], &sps,
+            &[0, 0, 1], &pps,
+            &[0, 0, 0, 1], &slice,
+        ].concat();
+        assert_eq!(hevc_sps_dimensions(&access_unit).unwrap(), Some((1920, 1080)));
+
+        let sub_layers = hevc_sps(1280, 720, 0, &[(true, true), (false, true)]);
+        assert_eq!(hevc_sps_dimensions(&[&[0, 0, 1][..], &sub_layers].concat()).unwrap(), Some((1280, 720)));
+
+        // a 1080p main profile SPS of an x265 stream
+        let x265_sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x78,
+            0xa0, 0x03, 0xc0, 0x80, 0x10, 0xe5, 0x96, 0x66, 0x69, 0x24, 0xca, 0xe0, 0x10, 0x00, 0x00, 0x03, 0x00, 0x10,
+            0x00, 0x00, 0x03, 0x01, 0xe0, 0x80,
+        ];
+        assert_eq!(hevc_sps_dimensions(&[&[0, 0, 0, 1][..], &x265_sps].concat()).unwrap(), Some((1920, 1080)));
+
+        let x265_sps = HevcSps::parse(&x265_sps).unwrap();
+        assert_eq!(x265_sps.general_profile, [0x01, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 0x78], "main profile, level 4");
+        assert_eq!((x265_sps.max_sub_layers, x265_sps.temporal_id_nesting), (1, true));
+        assert_eq!((x265_sps.chroma_format_idc, x265_sps.bit_depth_luma, x265_sps.bit_depth_chroma), (1, 8, 8));
+
+        assert_eq!(hevc_sps_dimensions(&[&[0, 0, 0, 1][..], &slice].concat()).unwrap(), None);
+        assert!(hevc_sps_dimensions(&[&[0, 0, 0, 1][..], &sps[..8]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_annex_b_nal_units() {
+        let data = [0, 0, 0, 1, 0x67, 0x42, 0, 0, 1, 0x68, 0xce, 0, 0, 0, 1, 0x65, 0x88];
+
+        let nal_units = annex_b_nal_units(&data).collect::<Vec<_>>();
+
+        assert_eq!(nal_units, vec![&[0x67, 0x42][..], &[0x68, 0xce], &[0x65, 0x88]]);
+    }
+
+    #[test]
+    fn test_codec_selection() {
+        assert_eq!(VideoCodec::from_encoding_name("H264"), Some(VideoCodec::H264));
+        assert_eq!(VideoCodec::from_encoding_name("h265"), Some(VideoCodec::H265));
+        assert_eq!(VideoCodec::from_encoding_name("HEVC"), Some(VideoCodec::H265));
+        assert_eq!(VideoCodec::from_encoding_name("MP4V-ES"), None);
+
+        assert_eq!(VideoCodec::from_rfc6381_codec("avc1.64001F"), Some(VideoCodec::H264));
+        assert_eq!(VideoCodec::from_rfc6381_codec("hvc1.1.6.L120.90"), Some(VideoCodec::H265));
+        assert_eq!(VideoCodec::from_rfc6381_codec("mp4a.40.2"), None);
+    }
+
+    #[cfg(feature = "hevc")]
+    #[test]
+    fn test_h265_decoder_waits_for_sps() {
+        // access units before the first SPS are skipped instead of failing the decoder
+        let mut decoder = create_decoder(VideoCodec::H265).unwrap();
+        let slice = [0, 0, 0, 1, 0x26, 0x01, 0xaf, 0x09];
+
+        let mut frames = 0;
+        decoder.decode(&slice, 0, &mut |_, _, _| frames += 1).unwrap();
+        decoder.flush(&mut |_, _, _| frames += 1).unwrap();
+
+        assert_eq!(frames, 0);
+    }
+
+    #[cfg(not(feature = "hevc"))]
+    #[test]
+    fn test_h265_decoding_requires_the_hevc_feature() {
+        let error = create_decoder(VideoCodec::H265).err().expect("h.265 is not decodable without libavcodec");
+
+        assert!(error.to_string().contains("`hevc` feature"));
+    }
+}
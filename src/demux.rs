@@ -0,0 +1,899 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Error};
+
+use crate::{
+    decoder::VideoCodec,
+    mp4::{Mp4Metadata, MP4_UNIX_OFFSET_SECS},
+};
+
+
+/// a video sample description (`avc1`/`hvc1`) of a track
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleEntry {
+    pub fourcc: [u8; 4],
+    pub width: u16,
+    pub height: u16,
+
+    /// contents of the `avcC` or `hvcC` box
+    pub configuration: Vec<u8>,
+}
+
+impl SampleEntry {
+    pub fn codec(&self) -> Option<VideoCodec> {
+        match &self.fourcc {
+            b"avc1" | b"avc3" => Some(VideoCodec::H264),
+            b"hvc1" | b"hev1" => Some(VideoCodec::H265),
+            _ => None,
+        }
+    }
+
+    /// the SPS/PPS (and VPS for h.265) NAL units of the decoder configuration record
+    pub fn parameter_sets(&self) -> Result<Vec<Vec<u8>>, Error> {
+        let config = &self.configuration;
+        let mut parameter_sets = vec![];
+
+        match self.codec() {
+            Some(VideoCodec::H264) => {
+                let mut reader = ByteReader::new(config);
+                reader.skip(5)?;
+
+                let sps_count = reader.u8()? & 0x1f;
+                for _ in 0..sps_count {
+                    let len = reader.u16()? as usize;
+                    parameter_sets.push(reader.bytes(len)?.to_vec());
+                }
+
+                let pps_count = reader.u8()?;
+                for _ in 0..pps_count {
+                    let len = reader.u16()? as usize;
+                    parameter_sets.push(reader.bytes(len)?.to_vec());
+                }
+            },
+            Some(VideoCodec::H265) => {
+                let mut reader = ByteReader::new(config);
+                reader.skip(22)?;
+
+                let array_count = reader.u8()?;
+                for _ in 0..array_count {
+                    reader.skip(1)?; // array_completeness + nal_unit_type
+                    let nal_count = reader.u16()?;
+                    for _ in 0..nal_count {
+                        let len = reader.u16()? as usize;
+                        parameter_sets.push(reader.bytes(len)?.to_vec());
+                    }
+                }
+            },
+            None => bail!("unsupported sample entry {}", String::from_utf8_lossy(&self.fourcc)),
+        }
+
+        Ok(parameter_sets)
+    }
+
+    /// parameter sets with annex b start codes, to prefix the first access unit handed to a decoder
+    pub fn parameter_sets_annex_b(&self) -> Result<Vec<u8>, Error> {
+        Ok(
+            self.parameter_sets()?
+                .iter()
+                .flat_map(|nal| [&[0, 0, 0, 1][..], nal.as_slice()].concat())
+                .collect()
+        )
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mp4Sample {
+    /// absolute byte offset within the file
+    pub offset: u64,
+    pub size: u32,
+
+    /// in units of the track timescale
+    pub decode_time: u64,
+    pub duration: u32,
+    pub is_sync: bool,
+
+    /// 1-based index into `Mp4Track::sample_entries`
+    pub sample_description_index: u32,
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct Mp4Track {
+    pub track_id: u32,
+    pub handler: [u8; 4],
+    pub timescale: u32,
+    pub sample_entries: Vec<SampleEntry>,
+    pub samples: Vec<Mp4Sample>,
+}
+
+impl Mp4Track {
+    pub fn is_video(&self) -> bool {
+        &self.handler == b"vide"
+    }
+
+    pub fn sample_entry(&self, sample: &Mp4Sample) -> Option<&SampleEntry> {
+        self.sample_entries.get(sample.sample_description_index.checked_sub(1)? as usize)
+    }
+
+    pub fn seconds(&self, time: u64) -> f64 {
+        time as f64 / self.timescale.max(1) as f64
+    }
+}
+
+
+/// reads the `.mp4` files written by `Mp4Writer`, progressive or fragmented
+pub struct Mp4Reader<R: Read + Seek> {
+    pub tracks: Vec<Mp4Track>,
+    pub metadata: Mp4Metadata,
+    inner: R,
+}
+
+impl Mp4Reader<std::fs::File> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = std::fs::File::open(path.as_ref())?;
+        Self::new(file)
+    }
+}
+
+impl<R: Read + Seek> Mp4Reader<R> {
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        let file_size = inner.seek(SeekFrom::End(0))?;
+        let headers = top_level_boxes(&mut inner, file_size)?;
+
+        let mut moov = None;
+        for header in headers.iter() {
+            if &header.fourcc == b"moov" {
+                moov = read_box_payload(&mut inner, header)?.into();
+            }
+        }
+
+        let moov = moov.ok_or_else(|| anyhow!("missing moov box"))?;
+        let mut tracks = parse_moov(&moov)?;
+        let metadata = parse_metadata(&moov)?;
+        let track_defaults = find_child(&moov, b"mvex")
+            .map(parse_mvex)
+            .transpose()?
+            .unwrap_or_default();
+
+        // fragmented recordings, the last fragment may be cut short by an interrupted recording
+        for header in headers.iter().filter(|header| &header.fourcc == b"moof") {
+            let moof = read_box_payload(&mut inner, header)?;
+            let parsed = parse_moof(&moof, header.start, &track_defaults, &mut tracks, file_size);
+
+            if let Err(error) = parsed {
+                let truncated = header.start + header.size >= file_size;
+                if !truncated {
+                    return Err(error);
+                }
+                break;
+            }
+        }
+
+        Ok(Self {
+            tracks,
+            metadata,
+            inner,
+        })
+    }
+
+    pub fn video_track(&self) -> Option<&Mp4Track> {
+        self.tracks.iter().find(|track| track.is_video())
+    }
+
+    pub fn read_sample(&mut self, sample: &Mp4Sample) -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; sample.size as usize];
+
+        self.inner.seek(SeekFrom::Start(sample.offset))?;
+        self.inner.read_exact(&mut data)?;
+
+        Ok(data)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BoxHeader {
+    pub fourcc: [u8; 4],
+    pub start: u64,
+    pub header_size: u64,
+
+    /// total size including the header, a box extending to the end of the file reports the remaining size
+    pub size: u64,
+}
+
+impl BoxHeader {
+    pub fn payload_start(&self) -> u64 {
+        self.start + self.header_size
+    }
+}
+
+pub(crate) fn top_level_boxes<R: Read + Seek>(
+    reader: &mut R,
+    file_size: u64,
+) -> Result<Vec<BoxHeader>, Error> {
+    let mut headers = vec![];
+    let mut pos = 0;
+
+    while pos + 8 <= file_size {
+        reader.seek(SeekFrom::Start(pos))?;
+
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let fourcc = [header[4], header[5], header[6], header[7]];
+
+        let (size, header_size) = match size {
+            0 => (file_size - pos, 8),
+            1 => {
+                let mut large_size = [0; 8];
+                reader.read_exact(&mut large_size)?;
+                (u64::from_be_bytes(large_size), 16)
+            },
+            size => (size, 8),
+        };
+
+        if size < header_size {
+            bail!("invalid {} box size {}", String::from_utf8_lossy(&fourcc), size);
+        }
+
+        headers.push(BoxHeader {
+            fourcc,
+            start: pos,
+            header_size,
+            size: size.min(file_size - pos),
+        });
+
+        pos += size;
+    }
+
+    Ok(headers)
+}
+
+pub(crate) fn read_box_payload<R: Read + Seek>(
+    reader: &mut R,
+    header: &BoxHeader,
+) -> Result<Vec<u8>, Error> {
+    let mut payload = vec![0; (header.size - header.header_size) as usize];
+
+    reader.seek(SeekFrom::Start(header.payload_start()))?;
+    reader.read_exact(&mut payload)?;
+
+    Ok(payload)
+}
+
+
+/// iterates the child boxes of a box payload as (fourcc, payload)
+pub(crate) fn child_boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0;
+
+    std::iter::from_fn(move || {
+        if pos + 8 > data.len() {
+            return None;
+        }
+
+        let size = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let fourcc = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+
+        let (size, header_size) = match size {
+            0 => (data.len() - pos, 8),
+            1 if pos + 16 <= data.len() => {
+                let large_size = u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap());
+                (large_size as usize, 16)
+            },
+            size => (size, 8),
+        };
+
+        if size < header_size || pos + size > data.len() {
+            return None;
+        }
+
+        let payload = &data[pos + header_size..pos + size];
+        pos += size;
+
+        Some((fourcc, payload))
+    })
+}
+
+pub(crate) fn find_child<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    child_boxes(data)
+        .find(|(child, _)| child == fourcc)
+        .map(|(_, payload)| payload)
+}
+
+
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.remaining() < len {
+            bail!("unexpected end of box");
+        }
+
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<(), Error> {
+        self.bytes(len).map(|_| ())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn fourcc(&mut self) -> Result<[u8; 4], Error> {
+        Ok(self.bytes(4)?.try_into().unwrap())
+    }
+}
+
+
+/// the `trex` defaults of a fragmented track
+#[derive(Debug, Clone, Copy, Default)]
+struct TrackDefaults {
+    track_id: u32,
+    sample_description_index: u32,
+    sample_duration: u32,
+    sample_size: u32,
+    sample_flags: u32,
+}
+
+/// sample_is_non_sync_sample of the fragment sample flags
+const NON_SYNC_SAMPLE: u32 = 0x10000;
+
+fn parse_moov(moov: &[u8]) -> Result<Vec<Mp4Track>, Error> {
+    child_boxes(moov)
+        .filter(|(fourcc, _)| fourcc == b"trak")
+        .map(|(_, trak)| parse_trak(trak))
+        .collect()
+}
+
+/// the `mvhd` creation time and `udta/meta` `mdta` items of a `moov`
+fn parse_metadata(moov: &[u8]) -> Result<Mp4Metadata, Error> {
+    let mut metadata = Mp4Metadata::default();
+
+    if let Some(mvhd) = find_child(moov, b"mvhd") {
+        let mut reader = ByteReader::new(mvhd);
+        let version = reader.u8()?;
+        reader.skip(3)?;
+
+        let creation_time = match version {
+            1 => reader.u64()?,
+            _ => u64::from(reader.u32()?),
+        };
+
+        // zero is unknown, as are times before the unix epoch
+        if creation_time > MP4_UNIX_OFFSET_SECS {
+            metadata.creation_time = Some(UNIX_EPOCH + Duration::from_secs(creation_time - MP4_UNIX_OFFSET_SECS));
+        }
+    }
+
+    let Some(meta) = find_child(moov, b"udta").and_then(|udta| find_child(udta, b"meta")) else {
+        return Ok(metadata);
+    };
+
+    // quicktime `meta` boxes have no version and flags
+    let meta = if meta.get(4..8) == Some(&b"hdlr"[..]) { meta } else { meta.get(4..).unwrap_or_default() };
+
+    let mut keys = vec![];
+    if let Some(keys_box) = find_child(meta, b"keys") {
+        let mut reader = ByteReader::new(keys_box);
+        reader.skip(4)?;
+
+        let entry_count = reader.u32()?;
+        for _ in 0..entry_count {
+            let key_size = reader.u32()? as usize;
+            let namespace = reader.fourcc()?;
+            let key = reader.bytes(key_size.checked_sub(8).ok_or_else(|| anyhow!("invalid key size"))?)?;
+
+            keys.push((namespace, String::from_utf8_lossy(key).into_owned()));
+        }
+    }
+
+    for (item, payload) in find_child(meta, b"ilst").map(child_boxes).into_iter().flatten() {
+        let key_index = u32::from_be_bytes(item) as usize;
+        let Some((namespace, key)) = key_index.checked_sub(1).and_then(|i| keys.get(i)) else {
+            continue;
+        };
+
+        let Some(data) = find_child(payload, b"data") else {
+            continue;
+        };
+
+        let mut reader = ByteReader::new(data);
+        let type_indicator = reader.u32()?;
+        reader.skip(4)?; // locale
+
+        // only UTF-8 values in the mdta namespace
+        if namespace == b"mdta" && type_indicator == 1 {
+            let value = reader.bytes(reader.remaining())?;
+            metadata.items.push((key.clone(), String::from_utf8_lossy(value).into_owned()));
+        }
+    }
+
+    Ok(metadata)
+}
+
+fn parse_trak(trak: &[u8]) -> Result<Mp4Track, Error> {
+    let mut track = Mp4Track::default();
+
+    let tkhd = find_child(trak, b"tkhd").ok_or_else(|| anyhow!("missing tkhd box"))?;
+    let mut reader = ByteReader::new(tkhd);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    if version == 1 {
+        reader.skip(16)?;
+    } else {
+        reader.skip(8)?;
+    }
+    track.track_id = reader.u32()?;
+
+    let mdia = find_child(trak, b"mdia").ok_or_else(|| anyhow!("missing mdia box"))?;
+
+    let mdhd = find_child(mdia, b"mdhd").ok_or_else(|| anyhow!("missing mdhd box"))?;
+    let mut reader = ByteReader::new(mdhd);
+    let version = reader.u8()?;
+    reader.skip(3)?;
+    if version == 1 {
+        reader.skip(16)?;
+    } else {
+        reader.skip(8)?;
+    }
+    track.timescale = reader.u32()?;
+
+    let hdlr = find_child(mdia, b"hdlr").ok_or_else(|| anyhow!("missing hdlr box"))?;
+    let mut reader = ByteReader::new(hdlr);
+    reader.skip(8)?;
+    track.handler = reader.fourcc()?;
+
+    let stbl = find_child(mdia, b"minf")
+        .and_then(|minf| find_child(minf, b"stbl"))
+        .ok_or_else(|| anyhow!("missing stbl box"))?;
+
+    if track.is_video() {
+        if let Some(stsd) = find_child(stbl, b"stsd") {
+            track.sample_entries = parse_video_stsd(stsd)?;
+        }
+    }
+
+    track.samples = parse_sample_table(stbl)?;
+
+    Ok(track)
+}
+
+fn parse_mvex(mvex: &[u8]) -> Result<Vec<TrackDefaults>, Error> {
+    child_boxes(mvex)
+        .filter(|(fourcc, _)| fourcc == b"trex")
+        .map(|(_, trex)| {
+            let mut reader = ByteReader::new(trex);
+            reader.skip(4)?;
+
+            Ok(TrackDefaults {
+                track_id: reader.u32()?,
+                sample_description_index: reader.u32()?,
+                sample_duration: reader.u32()?,
+                sample_size: reader.u32()?,
+                sample_flags: reader.u32()?,
+            })
+        })
+        .collect()
+}
+
+/// appends the samples of a `moof` (starting at `moof_start`) to their tracks, samples past the end of the file are dropped
+fn parse_moof(
+    moof: &[u8],
+    moof_start: u64,
+    track_defaults: &[TrackDefaults],
+    tracks: &mut [Mp4Track],
+    file_size: u64,
+) -> Result<(), Error> {
+    for (_, traf) in child_boxes(moof).filter(|(fourcc, _)| fourcc == b"traf") {
+        let tfhd = find_child(traf, b"tfhd").ok_or_else(|| anyhow!("missing tfhd box"))?;
+        let mut reader = ByteReader::new(tfhd);
+        let flags = reader.u32()? & 0xffffff;
+        let track_id = reader.u32()?;
+
+        let mut defaults = track_defaults.iter()
+            .find(|defaults| defaults.track_id == track_id)
+            .copied()
+            .unwrap_or_default();
+
+        // without an explicit base offset, both iso5 default-base-is-moof and our single-traf fragments start at the moof
+        let base_data_offset = if flags & 0x1 != 0 { reader.u64()? } else { moof_start };
+        if flags & 0x2 != 0 {
+            defaults.sample_description_index = reader.u32()?;
+        }
+        if flags & 0x8 != 0 {
+            defaults.sample_duration = reader.u32()?;
+        }
+        if flags & 0x10 != 0 {
+            defaults.sample_size = reader.u32()?;
+        }
+        if flags & 0x20 != 0 {
+            defaults.sample_flags = reader.u32()?;
+        }
+
+        let track = tracks.iter_mut()
+            .find(|track| track.track_id == track_id)
+            .ok_or_else(|| anyhow!("fragment references missing track {}", track_id))?;
+
+        let mut decode_time = match find_child(traf, b"tfdt") {
+            Some(tfdt) => {
+                let mut reader = ByteReader::new(tfdt);
+                let version = reader.u8()?;
+                reader.skip(3)?;
+                if version == 1 {
+                    reader.u64()?
+                } else {
+                    u64::from(reader.u32()?)
+                }
+            },
+            None => track.samples.last()
+                .map(|sample| sample.decode_time + u64::from(sample.duration))
+                .unwrap_or_default(),
+        };
+
+        let mut offset = base_data_offset;
+        for (_, trun) in child_boxes(traf).filter(|(fourcc, _)| fourcc == b"trun") {
+            let mut reader = ByteReader::new(trun);
+            let flags = reader.u32()? & 0xffffff;
+            let sample_count = reader.u32()?;
+
+            if flags & 0x1 != 0 {
+                let data_offset = reader.u32()? as i32;
+                offset = base_data_offset.checked_add_signed(i64::from(data_offset))
+                    .ok_or_else(|| anyhow!("invalid trun data offset"))?;
+            }
+            let first_sample_flags = if flags & 0x4 != 0 { Some(reader.u32()?) } else { None };
+
+            for i in 0..sample_count {
+                let duration = if flags & 0x100 != 0 { reader.u32()? } else { defaults.sample_duration };
+                let size = if flags & 0x200 != 0 { reader.u32()? } else { defaults.sample_size };
+                let sample_flags = if flags & 0x400 != 0 {
+                    reader.u32()?
+                } else if i == 0 && first_sample_flags.is_some() {
+                    first_sample_flags.unwrap()
+                } else {
+                    defaults.sample_flags
+                };
+                if flags & 0x800 != 0 {
+                    reader.skip(4)?; // composition time offset
+                }
+
+                if offset + u64::from(size) > file_size {
+                    return Ok(());
+                }
+
+                track.samples.push(Mp4Sample {
+                    offset,
+                    size,
+                    decode_time,
+                    duration,
+                    is_sync: sample_flags & NON_SYNC_SAMPLE == 0,
+                    sample_description_index: defaults.sample_description_index,
+                });
+
+                offset += u64::from(size);
+                decode_time += u64::from(duration);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_video_stsd(stsd: &[u8]) -> Result<Vec<SampleEntry>, Error> {
+    // skip the version, flags and entry count
+    let entries = stsd.get(8..).ok_or_else(|| anyhow!("truncated stsd box"))?;
+
+    child_boxes(entries)
+        .map(|(fourcc, entry)| {
+            let mut reader = ByteReader::new(entry);
+            reader.skip(24)?;
+            let width = reader.u16()?;
+            let height = reader.u16()?;
+            reader.skip(50)?;
+
+            let configuration = child_boxes(&entry[78..])
+                .find(|(child, _)| child == b"avcC" || child == b"hvcC")
+                .map(|(_, configuration)| configuration.to_vec())
+                .unwrap_or_default();
+
+            Ok(SampleEntry {
+                fourcc,
+                width,
+                height,
+                configuration,
+            })
+        })
+        .collect()
+}
+
+fn parse_sample_table(stbl: &[u8]) -> Result<Vec<Mp4Sample>, Error> {
+    let sizes = match find_child(stbl, b"stsz") {
+        Some(stsz) => {
+            let mut reader = ByteReader::new(stsz);
+            reader.skip(4)?;
+            let sample_size = reader.u32()?;
+            let sample_count = reader.u32()?;
+
+            if sample_size != 0 {
+                vec![sample_size; sample_count as usize]
+            } else {
+                (0..sample_count)
+                    .map(|_| reader.u32())
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        },
+        None => vec![],
+    };
+
+    let chunk_offsets = if let Some(stco) = find_child(stbl, b"stco") {
+        let mut reader = ByteReader::new(stco);
+        reader.skip(4)?;
+        let entry_count = reader.u32()?;
+        (0..entry_count)
+            .map(|_| reader.u32().map(u64::from))
+            .collect::<Result<Vec<_>, _>>()?
+    } else if let Some(co64) = find_child(stbl, b"co64") {
+        let mut reader = ByteReader::new(co64);
+        reader.skip(4)?;
+        let entry_count = reader.u32()?;
+        (0..entry_count)
+            .map(|_| reader.u64())
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        vec![]
+    };
+
+    // (first_chunk, samples_per_chunk, sample_description_index)
+    let sample_to_chunk = match find_child(stbl, b"stsc") {
+        Some(stsc) => {
+            let mut reader = ByteReader::new(stsc);
+            reader.skip(4)?;
+            let entry_count = reader.u32()?;
+            (0..entry_count)
+                .map(|_| -> Result<_, Error> {
+                    Ok((reader.u32()?, reader.u32()?, reader.u32()?))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        },
+        None => vec![],
+    };
+
+    let durations = match find_child(stbl, b"stts") {
+        Some(stts) => {
+            let mut reader = ByteReader::new(stts);
+            reader.skip(4)?;
+            let entry_count = reader.u32()?;
+            let mut durations = vec![];
+            for _ in 0..entry_count {
+                let count = reader.u32()?;
+                let duration = reader.u32()?;
+                durations.extend(std::iter::repeat(duration).take(count as usize));
+            }
+            durations
+        },
+        None => vec![],
+    };
+
+    let sync_samples = match find_child(stbl, b"stss") {
+        Some(stss) => {
+            let mut reader = ByteReader::new(stss);
+            reader.skip(4)?;
+            let entry_count = reader.u32()?;
+            let sync_samples = (0..entry_count)
+                .map(|_| reader.u32())
+                .collect::<Result<Vec<_>, _>>()?;
+            Some(sync_samples)
+        },
+        None => None,
+    };
+
+    let mut samples = Vec::with_capacity(sizes.len());
+    let mut decode_time = 0;
+
+    for (entry_idx, &(first_chunk, samples_per_chunk, sample_description_index)) in sample_to_chunk.iter().enumerate() {
+        let last_chunk = sample_to_chunk.get(entry_idx + 1)
+            .map(|next| next.0 - 1)
+            .unwrap_or(chunk_offsets.len() as u32);
+
+        for chunk in first_chunk..=last_chunk {
+            let mut offset = *chunk_offsets.get(chunk as usize - 1)
+                .ok_or_else(|| anyhow!("stsc references missing chunk {}", chunk))?;
+
+            for _ in 0..samples_per_chunk {
+                let sample_idx = samples.len();
+                let Some(&size) = sizes.get(sample_idx) else {
+                    break;
+                };
+
+                let duration = durations.get(sample_idx).copied().unwrap_or_default();
+                let is_sync = sync_samples.as_ref()
+                    .map_or(true, |sync_samples| sync_samples.binary_search(&(sample_idx as u32 + 1)).is_ok());
+
+                samples.push(Mp4Sample {
+                    offset,
+                    size,
+                    decode_time,
+                    duration,
+                    is_sync,
+                    sample_description_index,
+                });
+
+                offset += u64::from(size);
+                decode_time += u64::from(duration);
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_avc_parameter_sets() {
+        let sps = [0x67, 0x42, 0x00, 0x1e];
+        let pps = [0x68, 0xce, 0x38, 0x80];
+
+        let mut configuration = vec![1, 0x42, 0x00, 0x1e, 0xff, 0xe1];
+        configuration.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        configuration.extend_from_slice(&sps);
+        configuration.push(1);
+        configuration.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        configuration.extend_from_slice(&pps);
+
+        let entry = SampleEntry {
+            fourcc: *b"avc1",
+            width: 1920,
+            height: 1080,
+            configuration,
+        };
+
+        assert_eq!(entry.parameter_sets().unwrap(), vec![sps.to_vec(), pps.to_vec()]);
+        assert_eq!(
+            entry.parameter_sets_annex_b().unwrap(),
+            [&[0, 0, 0, 1][..], &sps, &[0, 0, 0, 1], &pps].concat(),
+        );
+    }
+
+
+    fn boxed(fourcc: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        [&(payload.len() as u32 + 8).to_be_bytes()[..], fourcc, payload].concat()
+    }
+
+
+    #[test]
+    fn test_parse_metadata() {
+        let creation_time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let metadata = Mp4Metadata {
+            creation_time: Some(creation_time),
+            items: vec![
+                ("com.example.first".to_string(), "{\"a\":1}".to_string()),
+                ("com.example.second".to_string(), "value".to_string()),
+            ],
+        };
+
+        let mut mvhd = vec![1, 0, 0, 0];
+        mvhd.extend_from_slice(&(1_700_000_000 + MP4_UNIX_OFFSET_SECS).to_be_bytes());
+
+        let mut udta = bytes::BytesMut::new();
+        crate::mp4::write_udta(&mut udta, &metadata).unwrap();
+
+        let moov = [boxed(b"mvhd", &mvhd), udta.to_vec()].concat();
+
+        assert_eq!(parse_metadata(&moov).unwrap(), metadata);
+        assert_eq!(parse_metadata(&moov).unwrap().get("com.example.second"), Some("value"));
+        assert_eq!(parse_metadata(&boxed(b"mvhd", &[0; 8])).unwrap(), Mp4Metadata::default());
+    }
+
+
+    #[test]
+    fn test_parse_moof() {
+        let mut trun = vec![];
+        trun.extend_from_slice(&0x000701u32.to_be_bytes()); // data-offset, duration, size, flags
+        trun.extend_from_slice(&2u32.to_be_bytes());
+        trun.extend_from_slice(&0i32.to_be_bytes()); // patched below
+        for (duration, size, flags) in [(3000u32, 4u32, 0x02000000u32), (3000, 2, 0x01010000)] {
+            trun.extend_from_slice(&duration.to_be_bytes());
+            trun.extend_from_slice(&size.to_be_bytes());
+            trun.extend_from_slice(&flags.to_be_bytes());
+        }
+
+        let tfhd = [&0x020000u32.to_be_bytes()[..], &1u32.to_be_bytes()].concat();
+        let tfdt = [&(1u32 << 24).to_be_bytes()[..], &9000u64.to_be_bytes()].concat();
+
+        let build_moof = |trun: &[u8]| {
+            let traf = [boxed(b"tfhd", &tfhd), boxed(b"tfdt", &tfdt), boxed(b"trun", trun)].concat();
+            boxed(b"moof", &[boxed(b"mfhd", &[0; 8]), boxed(b"traf", &traf)].concat())
+        };
+
+        let data_offset = build_moof(&trun).len() as i32 + 8;
+        trun[8..12].copy_from_slice(&data_offset.to_be_bytes());
+        let moof = build_moof(&trun);
+
+        let mut tracks = vec![Mp4Track {
+            track_id: 1,
+            handler: *b"vide",
+            timescale: 90000,
+            ..Default::default()
+        }];
+        let defaults = [TrackDefaults {
+            track_id: 1,
+            sample_description_index: 1,
+            ..Default::default()
+        }];
+
+        let file_size = moof.len() as u64 + 8 + 6;
+        parse_moof(&moof[8..], 0, &defaults, &mut tracks, file_size).unwrap();
+
+        let samples = &tracks[0].samples;
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].offset, data_offset as u64);
+        assert_eq!(samples[1].offset, data_offset as u64 + 4);
+        assert_eq!(samples[1].decode_time, 12000);
+        assert!(samples[0].is_sync);
+        assert!(!samples[1].is_sync);
+
+        // an interrupted recording drops the samples missing from the file
+        tracks[0].samples.clear();
+        parse_moof(&moof[8..], 0, &defaults, &mut tracks, file_size - 1).unwrap();
+        assert_eq!(tracks[0].samples.len(), 1);
+    }
+
+
+    #[test]
+    fn test_child_boxes() {
+        let mut data = vec![];
+        data.extend_from_slice(&12u32.to_be_bytes());
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        data.extend_from_slice(&8u32.to_be_bytes());
+        data.extend_from_slice(b"skip");
+
+        let children = child_boxes(&data).collect::<Vec<_>>();
+
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0], (*b"free", &[1, 2, 3, 4][..]));
+        assert_eq!(children[1], (*b"skip", &[][..]));
+    }
+}
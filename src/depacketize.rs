@@ -0,0 +1,641 @@
+//! RTP depacketizers for h.265 (RFC 7798), which retina 0.4 lacks, and for the AAC audio (RFC 3640)
+//! of h.265 sessions, which are read as raw packets instead of through retina's demuxer.
+
+use std::{
+    collections::VecDeque,
+    num::NonZeroU32,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Error};
+use bytes::Bytes;
+
+use crate::{
+    decoder::{
+        hevc_nal_type,
+        HevcSps,
+        VideoCodec,
+        HEVC_PPS_NUT,
+        HEVC_SPS_NUT,
+        HEVC_VPS_NUT,
+    },
+    mp4::{EncodedVideoFrame, VideoStreamParameters},
+};
+
+
+/// aggregation packet, several NAL units with 16-bit sizes
+const AP_NUT: u8 = 48;
+
+/// fragmentation unit, a NAL unit split over several packets
+const FU_NUT: u8 = 49;
+
+/// IRAP (IDR, CRA and BLA) pictures start a decodable sequence
+const IRAP_NUTS: std::ops::RangeInclusive<u8> = 16..=23;
+
+
+/// the NAL units received for one RTP timestamp
+struct AccessUnit {
+    pts: i64,
+    loss: u16,
+    nals: Vec<Vec<u8>>,
+}
+
+/// reassembles h.265 access units from RTP payloads, tracking the parameter sets of the SDP and in-band
+pub struct H265Depacketizer {
+    clock_rate: NonZeroU32,
+
+    vps: Option<Vec<u8>>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    parameters: Option<Arc<VideoStreamParameters>>,
+    parameters_changed: bool,
+
+    /// the SDP parameter sets are inserted before the first access unit, cameras may not repeat them in-band
+    insert_parameter_sets: bool,
+
+    access_unit: Option<AccessUnit>,
+    fragment: Option<Vec<u8>>,
+    loss: u16,
+    frames: VecDeque<EncodedVideoFrame>,
+}
+
+impl H265Depacketizer {
+    /// `fmtp` is the format parameters of the SDP media description, with the optional `sprop-vps`, `sprop-sps` and `sprop-pps`
+    pub fn new(clock_rate: u32, fmtp: Option<&str>) -> Result<Self, Error> {
+        let clock_rate = NonZeroU32::new(clock_rate)
+            .ok_or_else(|| anyhow!("invalid h.265 clock rate {}", clock_rate))?;
+
+        let mut depacketizer = Self {
+            clock_rate,
+            vps: None,
+            sps: None,
+            pps: None,
+            parameters: None,
+            parameters_changed: false,
+            insert_parameter_sets: false,
+            access_unit: None,
+            fragment: None,
+            loss: 0,
+            frames: VecDeque::new(),
+        };
+
+        for key in ["sprop-vps", "sprop-sps", "sprop-pps"] {
+            // a list of parameter sets, the first one is active when the stream starts
+            let Some(parameter_set) = fmtp
+                .and_then(|fmtp| fmtp_parameter(fmtp, key))
+                .and_then(|value| value.split(',').next())
+            else {
+                continue;
+            };
+
+            depacketizer.parameter_set(decode_base64(parameter_set)?);
+        }
+
+        depacketizer.update_parameters()?;
+        depacketizer.insert_parameter_sets = depacketizer.parameters.is_some();
+
+        Ok(depacketizer)
+    }
+
+    /// the parameters of the last access unit, `None` until the stream has announced its VPS, SPS and PPS
+    pub fn parameters(&self) -> Option<&Arc<VideoStreamParameters>> {
+        self.parameters.as_ref()
+    }
+
+    /// adds the payload of an RTP packet, `loss` is the number of packets missing before it
+    pub fn push(&mut self, pts: i64, mark: bool, loss: u16, payload: &[u8]) -> Result<(), Error> {
+        if loss > 0 {
+            // a fragmented NAL unit with missing packets can't be reassembled
+            self.fragment = None;
+            self.loss = self.loss.saturating_add(loss);
+        }
+
+        // the access unit ends at the marker bit, or at the next timestamp if the packet with the marker was lost
+        if self.access_unit.as_ref().is_some_and(|access_unit| access_unit.pts != pts) {
+            self.finish_access_unit()?;
+        }
+
+        if payload.len() < 2 {
+            bail!("truncated h.265 RTP payload of {} bytes", payload.len());
+        }
+
+        match hevc_nal_type(payload).unwrap() {
+            AP_NUT => {
+                let mut nals = &payload[2..];
+                while !nals.is_empty() {
+                    let size = match nals {
+                        [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+                        _ => bail!("truncated h.265 aggregation packet"),
+                    };
+                    let nal = nals.get(2..2 + size)
+                        .ok_or_else(|| anyhow!("truncated h.265 aggregation packet"))?;
+
+                    self.add_nal(pts, nal.to_vec());
+                    nals = &nals[2 + size..];
+                }
+            },
+            FU_NUT => {
+                let (header, data) = match payload {
+                    [_, _, header, data @ ..] => (*header, data),
+                    _ => bail!("truncated h.265 fragmentation unit"),
+                };
+
+                let start = header & 0x80 != 0;
+                let end = header & 0x40 != 0;
+                let nal_type = header & 0x3f;
+
+                if start {
+                    // the NAL unit header is the payload header with the type of the fragmented NAL unit
+                    let nal_header = [(payload[0] & 0x81) | (nal_type << 1), payload[1]];
+                    self.fragment = Some([&nal_header[..], data].concat());
+                } else if let Some(fragment) = self.fragment.as_mut() {
+                    fragment.extend_from_slice(data);
+                }
+
+                if end {
+                    if let Some(nal) = self.fragment.take() {
+                        self.add_nal(pts, nal);
+                    }
+                }
+            },
+            // PACI packets (50) carry no NAL unit which is needed for decoding
+            nal_type if nal_type >= AP_NUT => {},
+            _ => self.add_nal(pts, payload.to_vec()),
+        }
+
+        if mark {
+            self.finish_access_unit()?;
+        }
+
+        Ok(())
+    }
+
+    /// the next complete access unit
+    pub fn pull(&mut self) -> Option<EncodedVideoFrame> {
+        self.frames.pop_front()
+    }
+
+    fn add_nal(&mut self, pts: i64, nal: Vec<u8>) {
+        if nal.len() > 2 && (HEVC_VPS_NUT..=HEVC_PPS_NUT).contains(&hevc_nal_type(&nal).unwrap()) {
+            self.parameter_set(nal.clone());
+        }
+
+        let loss = &mut self.loss;
+        self.access_unit
+            .get_or_insert_with(|| AccessUnit {
+                pts,
+                loss: std::mem::take(loss),
+                nals: vec![],
+            })
+            .nals
+            .push(nal);
+    }
+
+    fn parameter_set(&mut self, nal: Vec<u8>) {
+        let parameter_set = match hevc_nal_type(&nal) {
+            Some(HEVC_VPS_NUT) => &mut self.vps,
+            Some(HEVC_SPS_NUT) => &mut self.sps,
+            Some(HEVC_PPS_NUT) => &mut self.pps,
+            _ => return,
+        };
+
+        if parameter_set.as_ref() != Some(&nal) {
+            *parameter_set = Some(nal);
+            self.parameters_changed = true;
+        }
+    }
+
+    fn update_parameters(&mut self) -> Result<(), Error> {
+        if !self.parameters_changed {
+            return Ok(());
+        }
+
+        if let (Some(vps), Some(sps), Some(pps)) = (&self.vps, &self.sps, &self.pps) {
+            let parsed_sps = HevcSps::parse(sps)?;
+
+            self.parameters = Some(Arc::new(VideoStreamParameters {
+                codec: VideoCodec::H265,
+                pixel_dimensions: (u32::try_from(parsed_sps.width)?, u32::try_from(parsed_sps.height)?),
+                configuration_record: hevc_configuration_record(vps, sps, pps)?,
+            }));
+            self.parameters_changed = false;
+        }
+
+        Ok(())
+    }
+
+    fn finish_access_unit(&mut self) -> Result<(), Error> {
+        let Some(mut access_unit) = self.access_unit.take() else {
+            return Ok(());
+        };
+
+        self.update_parameters()?;
+
+        let nal_types = access_unit.nals.iter()
+            .filter_map(|nal| hevc_nal_type(nal))
+            .collect::<Vec<_>>();
+        let is_random_access_point = nal_types.iter().any(|nal_type| IRAP_NUTS.contains(nal_type));
+
+        if self.insert_parameter_sets && is_random_access_point {
+            self.insert_parameter_sets = false;
+
+            if !nal_types.contains(&HEVC_SPS_NUT) {
+                let parameter_sets = [&self.vps, &self.sps, &self.pps]
+                    .into_iter()
+                    .flatten()
+                    .cloned();
+                access_unit.nals.splice(0..0, parameter_sets);
+            }
+        }
+
+        let mut data = Vec::with_capacity(access_unit.nals.iter().map(|nal| 4 + nal.len()).sum());
+        for nal in &access_unit.nals {
+            data.extend_from_slice(&u32::try_from(nal.len())?.to_be_bytes());
+            data.extend_from_slice(nal);
+        }
+
+        self.frames.push_back(EncodedVideoFrame {
+            data: Bytes::from(data),
+            pts: access_unit.pts,
+            clock_rate: self.clock_rate,
+            loss: access_unit.loss,
+            is_random_access_point,
+            parameters: self.parameters.clone(),
+        });
+
+        Ok(())
+    }
+}
+
+
+/// the `hvcC` decoder configuration record (ISO/IEC 14496-15 8.3.3) of a VPS, SPS and PPS, with 4-byte NAL unit lengths
+pub fn hevc_configuration_record(vps: &[u8], sps: &[u8], pps: &[u8]) -> Result<Vec<u8>, Error> {
+    let parsed_sps = HevcSps::parse(sps)?;
+
+    let mut record = vec![1]; // configurationVersion
+    record.extend_from_slice(&parsed_sps.general_profile);
+    record.extend_from_slice(&[
+        0xf0, 0x00, // min_spatial_segmentation_idc
+        0xfc, // parallelismType
+        0xfc | parsed_sps.chroma_format_idc,
+        0xf8 | ((parsed_sps.bit_depth_luma - 8) & 0x07),
+        0xf8 | ((parsed_sps.bit_depth_chroma - 8) & 0x07),
+        0x00, 0x00, // avgFrameRate
+        (parsed_sps.max_sub_layers << 3) | ((parsed_sps.temporal_id_nesting as u8) << 2) | 3,
+        3, // numOfArrays
+    ]);
+
+    for (nal_type, nal) in [(HEVC_VPS_NUT, vps), (HEVC_SPS_NUT, sps), (HEVC_PPS_NUT, pps)] {
+        record.push(0x80 | nal_type); // array_completeness
+        record.extend_from_slice(&1u16.to_be_bytes());
+        record.extend_from_slice(&u16::try_from(nal.len())?.to_be_bytes());
+        record.extend_from_slice(nal);
+    }
+
+    Ok(record)
+}
+
+
+/// an AAC access unit of an RTP stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioSample {
+    pub data: Vec<u8>,
+    pub pts: i64,
+    pub loss: u16,
+}
+
+/// splits `mpeg4-generic` RTP payloads (RFC 3640) into AAC access units
+pub struct AacDepacketizer {
+    size_length: usize,
+    index_length: usize,
+    index_delta_length: usize,
+
+    /// the pts, size and data received so far of an access unit fragmented over several packets
+    fragment: Option<(i64, usize, Vec<u8>)>,
+    loss: u16,
+    samples: VecDeque<AudioSample>,
+}
+
+impl AacDepacketizer {
+    /// the samples per AAC access unit (AAC-LC)
+    const FRAME_LENGTH: i64 = 1024;
+
+    pub fn new(fmtp: Option<&str>) -> Result<Self, Error> {
+        let length = |key| -> Result<usize, Error> {
+            Ok(match fmtp.and_then(|fmtp| fmtp_parameter(fmtp, key)) {
+                Some(value) => value.parse()?,
+                None => 0,
+            })
+        };
+
+        let size_length = length("sizelength")?;
+        if !(1..=16).contains(&size_length) {
+            bail!("unsupported AAC sizelength {}, only AAC-hbr and AAC-lbr streams are recorded", size_length);
+        }
+
+        Ok(Self {
+            size_length,
+            index_length: length("indexlength")?,
+            index_delta_length: length("indexdeltalength")?,
+            fragment: None,
+            loss: 0,
+            samples: VecDeque::new(),
+        })
+    }
+
+    /// adds the payload of an RTP packet, `loss` is the number of packets missing before it
+    pub fn push(&mut self, pts: i64, mark: bool, loss: u16, payload: &[u8]) -> Result<(), Error> {
+        if loss > 0 {
+            self.fragment = None;
+            self.loss = self.loss.saturating_add(loss);
+        }
+
+        let headers_length = match payload {
+            [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+            _ => bail!("truncated AAC RTP payload"),
+        };
+        let headers_end = 2 + headers_length.div_ceil(8);
+        let headers = payload.get(2..headers_end)
+            .ok_or_else(|| anyhow!("truncated AAC RTP payload headers"))?;
+        let mut data = &payload[headers_end..];
+
+        let mut sizes = vec![];
+        let mut position = 0;
+        while position < headers_length {
+            let index_length = if sizes.is_empty() { self.index_length } else { self.index_delta_length };
+            sizes.push(read_bits(headers, position, self.size_length)?);
+            position += self.size_length + index_length;
+        }
+
+        // the remainder of a fragmented access unit, which has a single header with the full size
+        if let Some((fragment_pts, size, mut fragment)) = self.fragment.take() {
+            if fragment_pts == pts && sizes.len() == 1 && sizes[0] == size {
+                fragment.extend_from_slice(data);
+                if !mark {
+                    self.fragment = Some((fragment_pts, size, fragment));
+                    return Ok(());
+                }
+
+                if fragment.len() != size {
+                    bail!("fragmented AAC access unit of {} bytes, expected {}", fragment.len(), size);
+                }
+                self.push_sample(fragment, pts);
+                return Ok(());
+            }
+        }
+
+        for (i, size) in sizes.iter().copied().enumerate() {
+            let sample_pts = pts + i as i64 * Self::FRAME_LENGTH;
+
+            if size > data.len() {
+                if sizes.len() == 1 && !mark {
+                    self.fragment = Some((pts, size, data.to_vec()));
+                    return Ok(());
+                }
+                bail!("truncated AAC access unit of {} bytes, expected {}", data.len(), size);
+            }
+
+            self.push_sample(data[..size].to_vec(), sample_pts);
+            data = &data[size..];
+        }
+
+        Ok(())
+    }
+
+    /// the next complete access unit
+    pub fn pull(&mut self) -> Option<AudioSample> {
+        self.samples.pop_front()
+    }
+
+    fn push_sample(&mut self, data: Vec<u8>, pts: i64) {
+        self.samples.push_back(AudioSample {
+            data,
+            pts,
+            loss: std::mem::take(&mut self.loss),
+        });
+    }
+}
+
+fn read_bits(data: &[u8], position: usize, count: usize) -> Result<usize, Error> {
+    (position..position + count).try_fold(0, |value, bit| {
+        let byte = data.get(bit / 8).ok_or_else(|| anyhow!("truncated AAC RTP payload headers"))?;
+        Ok((value << 1) | ((byte >> (7 - bit % 8)) & 1) as usize)
+    })
+}
+
+
+/// the value of `key` in SDP format parameters (`a=fmtp:<payload type> key=value;key=value`)
+pub fn fmtp_parameter<'a>(fmtp: &'a str, key: &str) -> Option<&'a str> {
+    fmtp.split(';')
+        .filter_map(|parameter| parameter.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.trim())
+}
+
+/// the format parameters of the `media_index`th media description of an SDP session description, without the payload type
+pub fn sdp_fmtp(sdp: &[u8], media_index: usize) -> Option<String> {
+    let sdp = String::from_utf8_lossy(sdp);
+
+    sdp.lines()
+        .scan(None, |media, line| {
+            if line.starts_with("m=") {
+                *media = Some(media.map_or(0, |media: usize| media + 1));
+            }
+            Some((*media, line))
+        })
+        .filter(|(media, _)| *media == Some(media_index))
+        .find_map(|(_, line)| line.strip_prefix("a=fmtp:"))
+        .and_then(|fmtp| fmtp.split_once(' '))
+        .map(|(_, parameters)| parameters.trim().to_string())
+}
+
+/// decodes the base64 (standard or url-safe, padding optional) parameter sets of SDP format parameters
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in encoded.trim().trim_end_matches('=').bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => bail!("invalid base64 parameter set {}", encoded),
+        };
+
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(decoded)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::demux::SampleEntry;
+
+
+    /// a 1920x1080 main profile stream encoded by x265
+    const VPS: [u8; 24] = [
+        0x40, 0x01, 0x0c, 0x01, 0xff, 0xff, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03,
+        0x00, 0x00, 0x03, 0x00, 0x78, 0x95, 0x98, 0x09,
+    ];
+    const SPS: [u8; 42] = [
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00, 0x03, 0x00, 0x78,
+        0xa0, 0x03, 0xc0, 0x80, 0x10, 0xe5, 0x96, 0x66, 0x69, 0x24, 0xca, 0xe0, 0x10, 0x00, 0x00, 0x03, 0x00, 0x10,
+        0x00, 0x00, 0x03, 0x01, 0xe0, 0x80,
+    ];
+    const PPS: [u8; 7] = [0x44, 0x01, 0xc1, 0x72, 0xb4, 0x62, 0x40];
+
+    /// the first bytes of an IDR_W_RADL slice
+    const IDR: [u8; 6] = [0x26, 0x01, 0xaf, 0x09, 0x40, 0xf3];
+
+    fn base64(data: &[u8]) -> String {
+        const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+        data.chunks(3)
+            .flat_map(|chunk| {
+                let value = chunk.iter().enumerate().fold(0u32, |value, (i, byte)| value | ((*byte as u32) << (16 - 8 * i)));
+                (0..=chunk.len()).map(move |i| ALPHABET[(value >> (18 - 6 * i)) as usize & 0x3f] as char)
+            })
+            .collect()
+    }
+
+    fn nal_units(frame: &EncodedVideoFrame) -> Vec<Vec<u8>> {
+        let mut data = &frame.data[..];
+        let mut nals = vec![];
+        while !data.is_empty() {
+            let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+            nals.push(data[4..4 + size].to_vec());
+            data = &data[4 + size..];
+        }
+        nals
+    }
+
+
+    #[test]
+    fn test_h265_depacketizer() {
+        let mut depacketizer = H265Depacketizer::new(90000, None).unwrap();
+        assert!(depacketizer.parameters().is_none());
+
+        // the parameter sets in an aggregation packet
+        let mut aggregation = vec![0x60, 0x01];
+        for nal in [&VPS[..], &SPS, &PPS] {
+            aggregation.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            aggregation.extend_from_slice(nal);
+        }
+        depacketizer.push(0, false, 0, &aggregation).unwrap();
+        assert!(depacketizer.pull().is_none(), "the access unit continues until the marker bit");
+
+        // the IDR slice in three fragmentation units
+        let slice = [&IDR[..], &[0x55; 100]].concat();
+        let fu_header = [0x62, 0x01];
+        depacketizer.push(0, false, 0, &[&fu_header[..], &[0x80 | 19], &slice[2..40]].concat()).unwrap();
+        depacketizer.push(0, false, 0, &[&fu_header[..], &[19], &slice[40..80]].concat()).unwrap();
+        depacketizer.push(0, true, 0, &[&fu_header[..], &[0x40 | 19], &slice[80..]].concat()).unwrap();
+
+        let frame = depacketizer.pull().unwrap();
+        assert!(frame.is_random_access_point);
+        assert_eq!(nal_units(&frame), vec![VPS.to_vec(), SPS.to_vec(), PPS.to_vec(), slice]);
+
+        let parameters = frame.parameters.unwrap();
+        assert_eq!(parameters.codec, VideoCodec::H265);
+        assert_eq!(parameters.pixel_dimensions, (1920, 1080));
+
+        // a single NAL unit packet, completed by the next timestamp as its marker bit was lost
+        let trailing = [0x02, 0x01, 0xd0, 0x2f];
+        depacketizer.push(3000, false, 0, &trailing).unwrap();
+        depacketizer.push(6000, true, 2, &trailing).unwrap();
+
+        let frame = depacketizer.pull().unwrap();
+        assert_eq!((frame.pts, frame.loss, frame.is_random_access_point), (3000, 0, false));
+        assert_eq!(nal_units(&frame), vec![trailing.to_vec()]);
+
+        let frame = depacketizer.pull().unwrap();
+        assert_eq!((frame.pts, frame.loss), (6000, 2));
+        assert!(Arc::ptr_eq(frame.parameters.as_ref().unwrap(), &parameters), "parameters are shared until they change");
+        assert!(depacketizer.pull().is_none());
+    }
+
+
+    #[test]
+    fn test_h265_depacketizer_inserts_sdp_parameter_sets() {
+        let fmtp = format!("sprop-vps={};sprop-sps={};sprop-pps={}", base64(&VPS), base64(&SPS), base64(&PPS));
+        let mut depacketizer = H265Depacketizer::new(90000, Some(&fmtp)).unwrap();
+        assert_eq!(depacketizer.parameters().unwrap().pixel_dimensions, (1920, 1080));
+
+        depacketizer.push(0, true, 0, &IDR).unwrap();
+        depacketizer.push(3000, true, 0, &IDR).unwrap();
+
+        assert_eq!(nal_units(&depacketizer.pull().unwrap()), vec![VPS.to_vec(), SPS.to_vec(), PPS.to_vec(), IDR.to_vec()]);
+        assert_eq!(nal_units(&depacketizer.pull().unwrap()), vec![IDR.to_vec()]);
+    }
+
+
+    #[test]
+    fn test_hevc_configuration_record() {
+        let entry = SampleEntry {
+            fourcc: *b"hvc1",
+            width: 1920,
+            height: 1080,
+            configuration: hevc_configuration_record(&VPS, &SPS, &PPS).unwrap(),
+        };
+
+        assert_eq!(entry.configuration[1..13], [0x01, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 0x78]);
+        assert_eq!(entry.configuration[21], 0x0f, "one temporal layer, nested, 4-byte lengths");
+        assert_eq!(entry.parameter_sets().unwrap(), vec![VPS.to_vec(), SPS.to_vec(), PPS.to_vec()]);
+    }
+
+
+    #[test]
+    fn test_aac_depacketizer() {
+        let fmtp = "streamtype=5;profile-level-id=1;mode=AAC-hbr;sizelength=13;indexlength=3;indexdeltalength=3;config=1190";
+        let mut depacketizer = AacDepacketizer::new(Some(fmtp)).unwrap();
+
+        // two access units of 3 and 2 bytes, 16-bit headers of the size and index
+        let payload = [0x00, 0x20, 0x00, 0x18, 0x00, 0x10, 1, 2, 3, 4, 5];
+        depacketizer.push(48000, true, 1, &payload).unwrap();
+
+        assert_eq!(depacketizer.pull(), Some(AudioSample { data: vec![1, 2, 3], pts: 48000, loss: 1 }));
+        assert_eq!(depacketizer.pull(), Some(AudioSample { data: vec![4, 5], pts: 49024, loss: 0 }));
+
+        // an access unit of 4 bytes fragmented over two packets
+        depacketizer.push(50048, false, 0, &[0x00, 0x10, 0x00, 0x20, 6, 7]).unwrap();
+        assert_eq!(depacketizer.pull(), None);
+        depacketizer.push(50048, true, 0, &[0x00, 0x10, 0x00, 0x20, 8, 9]).unwrap();
+        assert_eq!(depacketizer.pull(), Some(AudioSample { data: vec![6, 7, 8, 9], pts: 50048, loss: 0 }));
+
+        assert!(AacDepacketizer::new(Some("mode=generic")).is_err());
+    }
+
+
+    #[test]
+    fn test_sdp_fmtp() {
+        let sdp = b"v=0\r\n\
+            m=video 0 RTP/AVP 96\r\n\
+            a=rtpmap:96 H265/90000\r\n\
+            a=fmtp:96 sprop-vps=QAEMAf//;sprop-sps=QgEBAWA=;sprop-pps=RAHBcrRiQA==\r\n\
+            m=audio 0 RTP/AVP 97\r\n\
+            a=rtpmap:97 mpeg4-generic/48000/2\r\n\
+            a=fmtp:97 mode=AAC-hbr; sizelength=13\r\n";
+
+        let video = sdp_fmtp(sdp, 0).unwrap();
+        assert_eq!(fmtp_parameter(&video, "sprop-sps"), Some("QgEBAWA="));
+        assert_eq!(decode_base64(fmtp_parameter(&video, "sprop-pps").unwrap()).unwrap(), PPS);
+
+        let audio = sdp_fmtp(sdp, 1).unwrap();
+        assert_eq!(fmtp_parameter(&audio, "SizeLength"), Some("13"));
+        assert_eq!(sdp_fmtp(sdp, 2), None);
+    }
+}
@@ -0,0 +1,516 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, bail, Error};
+use bevy::prelude::*;
+use image::{
+    imageops::FilterType,
+    DynamicImage,
+    GenericImageView,
+    RgbaImage,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    decoder::{
+        convert_annex_b,
+        create_decoder,
+        FrameCallback,
+        VideoDecoder,
+    },
+    demux::{Mp4Reader, Mp4Track},
+};
+
+
+/// which frames of a recording are extracted
+#[derive(Debug, Clone, Copy, PartialEq, Reflect, Serialize, Deserialize)]
+pub enum FrameSampling {
+    /// at most one frame per `1 / fps` interval of the recording timeline, the first frame of each interval
+    Fps(f64),
+
+    /// every nth frame, starting with the first
+    Stride(usize),
+
+    /// sync samples (keyframes) only, which are decoded without their dependent frames
+    Keyframes,
+}
+
+impl Default for FrameSampling {
+    fn default() -> Self {
+        FrameSampling::Fps(5.0)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Reflect, Serialize, Deserialize)]
+pub enum ExtractionResolution {
+    /// the decoded size
+    Native,
+
+    /// the largest size within the bounds with the aspect ratio of the recording, recordings are not upscaled
+    Fit {
+        width: u32,
+        height: u32,
+    },
+
+    /// exactly the given size, stretching recordings of another aspect ratio
+    Exact {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl ExtractionResolution {
+    pub fn output_size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        match *self {
+            ExtractionResolution::Native => (width, height),
+            ExtractionResolution::Fit { width: max_width, height: max_height } => {
+                let scale = (max_width as f64 / width as f64)
+                    .min(max_height as f64 / height as f64)
+                    .min(1.0);
+
+                (
+                    ((width as f64 * scale).round() as u32).max(1),
+                    ((height as f64 * scale).round() as u32).max(1),
+                )
+            },
+            ExtractionResolution::Exact { width, height } => (width, height),
+        }
+    }
+}
+
+
+/// resampling filter used when the output size differs from the decoded size
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Reflect, Serialize, Deserialize)]
+pub enum Scaler {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Gaussian,
+    #[default]
+    Lanczos,
+}
+
+impl From<Scaler> for FilterType {
+    fn from(scaler: Scaler) -> Self {
+        match scaler {
+            Scaler::Nearest => FilterType::Nearest,
+            Scaler::Bilinear => FilterType::Triangle,
+            Scaler::Bicubic => FilterType::CatmullRom,
+            Scaler::Gaussian => FilterType::Gaussian,
+            Scaler::Lanczos => FilterType::Lanczos3,
+        }
+    }
+}
+
+
+/// settings of the raw frames node, stored with the frames of each session
+#[derive(Debug, Clone, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrameExtraction {
+    pub sampling: FrameSampling,
+    pub resolution: ExtractionResolution,
+    pub scaler: Scaler,
+
+    /// seconds from the start of each recording, the whole recording if unset
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+}
+
+impl Default for FrameExtraction {
+    fn default() -> Self {
+        Self {
+            sampling: FrameSampling::default(),
+            resolution: ExtractionResolution::Fit {
+                width: 1920,
+                height: 1080,
+            },
+            scaler: Scaler::default(),
+            start: None,
+            end: None,
+        }
+    }
+}
+
+impl FrameExtraction {
+    pub fn path(frames_directory: impl AsRef<Path>) -> std::path::PathBuf {
+        frames_directory.as_ref().join("extraction.json")
+    }
+
+    /// the settings the frames in `frames_directory` were extracted with
+    pub fn load(frames_directory: impl AsRef<Path>) -> Result<Option<Self>, Error> {
+        let path = Self::path(frames_directory);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = std::fs::File::open(path)?;
+        Ok(Some(serde_json::from_reader(file)?))
+    }
+
+    pub fn save(&self, frames_directory: impl AsRef<Path>) -> Result<(), Error> {
+        std::fs::create_dir_all(frames_directory.as_ref())?;
+
+        let file = std::fs::File::create(Self::path(frames_directory))?;
+        serde_json::to_writer_pretty(file, self)?;
+
+        Ok(())
+    }
+
+    /// rejects sampling rates and time ranges which select no frames or are not numbers
+    pub fn validate(&self) -> Result<(), Error> {
+        if let FrameSampling::Fps(fps) = self.sampling {
+            if !(fps.is_finite() && fps > 0.0) {
+                bail!("invalid extraction fps {}, expected a positive number", fps);
+            }
+        }
+
+        for (name, seconds) in [("start", self.start), ("end", self.end)] {
+            if let Some(seconds) = seconds.filter(|seconds| !(seconds.is_finite() && *seconds >= 0.0)) {
+                bail!("invalid extraction {} {}s, expected a non-negative number of seconds", name, seconds);
+            }
+        }
+
+        if let Some(end) = self.end {
+            let start = self.start.unwrap_or_default();
+            if end <= start {
+                bail!("invalid extraction range {}s..{}s, the end has to be after the start", start, end);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn contains(&self, seconds: f64) -> bool {
+        self.start.map_or(true, |start| seconds >= start) && self.end.map_or(true, |end| seconds < end)
+    }
+}
+
+
+/// decodes a recording in process and writes the sampled frames as `<n>.png`
+#[derive(Debug, Clone)]
+pub struct FrameExtractor {
+    pub extraction: FrameExtraction,
+
+    /// number of the first written frame
+    pub start_number: usize,
+}
+
+impl Default for FrameExtractor {
+    fn default() -> Self {
+        Self {
+            extraction: FrameExtraction::default(),
+            start_number: 1,
+        }
+    }
+}
+
+impl FrameExtractor {
+    /// extracts the frames of the video track of `mp4_path` into `output_directory`, returning the number of frames written
+    pub fn extract(
+        &self,
+        mp4_path: impl AsRef<Path>,
+        output_directory: impl AsRef<Path>,
+    ) -> Result<usize, Error> {
+        let mp4_path = mp4_path.as_ref();
+        let output_directory = output_directory.as_ref();
+
+        self.extraction.validate()?;
+
+        let mut reader = Mp4Reader::open(mp4_path)?;
+        let track = reader.video_track()
+            .ok_or_else(|| anyhow!("no video track in {}", mp4_path.display()))?
+            .clone();
+
+        let selected = select_samples(&track, &self.extraction);
+        let Some(last_selected) = selected.iter().rposition(|&selected| selected) else {
+            return Ok(0);
+        };
+
+        // decoding starts at the keyframe before the first extracted frame
+        let first_selected = selected.iter().position(|&selected| selected).unwrap();
+        let first_decoded = track.samples[..=first_selected]
+            .iter()
+            .rposition(|sample| sample.is_sync)
+            .unwrap_or_default();
+
+        std::fs::create_dir_all(output_directory)?;
+
+        // pictures are matched to their samples by pts, decoders with frame reordering (h.265) return them several
+        // access units later. frames are numbered by their sample, the numbering gaps of samples which did not decode
+        // are closed at the end
+        let frame_ranks = track.samples.iter()
+            .zip(&selected)
+            .filter(|(_, &selected)| selected)
+            .enumerate()
+            .map(|(rank, (sample, _))| (sample.decode_time as i64, rank))
+            .collect::<HashMap<_, _>>();
+        let mut decoded = vec![false; frame_ranks.len()];
+        let mut pictures = vec![];
+
+        let mut decoder: Option<Box<dyn VideoDecoder>> = None;
+        let mut sample_description_index = 0;
+
+        for (i, sample) in track.samples.iter().enumerate().take(last_selected + 1).skip(first_decoded) {
+            // keyframes decode on their own, everything else needs the preceding frames
+            if self.extraction.sampling == FrameSampling::Keyframes && !sample.is_sync {
+                continue;
+            }
+
+            let mut data = reader.read_sample(sample)?;
+            convert_annex_b(&mut data)?;
+
+            if sample.sample_description_index != sample_description_index {
+                let entry = track.sample_entry(sample)
+                    .ok_or_else(|| anyhow!("sample {} references a missing sample description", i))?;
+                let codec = entry.codec()
+                    .ok_or_else(|| anyhow!("unsupported sample description {}", String::from_utf8_lossy(&entry.fourcc)))?;
+
+                if let Some(mut decoder) = decoder.take() {
+                    decoder.flush(&mut collect_pictures(&frame_ranks, &mut pictures))?;
+                }
+
+                decoder = Some(create_decoder(codec)?);
+                sample_description_index = sample.sample_description_index;
+
+                data = [entry.parameter_sets_annex_b()?, data].concat();
+            }
+
+            decoder.as_mut().unwrap().decode(&data, sample.decode_time as i64, &mut collect_pictures(&frame_ranks, &mut pictures))?;
+            self.save_pictures(pictures.drain(..), &mut decoded, output_directory)?;
+        }
+
+        if let Some(mut decoder) = decoder {
+            decoder.flush(&mut collect_pictures(&frame_ranks, &mut pictures))?;
+            self.save_pictures(pictures.drain(..), &mut decoded, output_directory)?;
+        }
+
+        let mut written = 0;
+        for (rank, _) in decoded.iter().enumerate().filter(|(_, &decoded)| decoded) {
+            if rank != written {
+                std::fs::rename(self.frame_path(output_directory, rank), self.frame_path(output_directory, written))?;
+            }
+            written += 1;
+        }
+
+        Ok(written)
+    }
+
+    fn frame_path(&self, output_directory: &Path, rank: usize) -> std::path::PathBuf {
+        output_directory.join(format!("{}.png", self.start_number + rank))
+    }
+
+    fn save_pictures(
+        &self,
+        pictures: impl Iterator<Item = (usize, RgbaImage)>,
+        decoded: &mut [bool],
+        output_directory: &Path,
+    ) -> Result<(), Error> {
+        for (rank, picture) in pictures {
+            self.resize(picture).save(self.frame_path(output_directory, rank))?;
+            decoded[rank] = true;
+        }
+
+        Ok(())
+    }
+
+    fn resize(&self, picture: RgbaImage) -> DynamicImage {
+        let picture = DynamicImage::ImageRgba8(picture);
+
+        let (width, height) = self.extraction.resolution.output_size(picture.dimensions());
+        let picture = if (width, height) != picture.dimensions() {
+            picture.resize_exact(width, height, self.extraction.scaler.into())
+        } else {
+            picture
+        };
+
+        // decoded pictures are opaque
+        DynamicImage::ImageRgb8(picture.to_rgb8())
+    }
+}
+
+
+/// collects the decoded pictures of the extracted samples with their selection rank, identified by pts
+fn collect_pictures<'a>(
+    frame_ranks: &'a HashMap<i64, usize>,
+    pictures: &'a mut Vec<(usize, RgbaImage)>,
+) -> Box<FrameCallback<'a>> {
+    Box::new(move |pts, (width, height), write_rgba8| {
+        let Some(&rank) = frame_ranks.get(&pts) else {
+            return;
+        };
+
+        let mut rgba = vec![0; width * height * 4];
+        write_rgba8(&mut rgba);
+        pictures.extend(RgbaImage::from_raw(width as u32, height as u32, rgba).map(|picture| (rank, picture)));
+    })
+}
+
+
+/// whether each sample of the track is extracted, derived from the sample table alone so the selection is reproducible
+pub fn select_samples(track: &Mp4Track, extraction: &FrameExtraction) -> Vec<bool> {
+    let first_decode_time = track.samples.first().map_or(0, |sample| sample.decode_time);
+    let range_start = extraction.start.unwrap_or_default().max(0.0);
+
+    let mut in_range_count = 0;
+    let mut last_interval = None;
+
+    track.samples.iter()
+        .map(|sample| {
+            let elapsed = track.seconds(sample.decode_time - first_decode_time);
+            if !extraction.contains(elapsed) {
+                return false;
+            }
+
+            let in_range_index = in_range_count;
+            in_range_count += 1;
+
+            match extraction.sampling {
+                FrameSampling::Fps(fps) => {
+                    // intervals from the range start, in track timescale units to avoid rounding at interval boundaries
+                    let elapsed = (sample.decode_time - first_decode_time) as f64 - range_start * track.timescale as f64;
+                    let interval = (elapsed * fps / track.timescale.max(1) as f64).floor() as i64;
+
+                    let selected = last_interval.map_or(true, |last_interval| interval > last_interval);
+                    if selected {
+                        last_interval = Some(interval);
+                    }
+                    selected
+                },
+                FrameSampling::Stride(stride) => in_range_index % stride.max(1) == 0,
+                FrameSampling::Keyframes => sample.is_sync,
+            }
+        })
+        .collect()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::demux::Mp4Sample;
+
+
+    fn track(frame_count: usize, timescale: u32, frame_duration: u32) -> Mp4Track {
+        Mp4Track {
+            track_id: 1,
+            handler: *b"vide",
+            timescale,
+            sample_entries: vec![],
+            samples: (0..frame_count)
+                .map(|i| Mp4Sample {
+                    offset: 0,
+                    size: 0,
+                    decode_time: (i as u32 * frame_duration) as u64,
+                    duration: frame_duration,
+                    is_sync: i % 10 == 0,
+                    sample_description_index: 1,
+                })
+                .collect(),
+        }
+    }
+
+    fn selected_indices(selected: &[bool]) -> Vec<usize> {
+        selected.iter()
+            .enumerate()
+            .filter(|(_, &selected)| selected)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+
+    #[test]
+    fn test_select_samples() {
+        // 30 fps at 90 kHz
+        let track = track(60, 90_000, 3_000);
+
+        let sampled = |sampling| selected_indices(&select_samples(&track, &FrameExtraction {
+            sampling,
+            ..default()
+        }));
+
+        assert_eq!(sampled(FrameSampling::Fps(5.0)), vec![0, 6, 12, 18, 24, 30, 36, 42, 48, 54]);
+
+        // a rate above the recording frame rate keeps every frame once
+        assert_eq!(sampled(FrameSampling::Fps(60.0)).len(), 60);
+
+        assert_eq!(sampled(FrameSampling::Stride(25)), vec![0, 25, 50]);
+        assert_eq!(sampled(FrameSampling::Keyframes), vec![0, 10, 20, 30, 40, 50]);
+
+        let range = FrameExtraction {
+            sampling: FrameSampling::Stride(5),
+            start: Some(0.5),
+            end: Some(1.5),
+            ..default()
+        };
+        assert_eq!(selected_indices(&select_samples(&track, &range)), vec![15, 20, 25, 30, 35, 40]);
+    }
+
+
+    #[test]
+    fn test_extraction_resolution() {
+        let fit = ExtractionResolution::Fit {
+            width: 1920,
+            height: 1080,
+        };
+
+        assert_eq!(fit.output_size((3840, 2160)), (1920, 1080));
+        assert_eq!(fit.output_size((2560, 1920)), (1440, 1080), "4:3 recordings keep their aspect ratio");
+        assert_eq!(fit.output_size((1080, 1920)), (608, 1080), "portrait recordings keep their aspect ratio");
+        assert_eq!(fit.output_size((1280, 720)), (1280, 720), "smaller recordings are not upscaled");
+        assert_eq!(ExtractionResolution::Native.output_size((2560, 1920)), (2560, 1920));
+    }
+
+
+    #[test]
+    fn test_collect_pictures_by_pts() {
+        let frame_ranks = HashMap::from([(0, 0), (3000, 1), (6000, 2)]);
+        let mut pictures = vec![];
+
+        // a reordering decoder returns the picture of a later access unit first
+        {
+            let mut on_frame = collect_pictures(&frame_ranks, &mut pictures);
+            for pts in [0, 6000, 4500, 3000] {
+                on_frame(pts, (2, 1), &|data| data.fill(pts as u8));
+            }
+        }
+
+        let ranks = pictures.iter().map(|(rank, _)| *rank).collect::<Vec<_>>();
+        assert_eq!(ranks, vec![0, 2, 1], "pictures of unselected samples are dropped");
+        assert_eq!(pictures[1].1.get_pixel(1, 0).0, [(6000 % 256) as u8; 4]);
+    }
+
+
+    #[test]
+    fn test_validate_extraction() {
+        let extraction = |sampling, start, end| FrameExtraction {
+            sampling,
+            start,
+            end,
+            ..default()
+        };
+
+        assert!(extraction(FrameSampling::Fps(5.0), Some(1.0), Some(2.5)).validate().is_ok());
+        assert!(extraction(FrameSampling::Stride(2), None, Some(2.5)).validate().is_ok());
+
+        assert!(extraction(FrameSampling::Fps(0.0), None, None).validate().is_err());
+        assert!(extraction(FrameSampling::Fps(-5.0), None, None).validate().is_err());
+        assert!(extraction(FrameSampling::Fps(f64::NAN), None, None).validate().is_err());
+        assert!(extraction(FrameSampling::Fps(f64::INFINITY), None, None).validate().is_err());
+
+        assert!(extraction(FrameSampling::Keyframes, Some(-1.0), None).validate().is_err());
+        assert!(extraction(FrameSampling::Keyframes, Some(2.0), Some(2.0)).validate().is_err());
+        assert!(extraction(FrameSampling::Keyframes, Some(3.0), Some(2.0)).validate().is_err());
+        assert!(extraction(FrameSampling::Keyframes, None, Some(0.0)).validate().is_err());
+        assert!(extraction(FrameSampling::Keyframes, None, Some(f64::NAN)).validate().is_err());
+
+        // extraction fails before the recording is opened
+        let extractor = FrameExtractor {
+            extraction: extraction(FrameSampling::Fps(f64::NAN), None, None),
+            ..default()
+        };
+        let error = extractor.extract("missing.mp4", std::env::temp_dir()).unwrap_err();
+        assert!(error.to_string().contains("fps"), "{}", error);
+    }
+}
@@ -3,7 +3,10 @@ use bevy::{
     window::PrimaryWindow,
 };
 
-use crate::materials::foreground::ForegroundMaterial;
+use crate::materials::{
+    color_correction::ColorCorrectionMaterial,
+    foreground::ForegroundMaterial,
+};
 
 
 pub struct GridViewPlugin;
@@ -18,6 +21,7 @@ impl Plugin for GridViewPlugin {
 pub enum Element {
     Image(Handle<Image>),
     Alphablend(Handle<ForegroundMaterial>),
+    ColorCorrected(Handle<ColorCorrectionMaterial>),
 }
 
 #[derive(Resource, Default)]
@@ -102,6 +106,17 @@ fn draw_grid_view(
                             ..default()
                         });
                     }
+                    Element::ColorCorrected(material) => {
+                        builder.spawn(MaterialNodeBundle {
+                            style: Style {
+                                width: Val::Percent(100.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            material: material.clone(),
+                            ..default()
+                        });
+                    }
                 }
             });
     });
@@ -1,14 +1,24 @@
 use bevy::prelude::*;
 use bevy_ort::BevyOrtPlugin;
 
-pub mod ffmpeg;
+pub mod calibration;
+pub mod colmap;
+pub mod decoder;
+pub mod demux;
+pub mod depacketize;
+pub mod extract;
 pub mod grid_view;
+pub mod manifest;
 pub mod materials;
 pub mod matting;
 pub mod mp4;
 pub mod person_detect;
 pub mod pipeline;
+pub mod preroll;
+pub mod recover;
 pub mod stream;
+pub mod sync;
+pub mod testsrc;
 pub mod yolo;
 
 
@@ -27,6 +37,8 @@ impl Plugin for LightFieldPlugin {
         app.add_plugins(stream::RtspStreamPlugin {
             stream_config: self.stream_config.clone(),
         });
+        app.add_plugins(preroll::PreRollPlugin);
+        app.add_plugins(sync::FrameSyncPlugin);
         app.add_plugins(yolo::YoloPlugin);
     }
 }
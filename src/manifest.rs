@@ -0,0 +1,234 @@
+use std::{
+    collections::BTreeMap,
+    io::BufReader,
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Error;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+
+/// what a pipeline node output was computed from, written to `<node directory>/manifest.json` once the node completes
+///
+/// a node directory without a manifest is a partial output of an interrupted run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeManifest {
+    pub node: String,
+    pub version: u32,
+
+    /// the settings the output depends on
+    pub config: serde_json::Value,
+
+    pub inputs: BTreeMap<String, FileFingerprint>,
+
+    /// output paths relative to the node directory and their sizes
+    pub outputs: BTreeMap<String, u64>,
+}
+
+impl NodeManifest {
+    pub fn path(directory: impl AsRef<Path>) -> std::path::PathBuf {
+        directory.as_ref().join("manifest.json")
+    }
+
+    pub fn load(directory: impl AsRef<Path>) -> Result<Option<Self>, Error> {
+        let path = Self::path(directory);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = std::fs::File::open(path)?;
+        Ok(Some(serde_json::from_reader(BufReader::new(file))?))
+    }
+
+    /// written last, so only completed outputs have a manifest
+    pub fn save(&self, directory: impl AsRef<Path>) -> Result<(), Error> {
+        let path = Self::path(&directory);
+        let partial_path = path.with_extension("json.partial");
+
+        serde_json::to_writer_pretty(std::fs::File::create(&partial_path)?, self)?;
+        std::fs::rename(partial_path, path)?;
+
+        Ok(())
+    }
+
+    /// lists the files of a completed node directory
+    pub fn outputs(directory: impl AsRef<Path>) -> Result<BTreeMap<String, u64>, Error> {
+        let directory = directory.as_ref();
+        let mut outputs = BTreeMap::new();
+        let mut pending = vec![directory.to_path_buf()];
+
+        while let Some(current) = pending.pop() {
+            for entry in std::fs::read_dir(current)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                    continue;
+                }
+
+                let path = entry.path();
+                let relative = path.strip_prefix(directory)?.to_string_lossy().replace('\\', "/");
+                if relative != "manifest.json" {
+                    outputs.insert(relative, metadata.len());
+                }
+            }
+        }
+
+        Ok(outputs)
+    }
+
+    /// why the output in `directory` has to be recomputed for `expected`, `None` if it can be reused
+    ///
+    /// `expected.outputs` is ignored, the recorded outputs are compared against the directory instead
+    pub fn stale_reason(&self, expected: &NodeManifest, directory: impl AsRef<Path>) -> Option<String> {
+        if self.node != expected.node || self.version != expected.version {
+            return Some(format!("node version changed from {} to {}", self.version, expected.version));
+        }
+
+        if self.config != expected.config {
+            return Some("config changed".to_string());
+        }
+
+        if self.inputs.len() != expected.inputs.len() {
+            return Some(format!("input count changed from {} to {}", self.inputs.len(), expected.inputs.len()));
+        }
+
+        let changed_input = expected.inputs.iter()
+            .find(|(path, fingerprint)| self.inputs.get(*path).map(|previous| &previous.blake3) != Some(&fingerprint.blake3));
+        if let Some((path, _)) = changed_input {
+            return Some(format!("input {} changed", path));
+        }
+
+        let directory = directory.as_ref();
+        let changed_output = self.outputs.iter()
+            .find(|(path, &size)| std::fs::metadata(directory.join(path)).map(|metadata| metadata.len()).ok() != Some(size));
+        if let Some((path, _)) = changed_output {
+            return Some(format!("output {} is missing or was modified", path));
+        }
+
+        None
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileFingerprint {
+    pub blake3: String,
+    pub size: u64,
+
+    /// nanoseconds since the unix epoch
+    pub modified: u64,
+}
+
+impl FileFingerprint {
+    /// hashes `path`, unless `previous` has the same size and modification time
+    pub fn compute(path: impl AsRef<Path>, previous: Option<&FileFingerprint>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let metadata = std::fs::metadata(path)?;
+
+        let size = metadata.len();
+        let modified = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+
+        if let Some(previous) = previous {
+            if previous.size == size && previous.modified == modified {
+                return Ok(previous.clone());
+            }
+        }
+
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(std::fs::File::open(path)?)?;
+
+        Ok(Self {
+            blake3: hasher.finalize().to_hex().to_string(),
+            size,
+            modified,
+        })
+    }
+}
+
+
+/// fingerprints of `paths`, reusing the hashes of `previous` for unmodified files
+pub fn fingerprint_files(
+    paths: &[String],
+    previous: Option<&NodeManifest>,
+) -> Result<BTreeMap<String, FileFingerprint>, Error> {
+    paths.par_iter()
+        .map(|path| {
+            let previous = previous.and_then(|manifest| manifest.inputs.get(path));
+            Ok((path.clone(), FileFingerprint::compute(path, previous)?))
+        })
+        .collect()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn write(directory: &Path, name: &str, content: &[u8]) -> String {
+        let path = directory.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn manifest(inputs: BTreeMap<String, FileFingerprint>, config: serde_json::Value) -> NodeManifest {
+        NodeManifest {
+            node: "test".to_string(),
+            version: 1,
+            config,
+            inputs,
+            outputs: BTreeMap::new(),
+        }
+    }
+
+
+    #[test]
+    fn test_manifest_staleness() {
+        let directory = std::env::temp_dir().join(format!("bevy_light_field_manifest_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let input = write(&directory, "input/0.png", b"frame");
+        let output_directory = directory.join("output");
+        write(&output_directory, "0/0.png", b"result");
+
+        let inputs = fingerprint_files(std::slice::from_ref(&input), None).unwrap();
+        let recorded = NodeManifest {
+            outputs: NodeManifest::outputs(&output_directory).unwrap(),
+            ..manifest(inputs.clone(), serde_json::json!({ "rotation": 90.0 }))
+        };
+        assert_eq!(recorded.outputs.get("0/0.png"), Some(&6));
+
+        recorded.save(&output_directory).unwrap();
+        let recorded = NodeManifest::load(&output_directory).unwrap().unwrap();
+        assert!(!NodeManifest::outputs(&output_directory).unwrap().contains_key("manifest.json"));
+
+        let expected = manifest(fingerprint_files(std::slice::from_ref(&input), Some(&recorded)).unwrap(), serde_json::json!({ "rotation": 90.0 }));
+        assert_eq!(recorded.stale_reason(&expected, &output_directory), None);
+
+        let rotated = manifest(inputs, serde_json::json!({ "rotation": 180.0 }));
+        assert!(recorded.stale_reason(&rotated, &output_directory).is_some());
+
+        let bumped = NodeManifest {
+            version: 2,
+            ..expected.clone()
+        };
+        assert!(recorded.stale_reason(&bumped, &output_directory).is_some());
+
+        write(&directory, "input/0.png", b"other frame");
+        let modified = manifest(fingerprint_files(&[input], Some(&recorded)).unwrap(), serde_json::json!({ "rotation": 90.0 }));
+        assert!(recorded.stale_reason(&modified, &output_directory).is_some(), "modified inputs are rehashed");
+
+        std::fs::remove_file(output_directory.join("0/0.png")).unwrap();
+        assert!(recorded.stale_reason(&expected, &output_directory).is_some(), "deleted outputs are recomputed");
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
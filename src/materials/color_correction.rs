@@ -0,0 +1,60 @@
+use bevy::{
+    prelude::*,
+    asset::load_internal_asset,
+    render::render_resource::*,
+};
+
+use crate::calibration::color::ColorCorrection;
+
+
+const COLOR_CORRECTION_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(8290517364);
+
+pub struct ColorCorrectionPlugin;
+impl Plugin for ColorCorrectionPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            COLOR_CORRECTION_SHADER_HANDLE,
+            "color_correction.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.add_plugins(UiMaterialPlugin::<ColorCorrectionMaterial>::default());
+    }
+}
+
+
+/// applies the color calibration of a camera to its stream
+#[derive(AsBindGroup, Asset, TypePath, Debug, Clone)]
+pub struct ColorCorrectionMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    pub input: Handle<Image>,
+
+    #[uniform(2)]
+    pub correction: ColorCorrectionUniform,
+}
+
+impl UiMaterial for ColorCorrectionMaterial {
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::Handle(COLOR_CORRECTION_SHADER_HANDLE)
+    }
+}
+
+
+#[derive(ShaderType, Debug, Clone)]
+pub struct ColorCorrectionUniform {
+    pub matrix: Mat3,
+    pub gamma: Vec3,
+}
+
+impl From<&ColorCorrection> for ColorCorrectionUniform {
+    fn from(correction: &ColorCorrection) -> Self {
+        let row = |row: usize| Vec3::from_array(correction.matrix[row].map(|value| value as f32));
+
+        Self {
+            matrix: Mat3::from_cols(row(0), row(1), row(2)).transpose(),
+            gamma: Vec3::from_array(correction.gamma.map(|value| value as f32)),
+        }
+    }
+}
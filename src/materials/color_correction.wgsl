@@ -0,0 +1,38 @@
+#import bevy_ui::ui_vertex_output::UiVertexOutput
+
+
+struct ColorCorrection {
+    matrix: mat3x3<f32>,
+    gamma: vec3<f32>,
+};
+
+@group(1) @binding(0) var input_texture: texture_2d<f32>;
+@group(1) @binding(1) var input_sampler: sampler;
+
+@group(1) @binding(2) var<uniform> correction: ColorCorrection;
+
+
+fn linear_to_srgb(linear: vec3<f32>) -> vec3<f32> {
+    return select(
+        1.055 * pow(linear, vec3<f32>(1.0 / 2.4)) - 0.055,
+        linear * 12.92,
+        linear <= vec3<f32>(0.0031308),
+    );
+}
+
+
+@fragment
+fn fragment(in: UiVertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(
+        input_texture,
+        input_sampler,
+        in.uv,
+    );
+
+    // the srgb stream texture is decoded when sampled, the tone curves apply to the camera values
+    let camera = clamp(linear_to_srgb(color.rgb), vec3<f32>(0.0), vec3<f32>(1.0));
+    let linear = correction.matrix * pow(camera, correction.gamma);
+
+    // written as linear srgb, which the srgb target encodes
+    return vec4<f32>(clamp(linear, vec3<f32>(0.0), vec3<f32>(1.0)), color.a);
+}
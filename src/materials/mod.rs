@@ -1,11 +1,15 @@
 use bevy::prelude::*;
 
+pub mod color_correction;
 pub mod foreground;
 
 
 pub struct StreamMaterialsPlugin;
 impl Plugin for StreamMaterialsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(foreground::ForegroundPlugin);
+        app.add_plugins((
+            color_correction::ColorCorrectionPlugin,
+            foreground::ForegroundPlugin,
+        ));
     }
 }
@@ -1,6 +1,7 @@
 use bevy::{
     prelude::*,
     ecs::system::CommandQueue,
+    render::render_resource::Extent3d,
     tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
 };
 use bevy_ort::{
@@ -13,7 +14,10 @@ use bevy_ort::{
 
 use crate::{
     materials::foreground::ForegroundMaterial,
-    stream::StreamId,
+    stream::{
+        StreamId,
+        StreamResolutionChanged,
+    },
 };
 
 
@@ -47,6 +51,7 @@ impl Plugin for MattingPlugin {
         app.insert_resource(self.max_inference_size.clone());
         app.add_systems(Startup, load_modnet);
         app.add_systems(Update, matting_inference);
+        app.add_systems(Update, resize_matted_streams);
     }
 }
 
@@ -61,6 +66,41 @@ fn load_modnet(
 }
 
 
+/// a cleared mask at `resolution`, the rows of the previous mask would be misaligned with the new frames
+fn cleared_mask(mask: &Image, resolution: UVec2) -> Image {
+    let pixel_size = mask.data.len() / (mask.width() * mask.height()).max(1) as usize;
+
+    let mut cleared = mask.clone();
+    cleared.texture_descriptor.size = Extent3d {
+        width: resolution.x,
+        height: resolution.y,
+        ..default()
+    };
+    cleared.data = vec![0; resolution.x as usize * resolution.y as usize * pixel_size];
+    cleared
+}
+
+
+/// keeps mask images at the resolution of their input stream, the mask is cleared until the next inference
+fn resize_matted_streams(
+    mut images: ResMut<Assets<Image>>,
+    mut foreground_materials: ResMut<Assets<ForegroundMaterial>>,
+    mut ev_resolution: EventReader<StreamResolutionChanged>,
+    matted_streams: Query<&MattedStream>,
+) {
+    for ev in ev_resolution.read() {
+        for matted_stream in matted_streams.iter().filter(|matted_stream| matted_stream.stream_id == ev.stream_id) {
+            if let Some(mask) = images.get_mut(&matted_stream.output) {
+                *mask = cleared_mask(mask, ev.resolution);
+            }
+
+            // touch the material so its bind group picks up the reallocated textures
+            foreground_materials.get_mut(&matted_stream.material);
+        }
+    }
+}
+
+
 #[derive(Default)]
 struct ModnetComputePipeline(Option<Task<CommandQueue>>);
 
@@ -97,7 +137,12 @@ fn matting_inference(
     let (inputs, outputs): (Vec<_>, Vec<_>) = matted_streams.iter()
         .map(|(_, matted_stream)| {
             let input = images.get(matted_stream.input.clone()).unwrap();
-            let output = (matted_stream.output.clone(), matted_stream.material.clone());
+            let output = (
+                matted_stream.output.clone(),
+                matted_stream.material.clone(),
+                matted_stream.input.clone(),
+                input.size(),
+            );
 
             (input.clone(), output)
         })
@@ -138,7 +183,13 @@ fn matting_inference(
                 command_queue.push(move |world: &mut World| {
                     world.resource_scope(|world, mut images: Mut<Assets<Image>>| {
                         world.resource_scope(|_world, mut foreground_materials: Mut<Assets<ForegroundMaterial>>| {
-                            outputs.into_iter().zip(mask_images).for_each(|((mask, material), mask_image)| {
+                            outputs.into_iter().zip(mask_images).for_each(|((mask, material, input, input_size), mask_image)| {
+                                // masks of frames from before a resolution change are dropped
+                                let resized = images.get(&input).map_or(true, |input| input.size() != input_size);
+                                if resized {
+                                    return;
+                                }
+
                                 images.insert(mask, mask_image);
                                 foreground_materials.get_mut(&material).unwrap();
                             });
@@ -157,3 +208,37 @@ fn matting_inference(
 
     *pipeline_local = ModnetComputePipeline(Some(task));
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bevy::render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{TextureDimension, TextureFormat},
+    };
+
+
+    #[test]
+    fn test_cleared_mask_follows_resolution() {
+        let mask = Image::new_fill(
+            Extent3d {
+                width: 4,
+                height: 2,
+                ..default()
+            },
+            TextureDimension::D2,
+            &[255],
+            TextureFormat::R8Unorm,
+            RenderAssetUsages::default(),
+        );
+
+        let resized = cleared_mask(&mask, UVec2::new(6, 3));
+
+        assert_eq!(resized.size(), UVec2::new(6, 3));
+        assert_eq!(resized.texture_descriptor.format, TextureFormat::R8Unorm);
+        assert_eq!(resized.data.len(), 6 * 3, "one byte per pixel of the new frame");
+        assert!(resized.data.iter().all(|&alpha| alpha == 0), "rows of the old mask would be misaligned");
+    }
+}
@@ -1,10 +1,18 @@
 // https://github.com/scottlamb/retina/blob/main/examples/client/src/mp4.rs
 
 use anyhow::{anyhow, bail, Error};
-use bytes::{Buf, BufMut, BytesMut};
-use retina::codec::{AudioParameters, ParametersRef, VideoParameters};
+use bytes::{BufMut, Bytes, BytesMut};
+use retina::codec::{AudioParameters, VideoFrame, VideoParameters};
+use serde::{Deserialize, Serialize};
 
-use std::io::SeekFrom;
+use crate::decoder::VideoCodec;
+
+use std::{
+    io::SeekFrom,
+    num::NonZeroU32,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tokio::io::{AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 
 
@@ -24,13 +32,273 @@ macro_rules! write_box {
         r
     }};
 }
+pub(crate) use write_box;
+
+/// Writes an `avc1`/`hvc1` sample entry around a decoder configuration record (`avcC`/`hvcC` contents).
+pub(crate) fn write_video_sample_entry(
+    buf: &mut BytesMut,
+    codec: VideoCodec,
+    pixel_dimensions: (u32, u32),
+    configuration_record: &[u8],
+) -> Result<(), Error> {
+    let (sample_entry, configuration) = match codec {
+        VideoCodec::H264 => (b"avc1", b"avcC"),
+        VideoCodec::H265 => (b"hvc1", b"hvcC"),
+    };
+
+    write_box!(buf, sample_entry, {
+        buf.put_u32(0);
+        buf.put_u32(1); // data_reference_index = 1
+        buf.extend_from_slice(&[0; 16]);
+        buf.put_u16(u16::try_from(pixel_dimensions.0)?);
+        buf.put_u16(u16::try_from(pixel_dimensions.1)?);
+        buf.extend_from_slice(&[
+            0x00, 0x48, 0x00, 0x00, // horizresolution
+            0x00, 0x48, 0x00, 0x00, // vertresolution
+            0x00, 0x00, 0x00, 0x00, // reserved
+            0x00, 0x01, // frame count
+            0x00, 0x00, 0x00, 0x00, // compressorname
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x00, 0x00, 0x00, //
+            0x00, 0x18, 0xff, 0xff, // depth + pre_defined
+        ]);
+        write_box!(buf, configuration, {
+            buf.extend_from_slice(configuration_record);
+        });
+    });
+    Ok(())
+}
+
+/// The `wide` placeholder and 32-bit `mdat` header written before the samples of a progressive recording.
+pub(crate) const MDAT_PLACEHOLDER: &[u8; 16] = b"\0\0\0\x08wide\0\0\0\0mdat";
+
+/// The `mdat` header which ends right before the samples. Past 4 GiB it is a 16-byte
+/// large size header which takes over the `wide` box of [`MDAT_PLACEHOLDER`].
+pub(crate) fn mdat_header(payload_size: u64) -> Vec<u8> {
+    match u32::try_from(payload_size + 8) {
+        Ok(size) => [&size.to_be_bytes()[..], b"mdat"].concat(),
+        Err(_) => [&1u32.to_be_bytes()[..], b"mdat", &(payload_size + 16).to_be_bytes()[..]].concat(),
+    }
+}
+
+/// seconds between the mp4 (1904) and unix (1970) epochs
+pub(crate) const MP4_UNIX_OFFSET_SECS: u64 = 2_082_844_800;
+
+/// File-level metadata of a recording: the `mvhd`/`tkhd`/`mdhd` creation times and
+/// `udta/meta` items under `mdta` keys (as written by `ffmpeg -movflags use_metadata_tags`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mp4Metadata {
+    pub creation_time: Option<SystemTime>,
+
+    /// (key, UTF-8 value) pairs
+    pub items: Vec<(String, String)>,
+}
+
+impl Mp4Metadata {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.items.iter()
+            .find(|(item_key, _)| item_key == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// seconds since the mp4 epoch, zero when unknown
+    fn mp4_time(&self) -> u64 {
+        self.creation_time
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_unix| since_unix.as_secs() + MP4_UNIX_OFFSET_SECS)
+    }
+}
+
+/// Writes the `udta` box of the metadata items, nothing if there are none.
+pub(crate) fn write_udta(buf: &mut BytesMut, metadata: &Mp4Metadata) -> Result<(), Error> {
+    if metadata.items.is_empty() {
+        return Ok(());
+    }
+
+    write_box!(buf, b"udta", {
+        write_box!(buf, b"meta", {
+            buf.put_u32(0); // version, flags
+            write_box!(buf, b"hdlr", {
+                buf.extend_from_slice(&[
+                    0x00, 0x00, 0x00, 0x00, // version + flags
+                    0x00, 0x00, 0x00, 0x00, // pre_defined
+                    b'm', b'd', b't', b'a', // handler = mdta
+                    0x00, 0x00, 0x00, 0x00, // reserved[0]
+                    0x00, 0x00, 0x00, 0x00, // reserved[1]
+                    0x00, 0x00, 0x00, 0x00, // reserved[2]
+                    0x00, // name, zero-terminated (empty)
+                ]);
+            });
+            write_box!(buf, b"keys", {
+                buf.put_u32(0); // version, flags
+                buf.put_u32(u32::try_from(metadata.items.len())?); // entry_count
+                for (key, _) in &metadata.items {
+                    buf.put_u32(u32::try_from(key.len() + 8)?); // key_size
+                    buf.extend_from_slice(b"mdta"); // key_namespace
+                    buf.extend_from_slice(key.as_bytes());
+                }
+            });
+            write_box!(buf, b"ilst", {
+                for (i, (_, value)) in metadata.items.iter().enumerate() {
+                    // items are boxes named by their 1-based key index
+                    let key_index = u32::try_from(i + 1)?.to_be_bytes();
+                    write_box!(buf, &key_index, {
+                        write_box!(buf, b"data", {
+                            buf.put_u32(1); // type indicator: UTF-8
+                            buf.put_u32(0); // locale
+                            buf.extend_from_slice(value.as_bytes());
+                        });
+                    });
+                }
+            });
+        });
+    });
+    Ok(())
+}
+
+/// The codec, dimensions and decoder configuration record (`avcC`/`hvcC` contents) of a video
+/// stream, from the H.264 parameters of retina or the H.265 parameters of [`crate::depacketize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoStreamParameters {
+    pub codec: VideoCodec,
+    pub pixel_dimensions: (u32, u32),
+    pub configuration_record: Vec<u8>,
+}
+
+impl TryFrom<&VideoParameters> for VideoStreamParameters {
+    type Error = Error;
+
+    fn try_from(parameters: &VideoParameters) -> Result<Self, Error> {
+        let codec = VideoCodec::from_rfc6381_codec(parameters.rfc6381_codec())
+            .ok_or_else(|| anyhow!("unsupported video codec {}", parameters.rfc6381_codec()))?;
+
+        Ok(Self {
+            codec,
+            pixel_dimensions: parameters.pixel_dimensions(),
+            configuration_record: parameters.extra_data().to_vec(),
+        })
+    }
+}
+
+/// An encoded video access unit together with the parameters needed to decode it,
+/// detached from the RTSP session so it can be buffered before being written.
+#[derive(Debug, Clone)]
+pub struct EncodedVideoFrame {
+    /// length-prefixed NAL units, as received
+    pub data: Bytes,
+    pub pts: i64,
+    pub clock_rate: NonZeroU32,
+    pub loss: u16,
+    pub is_random_access_point: bool,
+
+    /// `None` until the stream has announced its parameters, such frames are not written
+    pub parameters: Option<Arc<VideoStreamParameters>>,
+}
+
+impl EncodedVideoFrame {
+    pub fn new(frame: VideoFrame, parameters: Option<Arc<VideoStreamParameters>>) -> Self {
+        let timestamp = frame.timestamp();
+
+        Self {
+            pts: timestamp.timestamp(),
+            clock_rate: timestamp.clock_rate(),
+            loss: frame.loss(),
+            is_random_access_point: frame.is_random_access_point(),
+            data: Bytes::from(frame.into_data()),
+            parameters,
+        }
+    }
+
+    /// presentation time in seconds
+    pub fn seconds(&self) -> f64 {
+        self.pts as f64 / self.clock_rate.get() as f64
+    }
+}
+
+
+/// Fragment boundaries of a fragmented recording, a fragment is written when either limit is reached.
+///
+/// A fragmented recording starts with an init segment (`moov` without samples), followed by
+/// `moof`/`mdat` pairs, so everything up to the last complete fragment stays playable after
+/// an abrupt exit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FragmentOptions {
+    pub max_frames: u32,
+    pub max_duration: Duration,
+}
+
+impl Default for FragmentOptions {
+    fn default() -> Self {
+        Self {
+            max_frames: 30,
+            max_duration: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A sample held until the following sample of its track gives it a duration.
+struct PendingSample {
+    data: Bytes,
+    pts: i64,
+    is_sync: bool,
+}
+
+/// The samples and decode times of the fragment being built.
+#[derive(Default)]
+struct FragmentState {
+    sequence_number: u32,
+    video: Vec<PendingSample>,
+    audio: Vec<PendingSample>,
+    video_decode_time: u64,
+    audio_decode_time: u64,
+
+    /// durations of the last written samples, for a final sample without a successor
+    video_duration: u32,
+    audio_duration: u32,
+    audio_started: bool,
+
+    /// bytes written to the file so far
+    position: u64,
+}
+
+/// Removes the samples of `pending` which can be written, with their durations.
+/// The last sample is kept back (its duration is unknown) unless `finish` is set,
+/// then it repeats the duration before it, or `previous_duration` of the last fragment.
+fn take_fragment_samples(
+    pending: &mut Vec<PendingSample>,
+    finish: bool,
+    previous_duration: u32,
+) -> Result<Vec<(PendingSample, u32)>, Error> {
+    let count = if finish {
+        pending.len()
+    } else {
+        pending.len().saturating_sub(1)
+    };
+
+    let mut durations = Vec::with_capacity(count);
+    for i in 0..count {
+        let duration = match pending.get(i + 1) {
+            Some(next) => u32::try_from((next.pts - pending[i].pts).max(0))?,
+            None => durations.last().copied().unwrap_or(previous_duration),
+        };
+        durations.push(duration);
+    }
+
+    Ok(pending.drain(..count).zip(durations).collect())
+}
+
 
 /// Writes `.mp4` data to a sink.
 /// See module-level documentation for details.
 pub struct Mp4Writer<W: AsyncWrite + AsyncSeek + Send + Unpin> {
-    mdat_start: u32,
-    mdat_pos: u32,
-    video_params: Vec<VideoParameters>,
+    mdat_start: u64,
+    mdat_pos: u64,
+    video_params: Vec<Arc<VideoStreamParameters>>,
 
     /// The most recently used 1-based index within `video_params`.
     cur_video_params_sample_description_index: Option<u32>,
@@ -42,13 +310,24 @@ pub struct Mp4Writer<W: AsyncWrite + AsyncSeek + Send + Unpin> {
 
     video_trak: TrakTracker,
     audio_trak: TrakTracker,
+
+    /// The pts of the first video sample, and the offset (in the 90 kHz movie timescale)
+    /// of the first audio sample from it, used to align the tracks with an edit list.
+    first_video_pts: Option<i64>,
+    audio_start: u64,
+
+    /// `Some` for fragmented recordings.
+    fragments: Option<FragmentOptions>,
+    metadata: Mp4Metadata,
+    fragment: FragmentState,
+
     inner: W,
 }
 
 /// A chunk: a group of samples that have consecutive byte positions and same sample description.
 struct Chunk {
     first_sample_number: u32, // 1-based index
-    byte_pos: u32,            // starting byte of first sample
+    byte_pos: u64,            // starting byte of first sample
     sample_description_index: u32,
 }
 
@@ -56,7 +335,7 @@ struct Chunk {
 #[derive(Default)]
 struct TrakTracker {
     samples: u32,
-    next_pos: Option<u32>,
+    next_pos: Option<u64>,
     chunks: Vec<Chunk>,
     sizes: Vec<u32>,
 
@@ -72,9 +351,9 @@ impl TrakTracker {
     fn add_sample(
         &mut self,
         sample_description_index: u32,
-        byte_pos: u32,
+        byte_pos: u64,
         size: u32,
-        timestamp: retina::Timestamp,
+        pts: i64,
         loss: u16,
         allow_loss: bool,
     ) -> Result<(), Error> {
@@ -93,9 +372,9 @@ impl TrakTracker {
             });
         }
         self.sizes.push(size);
-        self.next_pos = Some(byte_pos + size);
-        if let Some(last_pts) = self.last_pts.replace(timestamp.timestamp()) {
-            let duration = timestamp.timestamp().checked_sub(last_pts).unwrap();
+        self.next_pos = Some(byte_pos + u64::from(size));
+        if let Some(last_pts) = self.last_pts.replace(pts) {
+            let duration = pts.checked_sub(last_pts).unwrap();
             self.tot_duration += u64::try_from(duration).unwrap();
             let duration = u32::try_from(duration)?;
             match self.durations.last_mut() {
@@ -117,7 +396,7 @@ impl TrakTracker {
         (self.durations.len() * 8) + // stts
         (self.chunks.len() * 12) +   // stsc
         (self.sizes.len() * 4) +     // stsz
-        (self.chunks.len() * 4) // stco
+        (self.chunks.len() * 8) // stco or co64
     }
 
     fn write_common_stbl_parts(&self, buf: &mut BytesMut) -> Result<(), Error> {
@@ -133,19 +412,14 @@ impl TrakTracker {
         write_box!(buf, b"stsc", {
             buf.put_u32(0); // version
             buf.put_u32(u32::try_from(self.chunks.len())?);
-            let mut prev_sample_number = 1;
-            let mut chunk_number = 1;
-            if !self.chunks.is_empty() {
-                for c in &self.chunks[1..] {
-                    buf.put_u32(chunk_number);
-                    buf.put_u32(c.first_sample_number - prev_sample_number);
-                    buf.put_u32(c.sample_description_index);
-                    prev_sample_number = c.first_sample_number;
-                    chunk_number += 1;
-                }
-                buf.put_u32(chunk_number);
-                buf.put_u32(self.samples + 1 - prev_sample_number);
-                buf.put_u32(1); // sample_description_index
+            for (i, c) in self.chunks.iter().enumerate() {
+                let next_sample_number = self.chunks
+                    .get(i + 1)
+                    .map_or(self.samples + 1, |next| next.first_sample_number);
+
+                buf.put_u32(u32::try_from(i + 1)?); // first_chunk
+                buf.put_u32(next_sample_number - c.first_sample_number); // samples_per_chunk
+                buf.put_u32(c.sample_description_index);
             }
         });
         write_box!(buf, b"stsz", {
@@ -156,13 +430,25 @@ impl TrakTracker {
                 buf.put_u32(*s);
             }
         });
-        write_box!(buf, b"stco", {
-            buf.put_u32(0); // version
-            buf.put_u32(u32::try_from(self.chunks.len())?); // entry_count
-            for c in &self.chunks {
-                buf.put_u32(c.byte_pos);
-            }
-        });
+        // 64-bit chunk offsets only once the samples reach past 4 GiB
+        let large_offsets = self.chunks.last().is_some_and(|c| c.byte_pos > u64::from(u32::MAX));
+        if large_offsets {
+            write_box!(buf, b"co64", {
+                buf.put_u32(0); // version
+                buf.put_u32(u32::try_from(self.chunks.len())?); // entry_count
+                for c in &self.chunks {
+                    buf.put_u64(c.byte_pos);
+                }
+            });
+        } else {
+            write_box!(buf, b"stco", {
+                buf.put_u32(0); // version
+                buf.put_u32(u32::try_from(self.chunks.len())?); // entry_count
+                for c in &self.chunks {
+                    buf.put_u32(c.byte_pos as u32);
+                }
+            });
+        }
         Ok(())
     }
 }
@@ -171,18 +457,37 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
     pub async fn new(
         audio_params: Option<Box<AudioParameters>>,
         allow_loss: bool,
+        fragments: Option<FragmentOptions>,
+        metadata: Mp4Metadata,
         mut inner: W,
     ) -> Result<Self, Error> {
         let mut buf = BytesMut::new();
-        write_box!(&mut buf, b"ftyp", {
-            buf.extend_from_slice(&[
-                b'i', b's', b'o', b'm', // major_brand
-                0, 0, 0, 0, // minor_version
-                b'i', b's', b'o', b'm', // compatible_brands[0]
-            ]);
-        });
-        buf.extend_from_slice(&b"\0\0\0\0mdat"[..]);
-        let mdat_start = u32::try_from(buf.len())?;
+        match fragments {
+            None => {
+                write_box!(&mut buf, b"ftyp", {
+                    buf.extend_from_slice(&[
+                        b'i', b's', b'o', b'm', // major_brand
+                        0, 0, 0, 0, // minor_version
+                        b'i', b's', b'o', b'm', // compatible_brands[0]
+                    ]);
+                });
+                // the mdat size is written by `finish`, the `wide` box leaves room for a large size
+                buf.extend_from_slice(&MDAT_PLACEHOLDER[..]);
+            },
+            Some(_) => {
+                // iso5 for default-base-is-moof; the init segment follows once the video parameters are known.
+                write_box!(&mut buf, b"ftyp", {
+                    buf.extend_from_slice(&[
+                        b'i', b's', b'o', b'5', // major_brand
+                        0, 0, 0, 0, // minor_version
+                        b'i', b's', b'o', b'5', // compatible_brands[0]
+                        b'i', b's', b'o', b'6', // compatible_brands[1]
+                        b'm', b'p', b'4', b'1', // compatible_brands[2]
+                    ]);
+                });
+            },
+        }
+        let mdat_start = buf.len() as u64;
         inner.write_all(&buf).await?;
         Ok(Mp4Writer {
             inner,
@@ -193,12 +498,27 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
             video_trak: TrakTracker::default(),
             audio_trak: TrakTracker::default(),
             video_sync_sample_nums: Vec::new(),
+            first_video_pts: None,
+            audio_start: 0,
+            fragments,
+            metadata,
+            fragment: FragmentState {
+                position: mdat_start,
+                ..FragmentState::default()
+            },
             mdat_start,
             mdat_pos: mdat_start,
         })
     }
 
-    pub async fn finish(mut self) -> Result<(), Error> {
+    /// Writes the pending samples and the `moov` of progressive recordings, returning the sink.
+    pub async fn finish(mut self) -> Result<W, Error> {
+        if self.fragments.is_some() {
+            self.write_fragment(true).await?;
+            self.inner.flush().await?;
+            return Ok(self.inner);
+        }
+
         self.video_trak.finish();
         self.audio_trak.finish();
         let mut buf = BytesMut::with_capacity(
@@ -207,38 +527,210 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
                 + 4 * self.video_sync_sample_nums.len(),
         );
         write_box!(&mut buf, b"moov", {
-            write_box!(&mut buf, b"mvhd", {
-                buf.put_u32(1 << 24); // version
-                buf.put_u64(0); // creation_time
-                buf.put_u64(0); // modification_time
-                buf.put_u32(90000); // timescale
-                buf.put_u64(self.video_trak.tot_duration);
-                buf.put_u32(0x00010000); // rate
-                buf.put_u16(0x0100); // volume
-                buf.put_u16(0); // reserved
-                buf.put_u64(0); // reserved
-                for v in &[0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000] {
-                    buf.put_u32(*v); // matrix
-                }
-                for _ in 0..6 {
-                    buf.put_u32(0); // pre_defined
-                }
-                buf.put_u32(2); // next_track_id
-            });
+            self.write_mvhd(&mut buf)?;
             if self.video_trak.samples > 0 {
                 self.write_video_trak(&mut buf)?;
             }
             if self.audio_trak.samples > 0 {
                 self.write_audio_trak(&mut buf, self.audio_params.as_ref().unwrap())?;
             }
+            write_udta(&mut buf, &self.metadata)?;
         });
         self.inner.write_all(&buf).await?;
+
+        let mdat_header = mdat_header(self.mdat_pos - self.mdat_start);
         self.inner
-            .seek(SeekFrom::Start(u64::from(self.mdat_start - 8)))
-            .await?;
-        self.inner
-            .write_all(&(self.mdat_pos + 8 - self.mdat_start).to_be_bytes()[..])
+            .seek(SeekFrom::Start(self.mdat_start - mdat_header.len() as u64))
             .await?;
+        self.inner.write_all(&mdat_header).await?;
+        self.inner.flush().await?;
+        Ok(self.inner)
+    }
+
+    /// Whether frames with `parameters` can be written. A fragmented recording only has the
+    /// sample description of its init segment, other parameters need a new recording.
+    pub fn accepts_parameters(&self, parameters: &VideoStreamParameters) -> bool {
+        match (self.fragments, self.video_params.first()) {
+            (Some(_), Some(initial)) => **initial == *parameters,
+            _ => true,
+        }
+    }
+
+    /// Approximate size of the file so far, excluding the `moov` written by `finish`.
+    pub fn bytes_written(&self) -> u64 {
+        match self.fragments {
+            Some(_) => self.fragment.position,
+            None => self.mdat_pos,
+        }
+    }
+
+    fn write_mvhd(&self, buf: &mut BytesMut) -> Result<(), Error> {
+        write_box!(buf, b"mvhd", {
+            buf.put_u32(1 << 24); // version
+            buf.put_u64(self.metadata.mp4_time()); // creation_time
+            buf.put_u64(self.metadata.mp4_time()); // modification_time
+            buf.put_u32(90000); // timescale
+            buf.put_u64(self.video_trak.tot_duration);
+            buf.put_u32(0x00010000); // rate
+            buf.put_u16(0x0100); // volume
+            buf.put_u16(0); // reserved
+            buf.put_u64(0); // reserved
+            for v in &[0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000] {
+                buf.put_u32(*v); // matrix
+            }
+            for _ in 0..6 {
+                buf.put_u32(0); // pre_defined
+            }
+            buf.put_u32(3); // next_track_id
+        });
+        Ok(())
+    }
+
+    /// Writes the `moov` of a fragmented recording, describing the tracks without samples.
+    async fn write_init_segment(&mut self) -> Result<(), Error> {
+        let mut buf = BytesMut::with_capacity(1024);
+        write_box!(&mut buf, b"moov", {
+            self.write_mvhd(&mut buf)?;
+            self.write_video_trak(&mut buf)?;
+            if let Some(audio_params) = self.audio_params.as_ref() {
+                self.write_audio_trak(&mut buf, audio_params)?;
+            }
+            write_udta(&mut buf, &self.metadata)?;
+            write_box!(&mut buf, b"mvex", {
+                let track_count = if self.audio_params.is_some() { 2 } else { 1 };
+                for track_id in 1..=track_count {
+                    write_box!(&mut buf, b"trex", {
+                        buf.put_u32(0); // version, flags
+                        buf.put_u32(track_id);
+                        buf.put_u32(1); // default_sample_description_index
+                        buf.put_u32(0); // default_sample_duration
+                        buf.put_u32(0); // default_sample_size
+                        buf.put_u32(0); // default_sample_flags
+                    });
+                }
+            });
+        });
+        self.inner.write_all(&buf).await?;
+        self.inner.flush().await?;
+        self.fragment.position += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the pending samples as a `moof`/`mdat` pair.
+    async fn write_fragment(&mut self, finish: bool) -> Result<(), Error> {
+        let video = take_fragment_samples(&mut self.fragment.video, finish, self.fragment.video_duration)?;
+        let audio = take_fragment_samples(&mut self.fragment.audio, finish, self.fragment.audio_duration)?;
+        if video.is_empty() && audio.is_empty() {
+            return Ok(());
+        }
+
+        self.fragment.sequence_number += 1;
+
+        let trafs = [
+            (1, &video, self.fragment.video_decode_time),
+            (2, &audio, self.fragment.audio_decode_time),
+        ];
+
+        let mut buf = BytesMut::new();
+        let mut data_offsets = vec![];
+        write_box!(&mut buf, b"moof", {
+            write_box!(&mut buf, b"mfhd", {
+                buf.put_u32(0); // version, flags
+                buf.put_u32(self.fragment.sequence_number);
+            });
+            for (track_id, samples, decode_time) in trafs.iter() {
+                if samples.is_empty() {
+                    continue;
+                }
+                write_box!(&mut buf, b"traf", {
+                    write_box!(&mut buf, b"tfhd", {
+                        buf.put_u32(0x020000); // version, flags=default-base-is-moof
+                        buf.put_u32(*track_id);
+                    });
+                    write_box!(&mut buf, b"tfdt", {
+                        buf.put_u32(1 << 24); // version
+                        buf.put_u64(*decode_time);
+                    });
+                    write_box!(&mut buf, b"trun", {
+                        // data-offset, sample-duration, sample-size, sample-flags
+                        buf.put_u32(0x000001 | 0x000100 | 0x000200 | 0x000400);
+                        buf.put_u32(u32::try_from(samples.len())?);
+                        data_offsets.push((buf.len(), samples.iter().map(|(s, _)| s.data.len()).sum::<usize>()));
+                        buf.put_i32(0); // data_offset, patched below
+                        for (sample, duration) in samples.iter() {
+                            buf.put_u32(*duration);
+                            buf.put_u32(u32::try_from(sample.data.len())?);
+                            buf.put_u32(if sample.is_sync {
+                                0x02000000 // sample_depends_on=2 (I-picture)
+                            } else {
+                                0x01010000 // sample_depends_on=1, sample_is_non_sync_sample
+                            });
+                        }
+                    });
+                });
+            }
+        });
+
+        // sample data follows the moof and the mdat header, video before audio
+        let mut data_offset = buf.len() + 8;
+        for (pos, size) in data_offsets {
+            buf[pos..pos + 4].copy_from_slice(&i32::try_from(data_offset)?.to_be_bytes());
+            data_offset += size;
+        }
+        let mdat_size = u32::try_from(data_offset - buf.len())?;
+        buf.put_u32(mdat_size);
+        buf.extend_from_slice(b"mdat");
+        self.fragment.position += buf.len() as u64 + u64::from(mdat_size - 8);
+
+        self.inner.write_all(&buf).await?;
+        for (sample, _) in video.iter().chain(audio.iter()) {
+            self.inner.write_all(&sample.data).await?;
+        }
+        self.inner.flush().await?;
+
+        self.fragment.video_decode_time += video.iter().map(|(_, d)| u64::from(*d)).sum::<u64>();
+        self.fragment.audio_decode_time += audio.iter().map(|(_, d)| u64::from(*d)).sum::<u64>();
+        if let Some((_, duration)) = video.last() {
+            self.fragment.video_duration = *duration;
+        }
+        if let Some((_, duration)) = audio.last() {
+            self.fragment.audio_duration = *duration;
+        }
+        Ok(())
+    }
+
+    async fn fragmented_video(
+        &mut self,
+        frame: &EncodedVideoFrame,
+        parameters: &Arc<VideoStreamParameters>,
+        options: FragmentOptions,
+    ) -> Result<(), Error> {
+        if frame.loss > 0 && !self.allow_loss && self.first_video_pts.is_some() {
+            bail!("Lost {} RTP packets mid-stream", frame.loss);
+        }
+
+        if !self.accepts_parameters(parameters) {
+            bail!("video parameters changed mid-recording, a fragmented recording continues in a new file");
+        }
+
+        if self.video_params.is_empty() {
+            self.video_params.push(parameters.clone());
+            self.write_init_segment().await?;
+        }
+
+        self.first_video_pts.get_or_insert(frame.pts);
+        self.fragment.video.push(PendingSample {
+            data: frame.data.clone(),
+            pts: frame.pts,
+            is_sync: frame.is_random_access_point,
+        });
+
+        // the last pending sample is held back, so a full fragment has one extra sample
+        let pending = &self.fragment.video;
+        let span = (pending[pending.len() - 1].pts - pending[0].pts) as f64 / 90000.0;
+        if pending.len() > options.max_frames as usize || span >= options.max_duration.as_secs_f64() {
+            self.write_fragment(false).await?;
+        }
         Ok(())
     }
 
@@ -246,8 +738,8 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
         write_box!(buf, b"trak", {
             write_box!(buf, b"tkhd", {
                 buf.put_u32((1 << 24) | 7); // version, flags
-                buf.put_u64(0); // creation_time
-                buf.put_u64(0); // modification_time
+                buf.put_u64(self.metadata.mp4_time()); // creation_time
+                buf.put_u64(self.metadata.mp4_time()); // modification_time
                 buf.put_u32(1); // track_id
                 buf.put_u32(0); // reserved
                 buf.put_u64(self.video_trak.tot_duration);
@@ -260,7 +752,7 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
                     buf.put_u32(*v); // matrix
                 }
                 let dims = self.video_params.iter().fold((0, 0), |prev_dims, p| {
-                    let dims = p.pixel_dimensions();
+                    let dims = p.pixel_dimensions;
                     (
                         std::cmp::max(prev_dims.0, dims.0),
                         std::cmp::max(prev_dims.1, dims.1),
@@ -274,8 +766,8 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
             write_box!(buf, b"mdia", {
                 write_box!(buf, b"mdhd", {
                     buf.put_u32(1 << 24); // version
-                    buf.put_u64(0); // creation_time
-                    buf.put_u64(0); // modification_time
+                    buf.put_u64(self.metadata.mp4_time()); // creation_time
+                    buf.put_u64(self.metadata.mp4_time()); // modification_time
                     buf.put_u32(90000); // timebase
                     buf.put_u64(self.video_trak.tot_duration);
                     buf.put_u32(0x55c40000); // language=und + pre-defined
@@ -310,17 +802,20 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
                             buf.put_u32(0); // version
                             buf.put_u32(u32::try_from(self.video_params.len())?); // entry_count
                             for p in &self.video_params {
-                                self.write_video_sample_entry(buf, p)?;
+                                write_video_sample_entry(buf, p.codec, p.pixel_dimensions, &p.configuration_record)?;
                             }
                         });
                         self.video_trak.write_common_stbl_parts(buf)?;
-                        write_box!(buf, b"stss", {
-                            buf.put_u32(0); // version
-                            buf.put_u32(u32::try_from(self.video_sync_sample_nums.len())?);
-                            for n in &self.video_sync_sample_nums {
-                                buf.put_u32(*n);
-                            }
-                        });
+                        // fragments carry their own sync sample flags
+                        if self.fragments.is_none() {
+                            write_box!(buf, b"stss", {
+                                buf.put_u32(0); // version
+                                buf.put_u32(u32::try_from(self.video_sync_sample_nums.len())?);
+                                for n in &self.video_sync_sample_nums {
+                                    buf.put_u32(*n);
+                                }
+                            });
+                        }
                     });
                 });
             });
@@ -333,14 +828,16 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
         buf: &mut BytesMut,
         parameters: &AudioParameters,
     ) -> Result<(), Error> {
+        // durations in the movie timescale
+        let media_duration = self.audio_trak.tot_duration * 90000 / u64::from(parameters.clock_rate());
         write_box!(buf, b"trak", {
             write_box!(buf, b"tkhd", {
                 buf.put_u32((1 << 24) | 7); // version, flags
-                buf.put_u64(0); // creation_time
-                buf.put_u64(0); // modification_time
+                buf.put_u64(self.metadata.mp4_time()); // creation_time
+                buf.put_u64(self.metadata.mp4_time()); // modification_time
                 buf.put_u32(2); // track_id
                 buf.put_u32(0); // reserved
-                buf.put_u64(self.audio_trak.tot_duration);
+                buf.put_u64(self.audio_start + media_duration);
                 buf.put_u64(0); // reserved
                 buf.put_u16(0); // layer
                 buf.put_u16(0); // alternate_group
@@ -352,11 +849,26 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
                 buf.put_u32(0); // width
                 buf.put_u32(0); // height
             });
+            if self.audio_start > 0 {
+                // audio starts after the video (e.g. after a pre-roll), delay it with an empty edit
+                write_box!(buf, b"edts", {
+                    write_box!(buf, b"elst", {
+                        buf.put_u32(0); // version, flags
+                        buf.put_u32(2); // entry_count
+                        buf.put_u32(u32::try_from(self.audio_start)?); // segment_duration
+                        buf.put_i32(-1); // media_time (empty edit)
+                        buf.put_u32(0x00010000); // media_rate
+                        buf.put_u32(u32::try_from(media_duration)?); // segment_duration
+                        buf.put_i32(0); // media_time
+                        buf.put_u32(0x00010000); // media_rate
+                    });
+                });
+            }
             write_box!(buf, b"mdia", {
                 write_box!(buf, b"mdhd", {
                     buf.put_u32(1 << 24); // version
-                    buf.put_u64(0); // creation_time
-                    buf.put_u64(0); // modification_time
+                    buf.put_u64(self.metadata.mp4_time()); // creation_time
+                    buf.put_u64(self.metadata.mp4_time()); // modification_time
                     buf.put_u32(parameters.clock_rate());
                     buf.put_u64(self.audio_trak.tot_duration);
                     buf.put_u32(0x55c40000); // language=und + pre-defined
@@ -401,25 +913,27 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
                         });
                         self.audio_trak.write_common_stbl_parts(buf)?;
 
-                        // AAC requires two samples (really, each is a set of 960 or 1024 samples)
-                        // to decode accurately. See
-                        // https://developer.apple.com/library/archive/documentation/QuickTime/QTFF/QTFFAppenG/QTFFAppenG.html .
-                        write_box!(buf, b"sgpd", {
-                            // BMFF section 8.9.3: SampleGroupDescriptionBox
-                            buf.put_u32(0); // version
-                            buf.extend_from_slice(b"roll"); // grouping type
-                            buf.put_u32(1); // entry_count
-                                            // BMFF section 10.1: AudioRollRecoveryEntry
-                            buf.put_i16(-1); // roll_distance
-                        });
-                        write_box!(buf, b"sbgp", {
-                            // BMFF section 8.9.2: SampleToGroupBox
-                            buf.put_u32(0); // version
-                            buf.extend_from_slice(b"roll"); // grouping type
-                            buf.put_u32(1); // entry_count
-                            buf.put_u32(self.audio_trak.samples);
-                            buf.put_u32(1); // group_description_index
-                        });
+                        if self.fragments.is_none() {
+                            // AAC requires two samples (really, each is a set of 960 or 1024 samples)
+                            // to decode accurately. See
+                            // https://developer.apple.com/library/archive/documentation/QuickTime/QTFF/QTFFAppenG/QTFFAppenG.html .
+                            write_box!(buf, b"sgpd", {
+                                // BMFF section 8.9.3: SampleGroupDescriptionBox
+                                buf.put_u32(0); // version
+                                buf.extend_from_slice(b"roll"); // grouping type
+                                buf.put_u32(1); // entry_count
+                                                // BMFF section 10.1: AudioRollRecoveryEntry
+                                buf.put_i16(-1); // roll_distance
+                            });
+                            write_box!(buf, b"sbgp", {
+                                // BMFF section 8.9.2: SampleToGroupBox
+                                buf.put_u32(0); // version
+                                buf.extend_from_slice(b"roll"); // grouping type
+                                buf.put_u32(1); // entry_count
+                                buf.put_u32(self.audio_trak.samples);
+                                buf.put_u32(1); // group_description_index
+                            });
+                        }
                     });
                 });
             });
@@ -427,110 +941,414 @@ impl<W: AsyncWrite + AsyncSeek + Send + Unpin> Mp4Writer<W> {
         Ok(())
     }
 
-    fn write_video_sample_entry(
-        &self,
-        buf: &mut BytesMut,
-        parameters: &VideoParameters,
-    ) -> Result<(), Error> {
-        // TODO: this should move to client::VideoParameters::sample_entry() or some such.
-        write_box!(buf, b"avc1", {
-            buf.put_u32(0);
-            buf.put_u32(1); // data_reference_index = 1
-            buf.extend_from_slice(&[0; 16]);
-            buf.put_u16(u16::try_from(parameters.pixel_dimensions().0)?);
-            buf.put_u16(u16::try_from(parameters.pixel_dimensions().1)?);
-            buf.extend_from_slice(&[
-                0x00, 0x48, 0x00, 0x00, // horizresolution
-                0x00, 0x48, 0x00, 0x00, // vertresolution
-                0x00, 0x00, 0x00, 0x00, // reserved
-                0x00, 0x01, // frame count
-                0x00, 0x00, 0x00, 0x00, // compressorname
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x00, 0x00, 0x00, //
-                0x00, 0x18, 0xff, 0xff, // depth + pre_defined
-            ]);
-            write_box!(buf, b"avcC", {
-                buf.extend_from_slice(parameters.extra_data());
-            });
-        });
-        Ok(())
-    }
-
     pub async fn video(
         &mut self,
-        stream: &retina::client::Stream,
-        frame: &retina::codec::VideoFrame,
+        frame: &EncodedVideoFrame,
     ) -> Result<(), Error> {
-        let sample_description_index = if let (Some(i), false) = (
-            self.cur_video_params_sample_description_index,
-            frame.has_new_parameters(),
-        ) {
+        let Some(parameters) = frame.parameters.as_ref() else {
+            return Ok(());
+        };
+
+        if let Some(options) = self.fragments {
+            return self.fragmented_video(frame, parameters, options).await;
+        }
+
+        let current_params = self.cur_video_params_sample_description_index
+            .map(|i| &self.video_params[i as usize - 1]);
+
+        let sample_description_index = match current_params {
             // Use the most recent sample description index for most frames, without having to
-            // scan through self.video_sample_index.
-            i
-        } else {
-            match stream.parameters() {
-                Some(ParametersRef::Video(params)) => {
-                    let pos = self.video_params.iter().position(|p| p == params);
-                    if let Some(pos) = pos {
-                        u32::try_from(pos + 1)?
-                    } else {
-                        self.video_params.push(params.clone());
-                        u32::try_from(self.video_params.len())?
-                    }
-                }
-                None => {
-                    return Ok(());
+            // scan through self.video_params.
+            Some(params) if Arc::ptr_eq(params, parameters) => {
+                self.cur_video_params_sample_description_index.unwrap()
+            },
+            _ => {
+                let pos = self.video_params.iter().position(|p| **p == **parameters);
+                if let Some(pos) = pos {
+                    u32::try_from(pos + 1)?
+                } else {
+                    self.video_params.push(parameters.clone());
+                    u32::try_from(self.video_params.len())?
                 }
-                _ => unreachable!(),
-            }
+            },
         };
         self.cur_video_params_sample_description_index = Some(sample_description_index);
-        let size = u32::try_from(frame.data().remaining())?;
+        self.first_video_pts.get_or_insert(frame.pts);
+        let size = u32::try_from(frame.data.len())?;
         self.video_trak.add_sample(
             sample_description_index,
             self.mdat_pos,
             size,
-            frame.timestamp(),
-            frame.loss(),
+            frame.pts,
+            frame.loss,
             self.allow_loss,
         )?;
-        self.mdat_pos = self
-            .mdat_pos
-            .checked_add(size)
-            .ok_or_else(|| anyhow!("mdat_pos overflow"))?;
-        if frame.is_random_access_point() {
+        self.mdat_pos += u64::from(size);
+        if frame.is_random_access_point {
             self.video_sync_sample_nums.push(self.video_trak.samples);
         }
-        self.inner.write_all(frame.data()).await?;
+        self.inner.write_all(&frame.data).await?;
         Ok(())
     }
 
     pub async fn audio(&mut self, frame: retina::codec::AudioFrame) -> Result<(), Error> {
-        println!(
-            "{}: {}-byte audio frame",
-            frame.timestamp(),
-            frame.data().remaining()
-        );
-        let size = u32::try_from(frame.data().remaining())?;
+        self.audio_sample(frame.data(), frame.timestamp().timestamp(), frame.loss()).await
+    }
+
+    pub(crate) async fn audio_sample(&mut self, data: &[u8], pts: i64, loss: u16) -> Result<(), Error> {
+        let Some(audio_params) = self.audio_params.as_ref() else {
+            bail!("audio frame written to a recording without audio parameters");
+        };
+
+        if self.fragments.is_some() {
+            // audio before the init segment has no track to belong to
+            if self.video_params.is_empty() {
+                return Ok(());
+            }
+
+            if !self.fragment.audio_started {
+                self.fragment.audio_started = true;
+                if let (Some(first), Some(last)) = (self.first_video_pts, self.fragment.video.last().map(|s| s.pts)) {
+                    let audio_start = u64::try_from(last - first).unwrap_or_default();
+                    self.fragment.audio_decode_time = audio_start * u64::from(audio_params.clock_rate()) / 90000;
+                }
+            }
+
+            self.fragment.audio.push(PendingSample {
+                data: Bytes::copy_from_slice(data),
+                pts,
+                is_sync: true,
+            });
+            return Ok(());
+        }
+
+        if self.audio_trak.samples == 0 {
+            if let (Some(first), Some(last)) = (self.first_video_pts, self.video_trak.last_pts) {
+                self.audio_start = u64::try_from(last - first).unwrap_or_default();
+            }
+        }
+        let size = u32::try_from(data.len())?;
         self.audio_trak.add_sample(
             /* sample_description_index */ 1,
             self.mdat_pos,
             size,
-            frame.timestamp(),
-            frame.loss(),
+            pts,
+            loss,
             self.allow_loss,
         )?;
-        self.mdat_pos = self
-            .mdat_pos
-            .checked_add(size)
-            .ok_or_else(|| anyhow!("mdat_pos overflow"))?;
-        self.inner.write_all(frame.data()).await?;
+        self.mdat_pos += u64::from(size);
+        self.inner.write_all(data).await?;
         Ok(())
     }
 }
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::{
+        io::Cursor,
+        num::NonZeroU16,
+    };
+
+    use retina::codec::{Depacketizer, ParametersRef};
+
+    use crate::demux::{child_boxes, find_child, read_box_payload, top_level_boxes, Mp4Reader, Mp4Track};
+
+
+    /// baseline profile SPS of a 640x480 and a 1280x720 stream, and their PPS
+    pub(crate) const SPS_640X480: &str = "Z0LAHtoCgPZA";
+    pub(crate) const SPS_1280X720: &str = "Z0LAHtoBQBbk";
+    const PPS: &str = "aM48gA==";
+
+    pub(crate) fn h264_parameters(sps: &str) -> Arc<VideoStreamParameters> {
+        let fmtp = format!("packetization-mode=1;sprop-parameter-sets={},{}", sps, PPS);
+        let depacketizer = Depacketizer::new("video", "h264", 90000, None, Some(&fmtp)).unwrap();
+
+        match depacketizer.parameters() {
+            Some(ParametersRef::Video(parameters)) => Arc::new(parameters.try_into().unwrap()),
+            _ => panic!("no video parameters in {}", fmtp),
+        }
+    }
+
+    /// a 30 fps frame with a payload unique to `index`, every 4th frame is a keyframe
+    pub(crate) fn video_frame(index: i64, parameters: &Arc<VideoStreamParameters>) -> EncodedVideoFrame {
+        EncodedVideoFrame {
+            data: Bytes::from([&[0, 0, 0, 5, 0x65][..], &(index as u32).to_be_bytes()].concat()),
+            pts: index * 3000,
+            clock_rate: NonZeroU32::new(90000).unwrap(),
+            loss: 0,
+            is_random_access_point: index % 4 == 0,
+            parameters: Some(parameters.clone()),
+        }
+    }
+
+    /// AAC-LC, 48 kHz stereo
+    fn aac_parameters() -> Box<AudioParameters> {
+        let fmtp = "streamtype=5;profile-level-id=1;mode=AAC-hbr;sizelength=13;indexlength=3;indexdeltalength=3;config=1190";
+        let depacketizer = Depacketizer::new("audio", "mpeg4-generic", 48000, NonZeroU16::new(2), Some(fmtp)).unwrap();
+
+        match depacketizer.parameters() {
+            Some(ParametersRef::Audio(parameters)) => Box::new(parameters.clone()),
+            _ => panic!("no audio parameters in {}", fmtp),
+        }
+    }
+
+    async fn write_recording(
+        frames: &[EncodedVideoFrame],
+        fragments: Option<FragmentOptions>,
+    ) -> Vec<u8> {
+        let mut writer = Mp4Writer::new(None, false, fragments, Mp4Metadata::default(), Cursor::new(vec![]))
+            .await
+            .unwrap();
+
+        for frame in frames {
+            writer.video(frame).await.unwrap();
+        }
+
+        writer.finish().await.unwrap().into_inner()
+    }
+
+    fn read_video_samples(data: Vec<u8>) -> (Mp4Track, Vec<Vec<u8>>) {
+        let mut reader = Mp4Reader::new(Cursor::new(data)).unwrap();
+        let track = reader.video_track().unwrap().clone();
+        let samples = track.samples.iter()
+            .map(|sample| reader.read_sample(sample).unwrap())
+            .collect();
+
+        (track, samples)
+    }
+
+
+    #[tokio::test]
+    async fn test_resolution_change_adds_sample_description() {
+        let small = h264_parameters(SPS_640X480);
+        let large = h264_parameters(SPS_1280X720);
+
+        let frames = (0..8)
+            .map(|i| video_frame(i, if i < 4 { &small } else { &large }))
+            .collect::<Vec<_>>();
+
+        let (track, samples) = read_video_samples(write_recording(&frames, None).await);
+
+        let dimensions = track.sample_entries.iter()
+            .map(|entry| (entry.width, entry.height))
+            .collect::<Vec<_>>();
+        assert_eq!(dimensions, [(640, 480), (1280, 720)]);
+        assert_eq!(track.sample_entries[1].configuration, large.configuration_record);
+
+        let indices = track.samples.iter()
+            .map(|sample| sample.sample_description_index)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, [1, 1, 1, 1, 2, 2, 2, 2]);
+
+        assert!(track.samples[4].is_sync, "the new sample description starts at a keyframe");
+        assert_eq!(samples, frames.iter().map(|frame| frame.data.to_vec()).collect::<Vec<_>>());
+    }
+
+
+    #[tokio::test]
+    async fn test_audio_track_starts_after_video() {
+        let parameters = h264_parameters(SPS_640X480);
+        let mut writer = Mp4Writer::new(Some(aac_parameters()), false, None, Mp4Metadata::default(), Cursor::new(vec![]))
+            .await
+            .unwrap();
+
+        // the microphone starts two video frames into the recording (e.g. after a pre-roll)
+        for i in 0..8 {
+            writer.video(&video_frame(i, &parameters)).await.unwrap();
+            if i >= 2 {
+                let pts = (i - 2) * 1024;
+                writer.audio_sample(&[0x21, i as u8], pts, 0).await.unwrap();
+            }
+        }
+
+        let data = writer.finish().await.unwrap().into_inner();
+        let mut reader = Mp4Reader::new(Cursor::new(data.clone())).unwrap();
+
+        assert_eq!(reader.video_track().unwrap().samples.len(), 8);
+
+        let audio = reader.tracks.iter().find(|track| &track.handler == b"soun").unwrap().clone();
+        assert_eq!(audio.track_id, 2);
+        assert_eq!(audio.timescale, 48000);
+        assert_eq!(audio.samples.len(), 6);
+        assert_eq!(audio.samples[1].decode_time, 1024);
+        assert_eq!(reader.read_sample(&audio.samples[5]).unwrap(), [0x21, 7]);
+
+        // an empty edit delays the audio by the two video frames
+        let moov = child_boxes(&data)
+            .find(|(fourcc, _)| fourcc == b"moov")
+            .map(|(_, moov)| moov)
+            .unwrap();
+        let audio_trak = child_boxes(moov)
+            .filter(|(fourcc, _)| fourcc == b"trak")
+            .nth(1)
+            .map(|(_, trak)| trak)
+            .unwrap();
+        let elst = find_child(audio_trak, b"edts")
+            .and_then(|edts| find_child(edts, b"elst"))
+            .unwrap();
+
+        assert_eq!(&elst[8..12], &6000u32.to_be_bytes(), "segment_duration of the empty edit");
+        assert_eq!(&elst[12..16], &(-1i32).to_be_bytes(), "media_time of the empty edit");
+    }
+
+
+    #[tokio::test]
+    async fn test_fragmented_round_trip() {
+        let parameters = h264_parameters(SPS_640X480);
+        let frames = (0..10)
+            .map(|i| video_frame(i, &parameters))
+            .collect::<Vec<_>>();
+
+        let fragments = FragmentOptions {
+            max_frames: 3,
+            max_duration: Duration::from_secs(10),
+        };
+        let data = write_recording(&frames, Some(fragments)).await;
+
+        let moofs = child_boxes(&data)
+            .filter(|(fourcc, _)| fourcc == b"moof")
+            .count();
+        assert_eq!(moofs, 4, "three fragments of three frames and the rest written by finish");
+
+        let (track, samples) = read_video_samples(data);
+
+        assert_eq!(track.sample_entries.len(), 1);
+        assert_eq!((track.sample_entries[0].width, track.sample_entries[0].height), (640, 480));
+        assert_eq!(samples, frames.iter().map(|frame| frame.data.to_vec()).collect::<Vec<_>>());
+
+        for (i, sample) in track.samples.iter().enumerate() {
+            assert_eq!(sample.decode_time, i as u64 * 3000);
+            assert_eq!(sample.duration, 3000);
+            assert_eq!(sample.is_sync, i % 4 == 0);
+        }
+    }
+
+
+    #[tokio::test]
+    async fn test_fragmented_parameter_change() {
+        let small = h264_parameters(SPS_640X480);
+        let large = h264_parameters(SPS_1280X720);
+
+        let mut writer = Mp4Writer::new(None, false, Some(FragmentOptions::default()), Mp4Metadata::default(), Cursor::new(vec![]))
+            .await
+            .unwrap();
+        assert!(writer.accepts_parameters(&large), "the init segment is written with the first frame");
+
+        writer.video(&video_frame(0, &small)).await.unwrap();
+
+        assert!(writer.accepts_parameters(&small));
+        assert!(!writer.accepts_parameters(&large));
+        assert!(writer.video(&video_frame(1, &large)).await.is_err(), "the init segment can't describe the new parameters");
+
+        // progressive recordings add a sample description instead
+        let progressive = Mp4Writer::new(None, false, None, Mp4Metadata::default(), Cursor::new(vec![]))
+            .await
+            .unwrap();
+        assert!(progressive.accepts_parameters(&large));
+    }
+
+
+    #[tokio::test]
+    async fn test_large_offsets_round_trip() {
+        let parameters = h264_parameters(SPS_640X480);
+        let mut writer = Mp4Writer::new(Some(aac_parameters()), false, None, Mp4Metadata::default(), Cursor::new(vec![]))
+            .await
+            .unwrap();
+
+        // continue as if almost 4 GiB of samples were written, the samples stay right after the header in memory
+        let position = u64::from(u32::MAX) - 32;
+        let mdat_start = writer.mdat_start;
+        writer.mdat_pos = position;
+
+        // interleaved audio starts a new chunk per frame, on both sides of 4 GiB, and the mdat grows past it
+        let frames = (0..8)
+            .map(|i| video_frame(i, &parameters))
+            .collect::<Vec<_>>();
+        for (i, frame) in frames.iter().enumerate() {
+            writer.video(frame).await.unwrap();
+            writer.audio_sample(&[0x21, i as u8], i as i64 * 1024, 0).await.unwrap();
+        }
+        let data = writer.finish().await.unwrap().into_inner();
+
+        let mut file = SkippedSamples {
+            data,
+            mdat_start,
+            skipped: position - mdat_start,
+            position: 0,
+        };
+        let mut reader = Mp4Reader::new(&mut file).unwrap();
+        let video = reader.video_track().unwrap().clone();
+
+        assert!(video.samples[0].offset < u64::from(u32::MAX));
+        assert!(video.samples[7].offset > u64::from(u32::MAX));
+        for (sample, frame) in video.samples.iter().zip(frames.iter()) {
+            assert_eq!(reader.read_sample(sample).unwrap(), frame.data);
+        }
+
+        let audio = reader.tracks.iter().find(|track| &track.handler == b"soun").unwrap().clone();
+        assert_eq!(reader.read_sample(&audio.samples[7]).unwrap(), [0x21, 7]);
+
+        let file_size = file.data.len() as u64 + file.skipped;
+        let headers = top_level_boxes(&mut file, file_size).unwrap();
+
+        let mdat = headers.iter().find(|header| &header.fourcc == b"mdat").unwrap();
+        assert_eq!(mdat.header_size, 16, "the mdat takes over the wide box for its large size");
+
+        let moov = headers.iter().find(|header| &header.fourcc == b"moov").unwrap();
+        let moov = read_box_payload(&mut file, moov).unwrap();
+        let has_co64 = moov.windows(4).any(|fourcc| fourcc == b"co64");
+        assert!(has_co64, "chunks past 4 GiB need 64-bit offsets");
+    }
+
+    /// a recording whose samples were written from a position past `mdat_start`, read with `skipped` zero bytes
+    /// before them as if the file had that size, without allocating or writing the skipped bytes
+    struct SkippedSamples {
+        data: Vec<u8>,
+        mdat_start: u64,
+        skipped: u64,
+        position: u64,
+    }
+
+    impl std::io::Read for SkippedSamples {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let skipped_end = self.mdat_start + self.skipped;
+
+            let count = if self.position < self.mdat_start {
+                let start = self.position as usize;
+                let count = buf.len().min(self.mdat_start as usize - start);
+                buf[..count].copy_from_slice(&self.data[start..start + count]);
+                count
+            } else if self.position < skipped_end {
+                let count = buf.len().min((skipped_end - self.position).try_into().unwrap_or(usize::MAX));
+                buf[..count].fill(0);
+                count
+            } else {
+                let start = ((self.position - self.skipped) as usize).min(self.data.len());
+                let count = buf.len().min(self.data.len() - start);
+                buf[..count].copy_from_slice(&self.data[start..start + count]);
+                count
+            };
+
+            self.position += count as u64;
+            Ok(count)
+        }
+    }
+
+    impl std::io::Seek for SkippedSamples {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            let size = self.data.len() as u64 + self.skipped;
+            let position = match pos {
+                SeekFrom::Start(position) => Some(position),
+                SeekFrom::End(offset) => size.checked_add_signed(offset),
+                SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            };
+
+            self.position = position
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the start of the file"))?;
+            Ok(self.position)
+        }
+    }
+}
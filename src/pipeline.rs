@@ -1,11 +1,26 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
+use anyhow::{anyhow, bail, Error};
 use bevy::{
+    asset::LoadState,
+    ecs::{
+        query::{Has, QueryItem, ReadOnlyQueryData},
+        system::{StaticSystemParam, SystemParam, SystemParamItem},
+    },
     prelude::*,
     render::{
         render_asset::RenderAssetUsages,
         render_resource::Extent3d,
     },
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
 };
 use bevy_ort::{
     models::{
@@ -37,12 +52,55 @@ use imageproc::geometric_transformations::{
 };
 use png::Transformations;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    ffmpeg::FfmpegArgs,
+    calibration::{
+        color::{
+            calibrate_color,
+            detect_color_checker,
+            ColorCorrection,
+        },
+        extrinsics::{
+            calibrate_extrinsics,
+            CameraExtrinsics,
+            CameraObservations,
+        },
+        intrinsics::{
+            calibrate_intrinsics,
+            CameraIntrinsics,
+        },
+        undistort::{
+            undistorted_intrinsics,
+            UndistortMap,
+            UndistortionConfig,
+        },
+        BoardCorners,
+        CalibrationConfig,
+    },
+    colmap::{
+        write_text_model,
+        ColmapCamera,
+        ColmapConfig,
+        ColmapImage,
+    },
+    extract::{
+        FrameExtraction,
+        FrameExtractor,
+    },
+    manifest::{
+        fingerprint_files,
+        NodeManifest,
+    },
+    recover::{
+        needs_recovery,
+        recover_mp4,
+    },
     stream::{
+        unix_now_secs,
+        RtspStreamHandle,
+        StreamDescriptor,
         StreamId,
-        StreamDescriptors,
     },
 };
 
@@ -54,32 +112,53 @@ impl Plugin for PipelinePlugin {
             ModnetPlugin,
             YoloPlugin,
         ));
-        app.add_systems(
-            Update,
-            (
-                generate_raw_frames,
-                generate_rotated_frames,
-                generate_mask_frames,
-                generate_alphablend_frames,
-                generate_yolo_frames,
-            )
-        );
+
+        app.init_resource::<PipelineActivity>();
+        app.add_systems(First, reset_waiting_pipeline_nodes);
+        app.add_systems(PreUpdate, load_session_manifests);
+
+        app.add_pipeline_node::<RawFrames>();
+        app.add_pipeline_node::<RotatedFrames>();
+        app.add_pipeline_node::<MaskFrames>();
+        app.add_pipeline_node::<AlphablendFrames>();
+        app.add_pipeline_node::<YoloFrames>();
+        app.add_pipeline_node::<ColorCalibration>();
+        app.add_pipeline_node::<LightFieldCameras>();
+        app.add_pipeline_node::<ColorCorrectedFrames>();
+        app.add_pipeline_node::<UndistortedFrames>();
+        app.add_pipeline_node::<UndistortedMaskFrames>();
+        app.add_pipeline_node::<UndistortedAlphablendFrames>();
+        app.add_pipeline_node::<UndistortedColorCorrectedFrames>();
+        app.add_pipeline_node::<ColmapDataset>();
     }
 }
 
 
-#[derive(Component, Reflect)]
+#[derive(Component, Clone, Debug, Reflect, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PipelineConfig {
     pub raw_frames: bool,
     pub rotate_raw_frames: bool,
+    pub undistort_frames: bool,             // requires `light_field_cameras`
+    pub undistort_masks: bool,
+    pub undistort_alphablend: bool,
+    pub color_correct_frames: bool,         // requires `color_calibration`
+    pub undistort_color_corrected: bool,
     pub alphablend_frames: bool,
     pub yolo: bool,                         // https://github.com/ultralytics/ultralytics
     pub repair_frames: bool,                // https://huggingface.co/docs/diffusers/en/optimization/onnx & https://github.com/bnm6900030/swintormer
     pub upsample_frames: bool,              // https://huggingface.co/ssube/stable-diffusion-x4-upscaler-onnx
     pub mask_frames: bool,                  // https://github.com/ZHKKKe/MODNet
-    pub light_field_cameras: bool,          // https://github.com/jasonyzhang/RayDiffusion
+    pub light_field_cameras: bool,          // checkerboard calibration, see `calibration`
+    pub color_calibration: bool,            // color checker calibration, merged into the `light_field_cameras` cameras
     pub depth_maps: bool,                   // https://github.com/fabio-sim/Depth-Anything-ONNX
+    pub colmap_dataset: bool,               // requires `mask_frames`, posed with `light_field_cameras`, https://colmap.github.io/format.html
     pub gaussian_cloud: bool,
+
+    pub frame_extraction: FrameExtraction,
+    pub calibration: CalibrationConfig,
+    pub undistortion: UndistortionConfig,
+    pub colmap: ColmapConfig,
 }
 
 impl Default for PipelineConfig {
@@ -87,14 +166,25 @@ impl Default for PipelineConfig {
         Self {
             raw_frames: true,
             rotate_raw_frames: true,
+            undistort_frames: false,
+            undistort_masks: false,
+            undistort_alphablend: false,
+            color_correct_frames: false,
+            undistort_color_corrected: false,
             yolo: true,
             alphablend_frames: true,
             mask_frames: true,
             upsample_frames: false,
             repair_frames: false,
             light_field_cameras: false,
+            color_calibration: false,
             depth_maps: false,
+            colmap_dataset: false,
             gaussian_cloud: false,
+            frame_extraction: FrameExtraction::default(),
+            calibration: CalibrationConfig::default(),
+            undistortion: UndistortionConfig::default(),
+            colmap: ColmapConfig::default(),
         }
     }
 }
@@ -107,9 +197,6 @@ pub struct StreamSessionBundle {
     pub session: Session,
 }
 
-// TODO: use an entity saver to write Session and it's components (e.g. `0/session.ron`)
-
-
 #[derive(Component, Default, Reflect)]
 pub struct Session {
     pub id: usize,
@@ -133,6 +220,112 @@ impl Session {
 }
 
 
+/// what started a recording session
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingTrigger {
+    #[default]
+    Manual,
+    PersonDetected,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionStream {
+    pub stream_id: usize,
+
+    /// recording path relative to the session directory, later segments are numbered after it (`recording_segment_path`)
+    pub file: String,
+
+    /// the stream configuration when recording started, without credentials
+    pub descriptor: StreamDescriptor,
+}
+
+
+/// the capture context of a session, written to `<session>/session.json` when recording starts so reprocessing does not
+/// depend on the current `streams.json`
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct SessionManifest {
+    pub session_id: usize,
+    pub crate_version: String,
+    pub trigger: RecordingTrigger,
+
+    pub start_unix_secs: f64,
+    #[serde(default)]
+    pub stop_unix_secs: Option<f64>,
+
+    pub streams: Vec<SessionStream>,
+    pub pipeline: PipelineConfig,
+}
+
+impl SessionManifest {
+    pub fn new(
+        session: &Session,
+        trigger: RecordingTrigger,
+        streams: Vec<SessionStream>,
+        pipeline: PipelineConfig,
+    ) -> Self {
+        Self {
+            session_id: session.id,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            trigger,
+            start_unix_secs: unix_now_secs(),
+            stop_unix_secs: None,
+            streams,
+            pipeline,
+        }
+    }
+
+    pub fn path(session_directory: impl AsRef<std::path::Path>) -> std::path::PathBuf {
+        session_directory.as_ref().join("session.json")
+    }
+
+    pub fn load(session_directory: impl AsRef<std::path::Path>) -> Result<Option<Self>, Error> {
+        let path = Self::path(session_directory);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = std::fs::File::open(path)?;
+        Ok(Some(serde_json::from_reader(std::io::BufReader::new(file))?))
+    }
+
+    pub fn save(&self, session_directory: impl AsRef<std::path::Path>) -> Result<(), Error> {
+        let file = std::fs::File::create(Self::path(session_directory))?;
+        serde_json::to_writer_pretty(file, self)?;
+
+        Ok(())
+    }
+
+    /// records the stop wallclock and saves the manifest
+    pub fn stop(&mut self, session_directory: impl AsRef<std::path::Path>) -> Result<(), Error> {
+        self.stop_unix_secs = Some(unix_now_secs());
+        self.save(session_directory)
+    }
+
+    pub fn stream(&self, stream_id: StreamId) -> Option<&SessionStream> {
+        self.streams.iter().find(|stream| stream.stream_id == stream_id.0)
+    }
+}
+
+
+/// inserts the `SessionManifest` of loaded sessions
+fn load_session_manifests(
+    mut commands: Commands,
+    sessions: Query<(Entity, &Session), (Added<Session>, Without<SessionManifest>)>,
+) {
+    for (entity, session) in sessions.iter() {
+        match SessionManifest::load(&session.directory) {
+            Ok(Some(manifest)) => {
+                commands.entity(entity).insert(manifest);
+            },
+            Ok(None) => {},
+            Err(err) => warn!("failed to load the manifest of session {}: {}", session.id, err),
+        }
+    }
+}
+
+
 #[derive(Component, Default, Reflect)]
 pub struct RawStreams {
     pub streams: Vec<String>,
@@ -149,480 +342,573 @@ impl RawStreams {
             .map(|entry| entry.path().to_str().unwrap().to_string())
             .collect::<Vec<_>>();
 
+        // recordings interrupted before `Mp4Writer::finish` have no moov
+        for mp4_path in streams.iter().filter(|path| path.ends_with(".mp4")) {
+            if !needs_recovery(mp4_path).unwrap_or(false) {
+                continue;
+            }
+
+            match recover_mp4(mp4_path) {
+                Ok(report) => info!(
+                    "recovered {} samples ({} keyframes, {}x{} @ {:.2} fps) from unfinished recording {}, discarded {} trailing bytes",
+                    report.samples,
+                    report.sync_samples,
+                    report.width,
+                    report.height,
+                    report.frame_rate,
+                    mp4_path,
+                    report.truncated_bytes,
+                ),
+                Err(err) => warn!("failed to recover unfinished recording {}: {}", mp4_path, err),
+            }
+        }
+
         Self {
             streams,
         }
     }
+
+    /// the recordings of each stream index, in segment order (`<idx>.mp4`, `<idx>-1.mp4`, ...)
+    pub fn segments(&self) -> BTreeMap<usize, Vec<String>> {
+        let mut segments: BTreeMap<usize, Vec<(usize, String)>> = BTreeMap::new();
+
+        for mp4_path in &self.streams {
+            let Some(stem) = std::path::Path::new(mp4_path).file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            let (stream_idx, segment) = match stem.split_once('-') {
+                Some((stream_idx, segment)) => (stream_idx.parse::<usize>(), segment.parse::<usize>()),
+                None => (stem.parse::<usize>(), Ok(0)),
+            };
+
+            match (stream_idx, segment) {
+                (Ok(stream_idx), Ok(segment)) => segments.entry(stream_idx).or_default().push((segment, mp4_path.clone())),
+                _ => warn!("skipping unrecognized raw stream {}", mp4_path),
+            }
+        }
+
+        segments.into_iter()
+            .map(|(stream_idx, mut paths)| {
+                paths.sort();
+                (stream_idx, paths.into_iter().map(|(_, path)| path).collect())
+            })
+            .collect()
+    }
 }
 
 
-// TODO: use the async task pool for all generate systems https://crates.io/crates/bevy-async-task
-fn generate_raw_frames(
-    mut commands: Commands,
-    raw_streams: Query<
+/// A cached step of the offline pipeline, which runs on the `AsyncComputeTaskPool` once its `Inputs` are present on
+/// the session entity. The output in `<session>/<DIRECTORY>` is reused while its `manifest.json` matches the node
+/// version, config and input hashes, otherwise it is recomputed.
+///
+/// Register nodes with `App::add_pipeline_node`, including nodes of downstream crates.
+pub trait PipelineNode: Component + Sized {
+    /// output directory, relative to the session directory
+    const DIRECTORY: &'static str;
+
+    /// bump when the output changes for the same inputs and config
+    const VERSION: u32 = 1;
+
+    /// the components of the session entity the node reads, typically the outputs of other nodes
+    type Inputs: ReadOnlyQueryData;
+
+    /// the resources and queries the node runs with
+    type Params: SystemParam;
+
+    fn enabled(_config: &PipelineConfig) -> bool {
+        true
+    }
+
+    /// whether the node can run yet, e.g. once its model asset is loaded. an error fails the node, e.g. when its
+    /// model failed to load, instead of leaving it waiting
+    fn prepare(_params: &mut SystemParamItem<Self::Params>) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    /// whether the inputs of a session are complete, e.g. once an enabled node providing an optional input finished
+    fn ready(_config: &PipelineConfig, _inputs: &QueryItem<Self::Inputs>) -> bool {
+        true
+    }
+
+    /// the files the output is computed from
+    fn input_files(inputs: &QueryItem<Self::Inputs>) -> Vec<String>;
+
+    /// the settings the output depends on, e.g. model paths or stream rotations
+    fn config(
+        _config: &PipelineConfig,
+        _inputs: &QueryItem<Self::Inputs>,
+        _params: &SystemParamItem<Self::Params>,
+    ) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// collects what the node needs from the world into a job, which writes the output off the main thread
+    fn job(
+        session: &Session,
+        config: &PipelineConfig,
+        inputs: QueryItem<Self::Inputs>,
+        params: &mut SystemParamItem<Self::Params>,
+    ) -> Result<PipelineJob, Error>;
+
+    fn load(directory: &str) -> Self;
+
+    fn directory(session: &Session) -> String {
+        format!("{}/{}", session.directory, Self::DIRECTORY)
+    }
+
+    fn load_from_session(session: &Session) -> Self {
+        Self::load(&Self::directory(session))
+    }
+}
+
+
+/// writes a node output to the given directory, reporting to and stopping on cancellation of the `NodeProgress`
+pub type PipelineJob = Box<dyn FnOnce(&str, &NodeProgress) -> Result<(), Error> + Send>;
+
+
+/// progress of a running node, shared between its job and the main thread
+#[derive(Clone, Default)]
+pub struct NodeProgress(Arc<NodeProgressState>);
+
+#[derive(Default)]
+struct NodeProgressState {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl NodeProgress {
+    pub fn set_total(&self, total: usize) {
+        self.0.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self) {
+        self.0.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// (done, total)
+    pub fn get(&self) -> (usize, usize) {
         (
-            Entity,
-            &PipelineConfig,
-            &RawStreams,
-            &Session,
-        ),
-        Without<RawFrames>,
-    >,
-) {
-    for (
-        entity,
-        config,
-        raw_streams,
-        session,
-    ) in raw_streams.iter() {
-        if config.raw_frames {
-            let run_node = !RawFrames::exists(session);
-            let mut raw_frames = RawFrames::load_from_session(session);
-
-            if run_node {
-                info!("generating raw frames for session {}", session.id);
-
-                let frame_directory = format!("{}/frames", session.directory);
-
-                raw_streams.streams.par_iter()
-                    .for_each(|mp4_path| {
-                        let stream_idx = std::path::Path::new(mp4_path).file_stem().unwrap().to_str().unwrap().parse::<usize>().unwrap();
-                        let output_directory = format!("{}/{}", frame_directory, stream_idx);
-                        std::fs::create_dir_all(&output_directory).unwrap();
-
-                        let _ = FfmpegArgs {
-                            mp4_path: mp4_path.clone(),
-                            fps: 5,
-                            width: 1920,
-                            height: 1080,
-                            interpolation: "lanczos".to_string(),
-                            output_directory,
-                        }.run();
-                    });
-
-                raw_frames.reload();
-            } else {
-                info!("raw frames already exist for session {}", session.id);
-            }
+            self.0.done.load(Ordering::Relaxed),
+            self.0.total.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Relaxed);
+    }
 
-            commands.entity(entity).insert(raw_frames);
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// jobs call this between units of work, so cancellation stops them early
+    pub fn check_cancelled(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            bail!("cancelled");
         }
+
+        Ok(())
     }
 }
 
 
-fn generate_rotated_frames(
-    mut commands: Commands,
-    descriptors: Res<StreamDescriptors>,
-    raw_frames: Query<
-        (
-            Entity,
-            &PipelineConfig,
-            &RawFrames,
-            &Session,
-        ),
-        Without<RotatedFrames>,
-    >,
-) {
-    // TODO: create a caching/loading system wrapper over run_node interior
-    for (
-        entity,
-        config,
-        raw_frames,
-        session,
-    ) in raw_frames.iter() {
-        if config.rotate_raw_frames {
-            let run_node = !RotatedFrames::exists(session);
-            let mut rotated_frames = RotatedFrames::load_from_session(session);
-
-            if run_node {
-                let rotations: HashMap<StreamId, f32> = descriptors.0.iter()
-                    .enumerate()
-                    .map(|(id, descriptor)| (StreamId(id), descriptor.rotation.unwrap_or_default()))
-                    .collect();
+/// sent while a node runs, and once more when it completes
+#[derive(Event, Clone, Debug)]
+pub struct PipelineProgress {
+    pub session: Entity,
+    pub node: &'static str,
+    pub done: usize,
+    pub total: usize,
+}
+
 
-                info!("generating rotated frames for session {}", session.id);
+/// sent once per node run on a session
+#[derive(Event, Clone, Debug)]
+pub struct PipelineNodeFinished {
+    pub session: Entity,
+    pub node: &'static str,
+    pub outcome: NodeOutcome,
+    pub seconds: f64,
+}
 
-                raw_frames.frames.iter()
-                    .for_each(|(stream_id, frames)| {
-                        let output_directory = format!("{}/{}", rotated_frames.directory, stream_id.0);
-                        std::fs::create_dir_all(&output_directory).unwrap();
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeOutcome {
+    Generated,
 
-                        let frames = frames.par_iter()
-                            .map(|frame| {
-                                let frame_idx = std::path::Path::new(frame).file_stem().unwrap().to_str().unwrap();
-                                let output_path = format!("{}/{}.png", output_directory, frame_idx);
+    /// the cached output was up to date
+    Reused,
+    Failed {
+        error: String,
+    },
+    Cancelled,
+}
 
-                                rotate_image(
-                                    std::path::Path::new(frame),
-                                    std::path::Path::new(&output_path),
-                                    rotations[stream_id],
-                                ).unwrap();
 
-                                output_path
-                            })
-                            .collect::<Vec<_>>();
+/// node runs in flight, and nodes with pending inputs waiting for `prepare` in the current frame
+#[derive(Resource, Default, Debug)]
+pub struct PipelineActivity {
+    pub running: usize,
+    pub waiting: usize,
+}
 
-                            rotated_frames.frames.insert(*stream_id, frames);
-                    });
-            } else {
-                info!("rotated frames already exist for session {}", session.id);
-            }
+impl PipelineActivity {
+    pub fn is_idle(&self) -> bool {
+        self.running == 0 && self.waiting == 0
+    }
+}
+
+
+/// logs the `PipelineProgress` of each node in 10% steps
+pub fn log_pipeline_progress(
+    mut progress_events: EventReader<PipelineProgress>,
+    sessions: Query<&Session>,
+    mut logged_percent: Local<HashMap<(Entity, &'static str), usize>>,
+) {
+    for progress in progress_events.read() {
+        let Ok(session) = sessions.get(progress.session) else {
+            continue;
+        };
 
-            commands.entity(entity).insert(rotated_frames);
+        let percent = (progress.done * 100).checked_div(progress.total).unwrap_or(100) / 10 * 10;
+        if logged_percent.insert((progress.session, progress.node), percent) == Some(percent) {
+            continue;
         }
+
+        info!("session {} {}: {}/{} ({}%)", session.id, progress.node, progress.done, progress.total, percent);
     }
 }
 
 
-fn generate_mask_frames(
-    mut commands: Commands,
-    frames: Query<
-        (
-            Entity,
-            &PipelineConfig,
-            &RotatedFrames,
-            &Session,
-        ),
-        Without<MaskFrames>,
-    >,
-    modnet: Res<Modnet>,
-    onnx_assets: Res<Assets<Onnx>>,
+fn reset_waiting_pipeline_nodes(
+    mut activity: ResMut<PipelineActivity>,
 ) {
-    for (
-        entity,
-        config,
-        frames,
-        session,
-    ) in frames.iter() {
-        if config.mask_frames {
-            if onnx_assets.get(&modnet.onnx).is_none() {
-                return;
-            }
+    activity.waiting = 0;
+}
 
-            let onnx = onnx_assets.get(&modnet.onnx).unwrap();
-            let onnx_session_arc = onnx.session.clone();
-            let onnx_session_lock = onnx_session_arc.lock().map_err(|e| e.to_string()).unwrap();
-            let onnx_session = onnx_session_lock.as_ref().ok_or("failed to get session from ONNX asset").unwrap();
-
-            let run_node = !MaskFrames::exists(session);
-            let mut mask_frames = MaskFrames::load_from_session(session);
-
-            if run_node {
-                info!("generating mask frames for session {}", session.id);
-
-                frames.frames.keys()
-                    .for_each(|stream_id| {
-                        let output_directory = format!("{}/{}", mask_frames.directory, stream_id.0);
-                        std::fs::create_dir_all(output_directory).unwrap();
-                    });
-
-                let mask_images = frames.frames.iter()
-                    .map(|(stream_id, frames)| {
-                        let frames = frames.iter()
-                            .map(|frame| {
-                                let mut decoder = png::Decoder::new(std::fs::File::open(frame).unwrap());
-                                decoder.set_transformations(Transformations::EXPAND | Transformations::ALPHA);
-                                let mut reader = decoder.read_info().unwrap();
-                                let mut img_data = vec![0; reader.output_buffer_size()];
-                                let _ = reader.next_frame(&mut img_data).unwrap();
-
-                                assert_eq!(reader.info().bytes_per_pixel(), 3);
-
-                                let width = reader.info().width;
-                                let height = reader.info().height;
-
-                                // TODO: separate image loading and onnx inference (so the image loading result can be viewed in the pipeline grid view)
-                                let image = Image::new(
-                                    Extent3d {
-                                        width,
-                                        height,
-                                        depth_or_array_layers: 1,
-                                    },
-                                    bevy::render::render_resource::TextureDimension::D2,
-                                    img_data,
-                                    bevy::render::render_resource::TextureFormat::Rgba8UnormSrgb,
-                                    RenderAssetUsages::all(),
-                                );
-
-                                let frame_idx = std::path::Path::new(frame).file_stem().unwrap().to_str().unwrap();
-
-                                (
-                                    frame_idx,
-                                    modnet_inference(
-                                        onnx_session,
-                                        &[&image],
-                                        Some((512, 512)),
-                                    ).pop().unwrap(),
-                                )
-                            })
-                            .collect::<Vec<_>>();
-
-                        (stream_id, frames)
-                    })
-                    .collect::<Vec<_>>();
 
-                mask_images.iter()
-                    .for_each(|(stream_id, frames)| {
-                        let output_directory = format!("{}/{}", mask_frames.directory, stream_id.0);
-                        let mask_paths = frames.iter()
-                            .map(|(frame_idx, frame)| {
-                                let path = format!("{}/{}.png", output_directory, frame_idx);
-
-                                let buffer = ImageBuffer::<Luma<u8>, Vec<u8>>::from_raw(
-                                    frame.width(),
-                                    frame.height(),
-                                    frame.data.clone(),
-                                ).unwrap();
-
-                                let _ = buffer.save(&path);
-
-                                path
-                            })
-                            .collect::<Vec<_>>();
-
-                        mask_frames.frames.insert(**stream_id, mask_paths);
-                    });
-            } else {
-                info!("mask frames already exist for session {}", session.id);
-            }
+/// cancels the running nodes of a session and keeps further nodes from starting, remove it to resume the pipeline
+#[derive(Component, Default)]
+pub struct PipelineCancelled;
 
-            commands.entity(entity).insert(mask_frames);
-        }
+
+/// marks a session on which a node failed, so it is not retried every frame
+#[derive(Component)]
+pub struct PipelineNodeFailed<N: PipelineNode>(PhantomData<fn() -> N>);
+
+
+/// a node running on the session entity
+#[derive(Component)]
+pub struct PipelineNodeTask<N: PipelineNode> {
+    task: Task<Result<(N, bool), Error>>,
+    progress: NodeProgress,
+    reported: Option<(usize, usize)>,
+    started: Instant,
+}
+
+impl<N: PipelineNode> PipelineNodeTask<N> {
+    pub fn progress(&self) -> &NodeProgress {
+        &self.progress
+    }
+}
+
+
+pub trait PipelineNodeAppExt {
+    fn add_pipeline_node<N: PipelineNode>(&mut self) -> &mut Self;
+}
+
+impl PipelineNodeAppExt for App {
+    fn add_pipeline_node<N: PipelineNode>(&mut self) -> &mut Self {
+        self.add_event::<PipelineProgress>();
+        self.add_event::<PipelineNodeFinished>();
+        self.init_resource::<PipelineActivity>();
+        self.add_systems(
+            Update,
+            (
+                start_pipeline_node::<N>,
+                poll_pipeline_node::<N>,
+            ),
+        )
     }
 }
 
 
-fn generate_alphablend_frames(
+#[allow(clippy::type_complexity)]
+fn start_pipeline_node<N: PipelineNode>(
     mut commands: Commands,
-    session: Query<
+    sessions: Query<
         (
             Entity,
             &PipelineConfig,
-            &RotatedFrames,
-            &MaskFrames,
             &Session,
+            N::Inputs,
+        ),
+        (
+            Without<N>,
+            Without<PipelineNodeFailed<N>>,
+            Without<PipelineNodeTask<N>>,
+            Without<PipelineCancelled>,
         ),
-        Without<AlphablendFrames>,
     >,
+    mut params: StaticSystemParam<N::Params>,
+    mut activity: ResMut<PipelineActivity>,
+    mut finished_events: EventWriter<PipelineNodeFinished>,
 ) {
     for (
         entity,
         config,
-        rotated_frames,
-        mask_frames,
         session,
-    ) in session.iter() {
-        if config.alphablend_frames {
-            let run_node = !AlphablendFrames::exists(session);
-            let mut alphablend_frames = AlphablendFrames::load_from_session(session);
-
-            if run_node {
-                info!("generating alphablend frames for session {}", session.id);
-
-                rotated_frames.frames.iter()
-                    .for_each(|(stream_id, frames)| {
-                        let output_directory = format!("{}/{}", alphablend_frames.directory, stream_id.0);
-                        std::fs::create_dir_all(&output_directory).unwrap();
-
-                        let frames = frames.par_iter()
-                            .zip(mask_frames.frames.get(stream_id).unwrap())
-                            .map(|(frame, mask)| {
-                                let frame_idx = std::path::Path::new(frame).file_stem().unwrap().to_str().unwrap();
-                                let output_path = format!("{}/{}.png", output_directory, frame_idx);
-
-                                alphablend_image(
-                                    std::path::Path::new(frame),
-                                    std::path::Path::new(mask),
-                                    std::path::Path::new(&output_path),
-                                ).unwrap();
-
-                                output_path
-                            })
-                            .collect::<Vec<_>>();
-
-                            alphablend_frames.frames.insert(*stream_id, frames);
-                    });
-            } else {
-                info!("alphablend frames already exist for session {}", session.id);
-            }
+        inputs,
+    ) in sessions.iter() {
+        if !N::enabled(config) || !N::ready(config, &inputs) {
+            continue;
+        }
 
-            commands.entity(entity).insert(alphablend_frames);
+        let ready = N::prepare(&mut params);
+        if matches!(ready, Ok(false)) {
+            activity.waiting += 1;
+            return;
         }
+
+        let output_directory = N::directory(session);
+        let input_files = N::input_files(&inputs);
+        let node_config = N::config(config, &inputs, &params);
+
+        let job = match ready.and_then(|_| N::job(session, config, inputs, &mut params)) {
+            Ok(job) => job,
+            Err(err) => {
+                error!("failed to start {} for session {}: {}", N::DIRECTORY, session.id, err);
+                commands.entity(entity).insert(PipelineNodeFailed::<N>(PhantomData));
+
+                finished_events.send(PipelineNodeFinished {
+                    session: entity,
+                    node: N::DIRECTORY,
+                    outcome: NodeOutcome::Failed {
+                        error: err.to_string(),
+                    },
+                    seconds: 0.0,
+                });
+                continue;
+            },
+        };
+
+        let progress = NodeProgress::default();
+        let task = AsyncComputeTaskPool::get().spawn({
+            let session_id = session.id;
+            let progress = progress.clone();
+
+            async move {
+                update_node_output::<N>(
+                    session_id,
+                    &output_directory,
+                    &input_files,
+                    node_config,
+                    job,
+                    &progress,
+                )
+            }
+        });
+
+        commands.entity(entity).insert(PipelineNodeTask::<N> {
+            task,
+            progress,
+            reported: None,
+            started: Instant::now(),
+        });
+        activity.running += 1;
     }
 }
 
 
-fn generate_yolo_frames(
+fn poll_pipeline_node<N: PipelineNode>(
     mut commands: Commands,
-    raw_frames: Query<
-        (
-            Entity,
-            &PipelineConfig,
-            &RawFrames,
-            &Session,
-        ),
-        Without<YoloFrames>,
-    >,
-    yolo: Res<Yolo>,
-    onnx_assets: Res<Assets<Onnx>>,
+    mut tasks: Query<(
+        Entity,
+        &Session,
+        &mut PipelineNodeTask<N>,
+        Has<PipelineCancelled>,
+    )>,
+    mut progress_events: EventWriter<PipelineProgress>,
+    mut finished_events: EventWriter<PipelineNodeFinished>,
+    mut activity: ResMut<PipelineActivity>,
 ) {
     for (
         entity,
-        config,
-        raw_frames,
         session,
-    ) in raw_frames.iter() {
-        if config.yolo {
-            if onnx_assets.get(&yolo.onnx).is_none() {
-                return;
-            }
+        mut task,
+        cancelled,
+    ) in tasks.iter_mut() {
+        if cancelled {
+            task.progress.cancel();
+        }
 
-            let onnx = onnx_assets.get(&yolo.onnx).unwrap();
-            let onnx_session_arc = onnx.session.clone();
-            let onnx_session_lock = onnx_session_arc.lock().map_err(|e| e.to_string()).unwrap();
-            let onnx_session = onnx_session_lock.as_ref().ok_or("failed to get session from ONNX asset").unwrap();
-
-            let run_node = !YoloFrames::exists(session);
-            let mut yolo_frames = YoloFrames::load_from_session(session);
-
-            if run_node {
-                info!("generating yolo frames for session {}", session.id);
-
-                raw_frames.frames.keys()
-                    .for_each(|stream_id| {
-                        let output_directory = format!("{}/{}", yolo_frames.directory, stream_id.0);
-                        std::fs::create_dir_all(output_directory).unwrap();
-                    });
-
-                // TODO: support async ort inference (re. progress bars)
-                let bounding_box_streams = raw_frames.frames.iter()
-                    .map(|(stream_id, frames)| {
-                        let frames = frames.iter()
-                            .map(|frame| {
-                                let mut decoder = png::Decoder::new(std::fs::File::open(frame).unwrap());
-                                decoder.set_transformations(Transformations::EXPAND | Transformations::ALPHA);
-                                let mut reader = decoder.read_info().unwrap();
-                                let mut img_data = vec![0; reader.output_buffer_size()];
-                                let _ = reader.next_frame(&mut img_data).unwrap();
-
-                                assert_eq!(reader.info().bytes_per_pixel(), 3);
-
-                                let width = reader.info().width;
-                                let height = reader.info().height;
-
-                                // TODO: separate image loading and onnx inference (so the image loading result can be viewed in the pipeline grid view)
-                                let image = Image::new(
-                                    Extent3d {
-                                        width,
-                                        height,
-                                        depth_or_array_layers: 1,
-                                    },
-                                    bevy::render::render_resource::TextureDimension::D2,
-                                    img_data,
-                                    bevy::render::render_resource::TextureFormat::Rgba8UnormSrgb,
-                                    RenderAssetUsages::all(),
-                                );
-
-                                let frame_idx = std::path::Path::new(frame).file_stem().unwrap().to_str().unwrap();
-
-                                (
-                                    frame_idx,
-                                    yolo_inference(
-                                        onnx_session,
-                                        &image,
-                                        0.5,
-                                    ),
-                                )
-                            })
-                            .collect::<Vec<_>>();
-
-                        (stream_id, frames)
-                    })
-                    .collect::<Vec<_>>();
+        let result = block_on(future::poll_once(&mut task.task));
 
-                bounding_box_streams.iter()
-                    .for_each(|(stream_id, frames)| {
-                        let output_directory = format!("{}/{}", yolo_frames.directory, stream_id.0);
-                        let bounding_boxes = frames.iter()
-                            .map(|(frame_idx, bounding_boxes)| {
-                                let path = format!("{}/{}.json", output_directory, frame_idx);
+        let (done, total) = task.progress.get();
+        if task.reported != Some((done, total)) || result.is_some() {
+            task.reported = Some((done, total));
 
-                                let _ = serde_json::to_writer(std::fs::File::create(path).unwrap(), bounding_boxes);
+            progress_events.send(PipelineProgress {
+                session: entity,
+                node: N::DIRECTORY,
+                done,
+                total,
+            });
+        }
 
-                                bounding_boxes.clone()
-                            })
-                            .collect::<Vec<_>>();
+        let Some(result) = result else {
+            continue;
+        };
 
-                        yolo_frames.frames.insert(**stream_id, bounding_boxes);
-                    });
-            } else {
-                info!("yolo frames already exist for session {}", session.id);
-            }
+        commands.entity(entity).remove::<PipelineNodeTask<N>>();
+        activity.running = activity.running.saturating_sub(1);
 
-            commands.entity(entity).insert(yolo_frames);
-        }
+        let outcome = match result {
+            Ok((output, reused)) => {
+                commands.entity(entity).insert(output);
+
+                if reused {
+                    NodeOutcome::Reused
+                } else {
+                    NodeOutcome::Generated
+                }
+            },
+            Err(_) if task.progress.is_cancelled() => {
+                info!("cancelled {} for session {}", N::DIRECTORY, session.id);
+                let _ = std::fs::remove_dir_all(N::directory(session));
+
+                NodeOutcome::Cancelled
+            },
+            Err(err) => {
+                error!("failed to generate {} for session {}: {}", N::DIRECTORY, session.id, err);
+
+                // a partial output would be recomputed anyway, as it has no manifest
+                let _ = std::fs::remove_dir_all(N::directory(session));
+                commands.entity(entity).insert(PipelineNodeFailed::<N>(PhantomData));
+
+                NodeOutcome::Failed {
+                    error: err.to_string(),
+                }
+            },
+        };
+
+        finished_events.send(PipelineNodeFinished {
+            session: entity,
+            node: N::DIRECTORY,
+            outcome,
+            seconds: task.started.elapsed().as_secs_f64(),
+        });
     }
 }
 
 
-#[derive(Component, Default)]
-pub struct AlphablendFrames {
-    pub frames: HashMap<StreamId, Vec<String>>,
-    pub directory: String,
+/// reuses the output in `output_directory` if its manifest is up to date, otherwise runs the job, returning the output
+/// and whether it was reused
+fn update_node_output<N: PipelineNode>(
+    session_id: usize,
+    output_directory: &str,
+    input_files: &[String],
+    config: serde_json::Value,
+    job: PipelineJob,
+    progress: &NodeProgress,
+) -> Result<(N, bool), Error> {
+    let previous = NodeManifest::load(output_directory)
+        .unwrap_or_else(|err| {
+            warn!("failed to load the manifest of {}: {}", output_directory, err);
+            None
+        });
+
+    let mut manifest = NodeManifest {
+        node: N::DIRECTORY.to_string(),
+        version: N::VERSION,
+        config,
+        inputs: fingerprint_files(input_files, previous.as_ref())?,
+        outputs: BTreeMap::new(),
+    };
+
+    let stale_reason = match &previous {
+        Some(previous) => previous.stale_reason(&manifest, output_directory),
+        None if std::fs::metadata(output_directory).is_ok() => Some("a previous run did not complete".to_string()),
+        None => None,
+    };
+
+    match (previous, stale_reason) {
+        (Some(previous), None) => {
+            info!("{} are up to date for session {}", N::DIRECTORY, session_id);
+
+            // refreshes the recorded modification times, so unchanged inputs are not hashed again
+            if previous.inputs != manifest.inputs {
+                manifest.outputs = previous.outputs;
+                manifest.save(output_directory)?;
+            }
+
+            return Ok((N::load(output_directory), true));
+        },
+        (_, Some(reason)) => {
+            info!("regenerating {} for session {}, {}", N::DIRECTORY, session_id, reason);
+            std::fs::remove_dir_all(output_directory)?;
+        },
+        (None, None) => info!("generating {} for session {}", N::DIRECTORY, session_id),
+    }
+
+    std::fs::create_dir_all(output_directory)?;
+    job(output_directory, progress)?;
+
+    manifest.outputs = NodeManifest::outputs(output_directory)?;
+    manifest.save(output_directory)?;
+
+    Ok((N::load(output_directory), false))
 }
-impl AlphablendFrames {
-    pub fn load_from_session(
-        session: &Session,
-    ) -> Self {
-        let directory = format!("{}/alphablend", session.directory);
-        std::fs::create_dir_all(&directory).unwrap();
 
-        let mut alphablend_frames = Self {
-            frames: HashMap::new(),
-            directory,
-        };
-        alphablend_frames.reload();
 
-        alphablend_frames
-    }
+/// the files with `extension` in each `<directory>/<stream id>/` directory, in frame number order
+pub fn load_stream_frames(directory: &str, extension: &str) -> HashMap<StreamId, Vec<String>> {
+    let Ok(stream_dirs) = std::fs::read_dir(directory) else {
+        return HashMap::new();
+    };
 
-    pub fn reload(&mut self) {
-        std::fs::read_dir(&self.directory)
-            .unwrap()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.path().is_dir())
-            .map(|stream_dir| {
-                let stream_id = StreamId(stream_dir.path().file_name().unwrap().to_str().unwrap().parse::<usize>().unwrap());
-
-                let frames = std::fs::read_dir(stream_dir.path()).unwrap()
-                    .filter_map(|entry| entry.ok())
-                    .filter(|entry| entry.path().is_file() && entry.path().extension().and_then(|s| s.to_str()) == Some("png"))
-                    .map(|entry| entry.path().to_str().unwrap().to_string())
-                    .collect::<Vec<_>>();
+    stream_dirs
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|stream_dir| {
+            let stream_id = StreamId(stream_dir.file_name().to_str()?.parse::<usize>().ok()?);
 
-                (stream_id, frames)
-            })
-            .for_each(|(stream_id, frames)| {
-                self.frames.insert(stream_id, frames);
-            });
-    }
+            let mut frames = std::fs::read_dir(stream_dir.path()).ok()?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_file() && entry.path().extension().and_then(|s| s.to_str()) == Some(extension))
+                .map(|entry| entry.path().to_str().unwrap().to_string())
+                .collect::<Vec<_>>();
 
-    pub fn exists(
-        session: &Session,
-    ) -> bool {
-        let output_directory = format!("{}/alphablend", session.directory);
-        std::fs::metadata(output_directory).is_ok()
-    }
+            frames.sort_by_key(|frame| frame_index(frame));
 
-    pub fn image(&self, _camera: usize, _frame: usize) -> Option<Image> {
-        todo!()
-    }
+            Some((stream_id, frames))
+        })
+        .collect()
 }
 
+/// the frame number of a `<n>.<extension>` frame path
+pub fn frame_index(frame: &str) -> Option<usize> {
+    std::path::Path::new(frame).file_stem()?.to_str()?.parse::<usize>().ok()
+}
 
-
-// TODO: support loading maskframes -> images into a pipeline mask viewer
+/// `<output_directory>/<stream id>/<n>.<extension>` for an input frame `<n>.*`, creating the stream directory
+pub fn stream_frame_path(
+    output_directory: &str,
+    stream_id: StreamId,
+    input_frame: &str,
+    extension: &str,
+) -> Result<String, Error> {
+    let stream_directory = format!("{}/{}", output_directory, stream_id.0);
+    std::fs::create_dir_all(&stream_directory)?;
+
+    let frame_idx = std::path::Path::new(input_frame)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| anyhow!("invalid frame path {}", input_frame))?;
+
+    Ok(format!("{}/{}.{}", stream_directory, frame_idx, extension))
+}
 
 
 #[derive(Component, Default)]
@@ -630,52 +916,371 @@ pub struct RawFrames {
     pub frames: HashMap<StreamId, Vec<String>>,
     pub directory: String,
 }
-impl RawFrames {
-    pub fn load_from_session(
-        session: &Session,
-    ) -> Self {
-        let directory = format!("{}/frames", session.directory);
-        std::fs::create_dir_all(&directory).unwrap();
 
-        let mut raw_frames = Self {
-            frames: HashMap::new(),
-            directory,
-        };
-        raw_frames.reload();
+impl PipelineNode for RawFrames {
+    const DIRECTORY: &'static str = "frames";
+    type Inputs = &'static RawStreams;
+    type Params = ();
 
-        raw_frames
+    fn enabled(config: &PipelineConfig) -> bool {
+        config.raw_frames
     }
 
-    pub fn reload(&mut self) {
-        std::fs::read_dir(&self.directory)
-            .unwrap()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.path().is_dir())
-            .map(|stream_dir| {
-                let stream_id = StreamId(stream_dir.path().file_name().unwrap().to_str().unwrap().parse::<usize>().unwrap());
-
-                let frames = std::fs::read_dir(stream_dir.path()).unwrap()
-                    .filter_map(|entry| entry.ok())
-                    .filter(|entry| entry.path().is_file() && entry.path().extension().and_then(|s| s.to_str()) == Some("png"))
-                    .map(|entry| entry.path().to_str().unwrap().to_string())
-                    .collect::<Vec<_>>();
+    fn input_files(raw_streams: &&RawStreams) -> Vec<String> {
+        raw_streams.streams.clone()
+    }
 
-                (stream_id, frames)
-            })
-            .for_each(|(stream_id, frames)| {
-                self.frames.insert(stream_id, frames);
-            });
+    fn config(config: &PipelineConfig, _inputs: &&RawStreams, _params: &()) -> serde_json::Value {
+        serde_json::to_value(&config.frame_extraction).unwrap_or_default()
     }
 
-    pub fn exists(
+    fn job(
         session: &Session,
-    ) -> bool {
-        let output_directory = format!("{}/frames", session.directory);
-        std::fs::metadata(output_directory).is_ok()
+        config: &PipelineConfig,
+        raw_streams: &RawStreams,
+        _params: &mut (),
+    ) -> Result<PipelineJob, Error> {
+        let session_id = session.id;
+        let extraction = config.frame_extraction.clone();
+        let segments = raw_streams.segments();
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            // stored with the frames so a session can be re-extracted identically
+            extraction.save(output_directory)?;
+
+            progress.set_total(segments.values().map(Vec::len).sum());
+
+            segments.into_par_iter()
+                .try_for_each(|(stream_idx, mp4_paths)| {
+                    let stream_directory = format!("{}/{}", output_directory, stream_idx);
+                    std::fs::create_dir_all(&stream_directory)?;
+
+                    // segments continue the frame numbering of the previous segment
+                    let mut start_number = 1;
+                    for mp4_path in mp4_paths {
+                        progress.check_cancelled()?;
+
+                        let extractor = FrameExtractor {
+                            extraction: extraction.clone(),
+                            start_number,
+                        };
+
+                        start_number += extractor.extract(&mp4_path, &stream_directory)
+                            .map_err(|err| anyhow!("failed to extract frames from {} of session {}: {}", mp4_path, session_id, err))?;
+
+                        progress.advance();
+                    }
+
+                    Ok(())
+                })
+        }))
     }
 
-    pub fn image(&self, _camera: usize, _frame: usize) -> Option<Image> {
-        todo!()
+    fn load(directory: &str) -> Self {
+        Self {
+            frames: load_stream_frames(directory, "png"),
+            directory: directory.to_string(),
+        }
+    }
+}
+
+
+#[derive(Component, Default)]
+pub struct RotatedFrames {
+    pub frames: HashMap<StreamId, Vec<String>>,
+    pub directory: String,
+}
+
+impl PipelineNode for RotatedFrames {
+    const DIRECTORY: &'static str = "rotated_frames";
+    type Inputs = (
+        &'static RawFrames,
+        Option<&'static SessionManifest>,
+    );
+    type Params = Query<'static, 'static, &'static RtspStreamHandle>;
+
+    fn enabled(config: &PipelineConfig) -> bool {
+        config.rotate_raw_frames
+    }
+
+    fn input_files((raw_frames, _): &(&RawFrames, Option<&SessionManifest>)) -> Vec<String> {
+        raw_frames.frames.values().flatten().cloned().collect()
+    }
+
+    fn config(
+        _config: &PipelineConfig,
+        (raw_frames, manifest): &(&RawFrames, Option<&SessionManifest>),
+        streams: &SystemParamItem<Self::Params>,
+    ) -> serde_json::Value {
+        let rotations: BTreeMap<usize, f32> = session_rotations(raw_frames, *manifest, streams)
+            .into_iter()
+            .map(|(stream_id, rotation)| (stream_id.0, rotation))
+            .collect();
+
+        serde_json::json!({ "rotations": rotations })
+    }
+
+    fn job(
+        session: &Session,
+        _config: &PipelineConfig,
+        (raw_frames, manifest): (&RawFrames, Option<&SessionManifest>),
+        streams: &mut SystemParamItem<Self::Params>,
+    ) -> Result<PipelineJob, Error> {
+        if manifest.is_none() {
+            warn!("session {} has no session.json, rotating with the current stream rotations", session.id);
+        }
+
+        let rotations = session_rotations(raw_frames, manifest, streams);
+        let frames = raw_frames.frames.clone();
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            progress.set_total(frames.values().map(Vec::len).sum());
+
+            frames.iter()
+                .try_for_each(|(stream_id, frames)| {
+                    frames.par_iter()
+                        .try_for_each(|frame| {
+                            progress.check_cancelled()?;
+
+                            let output_path = stream_frame_path(output_directory, *stream_id, frame, "png")?;
+
+                            rotate_image(
+                                std::path::Path::new(frame),
+                                std::path::Path::new(&output_path),
+                                rotations.get(stream_id).copied().unwrap_or_default(),
+                            )?;
+
+                            progress.advance();
+                            Ok(())
+                        })
+                })
+        }))
+    }
+
+    fn load(directory: &str) -> Self {
+        Self {
+            frames: load_stream_frames(directory, "png"),
+            directory: directory.to_string(),
+        }
+    }
+}
+
+
+/// rotations of the recorded streams, from the session manifest if the session has one
+fn session_rotations(
+    raw_frames: &RawFrames,
+    manifest: Option<&SessionManifest>,
+    streams: &Query<&RtspStreamHandle>,
+) -> HashMap<StreamId, f32> {
+    raw_frames.frames.keys()
+        .map(|&stream_id| {
+            let rotation = match manifest {
+                Some(manifest) => manifest.stream(stream_id).and_then(|stream| stream.descriptor.rotation),
+                None => streams.iter()
+                    .find(|stream| stream.id == stream_id)
+                    .and_then(|stream| stream.descriptor.rotation),
+            };
+
+            (stream_id, rotation.unwrap_or_default())
+        })
+        .collect()
+}
+
+
+/// requests the model at `path` unless one is requested already, whether it is loaded, or an error once loading it
+/// failed, e.g. when the model is missing from the assets directory
+fn prepare_onnx(
+    onnx: &mut Handle<Onnx>,
+    path: &str,
+    onnx_assets: &Assets<Onnx>,
+    asset_server: &AssetServer,
+) -> Result<bool, Error> {
+    if *onnx == Handle::default() {
+        *onnx = asset_server.load(path.to_string());
+    }
+
+    if onnx_assets.contains(onnx.id()) {
+        return Ok(true);
+    }
+
+    match asset_server.load_state(onnx.id()) {
+        LoadState::Failed => bail!("failed to load {}", onnx.path().map(|path| path.to_string()).unwrap_or_else(|| path.to_string())),
+        _ => Ok(false),
+    }
+}
+
+
+// TODO: support loading maskframes -> images into a pipeline mask viewer
+#[derive(Component, Default, Reflect)]
+pub struct MaskFrames {
+    pub frames: HashMap<StreamId, Vec<String>>,
+    pub directory: String
+}
+
+impl PipelineNode for MaskFrames {
+    const DIRECTORY: &'static str = "masks";
+    type Inputs = &'static RotatedFrames;
+    type Params = (
+        ResMut<'static, Modnet>,
+        Res<'static, Assets<Onnx>>,
+        Res<'static, AssetServer>,
+    );
+
+    fn enabled(config: &PipelineConfig) -> bool {
+        config.mask_frames
+    }
+
+    fn input_files(frames: &&RotatedFrames) -> Vec<String> {
+        frames.frames.values().flatten().cloned().collect()
+    }
+
+    fn config(
+        _config: &PipelineConfig,
+        _inputs: &&RotatedFrames,
+        (modnet, _, _): &SystemParamItem<Self::Params>,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "model": modnet.onnx.path().map(|path| path.to_string()),
+        })
+    }
+
+    fn prepare((modnet, onnx_assets, asset_server): &mut SystemParamItem<Self::Params>) -> Result<bool, Error> {
+        prepare_onnx(
+            &mut modnet.onnx,
+            "models/modnet_photographic_portrait_matting.onnx",
+            onnx_assets,
+            asset_server,
+        )
+    }
+
+    fn job(
+        _session: &Session,
+        _config: &PipelineConfig,
+        frames: &RotatedFrames,
+        (modnet, onnx_assets, _): &mut SystemParamItem<Self::Params>,
+    ) -> Result<PipelineJob, Error> {
+        let onnx = onnx_assets.get(&modnet.onnx).ok_or_else(|| anyhow!("modnet is not loaded"))?;
+        let onnx_session_arc = onnx.session.clone();
+        let frames = frames.frames.clone();
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            let onnx_session_lock = onnx_session_arc.lock().map_err(|e| anyhow!("{}", e))?;
+            let onnx_session = onnx_session_lock.as_ref().ok_or_else(|| anyhow!("failed to get session from ONNX asset"))?;
+
+            progress.set_total(frames.values().map(Vec::len).sum());
+
+            for (stream_id, frames) in frames.iter() {
+                for frame in frames {
+                    progress.check_cancelled()?;
+
+                    // TODO: separate image loading and onnx inference (so the image loading result can be viewed in the pipeline grid view)
+                    let image = load_rgb_png_as_rgba(frame)?;
+
+                    let mask = modnet_inference(
+                        onnx_session,
+                        &[&image],
+                        Some((512, 512)),
+                    ).pop().ok_or_else(|| anyhow!("no modnet output for {}", frame))?;
+
+                    let buffer = ImageBuffer::<Luma<u8>, Vec<u8>>::from_raw(
+                        mask.width(),
+                        mask.height(),
+                        mask.data,
+                    ).ok_or_else(|| anyhow!("invalid modnet output for {}", frame))?;
+
+                    buffer.save(stream_frame_path(output_directory, *stream_id, frame, "png")?)?;
+                    progress.advance();
+                }
+            }
+
+            Ok(())
+        }))
+    }
+
+    fn load(directory: &str) -> Self {
+        Self {
+            frames: load_stream_frames(directory, "png"),
+            directory: directory.to_string(),
+        }
+    }
+}
+
+
+#[derive(Component, Default)]
+pub struct AlphablendFrames {
+    pub frames: HashMap<StreamId, Vec<String>>,
+    pub directory: String,
+}
+
+impl PipelineNode for AlphablendFrames {
+    const DIRECTORY: &'static str = "alphablend";
+    type Inputs = (
+        &'static RotatedFrames,
+        &'static MaskFrames,
+    );
+    type Params = ();
+
+    fn enabled(config: &PipelineConfig) -> bool {
+        config.alphablend_frames
+    }
+
+    fn input_files((rotated_frames, mask_frames): &(&RotatedFrames, &MaskFrames)) -> Vec<String> {
+        rotated_frames.frames.values()
+            .chain(mask_frames.frames.values())
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    fn job(
+        _session: &Session,
+        _config: &PipelineConfig,
+        (rotated_frames, mask_frames): (&RotatedFrames, &MaskFrames),
+        _params: &mut (),
+    ) -> Result<PipelineJob, Error> {
+        let rotated_frames = rotated_frames.frames.clone();
+        let mask_frames = mask_frames.frames.clone();
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            progress.set_total(rotated_frames.values().map(Vec::len).sum());
+
+            rotated_frames.iter()
+                .try_for_each(|(stream_id, frames)| {
+                    // masks are named after their frame, frames without a mask are an error rather than paired with another mask
+                    let masks = mask_frames.get(stream_id)
+                        .ok_or_else(|| anyhow!("no masks for stream {}", stream_id.0))?
+                        .iter()
+                        .filter_map(|mask| Some((std::path::Path::new(mask).file_stem()?.to_owned(), mask)))
+                        .collect::<HashMap<_, _>>();
+
+                    frames.par_iter()
+                        .try_for_each(|frame| {
+                            progress.check_cancelled()?;
+
+                            let mask = std::path::Path::new(frame).file_stem()
+                                .and_then(|stem| masks.get(stem))
+                                .ok_or_else(|| anyhow!("no mask for frame {} of stream {}", frame, stream_id.0))?;
+
+                            let output_path = stream_frame_path(output_directory, *stream_id, frame, "png")?;
+
+                            alphablend_image(
+                                std::path::Path::new(frame),
+                                std::path::Path::new(mask),
+                                std::path::Path::new(&output_path),
+                            )?;
+
+                            progress.advance();
+                            Ok(())
+                        })
+                })
+        }))
+    }
+
+    fn load(directory: &str) -> Self {
+        Self {
+            frames: load_stream_frames(directory, "png"),
+            directory: directory.to_string(),
+        }
     }
 }
 
@@ -685,198 +1290,984 @@ pub struct YoloFrames {
     pub frames: HashMap<StreamId, Vec<Vec<BoundingBox>>>,
     pub directory: String,
 }
-impl YoloFrames {
-    pub fn load_from_session(
-        session: &Session,
-    ) -> Self {
-        let directory = format!("{}/yolo_frames", session.directory);
-        std::fs::create_dir_all(&directory).unwrap();
 
-        let mut yolo_frames = Self {
-            frames: HashMap::new(),
-            directory,
-        };
-        yolo_frames.reload();
+impl PipelineNode for YoloFrames {
+    const DIRECTORY: &'static str = "yolo_frames";
+    type Inputs = &'static RawFrames;
+    type Params = (
+        ResMut<'static, Yolo>,
+        Res<'static, Assets<Onnx>>,
+        Res<'static, AssetServer>,
+    );
 
-        yolo_frames
+    fn enabled(config: &PipelineConfig) -> bool {
+        config.yolo
     }
 
-    pub fn reload(&mut self) {
-        std::fs::read_dir(&self.directory)
-            .unwrap()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.path().is_dir())
-            .map(|stream_dir| {
-                let stream_id = StreamId(stream_dir.path().file_name().unwrap().to_str().unwrap().parse::<usize>().unwrap());
-
-                let frames = std::fs::read_dir(stream_dir.path()).unwrap()
-                    .filter_map(|entry| entry.ok())
-                    .filter(|entry| entry.path().is_file() && entry.path().extension().and_then(|s| s.to_str()) == Some("json"))
-                    .map(|entry| std::fs::File::open(entry.path()).unwrap())
-                    .map(|yolo_json_file| {
-                        let bounding_boxes: Vec<BoundingBox> = serde_json::from_reader(&yolo_json_file).unwrap();
-
-                        bounding_boxes
-                    })
-                    .collect::<Vec<_>>();
+    fn input_files(raw_frames: &&RawFrames) -> Vec<String> {
+        raw_frames.frames.values().flatten().cloned().collect()
+    }
+
+    fn config(
+        _config: &PipelineConfig,
+        _inputs: &&RawFrames,
+        (yolo, _, _): &SystemParamItem<Self::Params>,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "model": yolo.onnx.path().map(|path| path.to_string()),
+        })
+    }
+
+    fn prepare((yolo, onnx_assets, asset_server): &mut SystemParamItem<Self::Params>) -> Result<bool, Error> {
+        prepare_onnx(
+            &mut yolo.onnx,
+            "models/yolov8n.onnx",
+            onnx_assets,
+            asset_server,
+        )
+    }
+
+    fn job(
+        _session: &Session,
+        _config: &PipelineConfig,
+        raw_frames: &RawFrames,
+        (yolo, onnx_assets, _): &mut SystemParamItem<Self::Params>,
+    ) -> Result<PipelineJob, Error> {
+        let onnx = onnx_assets.get(&yolo.onnx).ok_or_else(|| anyhow!("yolo is not loaded"))?;
+        let onnx_session_arc = onnx.session.clone();
+        let frames = raw_frames.frames.clone();
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            let onnx_session_lock = onnx_session_arc.lock().map_err(|e| anyhow!("{}", e))?;
+            let onnx_session = onnx_session_lock.as_ref().ok_or_else(|| anyhow!("failed to get session from ONNX asset"))?;
+
+            progress.set_total(frames.values().map(Vec::len).sum());
+
+            for (stream_id, frames) in frames.iter() {
+                for frame in frames {
+                    progress.check_cancelled()?;
+
+                    // TODO: separate image loading and onnx inference (so the image loading result can be viewed in the pipeline grid view)
+                    let image = load_rgb_png_as_rgba(frame)?;
+
+                    let bounding_boxes = yolo_inference(
+                        onnx_session,
+                        &image,
+                        0.5,
+                    );
+
+                    let path = stream_frame_path(output_directory, *stream_id, frame, "json")?;
+                    serde_json::to_writer(std::fs::File::create(path)?, &bounding_boxes)?;
+                    progress.advance();
+                }
+            }
 
-                // TODO: parse the json at each frame path to get the bounding boxes
+            Ok(())
+        }))
+    }
+
+    fn load(directory: &str) -> Self {
+        let frames = load_stream_frames(directory, "json")
+            .into_iter()
+            .map(|(stream_id, frames)| {
+                let bounding_boxes = frames.iter()
+                    .map(|frame| {
+                        std::fs::File::open(frame)
+                            .ok()
+                            .and_then(|file| serde_json::from_reader::<_, Vec<BoundingBox>>(file).ok())
+                            .unwrap_or_default()
+                    })
+                    .collect();
 
-                (stream_id, frames)
+                (stream_id, bounding_boxes)
             })
-            .for_each(|(stream_id, frames)| {
-                self.frames.insert(stream_id, frames);
-            });
+            .collect();
+
+        Self {
+            frames,
+            directory: directory.to_string(),
+        }
     }
+}
 
-    pub fn write(&self) {
-        self.frames.iter()
-            .for_each(|(stream_id, frames)| {
-                let output_directory = format!("{}/{}", self.directory, stream_id.0);
-                std::fs::create_dir_all(&output_directory).unwrap();
 
-                frames.iter()
-                    .enumerate()
-                    .for_each(|(frame_idx, bounding_boxes)| {
-                        let path = format!("{}/{}.json", output_directory, frame_idx);
-                        let _ = serde_json::to_writer(std::fs::File::create(path).unwrap(), bounding_boxes);
-                    });
-            });
+/// loads an rgb8 png as an rgba8 image for model inference
+fn load_rgb_png_as_rgba(frame: &str) -> Result<Image, Error> {
+    let mut decoder = png::Decoder::new(std::fs::File::open(frame)?);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::ALPHA);
+    let mut reader = decoder.read_info()?;
+    let mut img_data = vec![0; reader.output_buffer_size()];
+    let _ = reader.next_frame(&mut img_data)?;
+
+    if reader.info().bytes_per_pixel() != 3 {
+        bail!("expected an rgb8 frame, {} has {} bytes per pixel", frame, reader.info().bytes_per_pixel());
     }
 
-    pub fn exists(
-        session: &Session,
+    let width = reader.info().width;
+    let height = reader.info().height;
+
+    Ok(Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        bevy::render::render_resource::TextureDimension::D2,
+        img_data,
+        bevy::render::render_resource::TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::all(),
+    ))
+}
+
+
+#[derive(Debug, Default, Clone, Reflect, Serialize, Deserialize)]
+pub struct LightFieldCamera {
+    pub stream_id: usize,
+    pub width: u32,
+    pub height: u32,
+
+    pub intrinsics: CameraIntrinsics,
+
+    /// root mean square reprojection error of the calibration views in pixels
+    pub rms_error: f64,
+
+    /// frames the intrinsics were estimated from
+    pub views: usize,
+
+    /// pose in the world frame, `None` if the camera never saw the board together with the other cameras
+    #[serde(default)]
+    pub extrinsics: Option<CameraExtrinsics>,
+
+    /// `None` if the camera never saw the color checker
+    #[serde(default)]
+    pub color: Option<ColorCorrection>,
+}
+
+#[derive(Component, Default, Reflect, Serialize, Deserialize)]
+pub struct LightFieldCameras {
+    pub cameras: Vec<LightFieldCamera>,
+
+    /// stream whose camera frame is the world frame of the extrinsics
+    #[serde(default)]
+    pub reference_stream: Option<usize>,
+
+    #[serde(skip)]
+    pub directory: String,
+}
+
+impl LightFieldCameras {
+    pub fn path(directory: impl AsRef<std::path::Path>) -> std::path::PathBuf {
+        directory.as_ref().join("cameras.json")
+    }
+
+    pub fn camera(&self, stream_id: StreamId) -> Option<&LightFieldCamera> {
+        self.cameras.iter().find(|camera| camera.stream_id == stream_id.0)
+    }
+
+    pub fn save(&self, directory: impl AsRef<std::path::Path>) -> Result<(), Error> {
+        let file = std::fs::File::create(Self::path(directory))?;
+        serde_json::to_writer_pretty(file, self)?;
+
+        Ok(())
+    }
+}
+
+impl PipelineNode for LightFieldCameras {
+    const DIRECTORY: &'static str = "calibration";
+    const VERSION: u32 = 5;
+    type Inputs = (
+        &'static RotatedFrames,
+        Option<&'static ColorCalibration>,
+        Has<PipelineNodeFailed<ColorCalibration>>,
+    );
+    type Params = ();
+
+    fn enabled(config: &PipelineConfig) -> bool {
+        config.light_field_cameras
+    }
+
+    // an enabled color calibration is waited for, the cameras are calibrated without colors if it fails
+    fn ready(
+        config: &PipelineConfig,
+        (_, colors, color_calibration_failed): &(&RotatedFrames, Option<&ColorCalibration>, bool),
     ) -> bool {
-        let output_directory = format!("{}/yolo_frames", session.directory);
-        std::fs::metadata(output_directory).is_ok()
+        !ColorCalibration::enabled(config) || colors.is_some() || *color_calibration_failed
     }
 
-    pub fn image(&self, _camera: usize, _frame: usize) -> Option<Image> {
-        todo!()
+    fn input_files((frames, colors, _): &(&RotatedFrames, Option<&ColorCalibration>, bool)) -> Vec<String> {
+        frames.frames.values()
+            .flatten()
+            .cloned()
+            .chain(colors.map(|colors| ColorCalibration::path(&colors.directory).to_string_lossy().to_string()))
+            .collect()
+    }
+
+    fn config(
+        config: &PipelineConfig,
+        _inputs: &(&RotatedFrames, Option<&ColorCalibration>, bool),
+        _params: &(),
+    ) -> serde_json::Value {
+        serde_json::to_value(&config.calibration).unwrap_or_default()
+    }
+
+    // calibrated on the rotated frames, which the masks and the undistorted frames are aligned with
+    fn job(
+        session: &Session,
+        config: &PipelineConfig,
+        (frames, colors, _): (&RotatedFrames, Option<&ColorCalibration>, bool),
+        _params: &mut (),
+    ) -> Result<PipelineJob, Error> {
+        let session_id = session.id;
+        let calibration = config.calibration.clone();
+        let colors = colors.map(|colors| colors.cameras.clone()).unwrap_or_default();
+
+        let mut frames = frames.frames.clone().into_iter().collect::<Vec<_>>();
+        frames.sort_by_key(|(stream_id, _)| stream_id.0);
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            progress.set_total(frames.iter().map(|(_, frames)| frames.len()).sum());
+
+            let object_points = calibration.checkerboard.object_points();
+
+            let cameras = frames.par_iter()
+                .map(|(stream_id, frames)| {
+                    let detections = frames.par_iter()
+                        .map(|frame| {
+                            progress.check_cancelled()?;
+
+                            let image = image::open(frame)?.to_luma8();
+                            let corners = calibration.checkerboard.detect(&image);
+
+                            progress.advance();
+                            Ok((frame_index(frame).unwrap_or_default(), image.dimensions(), corners))
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+
+                    let detected = detections.into_iter()
+                        .filter_map(|(frame, size, corners)| Some((frame, size, corners?)))
+                        .collect::<Vec<_>>();
+
+                    // paired by frame index across streams for the extrinsics, also kept to inspect failed calibrations
+                    let detections: BTreeMap<usize, BoardCorners> = detected.iter()
+                        .map(|(frame, _, corners)| (*frame, corners.clone()))
+                        .collect();
+
+                    let stream_directory = format!("{}/{}", output_directory, stream_id.0);
+                    std::fs::create_dir_all(&stream_directory)?;
+                    serde_json::to_writer(std::fs::File::create(format!("{}/corners.json", stream_directory))?, &detections)?;
+
+                    let Some(&(_, (width, height), _)) = detected.first() else {
+                        warn!("no calibration board detected in stream {} of session {}", stream_id.0, session_id);
+                        return Ok(None);
+                    };
+
+                    // evenly spaced views, consecutive frames of a slow moving board add little
+                    let stride = detected.len().div_ceil(calibration.max_views.max(1));
+                    let views = detected.iter()
+                        .step_by(stride.max(1))
+                        .map(|(_, _, corners)| corners.clone())
+                        .collect::<Vec<_>>();
+
+                    match calibrate_intrinsics(&views, &object_points, (width, height), calibration.outlier_factor) {
+                        Ok(intrinsics) => {
+                            info!(
+                                "calibrated stream {} of session {} from {} of {} frames, rms error {:.3} px",
+                                stream_id.0,
+                                session_id,
+                                intrinsics.views.len(),
+                                frames.len(),
+                                intrinsics.rms_error,
+                            );
+
+                            let camera = LightFieldCamera {
+                                stream_id: stream_id.0,
+                                width,
+                                height,
+                                intrinsics: intrinsics.intrinsics,
+                                rms_error: intrinsics.rms_error,
+                                views: intrinsics.views.len(),
+                                extrinsics: None,
+                                color: colors.get(&stream_id.0).cloned(),
+                            };
+
+                            Ok(Some((camera, detections)))
+                        },
+                        Err(err) => {
+                            warn!("failed to calibrate stream {} of session {}: {}", stream_id.0, session_id, err);
+                            Ok(None)
+                        },
+                    }
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            let (cameras, detections): (Vec<_>, Vec<_>) = cameras.into_iter().flatten().unzip();
+            let mut cameras = LightFieldCameras {
+                cameras,
+                reference_stream: None,
+                directory: output_directory.to_string(),
+            };
+
+            if cameras.cameras.is_empty() {
+                bail!(
+                    "no stream could be calibrated, the {}x{} board was not found in enough frames",
+                    calibration.checkerboard.columns,
+                    calibration.checkerboard.rows,
+                );
+            }
+
+            if cameras.cameras.len() > 1 {
+                progress.check_cancelled()?;
+
+                let observations = cameras.cameras.iter()
+                    .zip(detections)
+                    .map(|(camera, detections)| CameraObservations {
+                        intrinsics: camera.intrinsics.clone(),
+                        detections,
+                    })
+                    .collect::<Vec<_>>();
+
+                match calibrate_extrinsics(&observations, &object_points, &calibration.extrinsics) {
+                    Ok(extrinsics) => {
+                        info!("calibrated the extrinsics of session {}, rms error {:.3} px", session_id, extrinsics.rms_error);
+
+                        cameras.reference_stream = Some(cameras.cameras[extrinsics.reference].stream_id);
+
+                        for (camera, extrinsics) in cameras.cameras.iter_mut().zip(extrinsics.cameras) {
+                            match &extrinsics {
+                                Some(extrinsics) if extrinsics.flagged => warn!(
+                                    "camera of stream {} has a reprojection error of {:.3} px over {} frames, check its mount",
+                                    camera.stream_id,
+                                    extrinsics.rms_error,
+                                    extrinsics.frames,
+                                ),
+                                Some(extrinsics) => info!(
+                                    "camera of stream {}: reprojection error {:.3} px over {} frames",
+                                    camera.stream_id,
+                                    extrinsics.rms_error,
+                                    extrinsics.frames,
+                                ),
+                                None => warn!("camera of stream {} shares no frames with the other cameras", camera.stream_id),
+                            }
+
+                            camera.extrinsics = extrinsics;
+                        }
+                    },
+                    Err(err) => warn!("failed to calibrate the extrinsics of session {}: {}", session_id, err),
+                }
+            }
+
+            cameras.save(output_directory)
+        }))
+    }
+
+    fn load(directory: &str) -> Self {
+        let cameras = std::fs::File::open(Self::path(directory))
+            .map_err(Error::from)
+            .and_then(|file| Ok(serde_json::from_reader::<_, Self>(std::io::BufReader::new(file))?));
+
+        match cameras {
+            Ok(cameras) => Self {
+                directory: directory.to_string(),
+                ..cameras
+            },
+            Err(err) => {
+                warn!("failed to load the cameras of {}: {}", directory, err);
+
+                Self {
+                    directory: directory.to_string(),
+                    ..default()
+                }
+            },
+        }
     }
 }
 
 
+/// per stream color corrections fitted to a color checker, independent of the checkerboard calibration so a
+/// session capturing only the color checker is color corrected
+#[derive(Component, Default, Serialize, Deserialize)]
+pub struct ColorCalibration {
+    /// by stream id, streams which never saw the color checker are missing
+    pub cameras: BTreeMap<usize, ColorCorrection>,
+
+    #[serde(skip)]
+    pub directory: String,
+}
+
+impl ColorCalibration {
+    pub fn path(directory: impl AsRef<std::path::Path>) -> std::path::PathBuf {
+        directory.as_ref().join("colors.json")
+    }
+
+    pub fn camera(&self, stream_id: StreamId) -> Option<&ColorCorrection> {
+        self.cameras.get(&stream_id.0)
+    }
+
+    pub fn save(&self, directory: impl AsRef<std::path::Path>) -> Result<(), Error> {
+        let file = std::fs::File::create(Self::path(directory))?;
+        serde_json::to_writer_pretty(file, self)?;
+
+        Ok(())
+    }
+}
+
+impl PipelineNode for ColorCalibration {
+    const DIRECTORY: &'static str = "color_calibration";
+    type Inputs = &'static RotatedFrames;
+    type Params = ();
+
+    fn enabled(config: &PipelineConfig) -> bool {
+        config.color_calibration && config.calibration.max_color_frames > 0
+    }
+
+    fn input_files(frames: &&RotatedFrames) -> Vec<String> {
+        frames.frames.values().flatten().cloned().collect()
+    }
+
+    fn config(config: &PipelineConfig, _inputs: &&RotatedFrames, _params: &()) -> serde_json::Value {
+        serde_json::json!({ "max_color_frames": config.calibration.max_color_frames })
+    }
+
+    fn job(
+        session: &Session,
+        config: &PipelineConfig,
+        frames: &RotatedFrames,
+        _params: &mut (),
+    ) -> Result<PipelineJob, Error> {
+        let session_id = session.id;
+        let max_color_frames = config.calibration.max_color_frames;
+
+        // the color checker is searched in evenly spaced frames, as it is held still for a while
+        let mut frames = frames.frames.iter()
+            .map(|(stream_id, frames)| {
+                let stride = frames.len().div_ceil(max_color_frames.max(1)).max(1);
+                (*stream_id, frames.iter().step_by(stride).cloned().collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        frames.sort_by_key(|(stream_id, _)| stream_id.0);
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            progress.set_total(frames.iter().map(|(_, frames)| frames.len()).sum());
+
+            let cameras = frames.par_iter()
+                .map(|(stream_id, frames)| {
+                    let samples = frames.par_iter()
+                        .map(|frame| {
+                            progress.check_cancelled()?;
+
+                            let colors = detect_color_checker(&image::open(frame)?.to_rgb8());
+
+                            progress.advance();
+                            Ok(colors)
+                        })
+                        .collect::<Result<Vec<_>, Error>>()?;
+                    let samples = samples.into_iter().flatten().collect::<Vec<_>>();
+
+                    match calibrate_color(&samples) {
+                        Ok(color) => {
+                            info!(
+                                "calibrated the colors of stream {} of session {} from {} frames, rms error {:.1} levels",
+                                stream_id.0,
+                                session_id,
+                                color.frames,
+                                color.rms_error,
+                            );
+                            Ok(Some((stream_id.0, color)))
+                        },
+                        Err(err) => {
+                            warn!("failed to calibrate the colors of stream {} of session {}: {}", stream_id.0, session_id, err);
+                            Ok(None)
+                        },
+                    }
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            let colors = ColorCalibration {
+                cameras: cameras.into_iter().flatten().collect(),
+                directory: output_directory.to_string(),
+            };
+
+            if colors.cameras.is_empty() {
+                bail!("no stream could be color calibrated, the color checker was not found in enough frames");
+            }
+
+            colors.save(output_directory)
+        }))
+    }
+
+    fn load(directory: &str) -> Self {
+        let colors = std::fs::File::open(Self::path(directory))
+            .map_err(Error::from)
+            .and_then(|file| Ok(serde_json::from_reader::<_, Self>(std::io::BufReader::new(file))?));
+
+        match colors {
+            Ok(colors) => Self {
+                directory: directory.to_string(),
+                ..colors
+            },
+            Err(err) => {
+                warn!("failed to load the color calibration of {}: {}", directory, err);
+
+                Self {
+                    directory: directory.to_string(),
+                    ..default()
+                }
+            },
+        }
+    }
+}
+
 
 #[derive(Component, Default)]
-pub struct RotatedFrames {
+pub struct ColorCorrectedFrames {
     pub frames: HashMap<StreamId, Vec<String>>,
     pub directory: String,
 }
-impl RotatedFrames {
-    pub fn load_from_session(
-        session: &Session,
-    ) -> Self {
-        let directory = format!("{}/rotated_frames", session.directory);
-        std::fs::create_dir_all(&directory).unwrap();
 
-        let mut raw_frames = Self {
-            frames: HashMap::new(),
-            directory,
-        };
-        raw_frames.reload();
+impl PipelineNode for ColorCorrectedFrames {
+    const DIRECTORY: &'static str = "color_corrected_frames";
+    type Inputs = (
+        &'static RotatedFrames,
+        &'static ColorCalibration,
+    );
+    type Params = ();
 
-        raw_frames
+    fn enabled(config: &PipelineConfig) -> bool {
+        config.color_correct_frames
     }
 
-    pub fn reload(&mut self) {
-        std::fs::read_dir(&self.directory)
-            .unwrap()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.path().is_dir())
-            .map(|stream_dir| {
-                let stream_id = StreamId(stream_dir.path().file_name().unwrap().to_str().unwrap().parse::<usize>().unwrap());
-
-                let frames = std::fs::read_dir(stream_dir.path()).unwrap()
-                    .filter_map(|entry| entry.ok())
-                    .filter(|entry| entry.path().is_file() && entry.path().extension().and_then(|s| s.to_str()) == Some("png"))
-                    .map(|entry| entry.path().to_str().unwrap().to_string())
-                    .collect::<Vec<_>>();
+    fn input_files((frames, colors): &(&RotatedFrames, &ColorCalibration)) -> Vec<String> {
+        frames.frames.values()
+            .flatten()
+            .cloned()
+            .chain(std::iter::once(ColorCalibration::path(&colors.directory).to_string_lossy().to_string()))
+            .collect()
+    }
 
-                (stream_id, frames)
+    fn job(
+        session: &Session,
+        _config: &PipelineConfig,
+        (frames, colors): (&RotatedFrames, &ColorCalibration),
+        _params: &mut (),
+    ) -> Result<PipelineJob, Error> {
+        let session_id = session.id;
+
+        let streams = frames.frames.iter()
+            .filter_map(|(stream_id, frames)| {
+                let Some(color) = colors.camera(*stream_id).cloned() else {
+                    warn!("stream {} of session {} has no color calibration, its frames are not color corrected", stream_id.0, session_id);
+                    return None;
+                };
+
+                Some((*stream_id, frames.clone(), color))
             })
-            .for_each(|(stream_id, frames)| {
-                self.frames.insert(stream_id, frames);
-            });
+            .collect::<Vec<_>>();
+
+        if streams.is_empty() {
+            bail!("no stream of session {} has a color calibration", session_id);
+        }
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            progress.set_total(streams.iter().map(|(_, frames, _)| frames.len()).sum());
+
+            streams.iter()
+                .try_for_each(|(stream_id, frames, color)| {
+                    frames.par_iter()
+                        .try_for_each(|frame| {
+                            progress.check_cancelled()?;
+
+                            let output_path = stream_frame_path(output_directory, *stream_id, frame, "png")?;
+
+                            let mut image = image::open(frame)?.into_rgb8();
+                            color.correct_image(&mut image);
+                            image.save(output_path)?;
+
+                            progress.advance();
+                            Ok(())
+                        })
+                })
+        }))
     }
 
-    pub fn exists(
-        session: &Session,
-    ) -> bool {
-        let output_directory = format!("{}/rotated_frames", session.directory);
-        std::fs::metadata(output_directory).is_ok()
+    fn load(directory: &str) -> Self {
+        Self {
+            frames: load_stream_frames(directory, "png"),
+            directory: directory.to_string(),
+        }
     }
+}
 
-    pub fn image(&self, _camera: usize, _frame: usize) -> Option<Image> {
-        todo!()
+
+/// frame nodes with an undistorted counterpart, see `Undistorted`
+pub trait UndistortableFrames: PipelineNode {
+    /// output directory of the undistorted frames, relative to the session directory
+    const UNDISTORTED_DIRECTORY: &'static str;
+
+    fn undistort(config: &PipelineConfig) -> bool;
+
+    fn frames(&self) -> &HashMap<StreamId, Vec<String>>;
+}
+
+impl UndistortableFrames for RotatedFrames {
+    const UNDISTORTED_DIRECTORY: &'static str = "undistorted_frames";
+
+    fn undistort(config: &PipelineConfig) -> bool {
+        config.undistort_frames
+    }
+
+    fn frames(&self) -> &HashMap<StreamId, Vec<String>> {
+        &self.frames
     }
 }
 
+impl UndistortableFrames for MaskFrames {
+    const UNDISTORTED_DIRECTORY: &'static str = "undistorted_masks";
 
+    fn undistort(config: &PipelineConfig) -> bool {
+        config.undistort_masks
+    }
 
-#[derive(Component, Default, Reflect)]
-pub struct MaskFrames {
+    fn frames(&self) -> &HashMap<StreamId, Vec<String>> {
+        &self.frames
+    }
+}
+
+impl UndistortableFrames for AlphablendFrames {
+    const UNDISTORTED_DIRECTORY: &'static str = "undistorted_alphablend";
+
+    fn undistort(config: &PipelineConfig) -> bool {
+        config.undistort_alphablend
+    }
+
+    fn frames(&self) -> &HashMap<StreamId, Vec<String>> {
+        &self.frames
+    }
+}
+
+impl UndistortableFrames for ColorCorrectedFrames {
+    const UNDISTORTED_DIRECTORY: &'static str = "undistorted_color_corrected_frames";
+
+    fn undistort(config: &PipelineConfig) -> bool {
+        config.undistort_color_corrected
+    }
+
+    fn frames(&self) -> &HashMap<StreamId, Vec<String>> {
+        &self.frames
+    }
+}
+
+pub type UndistortedFrames = Undistorted<RotatedFrames>;
+pub type UndistortedMaskFrames = Undistorted<MaskFrames>;
+pub type UndistortedAlphablendFrames = Undistorted<AlphablendFrames>;
+pub type UndistortedColorCorrectedFrames = Undistorted<ColorCorrectedFrames>;
+
+/// the frames of `N` remapped with the lens distortion of their calibrated camera removed. every frame node is
+/// remapped with the same maps, so undistorted masks stay pixel-aligned with the undistorted frames
+#[derive(Component)]
+pub struct Undistorted<N: UndistortableFrames> {
     pub frames: HashMap<StreamId, Vec<String>>,
-    pub directory: String
+
+    /// the calibrated cameras with the distortion free intrinsics of the undistorted frames
+    pub cameras: LightFieldCameras,
+
+    pub directory: String,
+
+    node: PhantomData<fn() -> N>,
 }
-impl MaskFrames {
-    pub fn load_from_session(
-        session: &Session,
-    ) -> Self {
-        let directory = format!("{}/masks", session.directory);
-        std::fs::create_dir_all(&directory).unwrap();
 
-        let mut mask_frames = Self {
-            frames: HashMap::new(),
-            directory,
-        };
-        mask_frames.reload();
+impl<N: UndistortableFrames> PipelineNode for Undistorted<N> {
+    const DIRECTORY: &'static str = N::UNDISTORTED_DIRECTORY;
+    type Inputs = (
+        &'static N,
+        &'static LightFieldCameras,
+    );
+    type Params = ();
 
-        mask_frames
+    fn enabled(config: &PipelineConfig) -> bool {
+        N::undistort(config)
     }
 
-    pub fn reload(&mut self) {
-        std::fs::read_dir(&self.directory)
-            .unwrap()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.path().is_dir())
-            .map(|stream_dir| {
-                let stream_id = StreamId(stream_dir.path().file_name().unwrap().to_str().unwrap().parse::<usize>().unwrap());
-
-                let frames = std::fs::read_dir(stream_dir.path()).unwrap()
-                    .filter_map(|entry| entry.ok())
-                    .filter(|entry| entry.path().is_file() && entry.path().extension().and_then(|s| s.to_str()) == Some("png"))
-                    .map(|entry| entry.path().to_str().unwrap().to_string())
-                    .collect::<Vec<_>>();
+    fn input_files((frames, cameras): &(&N, &LightFieldCameras)) -> Vec<String> {
+        frames.frames().values()
+            .flatten()
+            .cloned()
+            .chain(std::iter::once(LightFieldCameras::path(&cameras.directory).to_string_lossy().to_string()))
+            .collect()
+    }
 
-                (stream_id, frames)
-            })
-            .for_each(|(stream_id, frames)| {
-                self.frames.insert(stream_id, frames);
-            });
+    fn config(config: &PipelineConfig, _inputs: &(&N, &LightFieldCameras), _params: &()) -> serde_json::Value {
+        serde_json::to_value(&config.undistortion).unwrap_or_default()
     }
 
-    pub fn exists(
+    fn job(
         session: &Session,
-    ) -> bool {
-        let output_directory = format!("{}/masks", session.directory);
-        std::fs::metadata(output_directory).is_ok()
+        config: &PipelineConfig,
+        (frames, cameras): (&N, &LightFieldCameras),
+        _params: &mut (),
+    ) -> Result<PipelineJob, Error> {
+        let session_id = session.id;
+        let reference_stream = cameras.reference_stream;
+
+        let mut streams = frames.frames().iter()
+            .filter_map(|(stream_id, frames)| {
+                let Some(camera) = cameras.camera(*stream_id) else {
+                    warn!("stream {} of session {} is not calibrated, its {} are not undistorted", stream_id.0, session_id, N::DIRECTORY);
+                    return None;
+                };
+
+                let intrinsics = undistorted_intrinsics(&camera.intrinsics, (camera.width, camera.height), config.undistortion.alpha);
+
+                Some((*stream_id, frames.clone(), camera.clone(), intrinsics))
+            })
+            .collect::<Vec<_>>();
+        streams.sort_by_key(|(stream_id, ..)| stream_id.0);
+
+        if streams.is_empty() {
+            bail!("no stream of session {} is calibrated", session_id);
+        }
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            progress.set_total(streams.iter().map(|(_, frames, ..)| frames.len()).sum());
+
+            streams.iter()
+                .try_for_each(|(stream_id, frames, camera, intrinsics)| -> Result<(), Error> {
+                    let map = UndistortMap::new(&camera.intrinsics, intrinsics, (camera.width, camera.height));
+
+                    frames.par_iter()
+                        .try_for_each(|frame| {
+                            progress.check_cancelled()?;
+
+                            let output_path = stream_frame_path(output_directory, *stream_id, frame, "png")?;
+
+                            undistort_image(
+                                std::path::Path::new(frame),
+                                std::path::Path::new(&output_path),
+                                &map,
+                            )?;
+
+                            progress.advance();
+                            Ok(())
+                        })
+                })?;
+
+            let cameras = LightFieldCameras {
+                cameras: streams.into_iter()
+                    .map(|(_, _, camera, intrinsics)| LightFieldCamera {
+                        intrinsics,
+                        ..camera
+                    })
+                    .collect(),
+                reference_stream,
+                directory: output_directory.to_string(),
+            };
+
+            cameras.save(output_directory)
+        }))
     }
 
-    pub fn image(&self, _camera: usize, _frame: usize) -> Option<Image> {
-        todo!()
+    fn load(directory: &str) -> Self {
+        Self {
+            frames: load_stream_frames(directory, "png"),
+            cameras: LightFieldCameras::load(directory),
+            directory: directory.to_string(),
+            node: PhantomData,
+        }
     }
 }
 
 
-#[derive(Default, Clone, Reflect)]
-pub struct LightFieldCamera {
-    // TODO: intrinsics/extrinsics
+/// COLMAP projects of the rotated frames, one per frame index as `<frame>/images/<stream>.png` and
+/// `<frame>/masks/<stream>.png.png`, with a `<frame>/sparse/0` text model of the posed cameras if the session is calibrated
+///
+/// the images keep their lens distortion, the model describes it with the `OPENCV` camera model of the calibration. it
+/// has no points, `colmap point_triangulator` adds them without re-estimating the calibrated poses. uncalibrated projects
+/// are reconstructed from scratch, e.g. with `colmap mapper`
+#[derive(Component, Default)]
+pub struct ColmapDataset {
+    /// project directory of each exported frame index
+    pub projects: BTreeMap<usize, String>,
+    pub directory: String,
 }
 
-#[derive(Component, Default, Reflect)]
-pub struct LightFieldCameras {
-    pub cameras: Vec<LightFieldCamera>,
+impl PipelineNode for ColmapDataset {
+    const DIRECTORY: &'static str = "colmap";
+    const VERSION: u32 = 2;
+    type Inputs = (
+        &'static RotatedFrames,
+        &'static MaskFrames,
+        Option<&'static LightFieldCameras>,
+        Has<PipelineNodeFailed<LightFieldCameras>>,
+    );
+    type Params = ();
+
+    fn enabled(config: &PipelineConfig) -> bool {
+        config.colmap_dataset
+    }
+
+    // an enabled calibration is waited for, the projects are exported without a model if it fails
+    fn ready(
+        config: &PipelineConfig,
+        (_, _, cameras, calibration_failed): &(&RotatedFrames, &MaskFrames, Option<&LightFieldCameras>, bool),
+    ) -> bool {
+        !config.light_field_cameras || cameras.is_some() || *calibration_failed
+    }
+
+    fn input_files((frames, masks, cameras, _): &(&RotatedFrames, &MaskFrames, Option<&LightFieldCameras>, bool)) -> Vec<String> {
+        frames.frames.values()
+            .chain(masks.frames.values())
+            .flatten()
+            .cloned()
+            .chain(cameras.map(|cameras| LightFieldCameras::path(&cameras.directory).to_string_lossy().to_string()))
+            .collect()
+    }
+
+    fn config(
+        config: &PipelineConfig,
+        _inputs: &(&RotatedFrames, &MaskFrames, Option<&LightFieldCameras>, bool),
+        _params: &(),
+    ) -> serde_json::Value {
+        serde_json::to_value(&config.colmap).unwrap_or_default()
+    }
+
+    fn job(
+        session: &Session,
+        config: &PipelineConfig,
+        (frames, masks, cameras, _): (&RotatedFrames, &MaskFrames, Option<&LightFieldCameras>, bool),
+        _params: &mut (),
+    ) -> Result<PipelineJob, Error> {
+        let session_id = session.id;
+
+        let mut streams = frames.frames.iter()
+            .filter_map(|(&stream_id, stream_frames)| {
+                let indexed = |paths: Option<&Vec<String>>| paths.into_iter()
+                    .flatten()
+                    .filter_map(|frame| Some((frame_index(frame)?, frame.clone())))
+                    .collect::<BTreeMap<_, _>>();
+
+                let stream_frames = indexed(Some(stream_frames));
+                let stream_masks = indexed(masks.frames.get(&stream_id));
+                if stream_frames.is_empty() {
+                    return None;
+                }
+
+                let camera = cameras.and_then(|cameras| cameras.camera(stream_id)).cloned();
+                match &camera {
+                    Some(camera) if camera.extrinsics.is_none() => {
+                        warn!("stream {} of session {} has no extrinsics, it is exported without a pose", stream_id.0, session_id);
+                    },
+                    None if cameras.is_some() => {
+                        warn!("stream {} of session {} is not calibrated, it is exported without a pose", stream_id.0, session_id);
+                    },
+                    _ => {},
+                }
+
+                Some((stream_id, camera, stream_frames, stream_masks))
+            })
+            .collect::<Vec<_>>();
+
+        // `colmap feature_extractor` numbers the images and their cameras in name order, the text model uses the same ids
+        streams.sort_by_key(|(stream_id, ..)| format!("{}.png", stream_id.0));
+
+        let mut frame_indices = streams.iter()
+            .flat_map(|(_, _, frames, _)| frames.keys().copied())
+            .collect::<BTreeSet<_>>();
+        if let Some(frame) = config.colmap.frame {
+            frame_indices.retain(|&index| index == frame);
+        }
+
+        if frame_indices.is_empty() {
+            match config.colmap.frame {
+                Some(frame) => bail!("no stream of session {} has frame {}", session_id, frame),
+                None => bail!("session {} has no frames", session_id),
+            }
+        }
+
+        match cameras {
+            None => info!("session {} is not calibrated, its colmap projects have no sparse model", session_id),
+            Some(_) if streams.iter().all(|(_, camera, ..)| camera.as_ref().and_then(|camera| camera.extrinsics.as_ref()).is_none()) => {
+                warn!("no camera of session {} has extrinsics, its colmap projects have no sparse model", session_id);
+            },
+            Some(_) => {},
+        }
+
+        let frame_indices = frame_indices.into_iter().collect::<Vec<_>>();
+
+        Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+            progress.set_total(frame_indices.len());
+
+            frame_indices.par_iter()
+                .try_for_each(|frame| -> Result<(), Error> {
+                    progress.check_cancelled()?;
+
+                    let project = std::path::Path::new(output_directory).join(frame.to_string());
+                    let images_directory = project.join("images");
+                    let masks_directory = project.join("masks");
+                    std::fs::create_dir_all(&images_directory)?;
+                    std::fs::create_dir_all(&masks_directory)?;
+
+                    let mut model_cameras = vec![];
+                    let mut images = vec![];
+                    let mut image_id = 0;
+                    for (stream_id, camera, frames, masks) in &streams {
+                        let Some(image) = frames.get(frame) else {
+                            continue;
+                        };
+                        image_id += 1;
+
+                        let name = format!("{}.png", stream_id.0);
+                        std::fs::copy(image, images_directory.join(&name))?;
+
+                        // colmap looks up the mask of `<name>` as `<name>.png`
+                        if let Some(mask) = masks.get(frame) {
+                            std::fs::copy(mask, masks_directory.join(format!("{}.png", name)))?;
+                        }
+
+                        let Some((camera, extrinsics)) = camera.as_ref()
+                            .and_then(|camera| Some((camera, camera.extrinsics.as_ref()?))) else {
+                            continue;
+                        };
+
+                        model_cameras.push(ColmapCamera {
+                            id: image_id,
+                            width: camera.width,
+                            height: camera.height,
+                            intrinsics: camera.intrinsics.clone(),
+                        });
+                        images.push(ColmapImage {
+                            id: image_id,
+                            camera_id: image_id,
+                            name,
+                            extrinsics: extrinsics.clone(),
+                        });
+                    }
+
+                    if !images.is_empty() {
+                        write_text_model(project.join("sparse/0"), &model_cameras, &images)?;
+                    }
+
+                    progress.advance();
+                    Ok(())
+                })
+        }))
+    }
+
+    fn load(directory: &str) -> Self {
+        let projects = std::fs::read_dir(directory)
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| {
+                    let frame = entry.file_name().to_str()?.parse::<usize>().ok()?;
+                    Some((frame, entry.path().to_string_lossy().to_string()))
+                })
+                .collect())
+            .unwrap_or_default();
+
+        Self {
+            projects,
+            directory: directory.to_string(),
+        }
+    }
 }
 
 
@@ -956,14 +2347,35 @@ fn rotate_image(
 }
 
 
+/// frames of another size than the calibration, e.g. masks, are resized to it first
+fn undistort_image(
+    image_path: &std::path::Path,
+    output_path: &std::path::Path,
+    map: &UndistortMap,
+) -> Result<(), Error> {
+    let mut image = image::open(image_path)?;
+    if image.dimensions() != (map.width, map.height) {
+        image = image.resize_exact(map.width, map.height, image::imageops::FilterType::Triangle);
+    }
+
+    match image {
+        DynamicImage::ImageLuma8(image) => map.remap(&image)?.save(output_path)?,
+        DynamicImage::ImageRgb8(image) => map.remap(&image)?.save(output_path)?,
+        image => map.remap(&image.into_rgba8())?.save(output_path)?,
+    }
+
+    Ok(())
+}
+
+
 fn alphablend_image(
     image_path: &std::path::Path,
     mask_path: &std::path::Path,
     output_path: &std::path::Path,
 ) -> image::ImageResult<()> {
-    let img = image::open(image_path).unwrap();
+    let img = image::open(image_path)?;
 
-    let mask = image::open(mask_path).unwrap();
+    let mask = image::open(mask_path)?;
     let mask = mask.resize_exact(img.width(), img.height(), image::imageops::FilterType::Triangle);
 
     let mut output_img: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(img.dimensions().0, img.dimensions().1);
@@ -979,3 +2391,632 @@ fn alphablend_image(
 
     Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use bevy::{
+        ecs::system::SystemState,
+        tasks::TaskPool,
+    };
+
+
+    /// a node of a downstream crate, which copies its input files
+    #[derive(Component, Default)]
+    struct CopiedFiles {
+        files: Vec<String>,
+    }
+
+    #[derive(Component, Clone, Default)]
+    struct CopyInputs {
+        files: Vec<String>,
+        runs: Arc<AtomicUsize>,
+
+        /// the job waits while this is set, until it is cancelled
+        hold: Arc<AtomicBool>,
+    }
+
+    impl PipelineNode for CopiedFiles {
+        const DIRECTORY: &'static str = "copied";
+        type Inputs = &'static CopyInputs;
+        type Params = ();
+
+        fn input_files(inputs: &&CopyInputs) -> Vec<String> {
+            inputs.files.clone()
+        }
+
+        fn job(
+            _session: &Session,
+            _config: &PipelineConfig,
+            inputs: &CopyInputs,
+            _params: &mut (),
+        ) -> Result<PipelineJob, Error> {
+            let inputs = inputs.clone();
+
+            Ok(Box::new(move |output_directory: &str, progress: &NodeProgress| {
+                inputs.runs.fetch_add(1, Ordering::Relaxed);
+                progress.set_total(inputs.files.len());
+
+                while inputs.hold.load(Ordering::Relaxed) {
+                    progress.check_cancelled()?;
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+
+                for file in &inputs.files {
+                    progress.check_cancelled()?;
+
+                    let name = std::path::Path::new(file).file_name().ok_or_else(|| anyhow!("invalid input {}", file))?;
+                    std::fs::copy(file, std::path::Path::new(output_directory).join(name))?;
+                    progress.advance();
+                }
+
+                Ok(())
+            }))
+        }
+
+        fn load(directory: &str) -> Self {
+            let mut files = std::fs::read_dir(directory)
+                .map(|entries| {
+                    entries
+                        .filter_map(|entry| entry.ok())
+                        .map(|entry| entry.file_name().to_string_lossy().to_string())
+                        .filter(|name| name != "manifest.json")
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            files.sort();
+
+            Self { files }
+        }
+    }
+
+
+    /// stands in for a model asset: `None` while loading, an error once loading it failed
+    #[derive(Resource, Default)]
+    struct TestModel(Option<Result<(), String>>);
+
+    /// a node which waits for `TestModel` to load
+    #[derive(Component, Default)]
+    struct ModelOutputs;
+
+    impl PipelineNode for ModelOutputs {
+        const DIRECTORY: &'static str = "model_outputs";
+        type Inputs = &'static CopyInputs;
+        type Params = Res<'static, TestModel>;
+
+        fn prepare(model: &mut Res<TestModel>) -> Result<bool, Error> {
+            match &model.0 {
+                None => Ok(false),
+                Some(Ok(())) => Ok(true),
+                Some(Err(err)) => bail!("{}", err),
+            }
+        }
+
+        fn input_files(inputs: &&CopyInputs) -> Vec<String> {
+            inputs.files.clone()
+        }
+
+        fn job(
+            _session: &Session,
+            _config: &PipelineConfig,
+            _inputs: &CopyInputs,
+            _model: &mut Res<TestModel>,
+        ) -> Result<PipelineJob, Error> {
+            Ok(Box::new(|_output_directory: &str, _progress: &NodeProgress| Ok(())))
+        }
+
+        fn load(_directory: &str) -> Self {
+            Self
+        }
+    }
+
+
+    fn test_directory(name: &str) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("bevy_light_field_pipeline_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    fn test_app<N: PipelineNode>() -> App {
+        AsyncComputeTaskPool::get_or_init(TaskPool::default);
+
+        let mut app = App::new();
+        app.add_systems(First, reset_waiting_pipeline_nodes);
+        app.add_pipeline_node::<N>();
+        app
+    }
+
+    /// updates `app` until a node finished, returning the progress and finished events
+    fn run_until_finished(app: &mut App) -> (Vec<PipelineProgress>, Vec<PipelineNodeFinished>) {
+        let mut progress_reader = app.world.resource::<Events<PipelineProgress>>().get_reader_current();
+        let mut finished_reader = app.world.resource::<Events<PipelineNodeFinished>>().get_reader_current();
+        let mut progress = vec![];
+
+        for _ in 0..1000 {
+            app.update();
+
+            progress.extend(progress_reader.read(app.world.resource::<Events<PipelineProgress>>()).cloned());
+            let finished = finished_reader.read(app.world.resource::<Events<PipelineNodeFinished>>())
+                .cloned()
+                .collect::<Vec<_>>();
+            if !finished.is_empty() {
+                return (progress, finished);
+            }
+
+            std::thread::sleep(Duration::from_millis(2));
+        }
+
+        panic!("no pipeline node finished");
+    }
+
+
+    #[test]
+    fn test_update_node_output_reuses_up_to_date_output() {
+        let directory = test_directory("cache");
+        let input = directory.join("0.txt").to_string_lossy().to_string();
+        std::fs::write(&input, "first").unwrap();
+
+        let session = Session::from_id(0, directory.to_string_lossy().to_string());
+        let output_directory = CopiedFiles::directory(&session);
+        let inputs = CopyInputs {
+            files: vec![input.clone()],
+            ..default()
+        };
+
+        let update = |config: serde_json::Value| {
+            let job = CopiedFiles::job(&session, &PipelineConfig::default(), &inputs, &mut ()).unwrap();
+            let (output, reused) = update_node_output::<CopiedFiles>(
+                session.id,
+                &output_directory,
+                &inputs.files,
+                config,
+                job,
+                &NodeProgress::default(),
+            ).unwrap();
+
+            (output.files, reused)
+        };
+
+        assert_eq!(update(serde_json::Value::Null), (vec!["0.txt".to_string()], false));
+        assert_eq!(update(serde_json::Value::Null), (vec!["0.txt".to_string()], true));
+        assert_eq!(inputs.runs.load(Ordering::Relaxed), 1, "the cached output is loaded without running the job");
+
+        std::fs::write(&input, "second").unwrap();
+        assert!(!update(serde_json::Value::Null).1, "a changed input regenerates the output");
+        assert!(!update(serde_json::json!({ "rotation": 90.0 })).1, "a changed config regenerates the output");
+        assert_eq!(inputs.runs.load(Ordering::Relaxed), 3);
+        assert_eq!(std::fs::read_to_string(format!("{}/0.txt", output_directory)).unwrap(), "second");
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+
+    #[test]
+    fn test_registered_node_runs_once_its_inputs_are_present() {
+        let directory = test_directory("registered");
+        let input = directory.join("0.txt").to_string_lossy().to_string();
+        std::fs::write(&input, "frame").unwrap();
+
+        let mut app = test_app::<CopiedFiles>();
+        let session = app.world.spawn((
+            PipelineConfig::default(),
+            Session::from_id(0, directory.to_string_lossy().to_string()),
+        )).id();
+
+        app.update();
+        assert!(app.world.get::<PipelineNodeTask<CopiedFiles>>(session).is_none(), "the node waits for its inputs");
+
+        let inputs = CopyInputs {
+            files: vec![input],
+            ..default()
+        };
+        app.world.entity_mut(session).insert(inputs.clone());
+
+        let (_, finished) = run_until_finished(&mut app);
+        assert_eq!(finished[0].node, CopiedFiles::DIRECTORY);
+        assert_eq!(finished[0].outcome, NodeOutcome::Generated);
+        assert_eq!(app.world.get::<CopiedFiles>(session).unwrap().files, ["0.txt"]);
+
+        // the output is a component now, so the node does not run again
+        app.update();
+        assert!(app.world.resource::<PipelineActivity>().is_idle());
+        assert_eq!(inputs.runs.load(Ordering::Relaxed), 1);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+
+    #[test]
+    fn test_cancelled_node_stops_and_resumes() {
+        let directory = test_directory("cancel");
+        let files = (0..2)
+            .map(|i| {
+                let input = directory.join(format!("{}.txt", i)).to_string_lossy().to_string();
+                std::fs::write(&input, "frame").unwrap();
+                input
+            })
+            .collect::<Vec<_>>();
+
+        let inputs = CopyInputs {
+            files,
+            hold: Arc::new(AtomicBool::new(true)),
+            ..default()
+        };
+
+        let mut app = test_app::<CopiedFiles>();
+        let session = Session::from_id(0, directory.to_string_lossy().to_string());
+        let output_directory = CopiedFiles::directory(&session);
+        let session = app.world.spawn((
+            PipelineConfig::default(),
+            session,
+            inputs.clone(),
+        )).id();
+
+        app.update();
+        assert_eq!(app.world.resource::<PipelineActivity>().running, 1);
+        while inputs.runs.load(Ordering::Relaxed) == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        app.world.entity_mut(session).insert(PipelineCancelled);
+        let (progress, finished) = run_until_finished(&mut app);
+
+        assert_eq!(finished[0].outcome, NodeOutcome::Cancelled);
+        assert_eq!(progress.last().map(|progress| (progress.done, progress.total)), Some((0, 2)));
+        assert!(!std::path::Path::new(&output_directory).exists(), "the partial output is removed");
+        assert!(app.world.get::<CopiedFiles>(session).is_none());
+
+        app.update();
+        assert!(app.world.resource::<PipelineActivity>().is_idle(), "cancelled sessions start no nodes");
+
+        inputs.hold.store(false, Ordering::Relaxed);
+        app.world.entity_mut(session).remove::<PipelineCancelled>();
+        let (progress, finished) = run_until_finished(&mut app);
+
+        assert_eq!(finished[0].outcome, NodeOutcome::Generated);
+        assert_eq!(progress.last().map(|progress| (progress.node, progress.done, progress.total)), Some((CopiedFiles::DIRECTORY, 2, 2)));
+        assert_eq!(app.world.get::<CopiedFiles>(session).unwrap().files, ["0.txt", "1.txt"]);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+
+    #[test]
+    fn test_failed_model_load_fails_the_node() {
+        let directory = test_directory("model");
+
+        let mut app = test_app::<ModelOutputs>();
+        app.init_resource::<TestModel>();
+        let session = app.world.spawn((
+            PipelineConfig::default(),
+            Session::from_id(0, directory.to_string_lossy().to_string()),
+            CopyInputs::default(),
+        )).id();
+
+        app.update();
+        app.update();
+        assert_eq!(app.world.resource::<PipelineActivity>().waiting, 1, "the node waits while the model loads");
+        assert!(app.world.get::<PipelineNodeTask<ModelOutputs>>(session).is_none());
+
+        app.world.resource_mut::<TestModel>().0 = Some(Err("failed to load models/test.onnx".to_string()));
+        let (_, finished) = run_until_finished(&mut app);
+
+        assert_eq!(
+            finished[0].outcome,
+            NodeOutcome::Failed {
+                error: "failed to load models/test.onnx".to_string(),
+            },
+        );
+        assert!(app.world.get::<PipelineNodeFailed<ModelOutputs>>(session).is_some());
+
+        app.update();
+        assert!(app.world.resource::<PipelineActivity>().is_idle(), "a failed node no longer keeps a batch waiting");
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+
+    fn recorded_stream(stream_id: usize, rotation: Option<f32>) -> SessionStream {
+        SessionStream {
+            stream_id,
+            file: format!("raw/{}.mp4", stream_id),
+            descriptor: StreamDescriptor {
+                id: Some(format!("camera_{}", stream_id)),
+                uri: format!("rtsp://192.168.1.{}/stream", 20 + stream_id),
+                rotation,
+                audio: stream_id == 0,
+                ..default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_session_manifest_round_trip() {
+        let directory = test_directory("manifest");
+        assert!(SessionManifest::load(&directory).unwrap().is_none());
+
+        let session = Session::from_id(3, directory.to_string_lossy().to_string());
+        let mut manifest = SessionManifest::new(
+            &session,
+            RecordingTrigger::PersonDetected,
+            vec![
+                recorded_stream(0, Some(90.0)),
+                recorded_stream(1, None),
+            ],
+            PipelineConfig {
+                yolo: false,
+                colmap_dataset: true,
+                colmap: ColmapConfig {
+                    frame: Some(4),
+                },
+                ..default()
+            },
+        );
+        manifest.stop(&directory).unwrap();
+
+        let loaded = SessionManifest::load(&directory).unwrap().unwrap();
+        assert_eq!(loaded.session_id, 3);
+        assert_eq!(loaded.trigger, RecordingTrigger::PersonDetected);
+        assert_eq!(loaded.stop_unix_secs, manifest.stop_unix_secs);
+        assert_eq!(loaded.streams, manifest.streams);
+        assert_eq!(loaded.stream(StreamId(0)).unwrap().descriptor.rotation, Some(90.0));
+        assert!(!loaded.pipeline.yolo);
+        assert!(loaded.pipeline.colmap_dataset);
+        assert_eq!(loaded.pipeline.colmap.frame, Some(4));
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&manifest).unwrap());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_session_rotations_prefer_the_manifest() {
+        let mut world = World::new();
+        let mut images = Assets::<Image>::default();
+        world.spawn(RtspStreamHandle::new(
+            StreamDescriptor {
+                rotation: Some(180.0),
+                ..default()
+            },
+            StreamId(0),
+            &mut images,
+        ));
+
+        let raw_frames = RawFrames {
+            frames: [StreamId(0), StreamId(1)]
+                .into_iter()
+                .map(|stream_id| (stream_id, vec![]))
+                .collect(),
+            ..default()
+        };
+        let manifest = SessionManifest::new(
+            &Session::from_id(0, "capture".to_string()),
+            RecordingTrigger::Manual,
+            vec![
+                recorded_stream(0, Some(90.0)),
+                recorded_stream(1, None),
+            ],
+            PipelineConfig::default(),
+        );
+
+        let mut streams = SystemState::<Query<&RtspStreamHandle>>::new(&mut world);
+        let streams = streams.get(&world);
+
+        let rotations = session_rotations(&raw_frames, Some(&manifest), &streams);
+        assert_eq!(rotations[&StreamId(0)], 90.0, "the recorded rotation wins over the current stream config");
+        assert_eq!(rotations[&StreamId(1)], 0.0);
+
+        let rotations = session_rotations(&raw_frames, None, &streams);
+        assert_eq!(rotations[&StreamId(0)], 180.0, "sessions without a manifest use the current stream config");
+        assert_eq!(rotations[&StreamId(1)], 0.0);
+    }
+
+
+    #[test]
+    fn test_light_field_cameras_wait_for_an_enabled_color_calibration() {
+        let (frames, colors) = (RotatedFrames::default(), ColorCalibration::default());
+        let color_calibrated = PipelineConfig {
+            color_calibration: true,
+            ..default()
+        };
+
+        assert!(LightFieldCameras::ready(&PipelineConfig::default(), &(&frames, None, false)));
+        assert!(!LightFieldCameras::ready(&color_calibrated, &(&frames, None, false)));
+        assert!(LightFieldCameras::ready(&color_calibrated, &(&frames, Some(&colors), false)));
+        assert!(LightFieldCameras::ready(&color_calibrated, &(&frames, None, true)), "a failed color calibration calibrates without colors");
+    }
+
+    #[test]
+    fn test_color_calibration_without_a_checkerboard() {
+        use crate::calibration::color::tests::{camera, render_chart};
+
+        let directory = test_directory("color_calibration");
+        let session = Session::from_id(0, directory.to_string_lossy().to_string());
+        let config = PipelineConfig {
+            light_field_cameras: true,
+            color_calibration: true,
+            ..default()
+        };
+
+        // a session capturing only the color checker
+        let stream_directory = directory.join("rotated/0");
+        std::fs::create_dir_all(&stream_directory).unwrap();
+        let views = [
+            [[210.0, 140.0], [590.0, 160.0], [575.0, 420.0], [200.0, 395.0]],
+            [[160.0, 120.0], [520.0, 90.0], [560.0, 350.0], [190.0, 380.0]],
+        ];
+        let stream_frames = views.iter()
+            .enumerate()
+            .map(|(frame_idx, &corners)| {
+                let path = stream_directory.join(format!("{}.png", frame_idx));
+                render_chart(&camera(), corners, (800, 540)).save(&path).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect::<Vec<_>>();
+        let frames = RotatedFrames {
+            frames: HashMap::from([(StreamId(0), stream_frames)]),
+            ..default()
+        };
+
+        let colors_directory = ColorCalibration::directory(&session);
+        std::fs::create_dir_all(&colors_directory).unwrap();
+        let job = ColorCalibration::job(&session, &config, &frames, &mut ()).unwrap();
+        job(&colors_directory, &NodeProgress::default()).unwrap();
+
+        let colors = ColorCalibration::load(&colors_directory);
+        assert!(colors.camera(StreamId(0)).is_some(), "the color checker should calibrate stream 0");
+
+        let cameras_directory = LightFieldCameras::directory(&session);
+        std::fs::create_dir_all(&cameras_directory).unwrap();
+        let job = LightFieldCameras::job(&session, &config, (&frames, Some(&colors), false), &mut ()).unwrap();
+        assert!(job(&cameras_directory, &NodeProgress::default()).is_err(), "no checkerboard was captured");
+
+        let corrected_directory = ColorCorrectedFrames::directory(&session);
+        let job = ColorCorrectedFrames::job(&session, &config, (&frames, &colors), &mut ()).unwrap();
+        job(&corrected_directory, &NodeProgress::default()).unwrap();
+        assert!(std::path::Path::new(&corrected_directory).join("0/1.png").exists());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_colmap_dataset_waits_for_an_enabled_calibration() {
+        let (frames, masks, cameras) = (RotatedFrames::default(), MaskFrames::default(), LightFieldCameras::default());
+        let calibrated = PipelineConfig {
+            light_field_cameras: true,
+            ..default()
+        };
+
+        assert!(ColmapDataset::ready(&PipelineConfig::default(), &(&frames, &masks, None, false)));
+        assert!(!ColmapDataset::ready(&calibrated, &(&frames, &masks, None, false)));
+        assert!(ColmapDataset::ready(&calibrated, &(&frames, &masks, Some(&cameras), false)));
+        assert!(ColmapDataset::ready(&calibrated, &(&frames, &masks, None, true)), "a failed calibration exports without a model");
+    }
+
+    #[test]
+    fn test_colmap_dataset_models_calibrated_sessions() {
+        let directory = test_directory("colmap");
+        let session = Session::from_id(0, directory.to_string_lossy().to_string());
+
+        let stream_files = |node: &str| {
+            let files = [0, 1]
+                .into_iter()
+                .map(|stream_id| {
+                    let stream_directory = directory.join(node).join(stream_id.to_string());
+                    std::fs::create_dir_all(&stream_directory).unwrap();
+
+                    let frame = stream_directory.join("7.png").to_string_lossy().to_string();
+                    std::fs::write(&frame, node).unwrap();
+                    (StreamId(stream_id), vec![frame])
+                })
+                .collect::<HashMap<_, _>>();
+
+            (files, directory.join(node).to_string_lossy().to_string())
+        };
+        let (frames, frames_directory) = stream_files("rotated");
+        let frames = RotatedFrames { frames, directory: frames_directory };
+        let (masks, masks_directory) = stream_files("masks");
+        let masks = MaskFrames { frames: masks, directory: masks_directory };
+
+        let cameras = LightFieldCameras {
+            cameras: vec![
+                LightFieldCamera {
+                    stream_id: 1,
+                    width: 1920,
+                    height: 1080,
+                    intrinsics: CameraIntrinsics {
+                        fx: 1400.0,
+                        fy: 1390.0,
+                        cx: 960.0,
+                        cy: 540.0,
+                        distortion: [-0.2, 0.05, 0.001, 0.002, 0.0],
+                    },
+                    rms_error: 0.3,
+                    views: 20,
+                    extrinsics: Some(CameraExtrinsics::default()),
+                    color: None,
+                },
+            ],
+            directory: directory.join("calibration").to_string_lossy().to_string(),
+            ..default()
+        };
+
+        let export = |cameras: Option<&LightFieldCameras>, name: &str| {
+            let output_directory = directory.join(name);
+            std::fs::create_dir_all(&output_directory).unwrap();
+
+            let job = ColmapDataset::job(&session, &PipelineConfig::default(), (&frames, &masks, cameras, false), &mut ()).unwrap();
+            job(&output_directory.to_string_lossy(), &NodeProgress::default()).unwrap();
+            output_directory.join("7")
+        };
+
+        let project = export(None, "uncalibrated");
+        assert_eq!(std::fs::read_to_string(project.join("images/1.png")).unwrap(), "rotated");
+        assert_eq!(std::fs::read_to_string(project.join("masks/1.png.png")).unwrap(), "masks");
+        assert!(!project.join("sparse").exists(), "uncalibrated sessions have no model");
+
+        let project = export(Some(&cameras), "calibrated");
+        assert!(project.join("images/0.png").exists(), "streams without calibration are exported without a pose");
+        let cameras_txt = std::fs::read_to_string(project.join("sparse/0/cameras.txt")).unwrap();
+        assert!(cameras_txt.lines().any(|line| line == "2 OPENCV 1920 1080 1400 1390 960 540 -0.2 0.05 0.001 0.002"));
+        let images_txt = std::fs::read_to_string(project.join("sparse/0/images.txt")).unwrap();
+        assert!(images_txt.lines().any(|line| line.ends_with(" 2 1.png")));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_alphablend_pairs_frames_with_their_masks() {
+        let directory = test_directory("alphablend");
+        let session = Session::from_id(0, directory.to_string_lossy().to_string());
+
+        let write_frames = |node: &str, frames: &[(usize, u8)]| {
+            let stream_directory = directory.join(node).join("0");
+            std::fs::create_dir_all(&stream_directory).unwrap();
+
+            let frames = frames.iter()
+                .map(|(frame_idx, value)| {
+                    let path = stream_directory.join(format!("{}.png", frame_idx));
+                    image::GrayImage::from_pixel(2, 1, image::Luma([*value])).save(&path).unwrap();
+                    path.to_string_lossy().to_string()
+                })
+                .collect::<Vec<_>>();
+
+            HashMap::from([(StreamId(0), frames)])
+        };
+
+        let frames = RotatedFrames {
+            frames: write_frames("rotated", &[(1, 10), (2, 20)]),
+            ..default()
+        };
+
+        // listed in another order than the frames, the masks are matched by name
+        let masks = MaskFrames {
+            frames: write_frames("masks", &[(2, 200), (1, 100)]),
+            ..default()
+        };
+
+        let output_directory = directory.join("alphablend");
+        let job = AlphablendFrames::job(&session, &PipelineConfig::default(), (&frames, &masks), &mut ()).unwrap();
+        job(&output_directory.to_string_lossy(), &NodeProgress::default()).unwrap();
+
+        let alpha = |frame_idx: usize| image::open(output_directory.join(format!("0/{}.png", frame_idx))).unwrap().to_rgba8().get_pixel(0, 0).0;
+        assert_eq!(alpha(1), [10, 10, 10, 100]);
+        assert_eq!(alpha(2), [20, 20, 20, 200]);
+
+        let masks = MaskFrames {
+            frames: write_frames("masks", &[(2, 200), (3, 50)]),
+            ..default()
+        };
+        let job = AlphablendFrames::job(&session, &PipelineConfig::default(), (&frames, &masks), &mut ()).unwrap();
+        let error = job(&output_directory.to_string_lossy(), &NodeProgress::default()).unwrap_err();
+        assert!(error.to_string().contains("no mask for frame"), "{}", error);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
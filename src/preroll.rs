@@ -0,0 +1,202 @@
+use std::{
+    collections::VecDeque,
+    time::Duration,
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    mp4::EncodedVideoFrame,
+    stream::RtspStreamHandle,
+};
+
+
+pub struct PreRollPlugin;
+impl Plugin for PreRollPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PreRollConfig>();
+        app.add_systems(Update, configure_pre_roll);
+    }
+}
+
+
+/// encoded frames kept per stream so recordings start before their trigger
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct PreRollConfig {
+    /// minimum footage before the start of a recording, zero disables the buffer
+    pub duration: Duration,
+
+    /// upper bound on the buffered encoded bytes per stream
+    pub max_bytes: usize,
+}
+
+impl Default for PreRollConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(3),
+            max_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+
+/// ring buffer of encoded frames which always starts at a keyframe
+#[derive(Default)]
+pub struct PreRollBuffer {
+    frames: VecDeque<EncodedVideoFrame>,
+    bytes: usize,
+}
+
+impl PreRollBuffer {
+    pub fn push(&mut self, frame: EncodedVideoFrame, config: &PreRollConfig) {
+        if config.duration.is_zero() {
+            self.clear();
+            return;
+        }
+
+        // frames before the first keyframe cannot be decoded
+        if self.frames.is_empty() && !frame.is_random_access_point {
+            return;
+        }
+
+        self.bytes += frame.data.len();
+        self.frames.push_back(frame);
+
+        let newest = self.frames.back().unwrap().seconds();
+        let duration = config.duration.as_secs_f64();
+
+        loop {
+            let next_keyframe = self.frames.iter()
+                .skip(1)
+                .position(|frame| frame.is_random_access_point)
+                .map(|index| index + 1);
+
+            let Some(next_keyframe) = next_keyframe else {
+                // a single group of pictures larger than the budget is dropped entirely
+                if self.bytes > config.max_bytes {
+                    self.clear();
+                }
+                break;
+            };
+
+            // drop the oldest group of pictures once the following one covers the pre-roll by itself
+            let covered = newest - self.frames[next_keyframe].seconds();
+            if covered < duration && self.bytes <= config.max_bytes {
+                break;
+            }
+
+            for frame in self.frames.drain(..next_keyframe) {
+                self.bytes -= frame.data.len();
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+    }
+
+    /// removes the buffered frames, oldest first
+    pub fn drain(&mut self) -> impl Iterator<Item = EncodedVideoFrame> + '_ {
+        self.bytes = 0;
+        self.frames.drain(..)
+    }
+
+    /// the oldest buffered frame, the first frame of the next recording
+    pub fn front(&self) -> Option<&EncodedVideoFrame> {
+        self.frames.front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// seconds between the first and last buffered frame
+    pub fn duration(&self) -> f64 {
+        match (self.frames.front(), self.frames.back()) {
+            (Some(first), Some(last)) => last.seconds() - first.seconds(),
+            _ => 0.0,
+        }
+    }
+}
+
+
+fn configure_pre_roll(
+    config: Res<PreRollConfig>,
+    streams: Query<&RtspStreamHandle>,
+    added_streams: Query<(), Added<RtspStreamHandle>>,
+) {
+    if !config.is_changed() && added_streams.is_empty() {
+        return;
+    }
+
+    for stream in streams.iter() {
+        stream.set_pre_roll(*config);
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::num::NonZeroU32;
+
+    use bytes::Bytes;
+
+
+    fn frame_at(seconds: f64, is_random_access_point: bool) -> EncodedVideoFrame {
+        EncodedVideoFrame {
+            data: Bytes::from(vec![0; 100]),
+            pts: (seconds * 90_000.0) as i64,
+            clock_rate: NonZeroU32::new(90_000).unwrap(),
+            loss: 0,
+            is_random_access_point,
+            parameters: None,
+        }
+    }
+
+
+    #[test]
+    fn test_pre_roll_aligned_to_keyframes() {
+        let config = PreRollConfig {
+            duration: Duration::from_secs(2),
+            max_bytes: usize::MAX,
+        };
+
+        let mut buffer = PreRollBuffer::default();
+
+        // a leading partial group of pictures is skipped
+        buffer.push(frame_at(0.0, false), &config);
+        assert!(buffer.is_empty());
+
+        // keyframes every second, 10 frames per second
+        for i in 10..60 {
+            buffer.push(frame_at(i as f64 / 10.0, i % 10 == 0), &config);
+        }
+
+        let frames = buffer.drain().collect::<Vec<_>>();
+        assert!(frames[0].is_random_access_point);
+
+        let duration = frames.last().unwrap().seconds() - frames[0].seconds();
+        assert!(duration >= 2.0, "pre-roll shorter than configured: {}", duration);
+        assert!(duration < 3.0, "pre-roll kept an unnecessary group of pictures: {}", duration);
+
+        let config = PreRollConfig {
+            max_bytes: 1_000,
+            ..config
+        };
+
+        for i in 60..120 {
+            buffer.push(frame_at(i as f64 / 10.0, i % 10 == 0), &config);
+        }
+
+        assert!(buffer.len() * 100 <= 1_000);
+        assert!(buffer.frames[0].is_random_access_point);
+    }
+}
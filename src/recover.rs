@@ -0,0 +1,745 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Error};
+use bytes::{BufMut, BytesMut};
+
+use crate::{
+    decoder::VideoCodec,
+    demux::top_level_boxes,
+    mp4::{mdat_header, write_box, write_video_sample_entry},
+};
+
+
+/// frame rate assumed when the SPS carries no timing information
+const NOMINAL_FRAME_RATE: f64 = 30.0;
+
+const NAL_SEI: u8 = 6;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+const NAL_AUD: u8 = 9;
+const NAL_IDR: u8 = 5;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveryReport {
+    pub samples: usize,
+    pub sync_samples: usize,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f64,
+
+    /// bytes of an incomplete trailing NAL which were discarded
+    pub truncated_bytes: u64,
+}
+
+
+/// whether the file is an `Mp4Writer` recording which was never finished (an `mdat` without a `moov`)
+pub fn needs_recovery(path: impl AsRef<Path>) -> Result<bool, Error> {
+    let mut file = std::fs::File::open(path.as_ref())?;
+    let file_size = file.seek(SeekFrom::End(0))?;
+    let headers = top_level_boxes(&mut file, file_size)?;
+
+    let has = |fourcc: &[u8; 4]| headers.iter().any(|header| &header.fourcc == fourcc);
+
+    Ok(has(b"mdat") && !has(b"moov") && !has(b"moof"))
+}
+
+
+/// rebuilds the `moov` of an unfinished h.264 recording in place
+///
+/// the `mdat` is re-split into access units from its length-prefixed NAL units, IDR access units become sync
+/// samples and every sample gets the nominal frame duration of the SPS timing (or 30 fps). recovery stops at the
+/// first chunk which isn't a NAL unit, e.g. interleaved audio, and everything after it is discarded
+pub fn recover_mp4(path: impl AsRef<Path>) -> Result<RecoveryReport, Error> {
+    let path = path.as_ref();
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let file_size = file.seek(SeekFrom::End(0))?;
+
+    let headers = top_level_boxes(&mut file, file_size)?;
+    let mdat_index = headers.iter()
+        .position(|header| &header.fourcc == b"mdat")
+        .ok_or_else(|| anyhow!("{} has no mdat box", path.display()))?;
+    let mdat = headers[mdat_index];
+
+    // older recordings have no `wide` placeholder and can't take a large size header
+    let has_placeholder = mdat_index
+        .checked_sub(1)
+        .is_some_and(|i| &headers[i].fourcc == b"wide" && headers[i].size == 8);
+
+    if mdat.header_size != 8 {
+        bail!("unexpected mdat header in {}", path.display());
+    }
+
+    let mut reader = BufReader::new(&mut file);
+    reader.seek(SeekFrom::Start(mdat.payload_start()))?;
+
+    let scan = scan_access_units(&mut reader, mdat.payload_start(), file_size)?;
+
+    let sps = scan.sps.as_ref().ok_or_else(|| anyhow!("no h.264 SPS found in {}", path.display()))?;
+    let pps = scan.pps.as_ref().ok_or_else(|| anyhow!("no h.264 PPS found in {}", path.display()))?;
+    let sps_info = parse_sps(sps)?;
+
+    let frame_rate = sps_info.frame_rate.unwrap_or(NOMINAL_FRAME_RATE);
+    let duration = (90000.0 / frame_rate).round() as u32;
+
+    let mdat_header = mdat_header(scan.end - mdat.payload_start());
+    if mdat_header.len() > 8 && !has_placeholder {
+        bail!("{} is over 4 GiB without room for a large mdat header", path.display());
+    }
+
+    let mut buf = BytesMut::with_capacity(1024 + scan.access_units.len() * 8);
+    write_recovered_moov(
+        &mut buf,
+        &scan.access_units,
+        mdat.payload_start(),
+        duration,
+        (sps_info.width, sps_info.height),
+        &avc_decoder_configuration_record(sps, pps),
+    )?;
+
+    drop(reader);
+
+    // drop the incomplete tail, append the moov, then close the mdat
+    file.set_len(scan.end)?;
+    file.seek(SeekFrom::Start(scan.end))?;
+    file.write_all(&buf)?;
+    file.seek(SeekFrom::Start(mdat.payload_start() - mdat_header.len() as u64))?;
+    file.write_all(&mdat_header)?;
+    file.sync_all()?;
+
+    Ok(RecoveryReport {
+        samples: scan.access_units.len(),
+        sync_samples: scan.access_units.iter().filter(|access_unit| access_unit.is_sync).count(),
+        width: sps_info.width,
+        height: sps_info.height,
+        frame_rate,
+        truncated_bytes: file_size - scan.end,
+    })
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AccessUnit {
+    offset: u64,
+    size: u32,
+    is_sync: bool,
+}
+
+struct Scan {
+    access_units: Vec<AccessUnit>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+
+    /// end of the last complete access unit
+    end: u64,
+}
+
+/// splits length-prefixed NAL units into access units (h.264 7.4.1.2.3)
+fn scan_access_units<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    file_size: u64,
+) -> Result<Scan, Error> {
+    let mut access_units = vec![];
+    let mut current: Option<AccessUnit> = None;
+    let mut current_has_vcl = false;
+    let mut sps = None;
+    let mut pps = None;
+
+    let mut interrupted = true;
+
+    let mut pos = start;
+    while pos + 4 <= file_size {
+        let mut length = [0; 4];
+        reader.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length);
+
+        let peek = (length as usize).min(2);
+        if length == 0 || pos + 4 + peek as u64 > file_size {
+            break;
+        }
+
+        let mut header = [0; 2];
+        reader.read_exact(&mut header[..peek])?;
+
+        // forbidden_zero_bit or an unspecified type, most likely interleaved audio which can't be re-split
+        let nal_type = header[0] & 0x1f;
+        if header[0] & 0x80 != 0 || nal_type == 0 {
+            interrupted = false;
+            break;
+        }
+
+        let is_vcl = (1..=5).contains(&nal_type);
+
+        let starts_access_unit = match nal_type {
+            NAL_AUD => true,
+            NAL_SEI | NAL_SPS | NAL_PPS | 14..=18 => current_has_vcl,
+
+            // first_mb_in_slice == 0 is a single set bit in exp-golomb
+            _ if is_vcl => current_has_vcl && peek == 2 && header[1] & 0x80 != 0,
+            _ => false,
+        };
+
+        // a cut off NAL unit which starts the next access unit means the current one is complete
+        if pos + 4 + u64::from(length) > file_size {
+            interrupted = !starts_access_unit;
+            break;
+        }
+
+        if nal_type == NAL_SPS || nal_type == NAL_PPS {
+            let mut nal = header[..peek].to_vec();
+            nal.resize(length as usize, 0);
+            reader.read_exact(&mut nal[peek..])?;
+
+            let parameter_set = if nal_type == NAL_SPS { &mut sps } else { &mut pps };
+            parameter_set.get_or_insert(nal);
+        } else {
+            reader.seek(SeekFrom::Current(i64::from(length) - peek as i64))?;
+        }
+
+        if starts_access_unit {
+            access_units.extend(current.take());
+            current_has_vcl = false;
+        }
+
+        let nal_size = 4 + length;
+        match current.as_mut() {
+            Some(access_unit) => access_unit.size += nal_size,
+            None => {
+                current = Some(AccessUnit {
+                    offset: pos,
+                    size: nal_size,
+                    is_sync: false,
+                });
+            },
+        }
+
+        let access_unit = current.as_mut().unwrap();
+        access_unit.is_sync |= nal_type == NAL_IDR;
+        current_has_vcl |= is_vcl;
+
+        pos += u64::from(nal_size);
+    }
+
+    // the last access unit may be missing slices when the recording was cut off inside of it
+    if let Some(access_unit) = current {
+        if current_has_vcl && (pos == file_size || !interrupted) {
+            access_units.push(access_unit);
+        }
+    }
+
+    let end = access_units.last()
+        .map_or(start, |access_unit| access_unit.offset + u64::from(access_unit.size));
+
+    Ok(Scan {
+        access_units,
+        sps,
+        pps,
+        end,
+    })
+}
+
+
+fn avc_decoder_configuration_record(sps: &[u8], pps: &[u8]) -> Vec<u8> {
+    let mut record = vec![
+        1, // configurationVersion
+        sps[1], // AVCProfileIndication
+        sps[2], // profile_compatibility
+        sps[3], // AVCLevelIndication
+        0xff, // lengthSizeMinusOne = 3
+        0xe1, // numOfSequenceParameterSets = 1
+    ];
+    record.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    record.extend_from_slice(sps);
+    record.push(1); // numOfPictureParameterSets
+    record.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    record.extend_from_slice(pps);
+    record
+}
+
+
+fn write_recovered_moov(
+    buf: &mut BytesMut,
+    access_units: &[AccessUnit],
+    chunk_offset: u64,
+    duration: u32,
+    pixel_dimensions: (u32, u32),
+    configuration_record: &[u8],
+) -> Result<(), Error> {
+    let total_duration = u64::from(duration) * access_units.len() as u64;
+
+    write_box!(buf, b"moov", {
+        write_box!(buf, b"mvhd", {
+            buf.put_u32(1 << 24); // version
+            buf.put_u64(0); // creation_time
+            buf.put_u64(0); // modification_time
+            buf.put_u32(90000); // timescale
+            buf.put_u64(total_duration);
+            buf.put_u32(0x00010000); // rate
+            buf.put_u16(0x0100); // volume
+            buf.put_u16(0); // reserved
+            buf.put_u64(0); // reserved
+            for v in &[0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000] {
+                buf.put_u32(*v); // matrix
+            }
+            for _ in 0..6 {
+                buf.put_u32(0); // pre_defined
+            }
+            buf.put_u32(2); // next_track_id
+        });
+        write_box!(buf, b"trak", {
+            write_box!(buf, b"tkhd", {
+                buf.put_u32((1 << 24) | 7); // version, flags
+                buf.put_u64(0); // creation_time
+                buf.put_u64(0); // modification_time
+                buf.put_u32(1); // track_id
+                buf.put_u32(0); // reserved
+                buf.put_u64(total_duration);
+                buf.put_u64(0); // reserved
+                buf.put_u16(0); // layer
+                buf.put_u16(0); // alternate_group
+                buf.put_u16(0); // volume
+                buf.put_u16(0); // reserved
+                for v in &[0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000] {
+                    buf.put_u32(*v); // matrix
+                }
+                buf.put_u32(u32::from(u16::try_from(pixel_dimensions.0)?) << 16);
+                buf.put_u32(u32::from(u16::try_from(pixel_dimensions.1)?) << 16);
+            });
+            write_box!(buf, b"mdia", {
+                write_box!(buf, b"mdhd", {
+                    buf.put_u32(1 << 24); // version
+                    buf.put_u64(0); // creation_time
+                    buf.put_u64(0); // modification_time
+                    buf.put_u32(90000); // timebase
+                    buf.put_u64(total_duration);
+                    buf.put_u32(0x55c40000); // language=und + pre-defined
+                });
+                write_box!(buf, b"hdlr", {
+                    buf.extend_from_slice(&[
+                        0x00, 0x00, 0x00, 0x00, // version + flags
+                        0x00, 0x00, 0x00, 0x00, // pre_defined
+                        b'v', b'i', b'd', b'e', // handler = vide
+                        0x00, 0x00, 0x00, 0x00, // reserved[0]
+                        0x00, 0x00, 0x00, 0x00, // reserved[1]
+                        0x00, 0x00, 0x00, 0x00, // reserved[2]
+                        0x00, // name, zero-terminated (empty)
+                    ]);
+                });
+                write_box!(buf, b"minf", {
+                    write_box!(buf, b"vmhd", {
+                        buf.put_u32(1);
+                        buf.put_u64(0);
+                    });
+                    write_box!(buf, b"dinf", {
+                        write_box!(buf, b"dref", {
+                            buf.put_u32(0);
+                            buf.put_u32(1); // entry_count
+                            write_box!(buf, b"url ", {
+                                buf.put_u32(1); // version, flags=self-contained
+                            });
+                        });
+                    });
+                    write_box!(buf, b"stbl", {
+                        write_box!(buf, b"stsd", {
+                            buf.put_u32(0); // version
+                            buf.put_u32(1); // entry_count
+                            write_video_sample_entry(buf, VideoCodec::H264, pixel_dimensions, configuration_record)?;
+                        });
+                        write_box!(buf, b"stts", {
+                            buf.put_u32(0); // version
+                            buf.put_u32(1); // entry_count
+                            buf.put_u32(u32::try_from(access_units.len())?);
+                            buf.put_u32(duration);
+                        });
+                        // the samples are contiguous, a single chunk
+                        write_box!(buf, b"stsc", {
+                            buf.put_u32(0); // version
+                            buf.put_u32(1); // entry_count
+                            buf.put_u32(1); // first_chunk
+                            buf.put_u32(u32::try_from(access_units.len())?);
+                            buf.put_u32(1); // sample_description_index
+                        });
+                        write_box!(buf, b"stsz", {
+                            buf.put_u32(0); // version
+                            buf.put_u32(0); // sample_size
+                            buf.put_u32(u32::try_from(access_units.len())?);
+                            for access_unit in access_units {
+                                buf.put_u32(access_unit.size);
+                            }
+                        });
+                        write_box!(buf, b"stco", {
+                            buf.put_u32(0); // version
+                            buf.put_u32(1); // entry_count
+                            buf.put_u32(u32::try_from(chunk_offset)?); // the mdat starts near the beginning of the file
+                        });
+                        write_box!(buf, b"stss", {
+                            buf.put_u32(0); // version
+                            let sync_samples = access_units.iter()
+                                .enumerate()
+                                .filter(|(_, access_unit)| access_unit.is_sync)
+                                .map(|(i, _)| i as u32 + 1)
+                                .collect::<Vec<_>>();
+                            buf.put_u32(u32::try_from(sync_samples.len())?);
+                            for sample_number in sync_samples {
+                                buf.put_u32(sample_number);
+                            }
+                        });
+                    });
+                });
+            });
+        });
+    });
+
+    Ok(())
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SpsInfo {
+    width: u32,
+    height: u32,
+    frame_rate: Option<f64>,
+}
+
+/// reads the picture size and VUI frame rate of an h.264 SPS NAL unit (7.3.2.1.1)
+fn parse_sps(nal: &[u8]) -> Result<SpsInfo, Error> {
+    let rbsp = remove_emulation_prevention(nal.get(1..).ok_or_else(|| anyhow!("empty SPS"))?);
+    let mut bits = BitReader::new(&rbsp);
+
+    let profile_idc = bits.bits(8)?;
+    bits.skip(16)?; // constraint flags, level_idc
+    bits.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+        chroma_format_idc = bits.ue()?;
+        if chroma_format_idc == 3 {
+            bits.skip(1)?; // separate_colour_plane_flag
+        }
+        bits.ue()?; // bit_depth_luma_minus8
+        bits.ue()?; // bit_depth_chroma_minus8
+        bits.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+
+        if bits.flag()? {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if bits.flag()? {
+                    skip_scaling_list(&mut bits, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    bits.ue()?; // log2_max_frame_num_minus4
+    match bits.ue()? {
+        0 => {
+            bits.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        },
+        1 => {
+            bits.skip(1)?; // delta_pic_order_always_zero_flag
+            bits.se()?; // offset_for_non_ref_pic
+            bits.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..bits.ue()? {
+                bits.se()?; // offset_for_ref_frame
+            }
+        },
+        _ => {},
+    }
+
+    bits.ue()?; // max_num_ref_frames
+    bits.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+
+    let width_in_mbs = bits.ue()? + 1;
+    let height_in_map_units = bits.ue()? + 1;
+    let frame_mbs_only = bits.flag()?;
+    if !frame_mbs_only {
+        bits.skip(1)?; // mb_adaptive_frame_field_flag
+    }
+    bits.skip(1)?; // direct_8x8_inference_flag
+
+    let frame_height_factor = if frame_mbs_only { 1 } else { 2 };
+    let mut width = width_in_mbs * 16;
+    let mut height = height_in_map_units * 16 * frame_height_factor;
+
+    if bits.flag()? {
+        let (crop_unit_x, crop_unit_y) = match chroma_format_idc {
+            0 => (1, frame_height_factor),
+            1 => (2, 2 * frame_height_factor),
+            2 => (2, frame_height_factor),
+            _ => (1, frame_height_factor),
+        };
+
+        let left = bits.ue()?;
+        let right = bits.ue()?;
+        let top = bits.ue()?;
+        let bottom = bits.ue()?;
+
+        width = width.checked_sub(crop_unit_x * (left + right)).ok_or_else(|| anyhow!("invalid SPS cropping"))?;
+        height = height.checked_sub(crop_unit_y * (top + bottom)).ok_or_else(|| anyhow!("invalid SPS cropping"))?;
+    }
+
+    let frame_rate = if bits.flag()? {
+        parse_vui_frame_rate(&mut bits).ok().flatten()
+    } else {
+        None
+    };
+
+    Ok(SpsInfo {
+        width,
+        height,
+        frame_rate,
+    })
+}
+
+/// the frame rate of the VUI timing info (E.1.1), if present
+fn parse_vui_frame_rate(bits: &mut BitReader) -> Result<Option<f64>, Error> {
+    if bits.flag()? {
+        // aspect_ratio_info_present_flag
+        if bits.bits(8)? == 255 {
+            bits.skip(32)?; // sar_width, sar_height
+        }
+    }
+    if bits.flag()? {
+        // overscan_info_present_flag
+        bits.skip(1)?;
+    }
+    if bits.flag()? {
+        // video_signal_type_present_flag
+        bits.skip(4)?; // video_format, video_full_range_flag
+        if bits.flag()? {
+            bits.skip(24)?; // colour_primaries, transfer_characteristics, matrix_coefficients
+        }
+    }
+    if bits.flag()? {
+        // chroma_loc_info_present_flag
+        bits.ue()?;
+        bits.ue()?;
+    }
+    if !bits.flag()? {
+        // timing_info_present_flag
+        return Ok(None);
+    }
+
+    let num_units_in_tick = bits.bits(32)?;
+    let time_scale = bits.bits(32)?;
+    if num_units_in_tick == 0 || time_scale == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(time_scale as f64 / (2.0 * num_units_in_tick as f64)))
+}
+
+fn skip_scaling_list(bits: &mut BitReader, size: usize) -> Result<(), Error> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+    for _ in 0..size {
+        if next_scale != 0 {
+            next_scale = (last_scale + bits.se()? + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+    Ok(())
+}
+
+fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+    for &byte in data {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+        }
+    }
+
+    fn bit(&mut self) -> Result<u32, Error> {
+        let byte = self.data.get(self.pos / 8).ok_or_else(|| anyhow!("unexpected end of SPS"))?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(u32::from(bit))
+    }
+
+    fn flag(&mut self) -> Result<bool, Error> {
+        Ok(self.bit()? == 1)
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, Error> {
+        (0..count).try_fold(0, |value, _| Ok((value << 1) | self.bit()?))
+    }
+
+    fn skip(&mut self, count: u32) -> Result<(), Error> {
+        self.bits(count).map(|_| ())
+    }
+
+    /// unsigned exp-golomb
+    fn ue(&mut self) -> Result<u32, Error> {
+        let mut leading_zeros = 0;
+        while self.bit()? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                bail!("invalid exp-golomb code");
+            }
+        }
+        Ok((1 << leading_zeros) - 1 + self.bits(leading_zeros)?)
+    }
+
+    /// signed exp-golomb
+    fn se(&mut self) -> Result<i32, Error> {
+        let value = self.ue()? as i32;
+        Ok(if value % 2 == 1 { (value + 1) / 2 } else { -(value / 2) })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+
+    #[derive(Default)]
+    struct BitWriter {
+        data: Vec<u8>,
+        bits: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, count: u32) {
+            for i in (0..count).rev() {
+                if self.bits % 8 == 0 {
+                    self.data.push(0);
+                }
+                let bit = ((value >> i) & 1) as u8;
+                *self.data.last_mut().unwrap() |= bit << (7 - self.bits % 8);
+                self.bits += 1;
+            }
+        }
+
+        fn ue(&mut self, value: u32) {
+            let code = value + 1;
+            let length = 32 - code.leading_zeros();
+            self.bits(0, length - 1);
+            self.bits(code, length);
+        }
+    }
+
+    fn sps(width_in_mbs: u32, height_in_mbs: u32, crop_bottom: u32, timing: Option<(u32, u32)>) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.bits(66, 8); // profile_idc: baseline
+        bits.bits(0, 8);
+        bits.bits(31, 8); // level_idc
+        bits.ue(0); // seq_parameter_set_id
+        bits.ue(0); // log2_max_frame_num_minus4
+        bits.ue(2); // pic_order_cnt_type
+        bits.ue(1); // max_num_ref_frames
+        bits.bits(0, 1);
+        bits.ue(width_in_mbs - 1);
+        bits.ue(height_in_mbs - 1);
+        bits.bits(1, 1); // frame_mbs_only_flag
+        bits.bits(1, 1); // direct_8x8_inference_flag
+        if crop_bottom > 0 {
+            bits.bits(1, 1);
+            bits.ue(0);
+            bits.ue(0);
+            bits.ue(0);
+            bits.ue(crop_bottom);
+        } else {
+            bits.bits(0, 1);
+        }
+        match timing {
+            Some((num_units_in_tick, time_scale)) => {
+                bits.bits(1, 1); // vui_parameters_present_flag
+                bits.bits(0, 4); // no aspect ratio, overscan, signal type, chroma location
+                bits.bits(1, 1); // timing_info_present_flag
+                bits.bits(num_units_in_tick, 32);
+                bits.bits(time_scale, 32);
+                bits.bits(1, 1); // fixed_frame_rate_flag
+            },
+            None => bits.bits(0, 1),
+        }
+        bits.bits(1, 1); // rbsp_stop_one_bit
+
+        let mut nal = vec![0x67];
+        let mut zeros = 0;
+        for byte in bits.data {
+            if zeros >= 2 && byte <= 3 {
+                nal.push(3); // emulation_prevention_three_byte
+                zeros = 0;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            nal.push(byte);
+        }
+        nal
+    }
+
+
+    #[test]
+    fn test_parse_sps() {
+        let info = parse_sps(&sps(80, 45, 0, None)).unwrap();
+        assert_eq!((info.width, info.height), (1280, 720));
+        assert_eq!(info.frame_rate, None);
+
+        let info = parse_sps(&sps(120, 68, 4, Some((1, 60)))).unwrap();
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.frame_rate, Some(30.0));
+    }
+
+
+    #[test]
+    fn test_scan_access_units() {
+        let nal = |header: &[u8]| -> Vec<u8> {
+            [&(header.len() as u32).to_be_bytes()[..], header].concat()
+        };
+
+        let sps = sps(80, 45, 0, None);
+        let pps = [0x68, 0xce, 0x3c, 0x80];
+
+        let stream = [
+            nal(&sps),
+            nal(&pps),
+            nal(&[0x65, 0x88, 0x80]), // idr, first_mb_in_slice = 0
+            nal(&[0x65, 0x00, 0x80]), // idr, second slice of the same picture
+            nal(&[0x41, 0x9a, 0x00]), // non-idr picture
+            nal(&[0x41, 0x9a, 0x00]),
+            nal(&[0x41, 0x9a, 0x00, 0x00])[..6].to_vec(), // truncated by the interrupted recording
+        ].concat();
+
+        let file_size = stream.len() as u64;
+        let scan = scan_access_units(&mut Cursor::new(&stream), 0, file_size).unwrap();
+
+        let sizes = scan.access_units.iter().map(|access_unit| access_unit.size).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(4 + sps.len() as u32) + 8 + 7 + 7, 7, 7]);
+        assert_eq!(
+            scan.access_units.iter().map(|access_unit| access_unit.is_sync).collect::<Vec<_>>(),
+            vec![true, false, false],
+        );
+        assert_eq!(scan.sps, Some(sps));
+        assert_eq!(scan.pps, Some(pps.to_vec()));
+        assert_eq!(scan.end, file_size - 6);
+    }
+}
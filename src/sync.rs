@@ -0,0 +1,438 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use bevy::prelude::*;
+
+use crate::stream::{
+    update_stream_resolution,
+    Bgra8Frame,
+    RtspStreamHandle,
+    StreamId,
+    StreamResolution,
+    StreamResolutionChanged,
+    StreamStatus,
+};
+
+
+pub struct FrameSyncPlugin;
+impl Plugin for FrameSyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FrameSyncConfig>();
+        app.init_resource::<FrameSyncStatistics>();
+        app.add_event::<SyncedFrameSet>();
+        app.add_systems(
+            Update,
+            (
+                configure_frame_queues,
+                synchronize_frames,
+                apply_synced_frames,
+            ).chain(),
+        );
+    }
+}
+
+
+/// groups frames across the camera array by capture time, cameras should share an NTP clock for sender report timing
+#[derive(Resource, Clone, Debug)]
+pub struct FrameSyncConfig {
+    pub enabled: bool,
+
+    /// maximum distance of a frame from the capture time of its set
+    pub tolerance: Duration,
+
+    /// decoded frames buffered per stream while waiting for the slowest stream
+    pub queue_length: usize,
+}
+
+impl Default for FrameSyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            tolerance: Duration::from_millis(20),
+            queue_length: 8,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct SyncedFrame {
+    pub frame: Arc<Bgra8Frame>,
+
+    /// seconds between the frame and the capture time of the set
+    pub skew: f64,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct SyncedFrameSet {
+    /// capture time of the set, in seconds since the unix epoch
+    pub wallclock: f64,
+    pub frames: HashMap<StreamId, SyncedFrame>,
+    pub max_skew: f64,
+}
+
+
+#[derive(Debug, Clone, Default)]
+pub struct StreamSkewStatistics {
+    pub sets: u64,
+    pub last_skew: f64,
+    pub mean_abs_skew: f64,
+    pub max_abs_skew: f64,
+    pub dropped_frames: u64,
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct FrameSyncStatistics {
+    pub sets: u64,
+    pub streams: HashMap<StreamId, StreamSkewStatistics>,
+}
+
+impl FrameSyncStatistics {
+    fn record(&mut self, set: &SyncedFrameSet) {
+        self.sets += 1;
+
+        for (stream_id, frame) in set.frames.iter() {
+            let statistics = self.streams.entry(*stream_id).or_default();
+            let abs_skew = frame.skew.abs();
+
+            statistics.sets += 1;
+            statistics.last_skew = frame.skew;
+            statistics.mean_abs_skew += (abs_skew - statistics.mean_abs_skew) / statistics.sets as f64;
+            statistics.max_abs_skew = statistics.max_abs_skew.max(abs_skew);
+        }
+    }
+}
+
+
+/// pending frames of each stream, matched into sets by wallclock
+pub struct FrameSynchronizer {
+    pending: HashMap<StreamId, VecDeque<Arc<Bgra8Frame>>>,
+    dropped: HashMap<StreamId, u64>,
+
+    /// pending frames per stream, the oldest are dropped while a stalled stream holds back every set
+    queue_length: usize,
+}
+
+impl Default for FrameSynchronizer {
+    fn default() -> Self {
+        Self::new(FrameSyncConfig::default().queue_length)
+    }
+}
+
+impl FrameSynchronizer {
+    pub fn new(queue_length: usize) -> Self {
+        Self {
+            pending: HashMap::new(),
+            dropped: HashMap::new(),
+            queue_length: queue_length.max(1),
+        }
+    }
+
+    pub fn set_queue_length(&mut self, queue_length: usize) {
+        self.queue_length = queue_length.max(1);
+    }
+
+    /// frames without a finite wallclock can't be matched and are dropped
+    pub fn push(&mut self, stream_id: StreamId, frame: Bgra8Frame) {
+        if !frame.timing.wallclock.is_finite() {
+            *self.dropped.entry(stream_id).or_default() += 1;
+            return;
+        }
+
+        let queue = self.pending.entry(stream_id).or_default();
+        queue.push_back(Arc::new(frame));
+
+        while queue.len() > self.queue_length {
+            queue.pop_front();
+            *self.dropped.entry(stream_id).or_default() += 1;
+        }
+    }
+
+    pub fn remove_stream(&mut self, stream_id: StreamId) {
+        self.pending.remove(&stream_id);
+    }
+
+    /// frames dropped per stream since the last call
+    pub fn take_dropped(&mut self) -> HashMap<StreamId, u64> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// the next set holding a frame of every stream in `streams` within `tolerance` seconds, frames which can no longer be matched are dropped
+    pub fn next_set(
+        &mut self,
+        streams: &[StreamId],
+        tolerance: f64,
+    ) -> Option<SyncedFrameSet> {
+        if streams.is_empty() {
+            return None;
+        }
+
+        loop {
+            let mut reference = f64::MIN;
+            for stream_id in streams {
+                let front = self.pending.get(stream_id)?.front()?;
+                reference = reference.max(front.timing.wallclock);
+            }
+
+            // frames older than the earliest frame of the latest stream can never be matched
+            for stream_id in streams {
+                let queue = self.pending.get_mut(stream_id).unwrap();
+                while queue.front().is_some_and(|frame| frame.timing.wallclock < reference - tolerance) {
+                    queue.pop_front();
+                    *self.dropped.entry(*stream_id).or_default() += 1;
+                }
+            }
+
+            let mut chosen = Vec::with_capacity(streams.len());
+            for stream_id in streams {
+                let queue = self.pending.get(stream_id)?;
+
+                let closest = queue.iter()
+                    .enumerate()
+                    .take_while(|(_, frame)| frame.timing.wallclock <= reference + tolerance)
+                    .min_by(|(_, a), (_, b)| {
+                        let a = (a.timing.wallclock - reference).abs();
+                        let b = (b.timing.wallclock - reference).abs();
+                        a.total_cmp(&b)
+                    })
+                    .map(|(index, _)| index);
+
+                match closest {
+                    Some(index) => chosen.push((*stream_id, index)),
+                    None => break,
+                }
+            }
+
+            if chosen.len() < streams.len() {
+                // a stream has no frame near the reference, retry against its (later) earliest frame
+                continue;
+            }
+
+            let frames = chosen.into_iter()
+                .map(|(stream_id, index)| {
+                    let queue = self.pending.get_mut(&stream_id).unwrap();
+                    let frame = queue.drain(..=index).last().unwrap();
+
+                    (stream_id, frame)
+                })
+                .collect::<Vec<_>>();
+
+            let wallclock = frames.iter()
+                .map(|(_, frame)| frame.timing.wallclock)
+                .sum::<f64>() / frames.len() as f64;
+
+            let frames = frames.into_iter()
+                .map(|(stream_id, frame)| {
+                    let skew = frame.timing.wallclock - wallclock;
+
+                    (stream_id, SyncedFrame { frame, skew })
+                })
+                .collect::<HashMap<_, _>>();
+
+            let max_skew = frames.values()
+                .map(|frame| frame.skew.abs())
+                .fold(0.0, f64::max);
+
+            return Some(SyncedFrameSet {
+                wallclock,
+                frames,
+                max_skew,
+            });
+        }
+    }
+}
+
+
+fn configure_frame_queues(
+    config: Res<FrameSyncConfig>,
+    streams: Query<&RtspStreamHandle>,
+    added_streams: Query<(), Added<RtspStreamHandle>>,
+) {
+    if !config.is_changed() && added_streams.is_empty() {
+        return;
+    }
+
+    let capacity = if config.enabled {
+        config.queue_length.max(1)
+    } else {
+        0
+    };
+
+    for stream in streams.iter() {
+        stream.set_frame_queue_capacity(capacity);
+    }
+}
+
+
+fn synchronize_frames(
+    config: Res<FrameSyncConfig>,
+    mut synchronizer: Local<FrameSynchronizer>,
+    mut statistics: ResMut<FrameSyncStatistics>,
+    mut ev_synced: EventWriter<SyncedFrameSet>,
+    streams: Query<(
+        &RtspStreamHandle,
+        Option<&StreamStatus>,
+    )>,
+) {
+    if !config.enabled {
+        return;
+    }
+
+    synchronizer.set_queue_length(config.queue_length);
+
+    let mut playing = vec![];
+    for (stream, status) in streams.iter() {
+        if status == Some(&StreamStatus::Playing) {
+            playing.push(stream.id);
+        } else {
+            synchronizer.remove_stream(stream.id);
+        }
+
+        for frame in stream.take_queued_frames() {
+            synchronizer.push(stream.id, frame);
+        }
+    }
+
+    let tolerance = config.tolerance.as_secs_f64();
+    while let Some(set) = synchronizer.next_set(&playing, tolerance) {
+        statistics.record(&set);
+        ev_synced.send(set);
+    }
+
+    for (stream_id, dropped) in synchronizer.take_dropped() {
+        statistics.streams.entry(stream_id).or_default().dropped_frames += dropped;
+    }
+}
+
+
+fn apply_synced_frames(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut ev_synced: EventReader<SyncedFrameSet>,
+    mut ev_resolution: EventWriter<StreamResolutionChanged>,
+    streams: Query<(
+        Entity,
+        &RtspStreamHandle,
+        Option<&StreamResolution>,
+    )>,
+) {
+    let Some(set) = ev_synced.read().last() else {
+        return;
+    };
+
+    for (entity, stream, resolution) in streams.iter() {
+        let Some(synced_frame) = set.frames.get(&stream.id) else {
+            continue;
+        };
+
+        let frame = &synced_frame.frame;
+        let image = images.get_mut(&stream.image).unwrap();
+
+        update_stream_resolution(
+            &mut commands,
+            &mut ev_resolution,
+            (entity, stream, resolution),
+            image,
+            UVec2::new(frame.width.into(), frame.height.into()),
+        );
+
+        image.data.clone_from(&frame.data);
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU32;
+
+    use crate::stream::FrameTiming;
+
+
+    fn frame_at(wallclock: f64) -> Bgra8Frame {
+        Bgra8Frame {
+            width: NonZeroU32::new(1).unwrap(),
+            height: NonZeroU32::new(1).unwrap(),
+            data: vec![0; 4],
+            timing: FrameTiming {
+                wallclock,
+                ..default()
+            },
+        }
+    }
+
+
+    #[test]
+    fn test_next_set_within_tolerance() {
+        let mut synchronizer = FrameSynchronizer::default();
+        let streams = [StreamId(0), StreamId(1)];
+
+        for wallclock in [0.0, 0.04, 0.08] {
+            synchronizer.push(StreamId(0), frame_at(wallclock));
+        }
+        for wallclock in [0.045, 0.085] {
+            synchronizer.push(StreamId(1), frame_at(wallclock));
+        }
+
+        let set = synchronizer.next_set(&streams, 0.01).expect("expected a synced frame set");
+        assert_eq!(set.frames[&StreamId(0)].frame.timing.wallclock, 0.04);
+        assert_eq!(set.frames[&StreamId(1)].frame.timing.wallclock, 0.045);
+        assert!(set.max_skew <= 0.01);
+
+        let dropped = synchronizer.take_dropped();
+        assert_eq!(dropped.get(&StreamId(0)), Some(&1), "the unmatched first frame should be dropped");
+
+        let set = synchronizer.next_set(&streams, 0.01).expect("expected a second synced frame set");
+        assert_eq!(set.frames[&StreamId(1)].frame.timing.wallclock, 0.085);
+
+        assert!(synchronizer.next_set(&streams, 0.01).is_none());
+    }
+
+
+    #[test]
+    fn test_next_set_waits_for_all_streams() {
+        let mut synchronizer = FrameSynchronizer::default();
+        let streams = [StreamId(0), StreamId(1)];
+
+        synchronizer.push(StreamId(0), frame_at(1.0));
+
+        assert!(synchronizer.next_set(&streams, 0.01).is_none());
+    }
+
+
+    #[test]
+    fn test_queue_length_is_bounded() {
+        let mut synchronizer = FrameSynchronizer::new(4);
+        let streams = [StreamId(0), StreamId(1)];
+
+        // stream 1 stalls, stream 0 keeps only its latest frames
+        for wallclock in 0..10 {
+            synchronizer.push(StreamId(0), frame_at(wallclock as f64));
+        }
+        assert!(synchronizer.next_set(&streams, 0.01).is_none());
+        assert_eq!(synchronizer.take_dropped().get(&StreamId(0)), Some(&6));
+
+        synchronizer.push(StreamId(1), frame_at(6.0));
+        let set = synchronizer.next_set(&streams, 0.01).expect("expected a synced frame set");
+        assert_eq!(set.frames[&StreamId(0)].frame.timing.wallclock, 6.0);
+    }
+
+
+    #[test]
+    fn test_non_finite_wallclocks_are_dropped() {
+        let mut synchronizer = FrameSynchronizer::default();
+        let streams = [StreamId(0), StreamId(1)];
+
+        synchronizer.push(StreamId(0), frame_at(f64::NAN));
+        synchronizer.push(StreamId(0), frame_at(1.0));
+        synchronizer.push(StreamId(1), frame_at(1.005));
+
+        let set = synchronizer.next_set(&streams, 0.01).expect("expected a synced frame set");
+        assert_eq!(set.frames[&StreamId(0)].frame.timing.wallclock, 1.0);
+        assert_eq!(synchronizer.take_dropped().get(&StreamId(0)), Some(&1));
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::{anyhow, bail, Error};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPattern {
+    /// vertical color bars scrolling horizontally
+    Bars,
+
+    /// the frame index, drawn large on a dark background
+    Counter,
+
+    /// a static checkerboard (also usable as a calibration target)
+    Checkerboard,
+
+    /// a white person-shaped silhouette walking across a gray background, for the matting path
+    Silhouette,
+}
+
+impl TestPattern {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bars" => Some(TestPattern::Bars),
+            "counter" => Some(TestPattern::Counter),
+            "checkerboard" => Some(TestPattern::Checkerboard),
+            "silhouette" => Some(TestPattern::Silhouette),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TestPatternOptions {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f32,
+
+    /// horizontal motion in pixels per second (bars and silhouette)
+    pub speed: f32,
+
+    /// checkerboard square size in pixels
+    pub square: u32,
+
+    /// silhouette height as a fraction of the frame height
+    pub size: f32,
+}
+
+impl Default for TestPatternOptions {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            fps: 30.0,
+            speed: 120.0,
+            square: 80,
+            size: 0.8,
+        }
+    }
+}
+
+
+/// generates frames in-process for `testsrc://<pattern>?width=1280&height=720&fps=30` stream uris
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestSource {
+    pub pattern: TestPattern,
+    pub options: TestPatternOptions,
+}
+
+impl TestSource {
+    pub fn from_uri(uri: &str) -> Result<Self, Error> {
+        let url = Url::parse(uri)?;
+        if url.scheme() != "testsrc" {
+            bail!("expected a testsrc:// uri, got {}", uri);
+        }
+
+        let name = url.host_str().unwrap_or_default();
+        let pattern = TestPattern::from_name(name)
+            .ok_or_else(|| anyhow!("unknown test pattern '{}'", name))?;
+
+        let options: TestPatternOptions = serde_qs::from_str(url.query().unwrap_or_default())
+            .map_err(|error| anyhow!("invalid test pattern options in {}: {}", uri, error))?;
+        if options.width == 0 || options.height == 0 {
+            bail!("invalid test pattern dimensions in {}", uri);
+        }
+
+        // frame timestamps are `frame_index / fps` seconds
+        if !options.fps.is_finite() || options.fps <= 0.0 {
+            bail!("test pattern fps must be a positive number, got {} in {}", options.fps, uri);
+        }
+
+        Ok(Self {
+            pattern,
+            options,
+        })
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.options.width as usize, self.options.height as usize)
+    }
+
+    /// writes frame `frame_index` as rgba8 into `data`
+    pub fn render(&self, frame_index: u64, data: &mut [u8]) {
+        let (width, height) = self.dimensions();
+        let seconds = frame_index as f32 / self.options.fps;
+
+        match self.pattern {
+            TestPattern::Bars => {
+                let offset = (seconds * self.options.speed) as usize;
+                let bar_width = (width / BAR_COLORS.len()).max(1);
+
+                for (y, row) in data.chunks_exact_mut(width * 4).take(height).enumerate() {
+                    for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                        let bar = ((x + offset) / bar_width) % BAR_COLORS.len();
+                        let color = if y > height * 3 / 4 {
+                            BAR_COLORS[BAR_COLORS.len() - 1 - bar]
+                        } else {
+                            BAR_COLORS[bar]
+                        };
+                        pixel.copy_from_slice(&[color[0], color[1], color[2], 255]);
+                    }
+                }
+            },
+            TestPattern::Counter => {
+                fill(data, [16, 16, 16, 255]);
+
+                let digits = frame_index.to_string();
+                let scale = (width / (digits.len() * 4 + 1)).min(height / 7).max(1);
+                let text_width = (digits.len() * 4 - 1) * scale;
+                let left = width.saturating_sub(text_width) / 2;
+                let top = height.saturating_sub(5 * scale) / 2;
+
+                for (i, digit) in digits.bytes().enumerate() {
+                    let glyph = DIGIT_GLYPHS[(digit - b'0') as usize];
+                    draw_glyph(data, width, height, glyph, left + i * 4 * scale, top, scale);
+                }
+            },
+            TestPattern::Checkerboard => {
+                let square = self.options.square.max(1) as usize;
+
+                for (y, row) in data.chunks_exact_mut(width * 4).take(height).enumerate() {
+                    for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                        let value = if (x / square + y / square) % 2 == 0 { 255 } else { 0 };
+                        pixel.copy_from_slice(&[value, value, value, 255]);
+                    }
+                }
+            },
+            TestPattern::Silhouette => {
+                fill(data, [96, 96, 96, 255]);
+
+                let figure_height = self.options.size.clamp(0.1, 1.0) * height as f32;
+                let margin = figure_height * 0.3;
+                let travel = (width as f32 - 2.0 * margin).max(1.0);
+
+                // walk back and forth across the frame
+                let distance = (seconds * self.options.speed) % (2.0 * travel);
+                let center_x = margin + if distance < travel { distance } else { 2.0 * travel - distance };
+                let top = height as f32 * 0.975 - figure_height;
+
+                for (y, row) in data.chunks_exact_mut(width * 4).take(height).enumerate() {
+                    let v = (y as f32 - top) / figure_height;
+                    if !(0.0..=1.0).contains(&v) {
+                        continue;
+                    }
+
+                    for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                        let u = (x as f32 - center_x) / figure_height;
+                        if silhouette_contains(u, v) {
+                            pixel.copy_from_slice(&[255, 255, 255, 255]);
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
+
+
+const BAR_COLORS: [[u8; 3]; 8] = [
+    [235, 235, 235],
+    [235, 235, 16],
+    [16, 235, 235],
+    [16, 235, 16],
+    [235, 16, 235],
+    [235, 16, 16],
+    [16, 16, 235],
+    [16, 16, 16],
+];
+
+/// 3x5 glyphs, one bit per pixel (msb first, row-major)
+const DIGIT_GLYPHS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+
+fn fill(data: &mut [u8], color: [u8; 4]) {
+    data.chunks_exact_mut(4)
+        .for_each(|pixel| pixel.copy_from_slice(&color));
+}
+
+fn draw_glyph(
+    data: &mut [u8],
+    width: usize,
+    height: usize,
+    glyph: u16,
+    left: usize,
+    top: usize,
+    scale: usize,
+) {
+    for glyph_y in 0..5 {
+        for glyph_x in 0..3 {
+            let bit = 14 - (glyph_y * 3 + glyph_x);
+            if glyph & (1 << bit) == 0 {
+                continue;
+            }
+
+            for y in top + glyph_y * scale..(top + (glyph_y + 1) * scale).min(height) {
+                for x in left + glyph_x * scale..(left + (glyph_x + 1) * scale).min(width) {
+                    let i = (y * width + x) * 4;
+                    data[i..i + 4].copy_from_slice(&[255, 255, 255, 255]);
+                }
+            }
+        }
+    }
+}
+
+/// whether the point (u, v), in units of the figure height with v = 0 at the top of the head, is inside the silhouette
+fn silhouette_contains(u: f32, v: f32) -> bool {
+    let head = u * u + (v - 0.12) * (v - 0.12) < 0.11 * 0.11;
+    let neck = u.abs() < 0.05 && (0.2..0.28).contains(&v);
+    let torso = u.abs() < 0.17 && (0.26..0.62).contains(&v);
+    let arms = (0.19..0.28).contains(&u.abs()) && (0.28..0.58).contains(&v);
+    let legs = (0.03..0.15).contains(&u.abs()) && (0.6..=1.0).contains(&v);
+
+    head || neck || torso || arms || legs
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn test_from_uri() {
+        let source = TestSource::from_uri("testsrc://silhouette?width=64&height=48&fps=10").unwrap();
+
+        assert_eq!(source.pattern, TestPattern::Silhouette);
+        assert_eq!(source.dimensions(), (64, 48));
+        assert_eq!(source.options.fps, 10.0);
+        assert_eq!(source.options.speed, TestPatternOptions::default().speed);
+
+        assert!(TestSource::from_uri("testsrc://unknown").is_err());
+        assert!(TestSource::from_uri("testsrc://bars?width=0").is_err());
+        assert!(TestSource::from_uri("rtsp://bars").is_err());
+    }
+
+
+    #[test]
+    fn test_from_uri_rejects_invalid_fps() {
+        let defaults = TestSource::from_uri("testsrc://bars").unwrap();
+        assert_eq!(defaults.options, TestPatternOptions::default());
+
+        let fractional = TestSource::from_uri("testsrc://counter?fps=29.97").unwrap();
+        assert_eq!(fractional.options.fps, 29.97);
+
+        for fps in ["0", "-30", "thirty", "NaN", "inf", ""] {
+            let uri = format!("testsrc://bars?fps={}", fps);
+            assert!(TestSource::from_uri(&uri).is_err(), "fps={} should be rejected", fps);
+        }
+    }
+
+
+    #[test]
+    fn test_render_counter_changes_per_frame() {
+        let source = TestSource::from_uri("testsrc://counter?width=64&height=32").unwrap();
+        let (width, height) = source.dimensions();
+
+        let mut first = vec![0; width * height * 4];
+        let mut second = vec![0; width * height * 4];
+        source.render(1, &mut first);
+        source.render(2, &mut second);
+
+        assert_ne!(first, second);
+        assert!(first.chunks_exact(4).any(|pixel| pixel == [255, 255, 255, 255]));
+    }
+}
@@ -0,0 +1,494 @@
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    prelude::*,
+    app::{AppExit, ScheduleRunnerPlugin},
+    log::LogPlugin,
+};
+use bevy_ort::BevyOrtPlugin;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+
+use bevy_light_field::pipeline::{
+    log_pipeline_progress,
+    AlphablendFrames,
+    ColmapDataset,
+    ColorCalibration,
+    ColorCorrectedFrames,
+    LightFieldCameras,
+    MaskFrames,
+    NodeOutcome,
+    PipelineActivity,
+    PipelineConfig,
+    PipelineNode,
+    PipelineNodeFinished,
+    PipelinePlugin,
+    RawFrames,
+    RawStreams,
+    RotatedFrames,
+    Session,
+    StreamSessionBundle,
+    UndistortedAlphablendFrames,
+    UndistortedColorCorrectedFrames,
+    UndistortedFrames,
+    UndistortedMaskFrames,
+    YoloFrames,
+};
+
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    ValueEnum,
+)]
+pub enum BatchNode {
+    Frames,
+    Rotated,
+    Masks,
+    Alphablend,
+    Yolo,
+    Calibration,
+    ColorCalibration,
+    ColorCorrected,
+    Undistorted,
+    UndistortedMasks,
+    UndistortedAlphablend,
+    UndistortedColorCorrected,
+    Colmap,
+}
+
+impl BatchNode {
+    fn directory(&self) -> &'static str {
+        match self {
+            BatchNode::Frames => RawFrames::DIRECTORY,
+            BatchNode::Rotated => RotatedFrames::DIRECTORY,
+            BatchNode::Masks => MaskFrames::DIRECTORY,
+            BatchNode::Alphablend => AlphablendFrames::DIRECTORY,
+            BatchNode::Yolo => YoloFrames::DIRECTORY,
+            BatchNode::Calibration => LightFieldCameras::DIRECTORY,
+            BatchNode::ColorCalibration => ColorCalibration::DIRECTORY,
+            BatchNode::ColorCorrected => ColorCorrectedFrames::DIRECTORY,
+            BatchNode::Undistorted => UndistortedFrames::DIRECTORY,
+            BatchNode::UndistortedMasks => UndistortedMaskFrames::DIRECTORY,
+            BatchNode::UndistortedAlphablend => UndistortedAlphablendFrames::DIRECTORY,
+            BatchNode::UndistortedColorCorrected => UndistortedColorCorrectedFrames::DIRECTORY,
+            BatchNode::Colmap => ColmapDataset::DIRECTORY,
+        }
+    }
+
+    /// the nodes producing the inputs of this node
+    fn dependencies(&self) -> &'static [BatchNode] {
+        match self {
+            BatchNode::Frames => &[],
+            BatchNode::Rotated => &[BatchNode::Frames],
+            BatchNode::Masks => &[BatchNode::Rotated],
+            BatchNode::Alphablend => &[BatchNode::Rotated, BatchNode::Masks],
+            BatchNode::Yolo => &[BatchNode::Frames],
+            BatchNode::Calibration => &[BatchNode::Rotated],
+            BatchNode::ColorCalibration => &[BatchNode::Rotated],
+            BatchNode::ColorCorrected => &[BatchNode::Rotated, BatchNode::ColorCalibration],
+            BatchNode::Undistorted => &[BatchNode::Rotated, BatchNode::Calibration],
+            BatchNode::UndistortedMasks => &[BatchNode::Masks, BatchNode::Calibration],
+            BatchNode::UndistortedAlphablend => &[BatchNode::Alphablend, BatchNode::Calibration],
+            BatchNode::UndistortedColorCorrected => &[BatchNode::ColorCorrected, BatchNode::Calibration],
+            BatchNode::Colmap => &[BatchNode::Rotated, BatchNode::Masks],
+        }
+    }
+
+    fn enable(&self, config: &mut PipelineConfig) {
+        match self {
+            BatchNode::Frames => config.raw_frames = true,
+            BatchNode::Rotated => config.rotate_raw_frames = true,
+            BatchNode::Masks => config.mask_frames = true,
+            BatchNode::Alphablend => config.alphablend_frames = true,
+            BatchNode::Yolo => config.yolo = true,
+            BatchNode::Calibration => config.light_field_cameras = true,
+            BatchNode::ColorCalibration => config.color_calibration = true,
+            BatchNode::ColorCorrected => config.color_correct_frames = true,
+            BatchNode::Undistorted => config.undistort_frames = true,
+            BatchNode::UndistortedMasks => config.undistort_masks = true,
+            BatchNode::UndistortedAlphablend => config.undistort_alphablend = true,
+            BatchNode::UndistortedColorCorrected => config.undistort_color_corrected = true,
+            BatchNode::Colmap => config.colmap_dataset = true,
+        }
+    }
+
+    fn enabled(&self, config: &PipelineConfig) -> bool {
+        match self {
+            BatchNode::Frames => config.raw_frames,
+            BatchNode::Rotated => config.rotate_raw_frames,
+            BatchNode::Masks => config.mask_frames,
+            BatchNode::Alphablend => config.alphablend_frames,
+            BatchNode::Yolo => config.yolo,
+            BatchNode::Calibration => config.light_field_cameras,
+            BatchNode::ColorCalibration => config.color_calibration,
+            BatchNode::ColorCorrected => config.color_correct_frames,
+            BatchNode::Undistorted => config.undistort_frames,
+            BatchNode::UndistortedMasks => config.undistort_masks,
+            BatchNode::UndistortedAlphablend => config.undistort_alphablend,
+            BatchNode::UndistortedColorCorrected => config.undistort_color_corrected,
+            BatchNode::Colmap => config.colmap_dataset,
+        }
+    }
+}
+
+
+#[derive(
+    Debug,
+    Clone,
+    Resource,
+    Parser,
+)]
+#[command(about = "bevy_light_field headless session pipeline", version)]
+pub struct LightFieldBatch {
+    /// session ids or inclusive ranges, e.g. `3 5-9`, every session of the capture directory if omitted
+    pub sessions: Vec<String>,
+
+    #[arg(long, default_value = "capture")]
+    pub capture_directory: String,
+
+    /// comma separated nodes to run along with the nodes they depend on, the nodes of the default pipeline if omitted
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub nodes: Vec<BatchNode>,
+
+    /// inner corners of the calibration checkerboard, e.g. `9x6`
+    #[arg(long, value_parser = parse_board_size)]
+    pub checkerboard: Option<(usize, usize)>,
+
+    /// checkerboard square edge length in meters
+    #[arg(long)]
+    pub square_size: Option<f64>,
+
+    /// ArUco marker edge length in meters, calibrates from a ChArUco board of the original ArUco dictionary
+    #[arg(long)]
+    pub marker_size: Option<f64>,
+
+    /// 0 crops the undistorted frames to valid pixels, 1 keeps every source pixel
+    #[arg(long)]
+    pub undistort_alpha: Option<f64>,
+
+    /// frame index exported by the `colmap` node, every frame index if omitted
+    #[arg(long)]
+    pub colmap_frame: Option<usize>,
+
+    /// recompute the selected nodes even if their outputs are up to date
+    #[arg(long, default_value = "false")]
+    pub force: bool,
+
+    /// write the JSON summary to this file instead of stdout
+    #[arg(long)]
+    pub summary: Option<PathBuf>,
+}
+
+
+#[derive(Debug, Default, Serialize)]
+struct NodeSummary {
+    node: &'static str,
+
+    /// `generated`, `reused`, `failed`, `cancelled` or `skipped` when an upstream node did not complete
+    status: &'static str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+
+    seconds: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct SessionSummary {
+    id: usize,
+    directory: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+
+    nodes: Vec<NodeSummary>,
+}
+
+#[derive(Debug, Serialize, Resource)]
+struct BatchSummary {
+    sessions: Vec<SessionSummary>,
+    seconds: f64,
+
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl BatchSummary {
+    fn failed(&self) -> bool {
+        self.sessions.iter()
+            .any(|session| session.error.is_some() || session.nodes.iter().any(|node| node.status != "generated" && node.status != "reused"))
+    }
+}
+
+
+/// set by `exit_when_idle`, as the app is consumed by its runner
+static BATCH_FAILED: AtomicBool = AtomicBool::new(false);
+
+
+fn main() {
+    let args = LightFieldBatch::parse();
+
+    let mut summary = BatchSummary {
+        sessions: vec![],
+        seconds: 0.0,
+        started: Instant::now(),
+    };
+
+    let session_ids = match select_sessions(&args) {
+        Ok(session_ids) => session_ids,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        },
+    };
+
+    let selected: BTreeSet<BatchNode> = if args.nodes.is_empty() {
+        BatchNode::value_variants().iter()
+            .filter(|node| node.enabled(&PipelineConfig::default()))
+            .copied()
+            .collect()
+    } else {
+        args.nodes.iter().copied().collect()
+    };
+
+    let mut app = App::new();
+    app
+        .add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_millis(10))),
+            LogPlugin::default(),
+            AssetPlugin::default(),
+            BevyOrtPlugin,
+            PipelinePlugin,
+        ))
+        .insert_resource(args.clone())
+        .add_systems(
+            Update,
+            (
+                log_pipeline_progress,
+                record_finished_nodes,
+            ),
+        )
+        .add_systems(Last, exit_when_idle);
+
+    let mut config = PipelineConfig {
+        raw_frames: false,
+        rotate_raw_frames: false,
+        alphablend_frames: false,
+        yolo: false,
+        mask_frames: false,
+        light_field_cameras: false,
+        ..default()
+    };
+    for node in with_dependencies(&selected) {
+        node.enable(&mut config);
+    }
+
+    if let Some((columns, rows)) = args.checkerboard {
+        config.calibration.checkerboard.columns = columns;
+        config.calibration.checkerboard.rows = rows;
+    }
+    if let Some(square_size) = args.square_size {
+        config.calibration.checkerboard.square_size = square_size;
+    }
+    if let Some(marker_size) = args.marker_size {
+        config.calibration.checkerboard.marker_size = Some(marker_size);
+    }
+    if let Some(alpha) = args.undistort_alpha {
+        config.undistortion.alpha = alpha;
+    }
+    if let Some(frame) = args.colmap_frame {
+        config.colmap.frame = Some(frame);
+    }
+
+    for id in session_ids {
+        let session = Session::from_id(id, args.capture_directory.clone());
+
+        let mut session_summary = SessionSummary {
+            id,
+            directory: session.directory.clone(),
+            ..default()
+        };
+
+        if !std::path::Path::new(&session.directory).join("raw").is_dir() {
+            session_summary.error = Some("no raw recordings".to_string());
+            summary.sessions.push(session_summary);
+            continue;
+        }
+
+        if args.force {
+            for node in &selected {
+                let directory = format!("{}/{}", session.directory, node.directory());
+                if let Err(err) = std::fs::remove_dir_all(&directory) {
+                    if err.kind() != std::io::ErrorKind::NotFound {
+                        warn!("failed to remove {}: {}", directory, err);
+                    }
+                }
+            }
+        }
+
+        // a skipped node is reported unless a finished event replaces it
+        session_summary.nodes = with_dependencies(&selected)
+            .into_iter()
+            .map(|node| NodeSummary {
+                node: node.directory(),
+                status: "skipped",
+                ..default()
+            })
+            .collect();
+
+        let raw_streams = RawStreams::load_from_session(&session);
+        app.world.spawn(StreamSessionBundle {
+            config: config.clone(),
+            raw_streams,
+            session,
+        });
+
+        summary.sessions.push(session_summary);
+    }
+
+    app.insert_resource(summary);
+    app.run();
+
+    if BATCH_FAILED.load(Ordering::Relaxed) {
+        std::process::exit(1);
+    }
+}
+
+
+fn select_sessions(args: &LightFieldBatch) -> Result<Vec<usize>, String> {
+    if args.sessions.is_empty() {
+        let entries = std::fs::read_dir(&args.capture_directory)
+            .map_err(|err| format!("failed to read {}: {}", args.capture_directory, err))?;
+
+        let mut session_ids = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<usize>().ok())
+            .collect::<Vec<_>>();
+        session_ids.sort();
+
+        return Ok(session_ids);
+    }
+
+    let mut session_ids = BTreeSet::new();
+    for selection in &args.sessions {
+        let invalid = || format!("invalid session id or range `{}`", selection);
+
+        match selection.split_once('-') {
+            Some((start, end)) => {
+                let start = start.trim().parse::<usize>().map_err(|_| invalid())?;
+                let end = end.trim().parse::<usize>().map_err(|_| invalid())?;
+                session_ids.extend(start..=end);
+            },
+            None => {
+                session_ids.insert(selection.trim().parse::<usize>().map_err(|_| invalid())?);
+            },
+        }
+    }
+
+    Ok(session_ids.into_iter().collect())
+}
+
+
+fn parse_board_size(size: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("invalid checkerboard size `{}`, expected inner corners as `<columns>x<rows>`", size);
+
+    let (columns, rows) = size.split_once('x').ok_or_else(invalid)?;
+    let columns = columns.trim().parse::<usize>().map_err(|_| invalid())?;
+    let rows = rows.trim().parse::<usize>().map_err(|_| invalid())?;
+
+    if columns < 2 || rows < 2 {
+        return Err(invalid());
+    }
+
+    Ok((columns, rows))
+}
+
+
+fn with_dependencies(nodes: &BTreeSet<BatchNode>) -> BTreeSet<BatchNode> {
+    let mut required = BTreeSet::new();
+    let mut pending = nodes.iter().copied().collect::<Vec<_>>();
+
+    while let Some(node) = pending.pop() {
+        if required.insert(node) {
+            pending.extend(node.dependencies());
+        }
+    }
+
+    required
+}
+
+
+fn record_finished_nodes(
+    mut finished_events: EventReader<PipelineNodeFinished>,
+    sessions: Query<&Session>,
+    mut summary: ResMut<BatchSummary>,
+) {
+    for finished in finished_events.read() {
+        let Ok(session) = sessions.get(finished.session) else {
+            continue;
+        };
+
+        let Some(node) = summary.sessions.iter_mut()
+            .filter(|summary| summary.id == session.id)
+            .flat_map(|summary| summary.nodes.iter_mut())
+            .find(|node| node.node == finished.node) else {
+            continue;
+        };
+
+        node.seconds = finished.seconds;
+        (node.status, node.error) = match &finished.outcome {
+            NodeOutcome::Generated => ("generated", None),
+            NodeOutcome::Reused => ("reused", None),
+            NodeOutcome::Failed { error } => ("failed", Some(error.clone())),
+            NodeOutcome::Cancelled => ("cancelled", None),
+        };
+    }
+}
+
+
+/// nodes start the frame after their inputs complete, so the pipeline is done once it stays idle for two frames
+fn exit_when_idle(
+    args: Res<LightFieldBatch>,
+    activity: Res<PipelineActivity>,
+    mut summary: ResMut<BatchSummary>,
+    mut idle_frames: Local<usize>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if !activity.is_idle() {
+        *idle_frames = 0;
+        return;
+    }
+
+    *idle_frames += 1;
+    if *idle_frames < 2 {
+        return;
+    }
+
+    summary.seconds = summary.started.elapsed().as_secs_f64();
+    BATCH_FAILED.store(summary.failed(), Ordering::Relaxed);
+
+    let json = serde_json::to_string_pretty(&*summary).expect("failed to serialize the batch summary");
+    match &args.summary {
+        Some(path) => {
+            if let Err(err) = std::fs::write(path, json) {
+                error!("failed to write the batch summary to {}: {}", path.display(), err);
+                BATCH_FAILED.store(true, Ordering::Relaxed);
+            }
+        },
+        None => println!("{}", json),
+    }
+
+    exit.send(AppExit);
+}
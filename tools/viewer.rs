@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use bevy::{
     prelude::*,
     app::AppExit,
@@ -27,34 +29,49 @@ use bevy_args::{
 use clap::ValueEnum;
 
 use bevy_light_field::{
+    calibration::color::ColorCorrection,
     grid_view::{
         Element,
         GridView
     },
-    materials::foreground::ForegroundMaterial,
+    materials::{
+        color_correction::ColorCorrectionMaterial,
+        foreground::ForegroundMaterial,
+    },
     matting::{
         MattedStream,
         MattingPlugin,
     },
+    mp4::FragmentOptions,
     person_detect::{
         DetectPersons,
         PersonDetectedEvent,
     },
     pipeline::{
         load_png,
+        log_pipeline_progress,
         AlphablendFrames,
+        ColorCalibration,
         MaskFrames,
+        PipelineCancelled,
         PipelineConfig,
+        PipelineNode,
         RawFrames,
         RawStreams,
+        RecordingTrigger,
         RotatedFrames,
         Session,
+        SessionManifest,
         StreamSessionBundle,
     },
+    preroll::PreRollConfig,
     stream::{
+        RecordingOptions,
         RtspStreamHandle,
         RtspStreamManager,
+        StreamId,
     },
+    sync::FrameSyncConfig,
     LightFieldPlugin,
 };
 
@@ -109,6 +126,26 @@ pub struct LightFieldViewer {
     #[arg(long, default_value = "1024")]
     pub max_matting_height: u32,
 
+    /// synchronize the live streams by capture time, within the given tolerance
+    #[arg(long)]
+    pub sync_tolerance_ms: Option<u64>,
+
+    /// seconds of footage before the recording trigger to include in each recording
+    #[arg(long, default_value = "3.0")]
+    pub pre_roll_secs: f32,
+
+    /// record fragmented mp4s with a fragment every given seconds, which survive an abrupt exit
+    #[arg(long)]
+    pub fragment_secs: Option<f32>,
+
+    /// split recordings into numbered files of about this many megabytes, at keyframes
+    #[arg(long)]
+    pub segment_mb: Option<u64>,
+
+    /// color correct the live streams with the color calibration of this session
+    #[arg(long)]
+    pub calibration_session: Option<usize>,
+
     #[arg(long)]
     pub session_id: Option<usize>,
     #[arg(long)]
@@ -159,20 +196,38 @@ fn main() {
         .add_systems(Startup, setup_camera)
         .add_systems(Update, press_esc_close);
 
+    app.insert_resource(RecordingOptions {
+        fragments: args.fragment_secs.map(|fragment_secs| FragmentOptions {
+            max_frames: u32::MAX,
+            max_duration: std::time::Duration::from_secs_f32(fragment_secs.max(0.0)),
+        }),
+        segment_bytes: args.segment_mb.map(|segment_mb| segment_mb * 1024 * 1024),
+    });
+
+    app.insert_resource(PreRollConfig {
+        duration: std::time::Duration::from_secs_f32(args.pre_roll_secs.max(0.0)),
+        ..default()
+    });
+
+    if let Some(tolerance_ms) = args.sync_tolerance_ms {
+        app.insert_resource(FrameSyncConfig {
+            enabled: true,
+            tolerance: std::time::Duration::from_millis(tolerance_ms),
+            ..default()
+        });
+    }
+
     if online {
         app
             .init_resource::<LiveSession>()
+            .insert_resource(load_color_corrections(args.calibration_session))
             .add_systems(
-                Startup,
+                Update,
                 (
                     create_mask_streams,
-                ),
-            )
-            .add_systems(
-                PostStartup,
-                (
+                    create_color_corrected_streams,
                     setup_live_gridview,
-                ),
+                ).chain(),
             )
             .add_systems(
                 Update,
@@ -196,13 +251,15 @@ fn main() {
                 (
                     offline_viewer,
                     press_arrow_key_frame_navigation,
+                    press_c_cancel_pipeline,
+                    log_pipeline_progress,
                 ),
             );
     }
 
     if args.show_fps {
         app.add_plugins(FrameTimeDiagnosticsPlugin);
-        app.add_systems(PostStartup, fps_display_setup.after(setup_live_gridview));
+        app.add_systems(PostStartup, fps_display_setup);
         app.add_systems(Update, fps_update_system);
     }
 
@@ -220,7 +277,7 @@ fn create_mask_streams(
             Entity,
             &RtspStreamHandle,
         ),
-        Without<MattedStream>
+        Added<RtspStreamHandle>,
     >,
 ) {
     let size = Extent3d {
@@ -269,11 +326,67 @@ fn create_mask_streams(
 }
 
 
+#[derive(Resource, Default)]
+struct LiveColorCorrections(HashMap<StreamId, ColorCorrection>);
+
+/// the color corrections of the cameras calibrated in the given session
+fn load_color_corrections(calibration_session: Option<usize>) -> LiveColorCorrections {
+    let Some(session_id) = calibration_session else {
+        return LiveColorCorrections::default();
+    };
+
+    let session = Session::from_id(session_id, "capture".to_string());
+    let corrections = ColorCalibration::load_from_session(&session).cameras
+        .into_iter()
+        .map(|(stream_id, color)| (StreamId(stream_id), color))
+        .collect::<HashMap<_, _>>();
+
+    if corrections.is_empty() {
+        warn!("session {} has no color calibration, the streams are shown uncorrected", session_id);
+    }
+
+    LiveColorCorrections(corrections)
+}
+
+
+#[derive(Component)]
+struct ColorCorrectedStream(Handle<ColorCorrectionMaterial>);
+
+fn create_color_corrected_streams(
+    mut commands: Commands,
+    mut color_correction_materials: ResMut<Assets<ColorCorrectionMaterial>>,
+    corrections: Res<LiveColorCorrections>,
+    input_streams: Query<
+        (
+            Entity,
+            &RtspStreamHandle,
+        ),
+        Added<RtspStreamHandle>,
+    >,
+) {
+    for (entity, stream) in input_streams.iter() {
+        let Some(correction) = corrections.0.get(&stream.id) else {
+            continue;
+        };
+
+        let material = color_correction_materials.add(ColorCorrectionMaterial {
+            input: stream.image.clone(),
+            correction: correction.into(),
+        });
+
+        commands.entity(entity).insert(ColorCorrectedStream(material));
+    }
+}
+
+
 fn setup_live_gridview(
     mut grid_view: ResMut<GridView>,
+    mut removed_streams: RemovedComponents<RtspStreamHandle>,
+    added_streams: Query<(), Or<(Added<RtspStreamHandle>, Added<MattedStream>, Added<ColorCorrectedStream>)>>,
     input_streams: Query<(
         Entity,
         &RtspStreamHandle,
+        Option<&ColorCorrectedStream>,
     )>,
     person_detection_stream: Query<
         (
@@ -283,12 +396,20 @@ fn setup_live_gridview(
         With<DetectPersons>,
     >,
 ) {
+    let removed = removed_streams.read().count() > 0;
+    if added_streams.is_empty() && !removed {
+        return;
+    }
+
     let visible_input_streams = input_streams.iter()
-        .filter(|(_, stream)| stream.descriptor.visible.unwrap_or_default())
+        .filter(|(_, stream, _)| stream.descriptor.visible.unwrap_or_default())
         .collect::<Vec<_>>();
 
     let grid_elements = visible_input_streams.iter()
-        .map(|(_, input_stream)| Element::Image(input_stream.image.clone()))
+        .map(|(_, input_stream, color_corrected)| match color_corrected {
+            Some(color_corrected) => Element::ColorCorrected(color_corrected.0.clone()),
+            None => Element::Image(input_stream.image.clone()),
+        })
         .chain(
             person_detection_stream.iter()
                 .map(|(_, matted_stream) | Element::Alphablend(matted_stream.material.clone()))
@@ -415,12 +536,56 @@ fn offline_viewer(
 
 
 
+/// starts recording a new session and writes its `session.json`
+fn start_session(
+    stream_manager: &RtspStreamManager,
+    recording_options: &RecordingOptions,
+    trigger: RecordingTrigger,
+    config: &PipelineConfig,
+) -> Option<(Session, SessionManifest)> {
+    let session = Session::new("capture".to_string());
+
+    let streams = match stream_manager.start_recording(
+        &session,
+        recording_options,
+    ) {
+        Ok(streams) => streams,
+        Err(err) => {
+            error!("failed to start recording session {}: {}", session.id, err);
+            return None;
+        },
+    };
+
+    let manifest = SessionManifest::new(&session, trigger, streams, config.clone());
+    if let Err(err) = manifest.save(&session.directory) {
+        error!("failed to write the manifest of session {}: {}", session.id, err);
+    }
+
+    Some((session, manifest))
+}
+
+fn stop_session(
+    sessions: &mut Query<(&Session, &mut SessionManifest)>,
+    session_entity: Entity,
+) {
+    let Ok((session, mut manifest)) = sessions.get_mut(session_entity) else {
+        return;
+    };
+
+    if let Err(err) = manifest.stop(&session.directory) {
+        error!("failed to write the manifest of session {}: {}", session.id, err);
+    }
+}
+
+
 fn automatic_recording(
     mut commands: Commands,
     time: Res<Time>,
     mut ev_person: EventReader<PersonDetectedEvent>,
     stream_manager: Res<RtspStreamManager>,
+    recording_options: Res<RecordingOptions>,
     mut live_session: ResMut<LiveSession>,
+    mut sessions: Query<(&Session, &mut SessionManifest)>,
     mut person_timeout: Local<Stopwatch>,
 ) {
     if live_session.0.is_some() {
@@ -429,8 +594,9 @@ fn automatic_recording(
 
             info!("no person detected for 3 seconds, stop recording");
 
-            let _session_entity = live_session.0.take().unwrap();
+            let session_entity = live_session.0.take().unwrap();
             let _raw_streams = stream_manager.stop_recording();
+            stop_session(&mut sessions, session_entity);
 
             // TODO: TODO: add a recording finished event when all streams are closed, then execute the following command if pipeline auto-processing is enabled (not ideal for fast recording)
 
@@ -454,15 +620,17 @@ fn automatic_recording(
     ev_person.clear();
 
     if person_detected {
-        // TODO: deduplicate start recording logic
-        let session = Session::new("capture".to_string());
-
-        stream_manager.start_recording(
-            &session,
-        );
-
         // TODO: build pipeline config from args
-        let entity = commands.spawn(session).id();
+        let Some((session, manifest)) = start_session(
+            &stream_manager,
+            &recording_options,
+            RecordingTrigger::PersonDetected,
+            &PipelineConfig::default(),
+        ) else {
+            return;
+        };
+
+        let entity = commands.spawn((session, manifest)).id();
         live_session.0 = Some(entity);
     }
 }
@@ -475,6 +643,7 @@ fn press_r_start_recording(
     mut commands: Commands,
     keys: Res<ButtonInput<KeyCode>>,
     stream_manager: Res<RtspStreamManager>,
+    recording_options: Res<RecordingOptions>,
     mut live_session: ResMut<LiveSession>,
 ) {
     if keys.just_pressed(KeyCode::KeyR) {
@@ -482,19 +651,24 @@ fn press_r_start_recording(
             return;
         }
 
-        let session = Session::new("capture".to_string());
-
-        stream_manager.start_recording(
-            &session,
-        );
+        let config = PipelineConfig::default();
+        let Some((session, manifest)) = start_session(
+            &stream_manager,
+            &recording_options,
+            RecordingTrigger::Manual,
+            &config,
+        ) else {
+            return;
+        };
 
-        let entity = commands.spawn(
+        let entity = commands.spawn((
             StreamSessionBundle {
                 session,
                 raw_streams: RawStreams::default(),
-                config: PipelineConfig::default(),
+                config,
             },
-        ).id();
+            manifest,
+        )).id();
         live_session.0 = Some(entity);
     }
 }
@@ -504,11 +678,13 @@ fn press_s_stop_recording(
     keys: Res<ButtonInput<KeyCode>>,
     stream_manager: Res<RtspStreamManager>,
     mut live_session: ResMut<LiveSession>,
+    mut sessions: Query<(&Session, &mut SessionManifest)>,
 ) {
     if keys.just_pressed(KeyCode::KeyS) && live_session.0.is_some() {
         let session_entity = live_session.0.take().unwrap();
 
         let raw_streams = stream_manager.stop_recording();
+        stop_session(&mut sessions, session_entity);
 
         commands.entity(session_entity)
             .insert(RawStreams {
@@ -532,6 +708,18 @@ fn press_arrow_key_frame_navigation(
 }
 
 
+fn press_c_cancel_pipeline(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    sessions: Query<Entity, (With<Session>, Without<PipelineCancelled>)>,
+) {
+    if keys.just_pressed(KeyCode::KeyC) {
+        for session in sessions.iter() {
+            commands.entity(session).insert(PipelineCancelled);
+        }
+    }
+}
+
 
 fn fps_display_setup(
     mut commands: Commands,